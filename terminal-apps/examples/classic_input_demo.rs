@@ -1,7 +1,7 @@
 use warp_terminal_apps::classic_input::{ClassicInput, PromptStyle, Prompt, AgentState};
 
 fn main() {
-    let mut input = ClassicInput::new();
+    let mut input = ClassicInput::from_env();
     
     println!("=== Warp Classic Input Demo ===\n");
     
@@ -123,6 +123,11 @@ fn demo_smart_features() {
 
     let mut features = SmartFeatures::new();
 
+    // Finished commands rank higher the more often they run and succeed
+    let mut block = Block::new("grep -rn TODO src".to_string(), ".".to_string());
+    block.set_output(String::new(), String::new(), 0);
+    features.auto_completion.record_block(&block);
+
     // Autocomplete
     println!("Autocompletion suggestions for 'gr':");
     let suggestions = features.get_suggestions("gr");
@@ -1,5 +1,6 @@
 use crate::keyset::KeySet;
 use crate::theme::Theme;
+use crate::workflow::ExtendedWorkflow;
 use std::collections::HashMap;
 
 /// Terminal application state and configuration
@@ -9,6 +10,7 @@ pub struct TerminalApp {
     pub current_keyset: Option<KeySet>,
     pub available_themes: HashMap<String, Theme>,
     pub available_keysets: HashMap<String, KeySet>,
+    pub workflows: HashMap<String, ExtendedWorkflow>,
     pub custom_config: HashMap<String, String>,
 }
 
@@ -20,6 +22,7 @@ impl TerminalApp {
             current_keyset: None,
             available_themes: HashMap::new(),
             available_keysets: HashMap::new(),
+            workflows: HashMap::new(),
             custom_config: HashMap::new(),
         }
     }
@@ -68,6 +71,16 @@ impl TerminalApp {
         }
     }
 
+    /// Register a workflow
+    pub fn register_workflow(&mut self, workflow: ExtendedWorkflow) {
+        self.workflows.insert(workflow.name.clone(), workflow);
+    }
+
+    /// Get a workflow by name
+    pub fn get_workflow(&self, name: &str) -> Option<&ExtendedWorkflow> {
+        self.workflows.get(name)
+    }
+
     /// Get a keybinding from the current keyset
     pub fn get_keybinding(&self, action: &str) -> Option<String> {
         self.current_keyset.as_ref().and_then(|ks| ks.get_binding(action).cloned())
@@ -97,6 +110,11 @@ impl TerminalApp {
     pub fn list_keysets(&self) -> Vec<&str> {
         self.available_keysets.keys().map(|s| s.as_str()).collect()
     }
+
+    /// List all registered workflows
+    pub fn list_workflows(&self) -> Vec<&str> {
+        self.workflows.keys().map(|s| s.as_str()).collect()
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,437 @@
+use base64::Engine;
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Bytes looked at to tell text from binary
+const SNIFF_BYTES: usize = 8 * 1024;
+
+/// Text file extensions with a more specific type than `text/plain`
+const TEXT_TYPES: &[(&str, &str)] = &[
+    ("rs", "text/x-rust"),
+    ("py", "text/x-python"),
+    ("js", "text/javascript"),
+    ("mjs", "text/javascript"),
+    ("ts", "text/typescript"),
+    ("tsx", "text/typescript"),
+    ("go", "text/x-go"),
+    ("c", "text/x-c"),
+    ("h", "text/x-c"),
+    ("cpp", "text/x-c++"),
+    ("java", "text/x-java"),
+    ("rb", "text/x-ruby"),
+    ("sh", "application/x-sh"),
+    ("json", "application/json"),
+    ("toml", "application/toml"),
+    ("yaml", "application/yaml"),
+    ("yml", "application/yaml"),
+    ("xml", "application/xml"),
+    ("html", "text/html"),
+    ("css", "text/css"),
+    ("md", "text/markdown"),
+    ("csv", "text/csv"),
+    ("svg", "image/svg+xml"),
+];
+
+#[derive(Error, Debug)]
+pub enum AttachmentError {
+    #[error("Not a file: {}", .0.display())]
+    NotAFile(PathBuf),
+    #[error("{} is {size} bytes, over the {limit} byte limit", path.display())]
+    TooLarge { path: PathBuf, size: u64, limit: u64 },
+    #[error("Attachments would exceed {limit} bytes in total")]
+    TotalTooLarge { limit: u64 },
+    #[error("Can't attach {}: {mime} files are not supported", path.display())]
+    Unsupported { path: PathBuf, mime: String },
+    #[error("{} changed since it was attached", .0.display())]
+    Changed(PathBuf),
+    #[error("Already attached: {0}")]
+    AlreadyAttached(String),
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+pub type AttachmentResult<T> = Result<T, AttachmentError>;
+
+/// Size limits, per file and for everything attached to one request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AttachmentLimits {
+    pub max_text_bytes: u64,
+    pub max_image_bytes: u64,
+    pub max_total_bytes: u64,
+}
+
+impl Default for AttachmentLimits {
+    fn default() -> Self {
+        Self {
+            max_text_bytes: 256 * 1024,
+            max_image_bytes: 5 * 1024 * 1024,
+            max_total_bytes: 10 * 1024 * 1024,
+        }
+    }
+}
+
+/// What the agent gets for an attachment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttachmentContent {
+    Text(String),
+    /// Base64 encoded image bytes
+    Image { base64: String },
+}
+
+/// Enough about a file to tell whether it changed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileStamp {
+    pub len: u64,
+    pub hash: u64,
+}
+
+impl FileStamp {
+    fn of(bytes: &[u8]) -> Self {
+        let mut hasher = DefaultHasher::new();
+        bytes.hash(&mut hasher);
+        Self { len: bytes.len() as u64, hash: hasher.finish() }
+    }
+}
+
+/// A file attached to an agent request, read once when attached
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attachment {
+    pub path: PathBuf,
+    /// How the file is shown on its chip and to the agent
+    pub name: String,
+    pub mime: String,
+    pub size: u64,
+    pub stamp: FileStamp,
+    pub content: AttachmentContent,
+}
+
+impl Attachment {
+    pub fn load(path: impl AsRef<Path>, limits: &AttachmentLimits) -> AttachmentResult<Self> {
+        let path = path.as_ref();
+        let metadata = fs::metadata(path)?;
+        if !metadata.is_file() {
+            return Err(AttachmentError::NotAFile(path.to_path_buf()));
+        }
+        let ceiling = limits.max_text_bytes.max(limits.max_image_bytes);
+        if metadata.len() > ceiling {
+            return Err(AttachmentError::TooLarge { path: path.to_path_buf(), size: metadata.len(), limit: ceiling });
+        }
+        let bytes = fs::read(path)?;
+        let mime = sniff_mime(path, &bytes);
+        let size = bytes.len() as u64;
+        let (limit, content) = if is_image(mime) {
+            let base64 = base64::engine::general_purpose::STANDARD.encode(&bytes);
+            (limits.max_image_bytes, AttachmentContent::Image { base64 })
+        } else if is_text(mime) {
+            (limits.max_text_bytes, AttachmentContent::Text(extract_text(&bytes)))
+        } else {
+            return Err(AttachmentError::Unsupported { path: path.to_path_buf(), mime: mime.to_string() });
+        };
+        if size > limit {
+            return Err(AttachmentError::TooLarge { path: path.to_path_buf(), size, limit });
+        }
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        Ok(Self {
+            path: path.to_path_buf(),
+            name,
+            mime: mime.to_string(),
+            size,
+            stamp: FileStamp::of(&bytes),
+            content,
+        })
+    }
+
+    pub fn is_image(&self) -> bool {
+        matches!(self.content, AttachmentContent::Image { .. })
+    }
+
+    pub fn text(&self) -> Option<&str> {
+        match &self.content {
+            AttachmentContent::Text(text) => Some(text),
+            AttachmentContent::Image { .. } => None,
+        }
+    }
+
+    /// `data:` URL for an image, as chat APIs take them
+    pub fn data_url(&self) -> Option<String> {
+        match &self.content {
+            AttachmentContent::Image { base64 } => Some(format!("data:{};base64,{}", self.mime, base64)),
+            AttachmentContent::Text(_) => None,
+        }
+    }
+
+    /// Whether the file on disk differs from what was attached. A file that
+    /// is gone or unreadable counts as changed.
+    pub fn has_changed(&self) -> bool {
+        let Ok(metadata) = fs::metadata(&self.path) else {
+            return true;
+        };
+        if metadata.len() != self.stamp.len {
+            return true;
+        }
+        match fs::read(&self.path) {
+            Ok(bytes) => FileStamp::of(&bytes) != self.stamp,
+            Err(_) => true,
+        }
+    }
+
+    /// Reads the file again, refusing if it changed since it was attached
+    pub fn reread(&self, limits: &AttachmentLimits) -> AttachmentResult<Self> {
+        let fresh = Self::load(&self.path, limits)?;
+        if fresh.stamp != self.stamp {
+            return Err(AttachmentError::Changed(self.path.clone()));
+        }
+        Ok(Self { name: self.name.clone(), ..fresh })
+    }
+
+    /// Lines for the preview pane: a header, then the start of the text or the
+    /// image's size, each cut to `width`
+    pub fn preview(&self, width: usize, height: usize) -> Vec<String> {
+        let mut lines = vec![format!("{} · {} · {}", self.name, self.mime, format_size(self.size))];
+        match &self.content {
+            AttachmentContent::Text(text) => {
+                let total = text.lines().count();
+                let room = height.saturating_sub(1);
+                let shown = if total > room { room.saturating_sub(1) } else { total };
+                lines.extend(text.lines().take(shown).map(|l| l.replace('\t', "    ")));
+                if total > shown {
+                    lines.push(format!("… {} more lines", total - shown));
+                }
+            }
+            AttachmentContent::Image { base64 } => {
+                let bytes = base64::engine::general_purpose::STANDARD.decode(base64).unwrap_or_default();
+                lines.push(match image_dimensions(&bytes) {
+                    Some((w, h)) => format!("image, {}×{}", w, h),
+                    None => "image".to_string(),
+                });
+            }
+        }
+        lines.truncate(height);
+        lines.into_iter().map(|l| l.chars().take(width).collect()).collect()
+    }
+}
+
+/// Attachments waiting to go out with the next agent request
+#[derive(Debug, Clone, Default)]
+pub struct AttachmentSet {
+    pub items: Vec<Attachment>,
+    pub limits: AttachmentLimits,
+}
+
+impl AttachmentSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_limits(mut self, limits: AttachmentLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Reads and adds `path`, keeping the set under the total size limit
+    pub fn attach(&mut self, path: impl AsRef<Path>) -> AttachmentResult<&Attachment> {
+        let mut attachment = Attachment::load(path, &self.limits)?;
+        if self.items.iter().any(|a| a.path == attachment.path) {
+            return Err(AttachmentError::AlreadyAttached(attachment.name));
+        }
+        if self.total_size() + attachment.size > self.limits.max_total_bytes {
+            return Err(AttachmentError::TotalTooLarge { limit: self.limits.max_total_bytes });
+        }
+        // Two files with one name are told apart by their paths
+        if self.get(&attachment.name).is_some() {
+            attachment.name = attachment.path.display().to_string();
+        }
+        self.items.push(attachment);
+        Ok(self.items.last().unwrap())
+    }
+
+    pub fn detach(&mut self, name: &str) -> Option<Attachment> {
+        let index = self.items.iter().position(|a| a.name == name)?;
+        Some(self.items.remove(index))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Attachment> {
+        self.items.iter().find(|a| a.name == name)
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.items.iter().map(|a| a.size).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Names of attachments whose files changed since they were attached
+    pub fn changed(&self) -> Vec<&str> {
+        self.items.iter().filter(|a| a.has_changed()).map(|a| a.name.as_str()).collect()
+    }
+
+    /// The attachments for a request, failing on the first file that changed
+    pub fn verified(&self) -> AttachmentResult<Vec<Attachment>> {
+        self.items.iter().map(|a| a.reread(&self.limits)).collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
+    }
+}
+
+/// MIME type from the file's leading bytes, falling back to its extension for text
+pub fn sniff_mime(path: &Path, bytes: &[u8]) -> &'static str {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"BM", "image/bmp"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"\x7fELF", "application/x-executable"),
+    ];
+    if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        return "image/webp";
+    }
+    if let Some((_, mime)) = SIGNATURES.iter().find(|(magic, _)| bytes.starts_with(magic)) {
+        return mime;
+    }
+    if !looks_like_text(&bytes[..bytes.len().min(SNIFF_BYTES)]) {
+        return "application/octet-stream";
+    }
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    TEXT_TYPES
+        .iter()
+        .find(|(ext, _)| *ext == extension)
+        .map(|(_, mime)| *mime)
+        .unwrap_or("text/plain")
+}
+
+fn is_image(mime: &str) -> bool {
+    mime.starts_with("image/") && mime != "image/svg+xml"
+}
+
+fn is_text(mime: &str) -> bool {
+    mime.starts_with("text/") || TEXT_TYPES.iter().any(|(_, text_mime)| *text_mime == mime)
+}
+
+/// No NUL bytes and valid UTF-8, allowing a character cut off at the end
+fn looks_like_text(bytes: &[u8]) -> bool {
+    if bytes.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(_) => true,
+        Err(err) => err.error_len().is_none() && bytes.len() == SNIFF_BYTES,
+    }
+}
+
+/// UTF-8 text without a byte order mark or carriage returns
+fn extract_text(bytes: &[u8]) -> String {
+    let bytes = bytes.strip_prefix(b"\xef\xbb\xbf").unwrap_or(bytes);
+    String::from_utf8_lossy(bytes).replace("\r\n", "\n")
+}
+
+/// Width and height from a PNG or GIF header
+fn image_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    if bytes.starts_with(b"\x89PNG") && bytes.len() >= 24 {
+        let w = u32::from_be_bytes(bytes[16..20].try_into().ok()?);
+        let h = u32::from_be_bytes(bytes[20..24].try_into().ok()?);
+        return Some((w, h));
+    }
+    if bytes.starts_with(b"GIF8") && bytes.len() >= 10 {
+        let w = u16::from_le_bytes([bytes[6], bytes[7]]);
+        let h = u16::from_le_bytes([bytes[8], bytes[9]]);
+        return Some((w as u32, h as u32));
+    }
+    None
+}
+
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        b if b < 1024 => format!("{} B", b),
+        b if b < 1024 * 1024 => format!("{:.1} KB", b as f64 / 1024.0),
+        b => format!("{:.1} MB", b as f64 / (1024.0 * 1024.0)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    const PNG_1X2: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\0\0\0\x01\0\0\0\x02\x08\x06\0\0\0";
+
+    #[test]
+    fn test_sniffing_and_extraction() {
+        let root = TempDir::new("attachments_sniff");
+        fs::write(root.join("main.rs"), "\u{feff}fn main() {}\r\n").unwrap();
+        fs::write(root.join("photo.dat"), PNG_1X2).unwrap();
+        fs::write(root.join("blob.bin"), [0u8, 1, 2, 3]).unwrap();
+        let limits = AttachmentLimits::default();
+
+        let source = Attachment::load(root.join("main.rs"), &limits).unwrap();
+        assert_eq!(source.mime, "text/x-rust");
+        assert_eq!(source.text(), Some("fn main() {}\n"));
+
+        // Images are recognised by content, not extension
+        let image = Attachment::load(root.join("photo.dat"), &limits).unwrap();
+        assert_eq!(image.mime, "image/png");
+        assert!(image.data_url().unwrap().starts_with("data:image/png;base64,iVBORw0KGgo"));
+        assert_eq!(image.preview(40, 5), vec!["photo.dat · image/png · 29 B", "image, 1×2"]);
+
+        assert!(matches!(
+            Attachment::load(root.join("blob.bin"), &limits),
+            Err(AttachmentError::Unsupported { ref mime, .. }) if mime == "application/octet-stream"
+        ));
+        assert!(matches!(Attachment::load(&root, &limits), Err(AttachmentError::NotAFile(_))));
+    }
+
+    #[test]
+    fn test_limits() {
+        let root = TempDir::new("attachments_limits");
+        fs::write(root.join("a.txt"), "a".repeat(100)).unwrap();
+        fs::write(root.join("b.txt"), "b".repeat(100)).unwrap();
+        let limits = AttachmentLimits { max_text_bytes: 150, max_image_bytes: 50, max_total_bytes: 150 };
+        let mut set = AttachmentSet::new().with_limits(limits);
+
+        set.attach(root.join("a.txt")).unwrap();
+        assert!(matches!(set.attach(root.join("a.txt")), Err(AttachmentError::AlreadyAttached(_))));
+        assert!(matches!(set.attach(root.join("b.txt")), Err(AttachmentError::TotalTooLarge { limit: 150 })));
+
+        fs::write(root.join("big.txt"), "c".repeat(200)).unwrap();
+        assert!(matches!(
+            Attachment::load(root.join("big.txt"), &limits),
+            Err(AttachmentError::TooLarge { size: 200, limit: 150, .. })
+        ));
+        // Images have their own, smaller limit here
+        fs::write(root.join("img.png"), [PNG_1X2, &[0u8; 40]].concat()).unwrap();
+        assert!(matches!(
+            Attachment::load(root.join("img.png"), &limits),
+            Err(AttachmentError::TooLarge { limit: 50, .. })
+        ));
+    }
+
+    #[test]
+    fn test_changed_files_are_refused() {
+        let root = TempDir::new("attachments_changed");
+        let path = root.join("notes.md");
+        fs::write(&path, "one\ntwo\nthree\nfour\n").unwrap();
+        let mut set = AttachmentSet::new();
+        set.attach(&path).unwrap();
+
+        assert_eq!(
+            set.get("notes.md").unwrap().preview(40, 4),
+            vec!["notes.md · text/markdown · 19 B", "one", "two", "… 2 more lines"]
+        );
+        assert!(set.changed().is_empty());
+        assert_eq!(set.verified().unwrap()[0].text(), Some("one\ntwo\nthree\nfour\n"));
+
+        fs::write(&path, "one\ntwo\nthree\nFOUR\n").unwrap();
+        assert_eq!(set.changed(), vec!["notes.md"]);
+        assert!(matches!(set.verified(), Err(AttachmentError::Changed(ref p)) if *p == path));
+    }
+}
@@ -1,4 +1,5 @@
 use super::block::Block;
+use crate::clipboard::{Clipboard, ClipboardError, ClipboardResult};
 
 #[derive(Clone, Debug)]
 pub enum BlockOperation {
@@ -44,6 +45,23 @@ impl BlockOperations {
         result
     }
 
+    /// Put the text for a copy operation on the clipboard
+    pub fn copy_to_clipboard(
+        block: &Block,
+        operation: &BlockOperation,
+        clipboard: &mut Clipboard,
+    ) -> ClipboardResult<()> {
+        let text = match operation {
+            BlockOperation::CopyCommand => Self::copy_command(block),
+            BlockOperation::CopyOutput => Self::copy_output(block),
+            BlockOperation::CopyFormattedOutput => Self::copy_formatted_output(block),
+            other => {
+                return Err(ClipboardError::NothingToCopy(format!("{:?} is not a copy operation", other)))
+            }
+        };
+        clipboard.copy(&text)
+    }
+
     pub fn create_share_link(block: &Block, base_url: &str) -> String {
         let encoded = urlencoding::encode(&block.command);
         format!("{}/blocks?cmd={}&id={}", base_url, encoded, block.id)
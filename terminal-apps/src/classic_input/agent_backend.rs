@@ -0,0 +1,507 @@
+use super::agent_mode::{AgentRequest, AgentResponse};
+use crate::attachments::AttachmentContent;
+use serde_json::{json, Value};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use thiserror::Error;
+
+const SYSTEM_PROMPT: &str = "You are an assistant inside a terminal. When the user asks for \
+something that can be done with shell commands, reply with a JSON plan in a fenced ```json \
+block: {\"steps\": [{\"command\": ..., \"explanation\": ..., \"risk\": \"read_only\" | \
+\"write\" | \"destructive\", \"side_effects\": [...]}]}. Otherwise answer briefly.";
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Error, Debug)]
+pub enum AgentError {
+    #[error("No agent backend configured")]
+    NoBackend,
+    #[error("HTTP error: {0}")]
+    HttpError(String),
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("JSON error: {0}")]
+    JsonError(#[from] serde_json::Error),
+    #[error("Unexpected response: {0}")]
+    InvalidResponse(String),
+}
+
+impl From<ureq::Error> for AgentError {
+    fn from(err: ureq::Error) -> Self {
+        match err {
+            ureq::Error::Status(code, response) => {
+                let body = response.into_string().unwrap_or_default();
+                AgentError::HttpError(format!("status {}: {}", code, body.trim()))
+            }
+            ureq::Error::Transport(transport) => AgentError::HttpError(transport.to_string()),
+        }
+    }
+}
+
+pub type AgentResult<T> = Result<T, AgentError>;
+
+/// A language model that can answer agent requests
+pub trait AgentBackend: Send + Sync + fmt::Debug {
+    fn name(&self) -> &str;
+
+    /// Answer `request`, calling `on_token` with each piece of text as it streams in
+    fn stream(&self, request: &AgentRequest, on_token: &mut dyn FnMut(&str)) -> AgentResult<AgentResponse>;
+
+    fn complete(&self, request: &AgentRequest) -> AgentResult<AgentResponse> {
+        self.stream(request, &mut |_| {})
+    }
+}
+
+/// System and user messages for a request, in the chat format both APIs
+/// share. Text attachments go into the user message; images are left to
+/// each backend.
+fn chat_messages(request: &AgentRequest) -> Value {
+    let mut content = match &request.context {
+        Some(context) => format!("Terminal context:\n{}\nRequest: {}", context, request.query),
+        None => request.query.clone(),
+    };
+    for attachment in &request.attachments {
+        match attachment.text() {
+            Some(text) => content.push_str(&format!(
+                "\n\nAttached file {} ({}):\n{}",
+                attachment.name,
+                attachment.mime,
+                text.trim_end()
+            )),
+            None => content.push_str(&format!("\n\nAttached image {} ({})", attachment.name, attachment.mime)),
+        }
+    }
+    json!([
+        { "role": "system", "content": SYSTEM_PROMPT },
+        { "role": "user", "content": content },
+    ])
+}
+
+/// OpenAI takes images as `image_url` parts alongside the text
+fn openai_messages(request: &AgentRequest) -> Value {
+    let mut messages = chat_messages(request);
+    let images: Vec<Value> = request
+        .attachments
+        .iter()
+        .filter_map(|a| a.data_url())
+        .map(|url| json!({ "type": "image_url", "image_url": { "url": url } }))
+        .collect();
+    if !images.is_empty() {
+        let text = messages[1]["content"].take();
+        let mut parts = vec![json!({ "type": "text", "text": text })];
+        parts.extend(images);
+        messages[1]["content"] = Value::Array(parts);
+    }
+    messages
+}
+
+/// Ollama takes images as bare base64 in the message's `images`
+fn ollama_messages(request: &AgentRequest) -> Value {
+    let mut messages = chat_messages(request);
+    let images: Vec<Value> = request
+        .attachments
+        .iter()
+        .filter_map(|a| match &a.content {
+            AttachmentContent::Image { base64 } => Some(Value::String(base64.clone())),
+            AttachmentContent::Text(_) => None,
+        })
+        .collect();
+    if !images.is_empty() {
+        messages[1]["images"] = Value::Array(images);
+    }
+    messages
+}
+
+/// Any server speaking the OpenAI chat completions API
+#[derive(Clone)]
+pub struct OpenAiBackend {
+    pub base_url: String,
+    pub model: String,
+    api_key: Option<String>,
+    pub timeout: Duration,
+}
+
+impl OpenAiBackend {
+    pub fn new(base_url: impl Into<String>, model: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            model: model.into(),
+            api_key: None,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Configure from `OPENAI_BASE_URL`, `OPENAI_MODEL` and `OPENAI_API_KEY`
+    pub fn from_env() -> Self {
+        let base_url = std::env::var("OPENAI_BASE_URL").unwrap_or_else(|_| "https://api.openai.com/v1".to_string());
+        let model = std::env::var("OPENAI_MODEL").unwrap_or_else(|_| "gpt-4o-mini".to_string());
+        let backend = Self::new(base_url, model);
+        match std::env::var("OPENAI_API_KEY") {
+            Ok(key) => backend.with_api_key(key),
+            Err(_) => backend,
+        }
+    }
+
+    pub fn with_api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+/// Shows whether a key is set, never the key itself
+impl fmt::Debug for OpenAiBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OpenAiBackend")
+            .field("base_url", &self.base_url)
+            .field("model", &self.model)
+            .field("api_key", &self.api_key.as_ref().map(|_| "<redacted>"))
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl AgentBackend for OpenAiBackend {
+    fn name(&self) -> &str {
+        "openai"
+    }
+
+    fn stream(&self, request: &AgentRequest, on_token: &mut dyn FnMut(&str)) -> AgentResult<AgentResponse> {
+        let body = json!({
+            "model": self.model,
+            "messages": openai_messages(request),
+            "stream": true,
+        });
+        let mut http = ureq::post(&format!("{}/chat/completions", self.base_url)).timeout(self.timeout);
+        if let Some(key) = &self.api_key {
+            http = http.set("Authorization", &format!("Bearer {}", key));
+        }
+        let response = http.send_json(body)?;
+        let text = read_sse_stream(BufReader::new(response.into_reader()), on_token)?;
+        Ok(AgentResponse::from_completion(&text))
+    }
+}
+
+/// Server-sent events with `choices[0].delta.content` chunks, ending in `[DONE]`
+fn read_sse_stream(reader: impl BufRead, on_token: &mut dyn FnMut(&str)) -> AgentResult<String> {
+    let mut text = String::new();
+    for line in reader.lines() {
+        let line = line?;
+        let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+            continue;
+        };
+        if data == "[DONE]" {
+            break;
+        }
+        let chunk: Value = serde_json::from_str(data)?;
+        if let Some(error) = chunk.get("error") {
+            return Err(AgentError::InvalidResponse(error.to_string()));
+        }
+        if let Some(token) = chunk["choices"][0]["delta"]["content"].as_str() {
+            on_token(token);
+            text.push_str(token);
+        }
+    }
+    Ok(text)
+}
+
+/// A local Ollama server's `/api/chat` endpoint
+#[derive(Debug, Clone)]
+pub struct OllamaBackend {
+    pub base_url: String,
+    pub model: String,
+    pub timeout: Duration,
+}
+
+impl OllamaBackend {
+    pub fn new(base_url: impl Into<String>, model: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            model: model.into(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    pub fn local(model: impl Into<String>) -> Self {
+        Self::new("http://localhost:11434", model)
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+impl AgentBackend for OllamaBackend {
+    fn name(&self) -> &str {
+        "ollama"
+    }
+
+    fn stream(&self, request: &AgentRequest, on_token: &mut dyn FnMut(&str)) -> AgentResult<AgentResponse> {
+        let body = json!({
+            "model": self.model,
+            "messages": ollama_messages(request),
+            "stream": true,
+        });
+        let response = ureq::post(&format!("{}/api/chat", self.base_url))
+            .timeout(self.timeout)
+            .send_json(body)?;
+        let text = read_ndjson_stream(BufReader::new(response.into_reader()), on_token)?;
+        Ok(AgentResponse::from_completion(&text))
+    }
+}
+
+/// One JSON object per line with `message.content`, the last having `done: true`
+fn read_ndjson_stream(reader: impl BufRead, on_token: &mut dyn FnMut(&str)) -> AgentResult<String> {
+    let mut text = String::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let chunk: Value = serde_json::from_str(&line)?;
+        if let Some(error) = chunk.get("error").and_then(Value::as_str) {
+            return Err(AgentError::InvalidResponse(error.to_string()));
+        }
+        if let Some(token) = chunk["message"]["content"].as_str() {
+            on_token(token);
+            text.push_str(token);
+        }
+        if chunk["done"].as_bool() == Some(true) {
+            break;
+        }
+    }
+    Ok(text)
+}
+
+/// A local HTTP server that streams a fixed reply word by word in both the
+/// OpenAI and Ollama formats, for tests and offline demos
+pub struct MockAgentServer {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<Value>>>,
+    shutdown: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl MockAgentServer {
+    pub fn start(reply: impl Into<String>) -> io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let reply = reply.into();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let handle = {
+            let requests = Arc::clone(&requests);
+            let shutdown = Arc::clone(&shutdown);
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if shutdown.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    if let Ok(stream) = stream {
+                        let _ = serve_mock_request(stream, &reply, &requests);
+                    }
+                }
+            })
+        };
+
+        Ok(Self {
+            addr,
+            requests,
+            shutdown,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// JSON bodies of the requests received so far
+    pub fn requests(&self) -> Vec<Value> {
+        self.requests.lock().map(|r| r.clone()).unwrap_or_default()
+    }
+}
+
+impl Drop for MockAgentServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // Wake the accept loop so it sees the flag
+        let _ = TcpStream::connect(self.addr);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl fmt::Debug for MockAgentServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MockAgentServer").field("addr", &self.addr).finish()
+    }
+}
+
+fn serve_mock_request(stream: TcpStream, reply: &str, requests: &Mutex<Vec<Value>>) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let path = request_line.split_whitespace().nth(1).unwrap_or("/").to_string();
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    if let Ok(json) = serde_json::from_slice(&body) {
+        if let Ok(mut requests) = requests.lock() {
+            requests.push(json);
+        }
+    }
+
+    let tokens: Vec<&str> = reply.split_inclusive(' ').collect();
+    let (content_type, chunks) = if path.ends_with("/chat/completions") {
+        let mut chunks: Vec<String> = tokens
+            .iter()
+            .map(|t| format!("data: {}\n\n", json!({ "choices": [{ "delta": { "content": t } }] })))
+            .collect();
+        chunks.push("data: [DONE]\n\n".to_string());
+        ("text/event-stream", chunks)
+    } else if path == "/api/chat" {
+        let mut chunks: Vec<String> = tokens
+            .iter()
+            .map(|t| format!("{}\n", json!({ "message": { "role": "assistant", "content": t }, "done": false })))
+            .collect();
+        chunks.push(format!("{}\n", json!({ "done": true })));
+        ("application/x-ndjson", chunks)
+    } else {
+        let mut stream = stream;
+        stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 9\r\nConnection: close\r\n\r\nnot found")?;
+        return Ok(());
+    };
+
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nConnection: close\r\n\r\n",
+        content_type
+    )?;
+    for chunk in chunks {
+        stream.write_all(chunk.as_bytes())?;
+        stream.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use crate::blocks::Block;
+    use crate::classic_input::{AgentContext, AgentMode, AgentState};
+
+    const REPLY: &str = "Use this:\n```sh\ndu -sh * | sort -h\n```\nIt sorts by size.";
+
+    #[test]
+    fn test_openai_backend_streams_tokens() {
+        let server = MockAgentServer::start(REPLY).unwrap();
+        let backend = OpenAiBackend::new(format!("{}/v1", server.url()), "test-model").with_api_key("secret");
+
+        let mut tokens = Vec::new();
+        let response = backend
+            .stream(&AgentRequest::new("biggest files here"), &mut |t| tokens.push(t.to_string()))
+            .unwrap();
+
+        assert!(tokens.len() > 1);
+        assert_eq!(tokens.concat(), REPLY);
+        assert_eq!(response.suggestion, "du -sh * | sort -h");
+        assert_eq!(response.plan.steps.len(), 1);
+        assert!(!response.requires_permission);
+        assert_eq!(server.requests()[0]["model"], "test-model");
+    }
+
+    #[test]
+    fn test_agent_mode_sends_context_to_ollama() {
+        let server = MockAgentServer::start("Nothing to run.").unwrap();
+        let mut mode = AgentMode::new().with_backend(Arc::new(OllamaBackend::new(server.url(), "llama3")));
+
+        let mut block = Block::new("cargo build".to_string(), "/src/app".to_string());
+        block.output.stderr = "error[E0425]: cannot find value `x`".to_string();
+        block.output.exit_code = Some(101);
+        let context = AgentContext::new("/src/app", "zsh").with_blocks(&[&block], 5);
+
+        let request = AgentRequest::new("why did that fail").with_context(&context);
+        let response = mode.submit(&request, &mut |_| {}).unwrap();
+
+        assert_eq!(response.suggestion, "Nothing to run.");
+        assert!(!response.requires_permission);
+        assert_eq!(mode.get_state(), AgentState::Active);
+
+        let sent = server.requests()[0]["messages"][1]["content"].as_str().unwrap().to_string();
+        assert!(sent.contains("cwd: /src/app\nshell: zsh"));
+        assert!(sent.contains("$ cargo build  (exit 101)\nerror[E0425]"));
+    }
+
+    #[test]
+    fn test_attachments_in_requests() {
+        let root = TempDir::new("backend_attachments");
+        root.write("main.rs", "fn main() {}\n");
+        root.write("shot.png", b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR");
+        let mut set = crate::attachments::AttachmentSet::new();
+        set.attach(root.join("main.rs")).unwrap();
+        set.attach(root.join("shot.png")).unwrap();
+        let request = AgentRequest::new("review this").with_attachments(set.verified().unwrap());
+
+        let server = MockAgentServer::start(REPLY).unwrap();
+        OpenAiBackend::new(server.url(), "m").complete(&request).unwrap();
+        let parts = &server.requests()[0]["messages"][1]["content"];
+        assert!(parts[0]["text"].as_str().unwrap().contains("Attached file main.rs (text/x-rust):\nfn main() {}"));
+        assert!(parts[1]["image_url"]["url"].as_str().unwrap().starts_with("data:image/png;base64,"));
+
+        let server = MockAgentServer::start(REPLY).unwrap();
+        OllamaBackend::new(server.url(), "m").complete(&request).unwrap();
+        let message = &server.requests()[0]["messages"][1];
+        assert!(message["content"].as_str().unwrap().contains("Attached image shot.png"));
+        assert_eq!(message["images"][0].as_str(), set.get("shot.png").and_then(|a| match &a.content {
+            AttachmentContent::Image { base64 } => Some(base64.as_str()),
+            AttachmentContent::Text(_) => None,
+        }));
+    }
+
+    #[test]
+    fn test_errors() {
+        let server = MockAgentServer::start(REPLY).unwrap();
+        let backend = OllamaBackend::new(format!("{}/missing", server.url()), "m");
+        let err = backend.complete(&AgentRequest::new("hi")).unwrap_err();
+        assert!(matches!(err, AgentError::HttpError(ref msg) if msg.contains("404")));
+
+        let mut mode = AgentMode::new();
+        assert!(matches!(mode.submit(&AgentRequest::new("hi"), &mut |_| {}), Err(AgentError::NoBackend)));
+    }
+
+    #[test]
+    fn test_debug_redacts_api_key() {
+        let backend = OpenAiBackend::new("http://localhost", "m").with_api_key("sk-secret");
+        let mode = AgentMode::new().with_backend(Arc::new(backend));
+        let debug = format!("{:?}", mode);
+        assert!(debug.contains("<redacted>"));
+        assert!(!debug.contains("sk-secret"));
+    }
+}
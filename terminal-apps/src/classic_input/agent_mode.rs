@@ -1,4 +1,16 @@
+use super::agent_backend::{AgentBackend, AgentError, AgentResult};
+use super::agent_plan::{ExecutionPlan, PermissionPolicy, RiskLevel};
+use super::conversation::{Conversation, TrimStrategy};
+use super::input_classifier::{InputClassifier, InputKind};
+use crate::attachments::Attachment;
+use crate::blocks::Block;
+use crate::universal_input::{ContextualChips, MentionPayload};
 use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::sync::Arc;
+
+/// Output lines kept per block when building request context
+const CONTEXT_OUTPUT_LINES: usize = 20;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AgentState {
@@ -11,14 +23,137 @@ pub enum AgentState {
 pub struct AgentRequest {
     pub query: String,
     pub context: Option<String>,
+    /// The user allowed steps the policy considers safe to run without asking
     pub requested_permission: bool,
+    /// Files and images sent along with the query
+    pub attachments: Vec<Attachment>,
+}
+
+impl AgentRequest {
+    pub fn new(query: impl Into<String>) -> Self {
+        Self {
+            query: query.into(),
+            context: None,
+            requested_permission: false,
+            attachments: Vec::new(),
+        }
+    }
+
+    pub fn with_attachments(mut self, attachments: Vec<Attachment>) -> Self {
+        self.attachments = attachments;
+        self
+    }
+
+    pub fn with_context(mut self, context: &AgentContext) -> Self {
+        self.context = Some(context.render());
+        self
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct AgentResponse {
     pub suggestion: String,
     pub confidence: f32,
+    /// Some step of the plan changes state and so can't run unasked
     pub requires_permission: bool,
+    pub plan: ExecutionPlan,
+}
+
+impl AgentResponse {
+    /// Interpret a model reply as a plan of commands. Replies without one are
+    /// plain answers.
+    pub fn from_completion(text: &str) -> Self {
+        let plan = ExecutionPlan::from_completion(text);
+        if plan.is_empty() {
+            return Self {
+                suggestion: text.trim().to_string(),
+                confidence: 0.5,
+                requires_permission: false,
+                plan,
+            };
+        }
+        Self {
+            suggestion: plan
+                .steps
+                .iter()
+                .map(|s| s.command.as_str())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            confidence: 0.9,
+            requires_permission: plan.risk() > RiskLevel::ReadOnly,
+            plan,
+        }
+    }
+}
+
+/// What the agent is told about the terminal: where it is, which shell,
+/// the last few commands with their output, and anything `@`-mentioned
+#[derive(Debug, Clone, Default)]
+pub struct AgentContext {
+    pub cwd: String,
+    pub shell: String,
+    pub recent_blocks: Vec<Block>,
+    pub mentions: Vec<MentionPayload>,
+}
+
+impl AgentContext {
+    pub fn new(cwd: impl Into<String>, shell: impl Into<String>) -> Self {
+        Self {
+            cwd: cwd.into(),
+            shell: shell.into(),
+            recent_blocks: Vec::new(),
+            mentions: Vec::new(),
+        }
+    }
+
+    /// Context for the current process: its cwd and `$SHELL`
+    pub fn from_env() -> Self {
+        let cwd = std::env::current_dir()
+            .map(|p| p.display().to_string())
+            .unwrap_or_default();
+        let shell = std::env::var("SHELL").unwrap_or_else(|_| "sh".to_string());
+        Self::new(cwd, shell)
+    }
+
+    /// Include the last `limit` of `blocks`, oldest first
+    pub fn with_blocks(mut self, blocks: &[&Block], limit: usize) -> Self {
+        let skip = blocks.len().saturating_sub(limit);
+        self.recent_blocks = blocks[skip..].iter().map(|b| (*b).clone()).collect();
+        self
+    }
+
+    pub fn with_mentions(mut self, mentions: Vec<MentionPayload>) -> Self {
+        self.mentions = mentions;
+        self
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = format!("cwd: {}\nshell: {}\n", self.cwd, self.shell);
+        if !self.recent_blocks.is_empty() {
+            out.push_str("recent commands:\n");
+        }
+        for block in &self.recent_blocks {
+            let _ = write!(out, "$ {}", block.command);
+            if let Some(code) = block.output.exit_code {
+                let _ = write!(out, "  (exit {})", code);
+            }
+            out.push('\n');
+            for stream in [&block.output.stdout, &block.output.stderr] {
+                let lines: Vec<&str> = stream.lines().collect();
+                for line in &lines[lines.len().saturating_sub(CONTEXT_OUTPUT_LINES)..] {
+                    out.push_str(line);
+                    out.push('\n');
+                }
+            }
+        }
+        for mention in &self.mentions {
+            let _ = write!(out, "mentioned {}:\n{}", mention.title, mention.content);
+            if !mention.content.ends_with('\n') {
+                out.push('\n');
+            }
+        }
+        out
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -26,23 +161,75 @@ pub struct AgentMode {
     state: AgentState,
     auto_detection_enabled: bool,
     denylist: HashSet<String>,
-    natural_language_keywords: Vec<&'static str>,
+    classifier: InputClassifier,
+    backend: Option<Arc<dyn AgentBackend>>,
+    policy: PermissionPolicy,
+    conversation: Option<Conversation>,
 }
 
 impl AgentMode {
+    /// Agent mode knowing only shell builtins as commands; see `from_env`
     pub fn new() -> Self {
         Self {
             state: AgentState::Inactive,
             auto_detection_enabled: true,
             denylist: HashSet::new(),
-            natural_language_keywords: vec![
-                "show", "list", "find", "search", "count", "check", "what", "when", "where",
-                "how", "why", "help", "create", "make", "generate", "build", "setup", "fix",
-                "debug", "test", "run", "execute", "do", "try", "can you", "please", "would",
-            ],
+            classifier: InputClassifier::default(),
+            backend: None,
+            policy: PermissionPolicy::default(),
+            conversation: None,
         }
     }
 
+    /// Agent mode that tells commands on `$PATH` from natural language
+    pub fn from_env() -> Self {
+        Self::new().with_classifier(InputClassifier::from_env())
+    }
+
+    pub fn with_backend(mut self, backend: Arc<dyn AgentBackend>) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    pub fn set_backend(&mut self, backend: Arc<dyn AgentBackend>) {
+        self.backend = Some(backend);
+    }
+
+    pub fn backend(&self) -> Option<&Arc<dyn AgentBackend>> {
+        self.backend.as_ref()
+    }
+
+    pub fn with_policy(mut self, policy: PermissionPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn policy(&self) -> &PermissionPolicy {
+        &self.policy
+    }
+
+    /// The response's plan with every step approved, blocked or awaiting
+    /// approval according to the policy
+    pub fn gate_plan(&self, request: &AgentRequest, response: &AgentResponse) -> ExecutionPlan {
+        let mut plan = response.plan.clone();
+        plan.apply_policy(&self.policy, request.requested_permission);
+        plan
+    }
+
+    /// Send a request to the backend, passing tokens to `on_token` as they
+    /// arrive. The agent is `Processing` until the reply is complete.
+    pub fn submit(
+        &mut self,
+        request: &AgentRequest,
+        on_token: &mut dyn FnMut(&str),
+    ) -> AgentResult<AgentResponse> {
+        let backend = self.backend.clone().ok_or(AgentError::NoBackend)?;
+        self.set_processing();
+        let result = backend.stream(request, on_token);
+        self.activate();
+        result
+    }
+
     pub fn toggle(&mut self) {
         self.state = match self.state {
             AgentState::Inactive => AgentState::Active,
@@ -95,23 +282,88 @@ impl AgentMode {
             return;
         }
 
-        // Check for natural language keywords
-        let has_keyword = self.natural_language_keywords.iter().any(|&kw| {
-            trimmed.contains(kw)
-        });
-
-        if has_keyword && !self.looks_like_shell_command(input) {
+        if !self.looks_like_shell_command(input) {
             self.activate();
         }
     }
 
-    fn looks_like_shell_command(&self, input: &str) -> bool {
-        let shell_indicators = vec![
-            "|", "&&", "||", ">", "<", "$", "`", "(", ")", "{", "}", ";",
-            "cd", "ls", "grep", "sed", "awk", "cat", "echo", "mv", "cp",
-        ];
+    /// Starts a new conversation and shows it in `chips`
+    pub fn start_conversation(&mut self, title: impl Into<String>, chips: &mut ContextualChips) -> &mut Conversation {
+        self.conversation = Some(Conversation::new(title));
+        self.sync_conversation_chip(chips);
+        self.conversation.as_mut().unwrap()
+    }
+
+    pub fn resume_conversation(&mut self, conversation: Conversation, chips: &mut ContextualChips) {
+        self.conversation = Some(conversation);
+        self.sync_conversation_chip(chips);
+    }
+
+    /// Leave the current conversation, returning it so it can be saved
+    pub fn end_conversation(&mut self, chips: &mut ContextualChips) -> Option<Conversation> {
+        let conversation = self.conversation.take();
+        self.sync_conversation_chip(chips);
+        conversation
+    }
+
+    /// Shows the active conversation's title in `chips`, or removes the chip
+    /// when there is none
+    pub fn sync_conversation_chip(&self, chips: &mut ContextualChips) {
+        match &self.conversation {
+            Some(conversation) => chips.set_conversation_chip(conversation.id.clone(), conversation.title.clone()),
+            None => chips.clear_conversation_chip(),
+        }
+    }
+
+    pub fn conversation(&self) -> Option<&Conversation> {
+        self.conversation.as_ref()
+    }
+
+    pub fn conversation_mut(&mut self) -> Option<&mut Conversation> {
+        self.conversation.as_mut()
+    }
 
-        shell_indicators.iter().any(|ind| input.contains(ind))
+    /// Ask a question in the current conversation (starting one if needed),
+    /// recording the reply and trimming old turns to the token budget. The
+    /// conversation chip follows the title the first question gives it. A
+    /// failed request leaves the conversation as it was.
+    pub fn ask(
+        &mut self,
+        query: &str,
+        terminal: Option<&AgentContext>,
+        attachments: Vec<Attachment>,
+        chips: &mut ContextualChips,
+        on_token: &mut dyn FnMut(&str),
+    ) -> AgentResult<AgentResponse> {
+        let conversation = self.conversation.get_or_insert_with(|| Conversation::new(""));
+        conversation.trim_to_budget(TrimStrategy::Summarize);
+        let title = conversation.title.clone();
+        let request = conversation.request(query, terminal, attachments);
+        let result = self.submit(&request, on_token);
+        if let Some(conversation) = &mut self.conversation {
+            match &result {
+                Ok(response) => conversation.record_response(response),
+                Err(_) => {
+                    conversation.messages.pop();
+                    conversation.title = title;
+                }
+            }
+        }
+        self.sync_conversation_chip(chips);
+        result
+    }
+
+    pub fn with_classifier(mut self, classifier: InputClassifier) -> Self {
+        self.classifier = classifier;
+        self
+    }
+
+    pub fn classifier_mut(&mut self) -> &mut InputClassifier {
+        &mut self.classifier
+    }
+
+    fn looks_like_shell_command(&self, input: &str) -> bool {
+        self.classifier.classify(input).kind == InputKind::Shell
     }
 
     pub fn get_state(&self) -> AgentState {
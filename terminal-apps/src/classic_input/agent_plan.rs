@@ -0,0 +1,788 @@
+use super::agent_mode::{AgentContext, AgentRequest};
+use super::input_classifier::{tokenize, ShellToken};
+use crate::blocks::{Block, BlockManager};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RiskLevel {
+    ReadOnly,
+    Write,
+    Destructive,
+    /// Wipes a system or disk; never run
+    Catastrophic,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StepStatus {
+    Pending,
+    AwaitingApproval,
+    Approved,
+    Rejected,
+    Blocked(String),
+    Succeeded,
+    Failed(i32),
+}
+
+#[derive(Debug, Clone)]
+pub struct PlanStep {
+    pub command: String,
+    pub explanation: String,
+    pub risk: RiskLevel,
+    pub side_effects: Vec<String>,
+    pub status: StepStatus,
+    /// Block the step ran in, once executed
+    pub block_id: Option<String>,
+}
+
+impl PlanStep {
+    /// A step whose risk and side effects come from inspecting the command
+    pub fn new(command: impl Into<String>, explanation: impl Into<String>) -> Self {
+        let command = command.into();
+        let (risk, side_effects) = classify_command(&command);
+        Self {
+            command,
+            explanation: explanation.into(),
+            risk,
+            side_effects,
+            status: StepStatus::Pending,
+            block_id: None,
+        }
+    }
+}
+
+/// What the policy says about a step
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Approval {
+    AutoRun,
+    Confirm,
+    Block(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PermissionPolicy {
+    pub auto_run_read_only: bool,
+    /// Command prefixes that may run without confirmation unless destructive
+    #[serde(default)]
+    pub trusted_prefixes: Vec<String>,
+    /// Extra substrings that block a command outright
+    #[serde(default)]
+    pub blocked_patterns: Vec<String>,
+}
+
+impl PermissionPolicy {
+    pub fn new() -> Self {
+        Self {
+            auto_run_read_only: true,
+            trusted_prefixes: Vec::new(),
+            blocked_patterns: Vec::new(),
+        }
+    }
+
+    pub fn with_trusted_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.trusted_prefixes.push(prefix.into());
+        self
+    }
+
+    pub fn with_blocked_pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.blocked_patterns.push(pattern.into());
+        self
+    }
+
+    pub fn decide(&self, step: &PlanStep) -> Approval {
+        if step.risk == RiskLevel::Catastrophic {
+            return Approval::Block("could destroy the system or a disk".to_string());
+        }
+        if let Some(pattern) = self.blocked_patterns.iter().find(|p| step.command.contains(p.as_str())) {
+            return Approval::Block(format!("matches blocked pattern '{}'", pattern));
+        }
+        let trusted = self
+            .trusted_prefixes
+            .iter()
+            .any(|prefix| step.command.trim_start().starts_with(prefix.as_str()));
+        match step.risk {
+            RiskLevel::ReadOnly if self.auto_run_read_only => Approval::AutoRun,
+            RiskLevel::ReadOnly | RiskLevel::Write if trusted => Approval::AutoRun,
+            _ => Approval::Confirm,
+        }
+    }
+}
+
+impl Default for PermissionPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RawPlan {
+    steps: Vec<RawStep>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawStep {
+    command: String,
+    #[serde(default)]
+    explanation: String,
+    risk: Option<RiskLevel>,
+    #[serde(default)]
+    side_effects: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ExecutionPlan {
+    pub steps: Vec<PlanStep>,
+}
+
+impl ExecutionPlan {
+    pub fn new(steps: Vec<PlanStep>) -> Self {
+        Self { steps }
+    }
+
+    /// Read a plan from a model reply: a JSON `{"steps": [...]}` object, or
+    /// failing that one step per line of the first fenced code block. The
+    /// model's risk level is only ever raised by local classification.
+    pub fn from_completion(text: &str) -> Self {
+        if let Some(raw) = find_json_plan(text) {
+            let steps = raw
+                .steps
+                .into_iter()
+                .filter(|s| !s.command.trim().is_empty())
+                .map(|raw| {
+                    let mut step = PlanStep::new(raw.command.trim(), raw.explanation);
+                    step.risk = step.risk.max(raw.risk.unwrap_or(RiskLevel::ReadOnly));
+                    for effect in raw.side_effects {
+                        if !step.side_effects.contains(&effect) {
+                            step.side_effects.push(effect);
+                        }
+                    }
+                    step
+                })
+                .collect();
+            return Self::new(steps);
+        }
+
+        let Some((code, prose)) = split_code_block(text) else {
+            return Self::default();
+        };
+        let explanation = prose.trim().lines().last().unwrap_or("").trim().to_string();
+        let steps = code
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| PlanStep::new(line.trim_start_matches("$ "), explanation.clone()))
+            .collect();
+        Self::new(steps)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    pub fn risk(&self) -> RiskLevel {
+        self.steps.iter().map(|s| s.risk).max().unwrap_or(RiskLevel::ReadOnly)
+    }
+
+    /// Mark every pending step as approved, awaiting approval or blocked.
+    /// Without `auto_run_allowed` nothing runs before the user approves it.
+    pub fn apply_policy(&mut self, policy: &PermissionPolicy, auto_run_allowed: bool) {
+        for step in self.steps.iter_mut().filter(|s| s.status == StepStatus::Pending) {
+            step.status = match policy.decide(step) {
+                Approval::AutoRun if auto_run_allowed => StepStatus::Approved,
+                Approval::AutoRun | Approval::Confirm => StepStatus::AwaitingApproval,
+                Approval::Block(reason) => StepStatus::Blocked(reason),
+            };
+        }
+    }
+
+    pub fn approve(&mut self, index: usize) -> Result<(), String> {
+        let step = self.steps.get_mut(index).ok_or_else(|| format!("No step {}", index))?;
+        match &step.status {
+            StepStatus::Blocked(reason) => Err(format!("Step is blocked: {}", reason)),
+            StepStatus::Pending | StepStatus::AwaitingApproval | StepStatus::Rejected => {
+                step.status = StepStatus::Approved;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn reject(&mut self, index: usize) {
+        if let Some(step) = self.steps.get_mut(index) {
+            if matches!(step.status, StepStatus::Pending | StepStatus::AwaitingApproval | StepStatus::Approved) {
+                step.status = StepStatus::Rejected;
+            }
+        }
+    }
+
+    /// Run approved steps in order as blocks, stopping at the first step that
+    /// still needs a decision or fails. Returns how many steps ran.
+    pub fn run_approved(&mut self, runner: &PlanRunner, blocks: &mut BlockManager) -> usize {
+        let mut ran = 0;
+        for step in &mut self.steps {
+            match step.status {
+                StepStatus::Succeeded | StepStatus::Rejected => continue,
+                StepStatus::Approved => {}
+                _ => break,
+            }
+            let block = runner.run(step);
+            blocks.add_block(block);
+            ran += 1;
+            if step.status != StepStatus::Succeeded {
+                break;
+            }
+        }
+        ran
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.steps.iter().all(|s| {
+            matches!(
+                s.status,
+                StepStatus::Succeeded | StepStatus::Rejected | StepStatus::Blocked(_)
+            )
+        }) || self.steps.iter().any(|s| matches!(s.status, StepStatus::Failed(_)))
+    }
+
+    /// A follow-up request telling the agent how each step went, with the
+    /// blocks that ran included in the context
+    pub fn feedback_request(&self, blocks: &BlockManager, context: AgentContext) -> AgentRequest {
+        let mut query = String::from("Results of the previous plan:\n");
+        let mut ran = Vec::new();
+        for (i, step) in self.steps.iter().enumerate() {
+            let outcome = match &step.status {
+                StepStatus::Succeeded => "succeeded".to_string(),
+                StepStatus::Failed(code) => format!("failed with exit code {}", code),
+                StepStatus::Rejected => "rejected by the user".to_string(),
+                StepStatus::Blocked(reason) => format!("blocked: {}", reason),
+                _ => "not run".to_string(),
+            };
+            query.push_str(&format!("{}. `{}` {}\n", i + 1, step.command, outcome));
+            if let Some(block) = step.block_id.as_deref().and_then(|id| blocks.get_block(id)) {
+                ran.push(block);
+            }
+        }
+        query.push_str("Continue with the task, or say it is done.");
+
+        let context = context.with_blocks(&ran, ran.len());
+        AgentRequest::new(query).with_context(&context)
+    }
+}
+
+/// Runs plan steps through a shell
+#[derive(Debug, Clone)]
+pub struct PlanRunner {
+    pub shell: String,
+    pub cwd: PathBuf,
+}
+
+impl PlanRunner {
+    pub fn new(shell: impl Into<String>, cwd: impl Into<PathBuf>) -> Self {
+        Self {
+            shell: shell.into(),
+            cwd: cwd.into(),
+        }
+    }
+
+    /// Run a step and return the block holding its output
+    pub fn run(&self, step: &mut PlanStep) -> Block {
+        let mut block = Block::new(step.command.clone(), self.cwd.display().to_string());
+        let started = Instant::now();
+        let output = Command::new(&self.shell)
+            .arg("-c")
+            .arg(&step.command)
+            .current_dir(&self.cwd)
+            .output();
+        block.metadata.duration_ms = started.elapsed().as_millis() as u64;
+
+        let exit_code = match output {
+            Ok(output) => {
+                let code = output.status.code().unwrap_or(-1);
+                block.set_output(
+                    String::from_utf8_lossy(&output.stdout).into_owned(),
+                    String::from_utf8_lossy(&output.stderr).into_owned(),
+                    code,
+                );
+                code
+            }
+            Err(err) => {
+                block.set_output(String::new(), err.to_string(), 127);
+                127
+            }
+        };
+        step.status = if exit_code == 0 {
+            StepStatus::Succeeded
+        } else {
+            StepStatus::Failed(exit_code)
+        };
+        step.block_id = Some(block.id.clone());
+        block
+    }
+}
+
+fn find_json_plan(text: &str) -> Option<RawPlan> {
+    let start = text.find('{')?;
+    let end = text.rfind('}')?;
+    if end < start {
+        return None;
+    }
+    let value: Value = serde_json::from_str(&text[start..=end]).ok()?;
+    serde_json::from_value(value).ok()
+}
+
+/// The first fenced code block and the text outside it
+fn split_code_block(text: &str) -> Option<(String, String)> {
+    let start = text.find("```")?;
+    let after_fence = &text[start + 3..];
+    let body_start = after_fence.find('\n')? + 1;
+    let body = &after_fence[body_start..];
+    let end = body.find("```")?;
+    let prose = format!("{}{}", &text[..start], &body[end + 3..]);
+    Some((body[..end].to_string(), prose))
+}
+
+const READ_ONLY_PROGRAMS: &[&str] = &[
+    "ls", "cat", "pwd", "echo", "grep", "rg", "head", "tail", "less", "wc", "du", "df", "ps", "top",
+    "which", "whoami", "env", "printenv", "uname", "date", "tree", "stat", "file", "sort", "uniq",
+    "cut", "jq", "diff", "id", "free", "uptime", "lsof", "history", "type",
+];
+const READ_ONLY_SUBCOMMANDS: &[(&str, &[&str])] = &[
+    ("git", &["status", "log", "diff", "show", "blame", "rev-parse", "ls-files", "remote", "branch"]),
+    ("cargo", &["tree", "metadata", "--version"]),
+    ("docker", &["ps", "images", "logs", "inspect"]),
+    ("kubectl", &["get", "describe", "logs"]),
+    ("npm", &["ls", "list", "view", "outdated"]),
+];
+const DESTRUCTIVE_PROGRAMS: &[&str] = &["rm", "rmdir", "shred", "dd", "truncate", "kill", "pkill", "killall", "fdisk"];
+const SYSTEM_PATHS: &[&str] = &[
+    "/", "/*", "~", "~/", "$HOME", "/home", "/etc", "/usr", "/bin", "/boot", "/var", "/lib",
+];
+/// Paths that mean "everything here" once a segment has `cd`-ed somewhere
+const RELATIVE_ALL: &[&str] = &["*", ".", "./", "./*", ".*"];
+/// Commands that run their arguments as another command, with the flags of
+/// each that take a value
+const WRAPPERS: &[(&str, &[&str])] = &[
+    ("sudo", &["-u", "-g", "-h", "-p", "-C", "-D", "-r", "-t", "-U", "-T"]),
+    ("doas", &["-u", "-C"]),
+    ("env", &["-u", "-C", "-S"]),
+    ("nice", &["-n"]),
+    ("nohup", &[]),
+    ("time", &["-f", "-o"]),
+    ("timeout", &["-s", "-k"]),
+    ("command", &[]),
+    ("exec", &["-a"]),
+    ("xargs", &["-I", "-n", "-P", "-L", "-d", "-E", "-s", "-a"]),
+];
+const SHELLS: &[&str] = &["sh", "bash", "zsh", "dash", "ksh", "fish"];
+/// Operators that end one command and start the next
+const SEPARATORS: &[&str] = &[";", "&&", "||", "|", "&", "(", ")"];
+
+/// Risk of a shell command and the side effects it is expected to have
+pub fn classify_command(command: &str) -> (RiskLevel, Vec<String>) {
+    let compact: String = command.split_whitespace().collect();
+    if compact.contains(":(){") {
+        return (RiskLevel::Catastrophic, vec!["fork bomb".to_string()]);
+    }
+
+    let mut risk = RiskLevel::ReadOnly;
+    let mut effects = Vec::new();
+    let mut merge = |(r, e): (RiskLevel, Vec<String>)| {
+        risk = risk.max(r);
+        effects.extend(e);
+    };
+    // `$(..)` and backticks run before the command they sit in
+    let (outer, substitutions) = extract_substitutions(command);
+    for inner in &substitutions {
+        merge(classify_command(inner));
+    }
+    let mut in_system_dir = false;
+    for segment in command_segments(&outer) {
+        merge(classify_segment(&segment, &mut in_system_dir));
+    }
+    (risk, effects)
+}
+
+/// The command with every `$(..)` and backtick substitution replaced by a
+/// placeholder word, the substitutions' contents, and unquoted newlines as `;`
+fn extract_substitutions(command: &str) -> (String, Vec<String>) {
+    let chars: Vec<char> = command.chars().collect();
+    let mut outer = String::new();
+    let mut inner = Vec::new();
+    let mut single_quoted = false;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if single_quoted {
+            single_quoted = c != '\'';
+            outer.push(c);
+        } else if c == '\\' && i + 1 < chars.len() {
+            outer.push(c);
+            outer.push(chars[i + 1]);
+            i += 1;
+        } else if c == '\'' {
+            single_quoted = true;
+            outer.push(c);
+        } else if c == '$' && chars.get(i + 1) == Some(&'(') {
+            let mut depth = 0;
+            let mut end = i + 1;
+            while end < chars.len() {
+                match chars[end] {
+                    '(' => depth += 1,
+                    ')' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                    _ => {}
+                }
+                end += 1;
+            }
+            inner.push(chars[i + 2..end.min(chars.len())].iter().collect());
+            outer.push_str("_subst_");
+            i = end;
+        } else if c == '`' {
+            let end = (i + 1..chars.len()).find(|&j| chars[j] == '`' && chars[j - 1] != '\\').unwrap_or(chars.len());
+            inner.push(chars[i + 1..end].iter().collect());
+            outer.push_str("_subst_");
+            i = end;
+        } else if c == '\n' {
+            outer.push(';');
+        } else {
+            outer.push(c);
+        }
+        i += 1;
+    }
+    (outer, inner)
+}
+
+/// Split into simple commands on `;`, `&&`, `||`, `|`, `&` and subshell
+/// parentheses, with shell quoting applied to the words
+fn command_segments(command: &str) -> Vec<Vec<ShellToken>> {
+    let mut segments = vec![Vec::new()];
+    for token in tokenize(command).tokens {
+        match &token {
+            ShellToken::Operator(op) if SEPARATORS.contains(&op.as_str()) => segments.push(Vec::new()),
+            _ => segments.last_mut().unwrap().push(token),
+        }
+    }
+    segments.retain(|s| !s.is_empty());
+    segments
+}
+
+/// Words of a simple command with redirections taken out, and the files it
+/// redirects output to
+fn split_redirects(segment: &[ShellToken]) -> (Vec<String>, Vec<String>) {
+    let mut words = Vec::new();
+    let mut targets = Vec::new();
+    let mut iter = segment.iter().peekable();
+    while let Some(token) = iter.next() {
+        match token {
+            ShellToken::Word(word) => {
+                // The fd in `2>file` belongs to the redirection
+                let fd = word.chars().all(|c| c.is_ascii_digit())
+                    && matches!(iter.peek(), Some(ShellToken::Operator(op)) if op.starts_with(['<', '>']));
+                if !fd {
+                    words.push(word.clone());
+                }
+            }
+            ShellToken::Operator(op) => {
+                if let Some(ShellToken::Word(target)) = iter.peek() {
+                    if op == ">" || op == ">>" || op == "&>" {
+                        targets.push(target.clone());
+                    }
+                    iter.next();
+                }
+            }
+        }
+    }
+    targets.retain(|t| t != "/dev/null");
+    (words, targets)
+}
+
+/// Drops wrappers like `sudo -u root` or `nice -n 5` from the front of
+/// `words`. Returns whether the command runs as root.
+fn strip_wrappers(words: &mut Vec<String>) -> bool {
+    let mut elevated = false;
+    loop {
+        let Some(first) = words.first() else {
+            return elevated;
+        };
+        let first = first.trim_start_matches('\\').to_string();
+        if first.contains('=') && !first.starts_with('-') {
+            words.remove(0);
+            continue;
+        }
+        let Some((wrapper, value_flags)) = WRAPPERS.iter().find(|(name, _)| *name == first) else {
+            return elevated;
+        };
+        elevated |= matches!(*wrapper, "sudo" | "doas");
+        words.remove(0);
+        while let Some(flag) = words.first() {
+            if *wrapper == "timeout" && flag.chars().next().is_some_and(|c| c.is_ascii_digit()) {
+                words.remove(0);
+                break;
+            }
+            if *wrapper == "env" && flag.contains('=') && !flag.starts_with('-') {
+                words.remove(0);
+                continue;
+            }
+            if !flag.starts_with('-') || flag == "-" {
+                break;
+            }
+            let takes_value = value_flags.contains(&flag.as_str());
+            let ends = flag == "--";
+            words.remove(0);
+            if takes_value && !words.is_empty() {
+                words.remove(0);
+            }
+            if ends {
+                break;
+            }
+        }
+    }
+}
+
+fn classify_segment(segment: &[ShellToken], in_system_dir: &mut bool) -> (RiskLevel, Vec<String>) {
+    let (mut words, redirect_targets) = split_redirects(segment);
+    let elevated = strip_wrappers(&mut words);
+    let mut effects = Vec::new();
+    for target in &redirect_targets {
+        if target.starts_with("/dev/sd") || target.starts_with("/dev/nvme") {
+            return (RiskLevel::Catastrophic, vec![format!("overwrites disk {}", target)]);
+        }
+        effects.push(format!("writes {}", target));
+    }
+    let Some(program) = words.first().map(|w| w.trim_start_matches('\\').to_string()) else {
+        let risk = if redirect_targets.is_empty() { RiskLevel::ReadOnly } else { RiskLevel::Write };
+        return (risk, effects);
+    };
+    let program = program.as_str();
+    let args: Vec<&str> = words[1..].iter().map(String::as_str).collect();
+    let args = args.as_slice();
+
+    if program == "cd" {
+        *in_system_dir = args.first().is_none_or(|dir| SYSTEM_PATHS.contains(dir));
+        return (RiskLevel::ReadOnly, effects);
+    }
+    // `bash -c '..'` and `eval` run their argument as a command
+    let script = if SHELLS.contains(&program) {
+        args.iter().position(|a| *a == "-c").and_then(|i| args.get(i + 1)).map(|s| s.to_string())
+    } else if program == "eval" {
+        Some(args.join(" "))
+    } else {
+        None
+    };
+    if let Some(script) = script {
+        let (risk, mut inner_effects) = classify_command(&script);
+        if elevated {
+            inner_effects.push("runs as root".to_string());
+        }
+        effects.extend(inner_effects);
+        return (risk.max(RiskLevel::Write), effects);
+    }
+
+    let recursive = args.iter().any(|a| {
+        *a == "--recursive" || (a.starts_with('-') && !a.starts_with("--") && (a.contains('r') || a.contains('R')))
+    });
+    let targets_system = args.iter().any(|a| SYSTEM_PATHS.contains(a) || (*in_system_dir && RELATIVE_ALL.contains(a)))
+        || args.contains(&"--no-preserve-root");
+
+    if program.starts_with("mkfs") || program == "wipefs" {
+        return (RiskLevel::Catastrophic, vec!["formats a filesystem".to_string()]);
+    }
+    if program == "dd" && args.iter().any(|a| a.starts_with("of=/dev/")) {
+        return (RiskLevel::Catastrophic, vec!["overwrites a device".to_string()]);
+    }
+    if matches!(program, "rm" | "chmod" | "chown") && recursive && targets_system {
+        return (RiskLevel::Catastrophic, vec![format!("recursively modifies {}", args.join(" "))]);
+    }
+
+    let mut risk = if DESTRUCTIVE_PROGRAMS.contains(&program) {
+        let targets: Vec<&str> = args.iter().copied().filter(|a| !a.starts_with('-')).collect();
+        effects.push(format!("{} {}", destructive_verb(program), targets.join(" ")).trim().to_string());
+        RiskLevel::Destructive
+    } else if program == "find" && args.iter().any(|a| *a == "-delete" || *a == "-exec") {
+        effects.push("deletes or modifies matched files".to_string());
+        RiskLevel::Destructive
+    } else if is_destructive_subcommand(program, args) {
+        effects.push(format!("{} {}", program, args.join(" ")));
+        RiskLevel::Destructive
+    } else if READ_ONLY_PROGRAMS.contains(&program) || is_read_only_subcommand(program, args) {
+        RiskLevel::ReadOnly
+    } else {
+        RiskLevel::Write
+    };
+
+    // `sort -o out`, `git diff --output=out` and the like write files
+    for (i, arg) in args.iter().enumerate() {
+        let target = match arg.strip_prefix("--output=") {
+            Some(target) => Some(target),
+            None if *arg == "-o" || *arg == "--output" => Some(args.get(i + 1).copied().unwrap_or("")),
+            None => None,
+        };
+        if let Some(target) = target {
+            risk = risk.max(RiskLevel::Write);
+            effects.push(format!("writes {}", target).trim().to_string());
+        }
+    }
+    if !redirect_targets.is_empty() || elevated {
+        risk = risk.max(RiskLevel::Write);
+    }
+    if elevated {
+        effects.push("runs as root".to_string());
+    }
+    (risk, effects)
+}
+
+fn destructive_verb(program: &str) -> &'static str {
+    match program {
+        "kill" | "pkill" | "killall" => "kills",
+        "truncate" => "truncates",
+        _ => "deletes",
+    }
+}
+
+fn is_read_only_subcommand(program: &str, args: &[&str]) -> bool {
+    let Some(&sub) = args.first() else {
+        return false;
+    };
+    let listed = READ_ONLY_SUBCOMMANDS
+        .iter()
+        .any(|(p, subs)| *p == program && subs.contains(&sub));
+    // `git branch <name>` and `git remote add ...` change state
+    let mutates = matches!((program, sub), ("git", "branch" | "remote"))
+        && args[1..].iter().any(|a| !a.starts_with('-'));
+    listed && !mutates
+}
+
+fn is_destructive_subcommand(program: &str, args: &[&str]) -> bool {
+    let has = |flag: &str| args.contains(&flag);
+    match (program, args.first().copied()) {
+        ("git", Some("reset")) => has("--hard"),
+        ("git", Some("clean")) => args.iter().any(|a| a.starts_with('-') && a.contains('f')),
+        ("git", Some("push")) => has("--force") || has("-f"),
+        ("git", Some("branch")) => has("-D"),
+        ("docker", Some("rm" | "rmi")) => true,
+        ("docker", Some("system")) => has("prune"),
+        ("kubectl", Some("delete")) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_commands() {
+        assert_eq!(classify_command("ls -la | grep foo 2>/dev/null").0, RiskLevel::ReadOnly);
+        assert_eq!(classify_command("git log --oneline").0, RiskLevel::ReadOnly);
+        assert_eq!(classify_command("git branch -D old").0, RiskLevel::Destructive);
+        assert_eq!(classify_command("echo hi > notes.txt").0, RiskLevel::Write);
+        assert_eq!(classify_command("cargo build").0, RiskLevel::Write);
+        assert_eq!(classify_command("rm -rf target").0, RiskLevel::Destructive);
+        assert_eq!(classify_command("git push --force").0, RiskLevel::Destructive);
+
+        for command in ["rm -rf /", "sudo rm -rf --no-preserve-root /", "rm -fr ~", "mkfs.ext4 /dev/sda1",
+            "dd if=/dev/zero of=/dev/sda", ":(){ :|:& };:", "chmod -R 777 /"]
+        {
+            assert_eq!(classify_command(command).0, RiskLevel::Catastrophic, "{}", command);
+        }
+    }
+
+    #[test]
+    fn test_classifier_bypasses() {
+        // Hidden behind `&`, substitutions, quoting, wrappers and `sh -c`
+        for command in [
+            "ls & rm -rf /",
+            "echo $(rm -rf ~)",
+            "echo `rm -rf ~`",
+            "echo \"$(rm -rf ~)\"",
+            "sudo -u root rm -rf /",
+            "doas -u root rm -rf /",
+            "rm -rf \"/\"",
+            "\\rm -rf /",
+            "bash -c 'rm -rf /'",
+            "sh -c \"sudo rm -rf /\"",
+            "nice rm -rf /",
+            "nice -n 5 rm -rf /",
+            "command rm -rf /",
+            "exec rm -rf /",
+            "find . | xargs -n 1 rm -rf /",
+            "cd / && rm -rf *",
+            "(rm -rf /)",
+            "ls\nrm -rf /",
+        ] {
+            assert_eq!(classify_command(command).0, RiskLevel::Catastrophic, "{}", command);
+        }
+        // No longer read-only
+        for command in [
+            "kubectl config delete-context prod",
+            "hostname evil",
+            "git diff --output=x",
+            "sort -o out in",
+            "sort --output out in",
+        ] {
+            assert!(classify_command(command).0 >= RiskLevel::Write, "{}", command);
+        }
+        assert_eq!(classify_command("sort -o out in").1, vec!["writes out"]);
+        assert_eq!(classify_command("ls 2>/dev/null && echo \"a;b\"").0, RiskLevel::ReadOnly);
+        assert_eq!(classify_command("cd src && rm -rf *").0, RiskLevel::Destructive);
+    }
+
+    #[test]
+    fn test_plan_from_json_and_policy() {
+        let reply = r#"Here is the plan:
+```json
+{"steps": [
+  {"command": "ls target", "explanation": "see what is there", "risk": "read_only"},
+  {"command": "rm -rf target/debug", "explanation": "free space", "risk": "read_only"},
+  {"command": "rm -rf /", "explanation": "oops"}
+]}
+```"#;
+        let mut plan = ExecutionPlan::from_completion(reply);
+        assert_eq!(plan.steps.len(), 3);
+        // The model can't talk a deletion down to read-only
+        assert_eq!(plan.steps[1].risk, RiskLevel::Destructive);
+        assert_eq!(plan.steps[1].side_effects, vec!["deletes target/debug"]);
+
+        plan.apply_policy(&PermissionPolicy::default(), true);
+        assert_eq!(plan.steps[0].status, StepStatus::Approved);
+        assert_eq!(plan.steps[1].status, StepStatus::AwaitingApproval);
+        assert!(matches!(plan.steps[2].status, StepStatus::Blocked(_)));
+        assert!(plan.approve(2).is_err());
+
+        let mut plan = ExecutionPlan::from_completion(reply);
+        plan.apply_policy(&PermissionPolicy::default(), false);
+        assert_eq!(plan.steps[0].status, StepStatus::AwaitingApproval);
+    }
+
+    #[test]
+    fn test_run_approved_steps_feeds_back_output() {
+        let mut plan = ExecutionPlan::from_completion("```sh\necho hello\nfalse\necho unreachable\n```\nTry it.");
+        assert_eq!(plan.steps.len(), 3);
+        assert_eq!(plan.steps[0].explanation, "Try it.");
+        plan.apply_policy(&PermissionPolicy::default().with_trusted_prefix("false"), true);
+        plan.approve(2).unwrap();
+
+        let runner = PlanRunner::new("sh", std::env::temp_dir());
+        let mut blocks = BlockManager::new(10);
+        assert_eq!(plan.run_approved(&runner, &mut blocks), 2);
+        assert_eq!(plan.steps[0].status, StepStatus::Succeeded);
+        assert_eq!(plan.steps[1].status, StepStatus::Failed(1));
+        assert_eq!(plan.steps[2].status, StepStatus::Approved);
+        assert!(plan.is_finished());
+
+        let request = plan.feedback_request(&blocks, AgentContext::new("/tmp", "sh"));
+        assert!(request.query.contains("1. `echo hello` succeeded"));
+        assert!(request.query.contains("2. `false` failed with exit code 1"));
+        assert!(request.context.unwrap().contains("$ echo hello  (exit 0)\nhello\n"));
+        // Follow-up plans still ask before running
+        assert!(!request.requested_permission);
+    }
+}
@@ -0,0 +1,264 @@
+use super::command_history::CommandHistory;
+use crate::latest_worker::LatestWorker;
+use crate::universal_input::spec_completion::SpecCompleter;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Where an autosuggestion came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SuggestionSource {
+    /// A command previously run in the current directory
+    DirectoryHistory,
+    History,
+    Completion,
+}
+
+/// The full line the user is predicted to type
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Autosuggestion {
+    pub line: String,
+    pub source: SuggestionSource,
+}
+
+impl Autosuggestion {
+    /// The part after what has been typed, drawn dimmed after the cursor
+    pub fn ghost_text(&self, input: &str) -> Option<&str> {
+        self.line
+            .strip_prefix(input)
+            .filter(|rest| !rest.is_empty())
+    }
+
+    /// The first word of the ghost text, with the whitespace before it
+    pub fn next_word(&self, input: &str) -> Option<&str> {
+        let ghost = self.ghost_text(input)?;
+        let start = ghost.len() - ghost.trim_start().len();
+        let end = ghost[start..]
+            .find(char::is_whitespace)
+            .map(|i| start + i)
+            .unwrap_or(ghost.len());
+        Some(&ghost[..end])
+    }
+}
+
+#[derive(Debug)]
+struct CompletionRequest {
+    generation: u64,
+    input: String,
+    cwd: PathBuf,
+}
+
+#[derive(Debug)]
+struct CompletionResult {
+    generation: u64,
+    suggestion: Option<Autosuggestion>,
+}
+
+/// Fish-style inline suggestions. History is searched on every keystroke;
+/// completion specs, whose generators may run commands, are consulted on a
+/// background worker and picked up by `poll`.
+#[derive(Debug)]
+pub struct Autosuggester {
+    pub enabled: bool,
+    specs: Arc<SpecCompleter>,
+    current: Option<Autosuggestion>,
+    generation: u64,
+    /// Started on the first lookup that needs it; ends when this is dropped
+    worker: Option<LatestWorker<CompletionRequest, CompletionResult>>,
+}
+
+impl Autosuggester {
+    pub fn new() -> Self {
+        Self::with_specs(SpecCompleter::new())
+    }
+
+    pub fn with_specs(specs: SpecCompleter) -> Self {
+        Self {
+            enabled: true,
+            specs: Arc::new(specs),
+            current: None,
+            generation: 0,
+            worker: None,
+        }
+    }
+
+    pub fn current(&self) -> Option<&Autosuggestion> {
+        self.current.as_ref()
+    }
+
+    pub fn ghost_text<'a>(&'a self, input: &str) -> Option<&'a str> {
+        self.current.as_ref()?.ghost_text(input)
+    }
+
+    pub fn clear(&mut self) {
+        self.generation += 1;
+        self.current = None;
+    }
+
+    /// Recompute the suggestion for `input` after it changed
+    pub fn update(&mut self, input: &str, cwd: &str, history: &CommandHistory) {
+        self.generation += 1;
+        if !self.enabled || input.trim().is_empty() || input.contains('\n') {
+            self.current = None;
+            return;
+        }
+        // Typing along the ghost text keeps it
+        if self.ghost_text(input).is_some() {
+            return;
+        }
+
+        self.current = from_history(input, cwd, history);
+        if self.current.is_some() {
+            return;
+        }
+
+        let specs = &self.specs;
+        let worker = self.worker.get_or_insert_with(|| {
+            let specs = Arc::clone(specs);
+            LatestWorker::spawn(move |request: CompletionRequest| CompletionResult {
+                generation: request.generation,
+                suggestion: from_specs(&specs, &request.input, &request.cwd),
+            })
+        });
+        let request = CompletionRequest {
+            generation: self.generation,
+            input: input.to_string(),
+            cwd: PathBuf::from(cwd),
+        };
+        if !worker.send(request) {
+            self.worker = None;
+        }
+    }
+
+    /// Pick up a finished background completion for the latest input, without
+    /// blocking. Returns true when the ghost text changed.
+    pub fn poll(&mut self) -> bool {
+        let Some(worker) = &self.worker else {
+            return false;
+        };
+        let mut changed = false;
+        while let Some(result) = worker.try_recv() {
+            if result.generation == self.generation && result.suggestion.is_some() {
+                self.current = result.suggestion;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+/// Clones start their own worker when they need one, so results never go to
+/// the wrong copy
+impl Clone for Autosuggester {
+    fn clone(&self) -> Self {
+        Self {
+            enabled: self.enabled,
+            specs: Arc::clone(&self.specs),
+            current: self.current.clone(),
+            generation: self.generation,
+            worker: None,
+        }
+    }
+}
+
+impl Default for Autosuggester {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// First spec completion that extends `input`
+fn from_specs(specs: &SpecCompleter, input: &str, cwd: &Path) -> Option<Autosuggestion> {
+    specs
+        .complete_in(input, input.len(), cwd)
+        .into_iter()
+        .map(|c| c.apply(input).0)
+        .find(|line| line.len() > input.len() && line.starts_with(input))
+        .map(|line| Autosuggestion { line, source: SuggestionSource::Completion })
+}
+
+/// Newest command extending `input`, preferring ones run in `cwd` and skipping ones that failed
+fn from_history(input: &str, cwd: &str, history: &CommandHistory) -> Option<Autosuggestion> {
+    let candidates = || {
+        history
+            .entries()
+            .rev()
+            .filter(|e| e.command.len() > input.len() && e.command.starts_with(input))
+            .filter(|e| e.exit_status.is_none_or(|code| code == 0))
+    };
+    if let Some(entry) = candidates().find(|e| e.cwd.as_deref() == Some(cwd)) {
+        return Some(Autosuggestion { line: entry.command.clone(), source: SuggestionSource::DirectoryHistory });
+    }
+    candidates()
+        .next()
+        .map(|e| Autosuggestion { line: e.command.clone(), source: SuggestionSource::History })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::completion_spec::CompletionSpec;
+    use std::time::{Duration, Instant};
+
+    fn history() -> CommandHistory {
+        let mut history = CommandHistory::new(100);
+        for (command, cwd, status) in [
+            ("cargo build --release", "/proj", 0),
+            ("cargo bench", "/other", 0),
+            ("cargo test --all", "/proj", 0),
+            ("cargo bnch", "/proj", 101),
+        ] {
+            history.start_command(command.to_string(), Some(cwd.to_string()));
+            let _ = history.finish_command(Some(status), None);
+        }
+        history
+    }
+
+    #[test]
+    fn test_prefers_directory_history_and_skips_failures() {
+        let mut suggester = Autosuggester::new();
+        suggester.update("cargo b", "/proj", &history());
+        let current = suggester.current().unwrap();
+        assert_eq!(current.line, "cargo build --release");
+        assert_eq!(current.source, SuggestionSource::DirectoryHistory);
+
+        let mut suggester = Autosuggester::new();
+        suggester.update("cargo b", "/elsewhere", &history());
+        assert_eq!(suggester.current().unwrap().line, "cargo bench");
+        assert_eq!(suggester.ghost_text("cargo b"), Some("ench"));
+    }
+
+    #[test]
+    fn test_next_word() {
+        let suggestion = Autosuggestion {
+            line: "git commit -m wip".to_string(),
+            source: SuggestionSource::History,
+        };
+        assert_eq!(suggestion.next_word("git"), Some(" commit"));
+        assert_eq!(suggestion.next_word("git com"), Some("mit"));
+        assert_eq!(suggestion.next_word("git commit -m wip"), None);
+    }
+
+    #[test]
+    fn test_completion_arrives_in_background() {
+        let spec: CompletionSpec = serde_yaml::from_str("name: tool\nsubcommands:\n  - name: deploy\n").unwrap();
+        let mut specs = SpecCompleter::new();
+        specs.add_spec(spec);
+        let mut suggester = Autosuggester::with_specs(specs);
+
+        suggester.update("tool de", "/", &CommandHistory::new(10));
+        assert!(suggester.current().is_none());
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !suggester.poll() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(suggester.ghost_text("tool de"), Some("ploy"));
+
+        // A result that arrives after the input moved on is dropped
+        suggester.update("tool d", "/", &CommandHistory::new(10));
+        suggester.clear();
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!suggester.poll());
+        assert!(suggester.current().is_none());
+    }
+}
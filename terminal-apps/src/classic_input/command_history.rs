@@ -1,84 +1,603 @@
-use std::collections::VecDeque;
+use super::history_file::{import_shell_history, HistoryFile, HistoryResult, ShellHistoryFormat};
+use crate::app::TerminalApp;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A single command recorded in the history, with the context it ran in
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct HistoryEntry {
+    pub command: String,
+    pub timestamp: u64,
+    #[serde(default)]
+    pub cwd: Option<String>,
+    #[serde(default)]
+    pub exit_status: Option<i32>,
+    #[serde(default)]
+    pub duration_ms: Option<u64>,
+    #[serde(default)]
+    pub session_id: String,
+}
+
+impl HistoryEntry {
+    pub fn new(command: String, session_id: impl Into<String>) -> Self {
+        Self {
+            command,
+            timestamp: unix_now(),
+            cwd: None,
+            exit_status: None,
+            duration_ms: None,
+            session_id: session_id.into(),
+        }
+    }
+
+    pub fn with_cwd(mut self, cwd: impl Into<String>) -> Self {
+        self.cwd = Some(cwd.into());
+        self
+    }
+}
+
+/// Filtering and sharing behaviour, modelled on the bash/zsh history options
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistoryOptions {
+    /// Skip a command identical to the one just before it (HISTCONTROL=ignoredups)
+    pub ignore_dups: bool,
+    /// Remove older copies of a command when it is run again (HISTCONTROL=erasedups)
+    pub erase_dups: bool,
+    /// Skip commands starting with a space (HISTCONTROL=ignorespace)
+    pub ignore_space: bool,
+    /// Pick up entries appended by other sessions before navigating (zsh SHARE_HISTORY)
+    pub share_history: bool,
+    /// Offer commands previously run in the current directory first when navigating
+    pub prefer_current_directory: bool,
+}
+
+impl Default for HistoryOptions {
+    fn default() -> Self {
+        Self {
+            ignore_dups: true,
+            erase_dups: false,
+            ignore_space: true,
+            share_history: false,
+            prefer_current_directory: false,
+        }
+    }
+}
+
+impl HistoryOptions {
+    /// Options from the app's `history.*` settings, e.g.
+    /// `history.prefer_current_directory = true`; unset or unparsable
+    /// settings keep their defaults
+    pub fn from_config(app: &TerminalApp) -> Self {
+        let defaults = Self::default();
+        let flag = |key: &str, default: bool| {
+            app.get_config(&format!("history.{}", key))
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        };
+        Self {
+            ignore_dups: flag("ignore_dups", defaults.ignore_dups),
+            erase_dups: flag("erase_dups", defaults.erase_dups),
+            ignore_space: flag("ignore_space", defaults.ignore_space),
+            share_history: flag("share_history", defaults.share_history),
+            prefer_current_directory: flag("prefer_current_directory", defaults.prefer_current_directory),
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
+struct Navigation {
+    candidates: Vec<usize>,
+    position: Option<usize>,
+}
+
+#[derive(Debug)]
 pub struct CommandHistory {
-    commands: VecDeque<String>,
+    entries: VecDeque<HistoryEntry>,
     max_size: usize,
-    current_index: Option<usize>,
+    navigation: Option<Navigation>,
+    options: HistoryOptions,
+    session_id: String,
+    file: Option<HistoryFile>,
+    pending: Option<(String, u64, SystemTime)>,
 }
 
 impl CommandHistory {
     pub fn new(max_size: usize) -> Self {
         Self {
-            commands: VecDeque::with_capacity(max_size),
+            entries: VecDeque::with_capacity(max_size),
             max_size,
-            current_index: None,
+            navigation: None,
+            options: HistoryOptions::default(),
+            session_id: new_session_id(),
+            file: None,
+            pending: None,
         }
     }
 
+    /// Open a history backed by `path`, loading what other sessions have written so far
+    pub fn open<P: AsRef<Path>>(path: P, max_size: usize, options: HistoryOptions) -> HistoryResult<Self> {
+        let mut history = Self::new(max_size).with_options(options);
+        let mut file = HistoryFile::new(path.as_ref()).with_max_entries(max_size);
+        for entry in file.load()? {
+            history.push_entry(entry);
+        }
+        history.file = Some(file);
+        Ok(history)
+    }
+
+    /// History backed by `HistoryFile::default_path`, falling back to memory
+    /// only when there is no home directory or the file can't be read. A new
+    /// history file starts with the login shell's history.
+    pub fn open_default(max_size: usize) -> Self {
+        let Some(path) = HistoryFile::default_path() else {
+            return Self::new(max_size);
+        };
+        if !path.exists() {
+            let _ = import_login_shell_history(&path);
+        }
+        Self::open(&path, max_size, HistoryOptions::default()).unwrap_or_else(|_| Self::new(max_size))
+    }
+
+    pub fn with_options(mut self, options: HistoryOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn set_options(&mut self, options: HistoryOptions) {
+        self.options = options;
+    }
+
+    pub fn options(&self) -> &HistoryOptions {
+        &self.options
+    }
+
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+
     pub fn add(&mut self, command: String) {
-        if !command.trim().is_empty() {
-            self.commands.push_back(command);
-            if self.commands.len() > self.max_size {
-                self.commands.pop_front();
+        let entry = HistoryEntry::new(command, self.session_id.clone());
+        // Persistence is best effort; the in-memory history is still updated
+        let _ = self.add_entry(entry);
+    }
+
+    /// Record an entry and append it to the history file. Returns whether it was kept.
+    pub fn add_entry(&mut self, entry: HistoryEntry) -> HistoryResult<bool> {
+        self.navigation = None;
+        if !self.should_record(&entry.command) {
+            return Ok(false);
+        }
+
+        self.push_entry(entry.clone());
+        self.persist(&entry)?;
+        Ok(true)
+    }
+
+    /// Record a submitted command now; it is written to the history file once
+    /// `finish_command` supplies its exit status and duration
+    pub fn start_command(&mut self, command: String, cwd: Option<String>) {
+        let _ = self.flush_pending();
+        self.navigation = None;
+        if !self.should_record(&command) {
+            return;
+        }
+
+        let mut entry = HistoryEntry::new(command, self.session_id.clone());
+        entry.cwd = cwd;
+        self.pending = Some((entry.command.clone(), entry.timestamp, SystemTime::now()));
+        self.push_entry(entry);
+    }
+
+    /// Complete the command passed to `start_command` and write it to history
+    pub fn finish_command(&mut self, exit_status: Option<i32>, duration_ms: Option<u64>) -> HistoryResult<bool> {
+        let started = match &self.pending {
+            Some((_, _, started)) => *started,
+            None => return Ok(false),
+        };
+
+        if let Some(idx) = self.pending_index() {
+            let entry = &mut self.entries[idx];
+            entry.exit_status = exit_status;
+            entry.duration_ms = duration_ms.or_else(|| {
+                started.elapsed().ok().map(|d| d.as_millis() as u64)
+            });
+        }
+        self.flush_pending()
+    }
+
+    /// Write a started command that never reported an exit status
+    pub fn flush_pending(&mut self) -> HistoryResult<bool> {
+        let entry = self.pending_index().map(|idx| self.entries[idx].clone());
+        self.pending = None;
+        match entry {
+            Some(entry) => {
+                self.persist(&entry)?;
+                Ok(true)
             }
+            None => Ok(false),
         }
-        self.current_index = None;
     }
 
-    pub fn previous(&mut self) -> Option<String> {
-        match self.current_index {
-            None => {
-                if self.commands.is_empty() {
-                    None
-                } else {
-                    self.current_index = Some(self.commands.len() - 1);
-                    self.commands.get(self.current_index.unwrap()).cloned()
+    // Entries from other sessions may have been merged after the pending one
+    fn pending_index(&self) -> Option<usize> {
+        let (command, timestamp, _) = self.pending.as_ref()?;
+        self.entries.iter().rposition(|e| {
+            e.session_id == self.session_id && e.timestamp == *timestamp && &e.command == command
+        })
+    }
+
+    fn persist(&mut self, entry: &HistoryEntry) -> HistoryResult<()> {
+        match &mut self.file {
+            Some(file) => file.append(entry),
+            None => Ok(()),
+        }
+    }
+
+    /// Merge entries other sessions appended to the history file since the last read.
+    /// When the file was compacted or replaced meanwhile, the history is
+    /// rebuilt from it instead, keeping only a command still running here.
+    pub fn sync(&mut self) -> HistoryResult<usize> {
+        let (new_entries, rewritten) = match &mut self.file {
+            Some(file) => (file.read_new()?, file.take_rewritten()),
+            None => return Ok(0),
+        };
+
+        if rewritten {
+            let pending = self.pending_index().map(|idx| self.entries[idx].clone());
+            self.entries.clear();
+            self.navigation = None;
+            for entry in new_entries {
+                if self.should_record(&entry.command) {
+                    self.push_entry(entry);
                 }
             }
-            Some(idx) if idx > 0 => {
-                self.current_index = Some(idx - 1);
-                self.commands.get(self.current_index.unwrap()).cloned()
+            if let Some(entry) = pending {
+                self.push_entry(entry);
             }
-            Some(_) => None,
+            return Ok(self.entries.len());
+        }
+
+        let mut merged = 0;
+        for entry in new_entries {
+            if entry.session_id != self.session_id && self.should_record(&entry.command) {
+                self.push_entry(entry);
+                merged += 1;
+            }
+        }
+        Ok(merged)
+    }
+
+    /// Add entries imported from another shell's history, oldest first
+    pub fn import(&mut self, entries: Vec<HistoryEntry>) -> HistoryResult<usize> {
+        let mut imported = 0;
+        for entry in entries {
+            if self.add_entry(entry)? {
+                imported += 1;
+            }
+        }
+        Ok(imported)
+    }
+
+    fn should_record(&self, command: &str) -> bool {
+        if command.trim().is_empty() {
+            return false;
+        }
+        if self.options.ignore_space && command.starts_with(' ') {
+            return false;
+        }
+        if self.options.ignore_dups
+            && self.entries.back().map(|e| e.command.as_str()) == Some(command)
+        {
+            return false;
         }
+        true
     }
 
+    fn push_entry(&mut self, entry: HistoryEntry) {
+        if self.options.erase_dups {
+            self.entries.retain(|e| e.command != entry.command);
+        }
+        self.entries.push_back(entry);
+        while self.entries.len() > self.max_size {
+            self.entries.pop_front();
+        }
+    }
+
+    pub fn previous(&mut self) -> Option<String> {
+        self.previous_matching("", None)
+    }
+
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> Option<String> {
-        match self.current_index {
-            None => None,
-            Some(idx) if idx < self.commands.len() - 1 => {
-                self.current_index = Some(idx + 1);
-                self.commands.get(self.current_index.unwrap()).cloned()
+        self.next_matching()
+    }
+
+    /// Step back through entries starting with `prefix`, like zsh's
+    /// history-beginning-search-backward. The prefix and `cwd` are fixed when
+    /// navigation starts; with `prefer_current_directory`, commands run in `cwd`
+    /// are offered before the rest.
+    pub fn previous_matching(&mut self, prefix: &str, cwd: Option<&str>) -> Option<String> {
+        if self.navigation.is_none() {
+            if self.options.share_history {
+                let _ = self.sync();
             }
-            Some(_) => {
-                self.current_index = None;
+            self.navigation = Some(Navigation {
+                candidates: self.navigation_candidates(prefix, cwd),
+                position: None,
+            });
+        }
+
+        let nav = self.navigation.as_mut()?;
+        let position = nav.position.map(|p| p + 1).unwrap_or(0);
+        let idx = *nav.candidates.get(position)?;
+        nav.position = Some(position);
+        self.entries.get(idx).map(|e| e.command.clone())
+    }
+
+    /// Step forward again; returns `None` and ends navigation past the newest match
+    pub fn next_matching(&mut self) -> Option<String> {
+        let nav = self.navigation.as_mut()?;
+        match nav.position {
+            Some(position) if position > 0 => {
+                nav.position = Some(position - 1);
+                let idx = nav.candidates[position - 1];
+                self.entries.get(idx).map(|e| e.command.clone())
+            }
+            _ => {
+                self.navigation = None;
                 None
             }
         }
     }
 
+    // Entry indices to visit, newest first
+    fn navigation_candidates(&self, prefix: &str, cwd: Option<&str>) -> Vec<usize> {
+        let matching = self
+            .entries
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, e)| e.command.starts_with(prefix));
+
+        // A bare up-arrow walks every entry; a prefix search skips repeats
+        let mut seen = HashSet::new();
+        let mut candidates: Vec<usize> = matching
+            .filter(|(_, e)| prefix.is_empty() || (e.command != prefix && seen.insert(e.command.as_str())))
+            .map(|(idx, _)| idx)
+            .collect();
+
+        if let (true, Some(cwd)) = (self.options.prefer_current_directory, cwd) {
+            // Stable sort keeps recency order within each group
+            candidates.sort_by_key(|&idx| self.entries[idx].cwd.as_deref() != Some(cwd));
+        }
+        candidates
+    }
+
     pub fn search(&self, query: &str) -> Vec<String> {
-        self.commands
+        self.entries
             .iter()
-            .filter(|cmd| cmd.contains(query))
-            .cloned()
+            .filter(|e| e.command.contains(query))
+            .map(|e| e.command.clone())
             .collect()
     }
 
     pub fn reset_index(&mut self) {
-        self.current_index = None;
+        self.navigation = None;
+    }
+
+    pub fn is_navigating(&self) -> bool {
+        self.navigation.is_some()
     }
 
     pub fn len(&self) -> usize {
-        self.commands.len()
+        self.entries.len()
     }
 
     pub fn is_empty(&self) -> bool {
-        self.commands.is_empty()
+        self.entries.is_empty()
     }
 
     pub fn get_all(&self) -> Vec<String> {
-        self.commands.iter().cloned().collect()
+        self.entries.iter().map(|e| e.command.clone()).collect()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&HistoryEntry> {
+        self.entries.get(index)
+    }
+
+    /// All recorded entries, oldest first
+    pub fn entries(&self) -> impl DoubleEndedIterator<Item = &HistoryEntry> {
+        self.entries.iter()
+    }
+}
+
+// A copy doesn't take over the command still running; only the original writes it
+impl Clone for CommandHistory {
+    fn clone(&self) -> Self {
+        Self {
+            entries: self.entries.clone(),
+            max_size: self.max_size,
+            navigation: self.navigation.clone(),
+            options: self.options.clone(),
+            session_id: self.session_id.clone(),
+            file: self.file.clone(),
+            pending: None,
+        }
+    }
+}
+
+// A command that never reported its exit status is still written out
+impl Drop for CommandHistory {
+    fn drop(&mut self) {
+        let _ = self.flush_pending();
+    }
+}
+
+/// Seeds a new history file at `path` from `$SHELL`'s own history
+fn import_login_shell_history(path: &Path) -> HistoryResult<()> {
+    let shell = std::env::var("SHELL").unwrap_or_default();
+    let (Some(format), Some(home)) = (ShellHistoryFormat::from_shell(&shell), std::env::var_os("HOME")) else {
+        return Ok(());
+    };
+    let source = format.default_path(Path::new(&home));
+    if !source.exists() {
+        return Ok(());
+    }
+    let entries = import_shell_history(source, format)?;
+    HistoryFile::new(path).append_all(&entries)
+}
+
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn new_session_id() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    format!("{}-{}-{:x}", std::process::id(), unix_now(), nanos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_pending_command_flushed_on_drop() {
+        let dir = TempDir::new("history_drop");
+        let path = dir.join("history.jsonl");
+        {
+            let mut history = CommandHistory::open(&path, 10, HistoryOptions::default()).unwrap();
+            history.start_command("sleep 100".to_string(), None);
+            // The copy doesn't write the running command a second time
+            drop(history.clone());
+        }
+        let reopened = CommandHistory::open(&path, 10, HistoryOptions::default()).unwrap();
+        assert_eq!(reopened.get_all(), vec!["sleep 100".to_string()]);
+    }
+
+    #[test]
+    fn test_ignore_dups_and_space() {
+        let mut history = CommandHistory::new(10);
+        history.add("ls".to_string());
+        history.add("ls".to_string());
+        history.add(" secret".to_string());
+        assert_eq!(history.get_all(), vec!["ls".to_string()]);
+    }
+
+    #[test]
+    fn test_erase_dups() {
+        let options = HistoryOptions {
+            erase_dups: true,
+            ..HistoryOptions::default()
+        };
+        let mut history = CommandHistory::new(10).with_options(options);
+        history.add("ls".to_string());
+        history.add("pwd".to_string());
+        history.add("ls".to_string());
+        assert_eq!(history.get_all(), vec!["pwd".to_string(), "ls".to_string()]);
+    }
+
+    #[test]
+    fn test_finish_command_records_status() {
+        let mut history = CommandHistory::new(10);
+        history.start_command("cargo build".to_string(), Some("/tmp".to_string()));
+        assert_eq!(history.previous(), Some("cargo build".to_string()));
+
+        history.finish_command(Some(101), Some(2500)).unwrap();
+        let entry = history.entries().last().unwrap();
+        assert_eq!(entry.exit_status, Some(101));
+        assert_eq!(entry.duration_ms, Some(2500));
+        assert_eq!(entry.cwd.as_deref(), Some("/tmp"));
+    }
+
+    #[test]
+    fn test_prefix_navigation() {
+        let mut history = CommandHistory::new(10);
+        for cmd in ["git status", "ls", "git log", "git status", "git"] {
+            history.add(cmd.to_string());
+        }
+
+        assert_eq!(history.previous_matching("git ", None), Some("git status".to_string()));
+        assert_eq!(history.previous_matching("git ", None), Some("git log".to_string()));
+        assert_eq!(history.previous_matching("git ", None), None);
+        assert_eq!(history.next_matching(), Some("git status".to_string()));
+        assert_eq!(history.next_matching(), None);
+        assert!(!history.is_navigating());
+    }
+
+    #[test]
+    fn test_prefer_current_directory() {
+        let mut app = TerminalApp::new("test");
+        app.set_config("history.prefer_current_directory", "true");
+        app.set_config("history.ignore_space", "maybe");
+        let options = HistoryOptions::from_config(&app);
+        assert!(options.prefer_current_directory && options.ignore_space);
+        let mut history = CommandHistory::new(10).with_options(options);
+        history.start_command("make test".to_string(), Some("/project".to_string()));
+        history.start_command("make clean".to_string(), Some("/other".to_string()));
+        history.flush_pending().unwrap();
+
+        assert_eq!(history.previous_matching("make", Some("/project")), Some("make test".to_string()));
+        assert_eq!(history.previous_matching("make", Some("/project")), Some("make clean".to_string()));
+    }
+
+    #[test]
+    fn test_sessions_share_history_file() {
+        let dir = TempDir::new("history_shared");
+        let path = dir.join("history.jsonl");
+        let options = HistoryOptions {
+            share_history: true,
+            ..HistoryOptions::default()
+        };
+
+        let mut first = CommandHistory::open(&path, 100, options.clone()).unwrap();
+        let mut second = CommandHistory::open(&path, 100, options).unwrap();
+        first.add("echo from first".to_string());
+        second.add("echo from second".to_string());
+
+        assert_eq!(first.previous(), Some("echo from second".to_string()));
+        assert_eq!(first.previous(), Some("echo from first".to_string()));
+
+        let reopened = CommandHistory::open(&path, 100, HistoryOptions::default()).unwrap();
+        assert_eq!(reopened.len(), 2);
+    }
+
+    #[test]
+    fn test_compacted_file_is_read_again() {
+        let dir = TempDir::new("history_compact");
+        let path = dir.join("history.jsonl");
+        let options = HistoryOptions {
+            share_history: true,
+            ..HistoryOptions::default()
+        };
+        let mut first = CommandHistory::open(&path, 2, options.clone()).unwrap();
+        let mut second = CommandHistory::open(&path, 2, options).unwrap();
+        for command in ["one", "two", "three", "four"] {
+            first.add(command.to_string());
+        }
+        second.sync().unwrap();
+        assert_eq!(second.get_all(), vec!["three".to_string(), "four".to_string()]);
+
+        // The fifth line takes the file past twice the limit: it is cut back
+        // to the newest two before the append, and `second` starts over
+        first.add("five".to_string());
+        let lines = std::fs::read_to_string(&path).unwrap().lines().count();
+        assert_eq!(lines, 3);
+        second.sync().unwrap();
+        assert_eq!(second.get_all(), vec!["four".to_string(), "five".to_string()]);
+
+        // A file truncated by hand is read from the start too
+        std::fs::write(&path, "").unwrap();
+        first.add("six".to_string());
+        second.sync().unwrap();
+        assert_eq!(second.get_all(), vec!["six".to_string()]);
     }
 }
@@ -0,0 +1,543 @@
+use super::agent_mode::{AgentContext, AgentRequest, AgentResponse};
+use super::command_history::unix_now;
+use crate::attachments::Attachment;
+use crate::blocks::Block;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+const DEFAULT_TOKEN_BUDGET: usize = 8000;
+/// Most recent messages that are never dropped or summarised
+const KEEP_RECENT_MESSAGES: usize = 4;
+/// Characters of a file or block output kept when attaching it
+const MAX_ATTACHMENT_CHARS: usize = 16 * 1024;
+
+#[derive(Error, Debug)]
+pub enum ConversationError {
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("JSON error: {0}")]
+    JsonError(#[from] serde_json::Error),
+    #[error("Conversation not found: {0}")]
+    NotFound(String),
+    #[error("Invalid conversation id: {0}")]
+    InvalidId(String),
+}
+
+pub type ConversationResult<T> = Result<T, ConversationError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    User,
+    Assistant,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Message {
+    pub role: Role,
+    pub content: String,
+    pub timestamp: u64,
+}
+
+impl Message {
+    pub fn new(role: Role, content: impl Into<String>) -> Self {
+        Self {
+            role,
+            content: content.into(),
+            timestamp: unix_now(),
+        }
+    }
+
+    pub fn tokens(&self) -> usize {
+        estimate_tokens(&self.content)
+    }
+}
+
+/// Something attached to a conversation for the agent to see
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ContextItem {
+    Block {
+        id: String,
+        command: String,
+        output: String,
+        exit_code: Option<i32>,
+    },
+    File {
+        path: String,
+        content: String,
+    },
+}
+
+impl ContextItem {
+    pub fn from_block(block: &Block) -> Self {
+        ContextItem::Block {
+            id: block.id.clone(),
+            command: block.command.clone(),
+            output: truncate_chars(&block.get_full_output(), MAX_ATTACHMENT_CHARS),
+            exit_code: block.output.exit_code,
+        }
+    }
+
+    pub fn from_file(path: &Path) -> std::io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        Ok(ContextItem::File {
+            path: path.display().to_string(),
+            content: truncate_chars(&content, MAX_ATTACHMENT_CHARS),
+        })
+    }
+
+    pub fn label(&self) -> String {
+        match self {
+            ContextItem::Block { command, .. } => format!("$ {}", command),
+            ContextItem::File { path, .. } => path.clone(),
+        }
+    }
+
+    pub fn render(&self) -> String {
+        match self {
+            ContextItem::Block { command, output, exit_code, .. } => {
+                let status = exit_code.map(|c| format!(" (exit {})", c)).unwrap_or_default();
+                format!("Block `{}`{}:\n{}\n", command, status, output.trim_end())
+            }
+            ContextItem::File { path, content } => format!("File {}:\n{}\n", path, content.trim_end()),
+        }
+    }
+
+    pub fn tokens(&self) -> usize {
+        estimate_tokens(&self.render())
+    }
+}
+
+/// How to make room when a conversation is over its token budget
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrimStrategy {
+    DropOldest,
+    Summarize,
+}
+
+/// An agent conversation: its messages, attached context and a summary of
+/// any turns that were folded away to stay within the token budget
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Conversation {
+    pub id: String,
+    pub title: String,
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub messages: Vec<Message>,
+    #[serde(default)]
+    pub context: Vec<ContextItem>,
+    #[serde(default)]
+    pub summary: Option<String>,
+    /// The conversation this one was forked from
+    #[serde(default)]
+    pub parent_id: Option<String>,
+    pub token_budget: usize,
+}
+
+impl Conversation {
+    pub fn new(title: impl Into<String>) -> Self {
+        let now = unix_now();
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            title: title.into(),
+            created_at: now,
+            updated_at: now,
+            messages: Vec::new(),
+            context: Vec::new(),
+            summary: None,
+            parent_id: None,
+            token_budget: DEFAULT_TOKEN_BUDGET,
+        }
+    }
+
+    pub fn with_token_budget(mut self, token_budget: usize) -> Self {
+        self.token_budget = token_budget;
+        self
+    }
+
+    pub fn push(&mut self, role: Role, content: impl Into<String>) {
+        self.messages.push(Message::new(role, content));
+        self.updated_at = unix_now();
+    }
+
+    pub fn attach(&mut self, item: ContextItem) {
+        if !self.context.contains(&item) {
+            self.context.push(item);
+            self.updated_at = unix_now();
+        }
+    }
+
+    pub fn attach_block(&mut self, block: &Block) {
+        self.attach(ContextItem::from_block(block));
+    }
+
+    pub fn attach_file(&mut self, path: &Path) -> std::io::Result<()> {
+        self.attach(ContextItem::from_file(path)?);
+        Ok(())
+    }
+
+    pub fn detach(&mut self, index: usize) -> Option<ContextItem> {
+        (index < self.context.len()).then(|| self.context.remove(index))
+    }
+
+    pub fn total_tokens(&self) -> usize {
+        self.summary.as_deref().map(estimate_tokens).unwrap_or(0)
+            + self.context.iter().map(ContextItem::tokens).sum::<usize>()
+            + self.messages.iter().map(Message::tokens).sum::<usize>()
+    }
+
+    pub fn is_over_budget(&self) -> bool {
+        self.total_tokens() > self.token_budget
+    }
+
+    pub fn trim_to_budget(&mut self, strategy: TrimStrategy) {
+        match strategy {
+            TrimStrategy::DropOldest => self.trim_to_budget_with(|_| None),
+            TrimStrategy::Summarize => self.trim_to_budget_with(|messages| Some(extractive_summary(messages))),
+        }
+    }
+
+    /// Fold the oldest messages into the summary with `summarize` (or drop them
+    /// when it returns `None`) until the conversation fits its budget. The most
+    /// recent turns are kept verbatim; if they alone are too big, the oldest
+    /// attachments go.
+    pub fn trim_to_budget_with(&mut self, mut summarize: impl FnMut(&[Message]) -> Option<String>) {
+        while self.is_over_budget() && self.messages.len() > KEEP_RECENT_MESSAGES {
+            let overflow = self.total_tokens() - self.token_budget;
+            let mut freed = 0;
+            let mut count = 0;
+            while count < self.messages.len() - KEEP_RECENT_MESSAGES && freed < overflow {
+                freed += self.messages[count].tokens();
+                count += 1;
+            }
+            let folded: Vec<Message> = self.messages.drain(..count).collect();
+            if let Some(summary) = summarize(&folded) {
+                let summary = match self.summary.take() {
+                    Some(previous) => format!("{}\n{}", previous, summary),
+                    None => summary,
+                };
+                self.summary = Some(cap_summary(summary, self.token_budget / 4));
+            }
+        }
+        while self.is_over_budget() && !self.context.is_empty() {
+            self.context.remove(0);
+        }
+    }
+
+    /// Record `query` as the next user message and build the request for it,
+    /// with the summary, attached blocks and earlier turns as context and
+    /// `attachments` sent alongside
+    pub fn request(
+        &mut self,
+        query: impl Into<String>,
+        terminal: Option<&AgentContext>,
+        attachments: Vec<Attachment>,
+    ) -> AgentRequest {
+        let query = query.into();
+        let mut context = String::new();
+        if let Some(terminal) = terminal {
+            context.push_str(&terminal.render());
+        }
+        if let Some(summary) = &self.summary {
+            context.push_str(&format!("Summary of earlier conversation:\n{}\n", summary));
+        }
+        for item in &self.context {
+            context.push_str(&item.render());
+        }
+        if !self.messages.is_empty() {
+            context.push_str("Conversation so far:\n");
+            for message in &self.messages {
+                let speaker = match message.role {
+                    Role::User => "User",
+                    Role::Assistant => "Assistant",
+                };
+                context.push_str(&format!("{}: {}\n", speaker, message.content));
+            }
+        }
+
+        self.push(Role::User, query.clone());
+        if self.title.is_empty() {
+            self.title = truncate_chars(&query, 60);
+        }
+        AgentRequest {
+            query,
+            context: (!context.is_empty()).then_some(context),
+            requested_permission: false,
+            attachments,
+        }
+    }
+
+    pub fn record_response(&mut self, response: &AgentResponse) {
+        self.push(Role::Assistant, response.suggestion.clone());
+    }
+
+    /// A copy to branch off from, sharing history up to now
+    pub fn fork(&self) -> Self {
+        let mut fork = self.clone();
+        let now = unix_now();
+        fork.id = uuid::Uuid::new_v4().to_string();
+        fork.parent_id = Some(self.id.clone());
+        fork.title = format!("{} (fork)", self.title);
+        fork.created_at = now;
+        fork.updated_at = now;
+        fork
+    }
+}
+
+/// Rough token count: about four characters per token for English and code
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Summary without a model: the first line of each turn
+fn extractive_summary(messages: &[Message]) -> String {
+    messages
+        .iter()
+        .map(|m| {
+            let speaker = match m.role {
+                Role::User => "User asked",
+                Role::Assistant => "Assistant answered",
+            };
+            format!("- {}: {}", speaker, truncate_chars(m.content.lines().next().unwrap_or(""), 120))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Keep the newest lines of a summary that fit in `max_tokens`
+fn cap_summary(mut summary: String, max_tokens: usize) -> String {
+    while estimate_tokens(&summary) > max_tokens {
+        match summary.split_once('\n') {
+            Some((_, rest)) => summary = rest.to_string(),
+            None => return truncate_chars(&summary, max_tokens.saturating_sub(1) * 4),
+        }
+    }
+    summary
+}
+
+fn truncate_chars(text: &str, max: usize) -> String {
+    match text.char_indices().nth(max) {
+        Some((idx, _)) => format!("{}…", &text[..idx]),
+        None => text.to_string(),
+    }
+}
+
+/// Listing entry for a saved conversation
+#[derive(Debug, Clone)]
+pub struct ConversationSummary {
+    pub id: String,
+    pub title: String,
+    pub updated_at: u64,
+    pub message_count: usize,
+    pub parent_id: Option<String>,
+}
+
+/// Conversations saved as one JSON file each in a directory
+#[derive(Debug, Clone)]
+pub struct ConversationStore {
+    dir: PathBuf,
+}
+
+impl ConversationStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// `$WARP_CONVERSATIONS_DIR`, or `~/.warp/conversations`
+    pub fn default_dir() -> PathBuf {
+        if let Ok(dir) = std::env::var("WARP_CONVERSATIONS_DIR") {
+            return PathBuf::from(dir);
+        }
+        let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
+        PathBuf::from(home).join(".warp").join("conversations")
+    }
+
+    /// File for `id`; ids are letters, digits and dashes so they can't leave the store
+    fn path(&self, id: &str) -> ConversationResult<PathBuf> {
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(ConversationError::InvalidId(id.to_string()));
+        }
+        Ok(self.dir.join(format!("{}.json", id)))
+    }
+
+    pub fn save(&self, conversation: &Conversation) -> ConversationResult<()> {
+        let path = self.path(&conversation.id)?;
+        fs::create_dir_all(&self.dir)?;
+        let json = serde_json::to_string_pretty(conversation)?;
+        fs::write(path, json)?;
+        Ok(())
+    }
+
+    pub fn load(&self, id: &str) -> ConversationResult<Conversation> {
+        let path = self.path(id)?;
+        if !path.exists() {
+            return Err(ConversationError::NotFound(id.to_string()));
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Saved conversations, most recently updated first
+    pub fn list(&self) -> ConversationResult<Vec<ConversationSummary>> {
+        let mut summaries = Vec::new();
+        if !self.dir.exists() {
+            return Ok(summaries);
+        }
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            // Skip files that aren't conversations rather than failing the listing
+            let Ok(conversation) = fs::read_to_string(&path)
+                .map_err(ConversationError::from)
+                .and_then(|s| Ok(serde_json::from_str::<Conversation>(&s)?))
+            else {
+                continue;
+            };
+            summaries.push(ConversationSummary {
+                id: conversation.id,
+                title: conversation.title,
+                updated_at: conversation.updated_at,
+                message_count: conversation.messages.len(),
+                parent_id: conversation.parent_id,
+            });
+        }
+        summaries.sort_by_key(|s| std::cmp::Reverse(s.updated_at));
+        Ok(summaries)
+    }
+
+    pub fn delete(&self, id: &str) -> ConversationResult<()> {
+        let path = self.path(id)?;
+        if !path.exists() {
+            return Err(ConversationError::NotFound(id.to_string()));
+        }
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    /// Fork a saved conversation and save the copy
+    pub fn fork(&self, id: &str) -> ConversationResult<Conversation> {
+        let fork = self.load(id)?.fork();
+        self.save(&fork)?;
+        Ok(fork)
+    }
+}
+
+impl Default for ConversationStore {
+    fn default() -> Self {
+        Self::new(Self::default_dir())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classic_input::agent_backend::{MockAgentServer, OllamaBackend};
+    use crate::classic_input::agent_mode::AgentMode;
+    use crate::attachments::AttachmentLimits;
+    use crate::test_util::TempDir;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_request_carries_history_and_attachments() {
+        let mut block = Block::new("cargo build".to_string(), "/src".to_string());
+        block.set_output(String::new(), "error: linker failed".to_string(), 1);
+
+        let mut conversation = Conversation::new("");
+        conversation.attach_block(&block);
+        conversation.attach_block(&block);
+        assert_eq!(conversation.context.len(), 1);
+
+        let first = conversation.request("why did the build fail?", None, Vec::new());
+        assert!(first.context.unwrap().contains("Block `cargo build` (exit 1):\nerror: linker failed"));
+        assert_eq!(conversation.title, "why did the build fail?");
+        conversation.push(Role::Assistant, "The linker is missing.");
+
+        let second = conversation.request("how do I install it?", None, Vec::new());
+        let context = second.context.unwrap();
+        assert!(context.contains("User: why did the build fail?\nAssistant: The linker is missing.\n"));
+        assert_eq!(conversation.messages.len(), 3);
+    }
+
+    #[test]
+    fn test_trim_to_budget() {
+        let mut conversation = Conversation::new("long").with_token_budget(60);
+        for i in 0..10 {
+            conversation.push(Role::User, format!("question number {} about something", i));
+            conversation.push(Role::Assistant, format!("answer {}", i));
+        }
+        let mut dropped = conversation.clone();
+
+        conversation.trim_to_budget(TrimStrategy::Summarize);
+        assert!(!conversation.is_over_budget());
+        assert!(conversation.messages.len() < 20);
+        assert!(conversation.summary.as_deref().unwrap().starts_with("- User asked: question number"));
+        assert_eq!(conversation.messages.last().unwrap().content, "answer 9");
+
+        dropped.trim_to_budget(TrimStrategy::DropOldest);
+        assert!(!dropped.is_over_budget());
+        assert!(dropped.summary.is_none());
+        assert!(dropped.messages.len() >= KEEP_RECENT_MESSAGES);
+    }
+
+    #[test]
+    fn test_store_save_list_resume_and_fork() {
+        let dir = TempDir::new("conversations");
+        let store = ConversationStore::new(dir.join("store"));
+
+        let mut conversation = Conversation::new("deploy");
+        conversation.push(Role::User, "deploy to staging");
+        store.save(&conversation).unwrap();
+
+        let fork = store.fork(&conversation.id).unwrap();
+        assert_eq!(fork.parent_id.as_deref(), Some(conversation.id.as_str()));
+        assert_eq!(fork.messages.len(), 1);
+
+        let listed = store.list().unwrap();
+        assert_eq!(listed.len(), 2);
+        let resumed = store.load(&conversation.id).unwrap();
+        assert_eq!(resumed.messages[0].content, "deploy to staging");
+
+        store.delete(&fork.id).unwrap();
+        assert!(matches!(store.load(&fork.id), Err(ConversationError::NotFound(_))));
+
+        // Ids can't reach outside the store
+        for id in ["../x", "a/b", "..", ""] {
+            assert!(matches!(store.load(id), Err(ConversationError::InvalidId(_))), "{}", id);
+            assert!(matches!(store.delete(id), Err(ConversationError::InvalidId(_))), "{}", id);
+        }
+    }
+
+    #[test]
+    fn test_conversation_chip_and_failed_turns() {
+        let server = MockAgentServer::start("Use `df -h` to see what is full.").unwrap();
+        let mut chips = crate::universal_input::ContextualChips::new();
+        let mut mode = AgentMode::new().with_backend(Arc::new(OllamaBackend::new(server.url(), "m")));
+
+        let dir = TempDir::new("conversation_notes");
+        let notes = dir.write("notes.txt", "disk is full\n");
+        let attachment = Attachment::load(&notes, &AttachmentLimits::default()).unwrap();
+
+        mode.start_conversation("", &mut chips);
+        assert_eq!(chips.chips[0].value, "New conversation");
+        mode.ask("free up disk space", None, vec![attachment], &mut chips, &mut |_| {}).unwrap();
+        assert_eq!(chips.chips[0].value, "free up disk space");
+        assert_eq!(mode.conversation().unwrap().messages.len(), 2);
+        let sent = server.requests()[0]["messages"][1]["content"].as_str().unwrap().to_string();
+        assert!(sent.contains("Attached file notes.txt"));
+        assert!(sent.contains("disk is full"));
+
+        // A failed request doesn't leave the question behind
+        mode.set_backend(Arc::new(OllamaBackend::new(format!("{}/missing", server.url()), "m")));
+        assert!(mode.ask("and now?", None, Vec::new(), &mut chips, &mut |_| {}).is_err());
+        assert_eq!(mode.conversation().unwrap().messages.len(), 2);
+
+        let conversation = mode.end_conversation(&mut chips).unwrap();
+        assert!(chips.active_conversation.is_none() && chips.chips.is_empty());
+        mode.resume_conversation(conversation, &mut chips);
+        assert_eq!(chips.active_conversation, mode.conversation().map(|c| c.id.clone()));
+    }
+}
@@ -21,6 +21,11 @@ impl ClassicEditor {
         self.cursor_pos += ch.len_utf8();
     }
 
+    pub fn insert_str(&mut self, text: &str) {
+        self.content.insert_str(self.cursor_pos, text);
+        self.cursor_pos += text.len();
+    }
+
     pub fn backspace(&mut self) {
         if self.cursor_pos > 0 {
             let char_size = self
@@ -103,6 +108,15 @@ impl ClassicEditor {
         self.cursor_pos
     }
 
+    /// Moves the cursor to byte offset `pos`, clamped to the input and a char boundary
+    pub fn set_cursor_position(&mut self, pos: usize) {
+        let mut pos = min(pos, self.content.len());
+        while !self.content.is_char_boundary(pos) {
+            pos -= 1;
+        }
+        self.cursor_pos = pos;
+    }
+
     pub fn render_with_cursor(&self) -> String {
         let mut result = String::new();
         for (i, ch) in self.content.chars().enumerate() {
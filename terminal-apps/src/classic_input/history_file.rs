@@ -0,0 +1,405 @@
+use super::command_history::HistoryEntry;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Entries a history file keeps when it is compacted, unless set otherwise
+const DEFAULT_MAX_ENTRIES: usize = 10_000;
+
+/// Append-only, JSON-lines history file shared between terminal sessions.
+///
+/// Writers take an exclusive lock for each append and readers a shared lock, so
+/// concurrent sessions never observe a partially written line. Once the file
+/// holds twice `max_entries` lines, the writer that notices cuts it back to
+/// the newest `max_entries` under the same lock.
+#[derive(Debug, Clone)]
+pub struct HistoryFile {
+    path: PathBuf,
+    read_offset: u64,
+    /// Lines in the file as far as this session knows, read or written
+    lines: usize,
+    max_entries: usize,
+    /// Set when reading had to start over from the top of the file
+    rewritten: bool,
+}
+
+impl HistoryFile {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            read_offset: 0,
+            lines: 0,
+            max_entries: DEFAULT_MAX_ENTRIES,
+            rewritten: false,
+        }
+    }
+
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries.max(1);
+        self
+    }
+
+    /// `$WARP_HISTFILE`, falling back to `~/.warp_history.jsonl`
+    pub fn default_path() -> Option<PathBuf> {
+        if let Ok(path) = std::env::var("WARP_HISTFILE") {
+            return Some(PathBuf::from(path));
+        }
+        std::env::var("HOME")
+            .ok()
+            .map(|home| Path::new(&home).join(".warp_history.jsonl"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Read every entry in the file and remember where reading stopped
+    pub fn load(&mut self) -> HistoryResult<Vec<HistoryEntry>> {
+        self.read_offset = 0;
+        self.lines = 0;
+        self.rewritten = false;
+        self.read_new()
+    }
+
+    /// Whether the file was compacted or replaced since this was last asked,
+    /// so that the entries read since are the whole file again
+    pub fn take_rewritten(&mut self) -> bool {
+        std::mem::take(&mut self.rewritten)
+    }
+
+    /// Read entries appended since the last `load`/`read_new`. When the file
+    /// has shrunk below what was read, another session compacted or replaced
+    /// it, and it is read again from the start.
+    pub fn read_new(&mut self) -> HistoryResult<Vec<HistoryEntry>> {
+        let mut file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        file.lock_shared()?;
+        let mut buffer = Vec::new();
+        let read = file.metadata().and_then(|metadata| {
+            if metadata.len() < self.read_offset {
+                self.read_offset = 0;
+                self.lines = 0;
+                self.rewritten = true;
+            }
+            file.seek(SeekFrom::Start(self.read_offset))?;
+            file.read_to_end(&mut buffer)
+        });
+        file.unlock()?;
+        read?;
+
+        // Only consume complete lines
+        let complete = match buffer.iter().rposition(|&b| b == b'\n') {
+            Some(pos) => pos + 1,
+            None => return Ok(Vec::new()),
+        };
+        self.read_offset += complete as u64;
+        self.lines += buffer[..complete].iter().filter(|&&b| b == b'\n').count();
+
+        Ok(String::from_utf8_lossy(&buffer[..complete])
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+
+    pub fn append(&mut self, entry: &HistoryEntry) -> HistoryResult<()> {
+        if let Some(parent) = self.path.parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        self.write_locked(&line, 1)
+    }
+
+    /// Append many entries under one lock, as when importing another shell's history
+    pub fn append_all(&mut self, entries: &[HistoryEntry]) -> HistoryResult<()> {
+        if let Some(parent) = self.path.parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+
+        let mut lines = String::new();
+        for entry in entries {
+            lines.push_str(&serde_json::to_string(entry)?);
+            lines.push('\n');
+        }
+        self.write_locked(&lines, entries.len())
+    }
+
+    /// Appends `lines` under an exclusive lock, compacting the file first
+    /// when it has grown past twice `max_entries`
+    fn write_locked(&mut self, lines: &str, count: usize) -> HistoryResult<()> {
+        let mut file = OpenOptions::new().create(true).read(true).append(true).open(&self.path)?;
+        file.lock()?;
+        let mut written = Ok(());
+        if self.lines + count > self.max_entries * 2 {
+            written = self.compact(&mut file);
+        }
+        let written = written.and_then(|_| file.write_all(lines.as_bytes()).and_then(|_| file.flush()));
+        file.unlock()?;
+        written?;
+        self.lines += count;
+        Ok(())
+    }
+
+    /// Rewrites the locked `file` with only its newest `max_entries` lines.
+    /// What this session had read stays read, unless unread lines were among
+    /// those dropped; then the rest is read again from the top.
+    fn compact(&mut self, file: &mut File) -> io::Result<()> {
+        let mut buffer = Vec::new();
+        file.seek(SeekFrom::Start(0))?;
+        file.read_to_end(&mut buffer)?;
+        // A line still being written by a crashed session is dropped too
+        let complete = buffer.iter().rposition(|&b| b == b'\n').map_or(0, |pos| pos + 1);
+        let line_starts: Vec<usize> = std::iter::once(0)
+            .chain(buffer[..complete].iter().enumerate().filter(|(_, &b)| b == b'\n').map(|(i, _)| i + 1))
+            .filter(|&start| start < complete)
+            .collect();
+        let keep_from = line_starts
+            .len()
+            .checked_sub(self.max_entries)
+            .map_or(0, |skip| line_starts[skip]);
+
+        file.set_len(0)?;
+        file.write_all(&buffer[keep_from..complete])?;
+        match self.read_offset.checked_sub(keep_from as u64) {
+            Some(offset) => self.read_offset = offset,
+            None => {
+                self.read_offset = 0;
+                self.rewritten = true;
+            }
+        }
+        self.lines = line_starts.len().min(self.max_entries);
+        Ok(())
+    }
+}
+
+/// History formats of other shells that can be imported
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShellHistoryFormat {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl ShellHistoryFormat {
+    /// Format for a shell path like `$SHELL`
+    pub fn from_shell(shell: &str) -> Option<Self> {
+        match Path::new(shell).file_name()?.to_str()? {
+            "bash" => Some(ShellHistoryFormat::Bash),
+            "zsh" => Some(ShellHistoryFormat::Zsh),
+            "fish" => Some(ShellHistoryFormat::Fish),
+            _ => None,
+        }
+    }
+
+    /// Default history file location for the shell, relative to `home`
+    pub fn default_path(&self, home: &Path) -> PathBuf {
+        match self {
+            ShellHistoryFormat::Bash => home.join(".bash_history"),
+            ShellHistoryFormat::Zsh => home.join(".zsh_history"),
+            ShellHistoryFormat::Fish => home.join(".local/share/fish/fish_history"),
+        }
+    }
+
+    fn session_id(&self) -> &'static str {
+        match self {
+            ShellHistoryFormat::Bash => "import:bash",
+            ShellHistoryFormat::Zsh => "import:zsh",
+            ShellHistoryFormat::Fish => "import:fish",
+        }
+    }
+}
+
+/// Parse another shell's history file into entries, oldest first
+pub fn import_shell_history<P: AsRef<Path>>(
+    path: P,
+    format: ShellHistoryFormat,
+) -> HistoryResult<Vec<HistoryEntry>> {
+    let bytes = fs::read(path)?;
+    Ok(match format {
+        ShellHistoryFormat::Bash => parse_bash_history(&String::from_utf8_lossy(&bytes)),
+        ShellHistoryFormat::Zsh => parse_zsh_history(&String::from_utf8_lossy(&unmetafy(&bytes))),
+        ShellHistoryFormat::Fish => parse_fish_history(&String::from_utf8_lossy(&bytes)),
+    })
+}
+
+/// Plain bash history, with the `#<epoch>` lines written when HISTTIMEFORMAT is set
+pub fn parse_bash_history(content: &str) -> Vec<HistoryEntry> {
+    let mut entries = Vec::new();
+    let mut timestamp = None;
+
+    for line in content.lines() {
+        if let Some(ts) = line.strip_prefix('#').and_then(|s| s.trim().parse::<u64>().ok()) {
+            timestamp = Some(ts);
+            continue;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let mut entry = HistoryEntry::new(line.to_string(), ShellHistoryFormat::Bash.session_id());
+        entry.timestamp = timestamp.take().unwrap_or(0);
+        entries.push(entry);
+    }
+
+    entries
+}
+
+/// zsh history, in both plain and EXTENDED_HISTORY (`: <start>:<elapsed>;<command>`) form
+pub fn parse_zsh_history(content: &str) -> Vec<HistoryEntry> {
+    let mut entries = Vec::new();
+    let mut lines = content.lines();
+
+    while let Some(first) = lines.next() {
+        // Multi-line commands are stored with a trailing backslash on each continued line
+        let mut raw = first.to_string();
+        while raw.ends_with('\\') {
+            raw.pop();
+            match lines.next() {
+                Some(next) => {
+                    raw.push('\n');
+                    raw.push_str(next);
+                }
+                None => break,
+            }
+        }
+
+        let (timestamp, duration_ms, command) = match parse_zsh_extended(&raw) {
+            Some((start, elapsed, command)) => (start, Some(elapsed * 1000), command.to_string()),
+            None => (0, None, raw),
+        };
+        if command.trim().is_empty() {
+            continue;
+        }
+
+        let mut entry = HistoryEntry::new(command, ShellHistoryFormat::Zsh.session_id());
+        entry.timestamp = timestamp;
+        entry.duration_ms = duration_ms;
+        entries.push(entry);
+    }
+
+    entries
+}
+
+fn parse_zsh_extended(line: &str) -> Option<(u64, u64, &str)> {
+    let rest = line.strip_prefix(": ")?;
+    let (meta, command) = rest.split_once(';')?;
+    let (start, elapsed) = meta.split_once(':')?;
+    Some((start.trim().parse().ok()?, elapsed.trim().parse().ok()?, command))
+}
+
+/// zsh escapes bytes >= 0x83 in its history file as 0x83 followed by the byte xor 32
+fn unmetafy(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut iter = bytes.iter();
+    while let Some(&b) = iter.next() {
+        if b == 0x83 {
+            if let Some(&next) = iter.next() {
+                out.push(next ^ 32);
+            }
+        } else {
+            out.push(b);
+        }
+    }
+    out
+}
+
+/// fish's pseudo-YAML history (`- cmd: ...` / `  when: ...`)
+pub fn parse_fish_history(content: &str) -> Vec<HistoryEntry> {
+    let mut entries: Vec<HistoryEntry> = Vec::new();
+
+    for line in content.lines() {
+        if let Some(cmd) = line.strip_prefix("- cmd: ") {
+            let mut entry = HistoryEntry::new(unescape_fish(cmd), ShellHistoryFormat::Fish.session_id());
+            entry.timestamp = 0;
+            entries.push(entry);
+        } else if let Some(when) = line.trim_start().strip_prefix("when: ") {
+            if let (Some(entry), Ok(ts)) = (entries.last_mut(), when.trim().parse()) {
+                entry.timestamp = ts;
+            }
+        }
+    }
+
+    entries
+}
+
+fn unescape_fish(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') => out.push('\n'),
+                Some('\\') => out.push('\\'),
+                Some(other) => {
+                    out.push('\\');
+                    out.push(other);
+                }
+                None => out.push('\\'),
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// History persistence errors
+#[derive(Error, Debug)]
+pub enum HistoryError {
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("JSON error: {0}")]
+    JsonError(#[from] serde_json::Error),
+}
+
+pub type HistoryResult<T> = Result<T, HistoryError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bash_with_timestamps() {
+        let entries = parse_bash_history("#1700000000\nls -la\ngit status\n");
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].timestamp, 1700000000);
+        assert_eq!(entries[1].command, "git status");
+        assert_eq!(entries[1].timestamp, 0);
+    }
+
+    #[test]
+    fn test_parse_zsh_extended() {
+        let content = ": 1700000000:3;cargo build\n: 1700000010:0;echo one \\\ntwo\nplain\n";
+        let entries = parse_zsh_history(content);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].command, "cargo build");
+        assert_eq!(entries[0].duration_ms, Some(3000));
+        assert_eq!(entries[1].command, "echo one \ntwo");
+        assert_eq!(entries[2].timestamp, 0);
+    }
+
+    #[test]
+    fn test_parse_fish() {
+        let content = "- cmd: echo a\\nb\n  when: 1700000000\n  paths:\n    - foo\n- cmd: ls\n  when: 1700000005\n";
+        let entries = parse_fish_history(content);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].command, "echo a\nb");
+        assert_eq!(entries[1].timestamp, 1700000005);
+    }
+
+    #[test]
+    fn test_unmetafy() {
+        assert_eq!(unmetafy(&[b'a', 0x83, 0xa3, b'b']), vec![b'a', 0x83, b'b']);
+    }
+}
@@ -0,0 +1,381 @@
+use super::command_history::{unix_now, CommandHistory, HistoryEntry};
+use crate::fuzzy::{fuzzy_match, rank, Frecency};
+use std::collections::HashMap;
+
+/// Incremental ctrl-r search backwards through history, as in readline
+#[derive(Debug, Clone)]
+pub struct ReverseSearch {
+    query: String,
+    current: Option<usize>,
+    failed: bool,
+    original_input: String,
+}
+
+impl ReverseSearch {
+    pub fn new(original_input: impl Into<String>) -> Self {
+        Self {
+            query: String::new(),
+            current: None,
+            failed: false,
+            original_input: original_input.into(),
+        }
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn original_input(&self) -> &str {
+        &self.original_input
+    }
+
+    pub fn is_failed(&self) -> bool {
+        self.failed
+    }
+
+    pub fn push_char(&mut self, ch: char, history: &CommandHistory) {
+        self.query.push(ch);
+        // Extending the query keeps the current match if it still matches
+        let start = self.current.map(|idx| idx + 1).unwrap_or(history.len());
+        self.search_from(start, history, None);
+    }
+
+    pub fn backspace(&mut self, history: &CommandHistory) {
+        self.query.pop();
+        self.current = None;
+        self.search_from(history.len(), history, None);
+    }
+
+    /// Move to the next older match (ctrl-r pressed again)
+    pub fn search_older(&mut self, history: &CommandHistory) {
+        let start = self.current.unwrap_or(history.len());
+        let skip = self.current_match(history).map(|s| s.to_string());
+        self.search_from(start, history, skip.as_deref());
+    }
+
+    fn search_from(&mut self, start: usize, history: &CommandHistory, skip: Option<&str>) {
+        if self.query.is_empty() {
+            self.failed = false;
+            return;
+        }
+
+        let found = (0..start.min(history.len())).rev().find(|&idx| {
+            history
+                .get(idx)
+                .map(|e| e.command.contains(&self.query) && Some(e.command.as_str()) != skip)
+                .unwrap_or(false)
+        });
+
+        match found {
+            Some(idx) => {
+                self.current = Some(idx);
+                self.failed = false;
+            }
+            None => self.failed = true,
+        }
+    }
+
+    pub fn current_match<'a>(&self, history: &'a CommandHistory) -> Option<&'a str> {
+        self.current
+            .and_then(|idx| history.get(idx))
+            .map(|e| e.command.as_str())
+    }
+
+    pub fn render(&self, history: &CommandHistory) -> String {
+        let label = if self.failed {
+            "failed reverse-i-search"
+        } else {
+            "reverse-i-search"
+        };
+        format!(
+            "({})`{}': {}",
+            label,
+            self.query,
+            self.current_match(history).unwrap_or("")
+        )
+    }
+}
+
+/// Which part of history the picker shows
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PickerScope {
+    All,
+    CurrentDirectory,
+    CurrentSession,
+}
+
+/// What to do with the command chosen in the picker
+#[derive(Debug, Clone, PartialEq)]
+pub enum PickerAction {
+    Insert(String),
+    Run(String),
+}
+
+/// A ranked picker row: the most recent entry for a command plus its usage
+#[derive(Debug, Clone)]
+pub struct PickerMatch {
+    pub entry: HistoryEntry,
+    pub uses: usize,
+    pub score: f64,
+    pub positions: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+struct Candidate {
+    entry: HistoryEntry,
+    uses: usize,
+}
+
+/// Full-screen fuzzy history picker ranked by match quality and frecency
+#[derive(Debug, Clone)]
+pub struct HistoryPicker {
+    query: String,
+    scope: PickerScope,
+    selected: usize,
+    cwd: Option<String>,
+    session_id: String,
+    entries: Vec<HistoryEntry>,
+    results: Vec<PickerMatch>,
+}
+
+impl HistoryPicker {
+    pub fn open(history: &CommandHistory, cwd: Option<String>) -> Self {
+        let mut picker = Self {
+            query: String::new(),
+            scope: PickerScope::All,
+            selected: 0,
+            cwd,
+            session_id: history.session_id().to_string(),
+            entries: history.entries().cloned().collect(),
+            results: Vec::new(),
+        };
+        picker.refresh();
+        picker
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn scope(&self) -> PickerScope {
+        self.scope
+    }
+
+    pub fn set_query(&mut self, query: impl Into<String>) {
+        self.query = query.into();
+        self.refresh();
+    }
+
+    pub fn push_char(&mut self, ch: char) {
+        self.query.push(ch);
+        self.refresh();
+    }
+
+    pub fn backspace(&mut self) {
+        self.query.pop();
+        self.refresh();
+    }
+
+    pub fn set_scope(&mut self, scope: PickerScope) {
+        self.scope = scope;
+        self.refresh();
+    }
+
+    /// Cycle All -> CurrentDirectory -> CurrentSession
+    pub fn cycle_scope(&mut self) {
+        let next = match self.scope {
+            PickerScope::All => PickerScope::CurrentDirectory,
+            PickerScope::CurrentDirectory => PickerScope::CurrentSession,
+            PickerScope::CurrentSession => PickerScope::All,
+        };
+        self.set_scope(next);
+    }
+
+    pub fn select_next(&mut self) {
+        if self.selected + 1 < self.results.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn select_prev(&mut self) {
+        if self.selected > 0 {
+            self.selected -= 1;
+        }
+    }
+
+    pub fn results(&self) -> &[PickerMatch] {
+        &self.results
+    }
+
+    pub fn selected(&self) -> Option<&PickerMatch> {
+        self.results.get(self.selected)
+    }
+
+    pub fn accept(&self, run: bool) -> Option<PickerAction> {
+        self.selected().map(|m| {
+            let command = m.entry.command.clone();
+            if run {
+                PickerAction::Run(command)
+            } else {
+                PickerAction::Insert(command)
+            }
+        })
+    }
+
+    /// Details of the selected entry for the preview pane
+    pub fn preview(&self) -> Vec<String> {
+        let m = match self.selected() {
+            Some(m) => m,
+            None => return Vec::new(),
+        };
+
+        let mut lines: Vec<String> = m.entry.command.lines().map(|l| l.to_string()).collect();
+        lines.push(String::new());
+        if let Some(cwd) = &m.entry.cwd {
+            lines.push(format!("Directory: {}", cwd));
+        }
+        if let Some(code) = m.entry.exit_status {
+            lines.push(format!("Exit status: {}", code));
+        }
+        if let Some(ms) = m.entry.duration_ms {
+            lines.push(format!("Duration: {}ms", ms));
+        }
+        if m.entry.timestamp > 0 {
+            lines.push(format!("Last run: {}", format_age(unix_now().saturating_sub(m.entry.timestamp))));
+        }
+        lines.push(format!("Runs: {}", m.uses));
+        lines
+    }
+
+    pub fn render(&self, width: usize, height: usize) -> Vec<String> {
+        let scope = match self.scope {
+            PickerScope::All => "all",
+            PickerScope::CurrentDirectory => "directory",
+            PickerScope::CurrentSession => "session",
+        };
+        let mut lines = vec![format!("history [{}]> {}", scope, self.query)];
+
+        let visible = height.saturating_sub(1);
+        let first = self.selected.saturating_sub(visible.saturating_sub(1));
+        for (i, m) in self.results.iter().enumerate().skip(first).take(visible) {
+            let marker = if i == self.selected { "▸" } else { " " };
+            let command: String = m.entry.command.replace('\n', " ⏎ ").chars().take(width.saturating_sub(2)).collect();
+            lines.push(format!("{} {}", marker, command));
+        }
+        lines
+    }
+
+    fn refresh(&mut self) {
+        let now = unix_now();
+        let mut frecency = Frecency::new();
+        for entry in self.entries.iter().filter(|e| self.in_scope(e)) {
+            frecency.record(&entry.command, entry.timestamp, entry.exit_status);
+        }
+        let mut results: Vec<PickerMatch> = self
+            .candidates()
+            .into_iter()
+            .filter_map(|c| {
+                let m = fuzzy_match(&self.query, &c.entry.command)?;
+                Some(PickerMatch {
+                    score: rank(m.score, 0.0, frecency.command_score(&c.entry.command, now)),
+                    entry: c.entry,
+                    uses: c.uses,
+                    positions: m.positions,
+                })
+            })
+            .collect();
+
+        results.sort_by(|a, b| {
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(b.entry.timestamp.cmp(&a.entry.timestamp))
+        });
+        self.results = results;
+        self.selected = 0;
+    }
+
+    fn candidates(&self) -> Vec<Candidate> {
+        let mut by_command: HashMap<&str, Candidate> = HashMap::new();
+        for entry in self.entries.iter().filter(|e| self.in_scope(e)) {
+            let candidate = by_command.entry(entry.command.as_str()).or_insert_with(|| Candidate {
+                entry: entry.clone(),
+                uses: 0,
+            });
+            candidate.uses += 1;
+            if entry.timestamp >= candidate.entry.timestamp {
+                candidate.entry = entry.clone();
+            }
+        }
+        by_command.into_values().collect()
+    }
+
+    fn in_scope(&self, entry: &HistoryEntry) -> bool {
+        match self.scope {
+            PickerScope::All => true,
+            PickerScope::CurrentDirectory => self.cwd.is_some() && entry.cwd == self.cwd,
+            PickerScope::CurrentSession => entry.session_id == self.session_id,
+        }
+    }
+}
+
+fn format_age(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{}s ago", secs),
+        60..=3_599 => format!("{}m ago", secs / 60),
+        3_600..=86_399 => format!("{}h ago", secs / 3_600),
+        _ => format!("{}d ago", secs / 86_400),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(commands: &[&str]) -> CommandHistory {
+        let mut history = CommandHistory::new(100);
+        for cmd in commands {
+            history.add(cmd.to_string());
+        }
+        history
+    }
+
+    #[test]
+    fn test_reverse_search_walks_older_matches() {
+        let history = history(&["git status", "ls", "git commit", "git status"]);
+        let mut search = ReverseSearch::new("");
+        for ch in "git".chars() {
+            search.push_char(ch, &history);
+        }
+        assert_eq!(search.current_match(&history), Some("git status"));
+
+        search.search_older(&history);
+        assert_eq!(search.current_match(&history), Some("git commit"));
+
+        search.push_char('x', &history);
+        assert!(search.is_failed());
+        assert!(search.render(&history).starts_with("(failed reverse-i-search)"));
+    }
+
+    #[test]
+    fn test_picker_ranks_frequent_commands_first() {
+        let history = history(&["cargo test", "cargo build", "cargo test --all", "cargo build", "ls", "cargo build"]);
+        let mut picker = HistoryPicker::open(&history, None);
+        picker.set_query("cb");
+        assert_eq!(picker.selected().unwrap().entry.command, "cargo build");
+        assert_eq!(picker.selected().unwrap().uses, 3);
+        assert_eq!(picker.accept(true), Some(PickerAction::Run("cargo build".to_string())));
+    }
+
+    #[test]
+    fn test_picker_directory_scope() {
+        let mut history = CommandHistory::new(100);
+        history.add_entry(HistoryEntry::new("make".to_string(), "s").with_cwd("/src")).unwrap();
+        history.add_entry(HistoryEntry::new("npm test".to_string(), "s").with_cwd("/web")).unwrap();
+
+        let mut picker = HistoryPicker::open(&history, Some("/web".to_string()));
+        picker.set_scope(PickerScope::CurrentDirectory);
+        assert_eq!(picker.results().len(), 1);
+        assert_eq!(picker.results()[0].entry.command, "npm test");
+    }
+}
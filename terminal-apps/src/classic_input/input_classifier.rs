@@ -0,0 +1,426 @@
+use crate::universal_input::command_resolver::CommandResolver;
+use std::collections::HashSet;
+use std::path::Path;
+
+pub(crate) const SHELL_BUILTINS: &[&str] = &[
+    ".", ":", "[", "alias", "bg", "builtin", "cd", "command", "declare", "dirs", "echo", "eval",
+    "exec", "exit", "export", "false", "fg", "hash", "history", "jobs", "kill", "let", "local",
+    "popd", "printf", "pushd", "pwd", "read", "return", "set", "shift", "source", "test", "time",
+    "trap", "true", "type", "ulimit", "umask", "unalias", "unset", "wait",
+];
+
+const RESERVED_WORDS: &[&str] = &["if", "for", "while", "until", "case", "select", "function", "{", "[["];
+
+const QUESTION_WORDS: &[&str] = &[
+    "what", "how", "why", "where", "when", "who", "which", "can", "could", "would", "should",
+    "is", "are", "does", "did", "will",
+];
+
+/// Words common in English sentences and rare as shell arguments
+const FUNCTION_WORDS: &[&str] = &[
+    "the", "a", "an", "to", "of", "in", "on", "for", "with", "my", "me", "i", "you", "your",
+    "that", "this", "these", "those", "all", "is", "are", "from", "into", "by", "and", "it",
+    "its", "there", "them", "which", "what", "some", "any", "every", "than", "then", "so",
+    "why", "how", "do", "does", "not", "be", "been", "was", "were", "have", "has", "we", "our",
+];
+
+const POLITE_WORDS: &[&str] = &["please", "thanks", "thank", "pls", "plz"];
+
+/// Below this probability of being a shell command, input is natural language
+const NATURAL_LANGUAGE_THRESHOLD: f32 = 0.5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShellToken {
+    Word(String),
+    Operator(String),
+}
+
+/// Input split with shell quoting rules
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tokenized {
+    pub tokens: Vec<ShellToken>,
+    /// A quote was opened and never closed, as with English contractions
+    pub unterminated_quote: bool,
+    /// `$VAR`, `${..}`, `$(..)` or backticks appeared outside single quotes
+    pub has_expansion: bool,
+}
+
+impl Tokenized {
+    pub fn words(&self) -> impl Iterator<Item = &str> {
+        self.tokens.iter().filter_map(|t| match t {
+            ShellToken::Word(w) => Some(w.as_str()),
+            ShellToken::Operator(_) => None,
+        })
+    }
+
+    pub fn has_operator(&self) -> bool {
+        self.tokens.iter().any(|t| matches!(t, ShellToken::Operator(_)))
+    }
+}
+
+pub fn tokenize(input: &str) -> Tokenized {
+    let mut result = Tokenized::default();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = input.chars().peekable();
+
+    let flush = |word: &mut String, in_word: &mut bool, tokens: &mut Vec<ShellToken>| {
+        if *in_word {
+            tokens.push(ShellToken::Word(std::mem::take(word)));
+            *in_word = false;
+        }
+    };
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => flush(&mut word, &mut in_word, &mut result.tokens),
+            '\\' => {
+                in_word = true;
+                if let Some(next) = chars.next() {
+                    word.push(next);
+                }
+            }
+            '\'' => {
+                in_word = true;
+                let mut closed = false;
+                for next in chars.by_ref() {
+                    if next == '\'' {
+                        closed = true;
+                        break;
+                    }
+                    word.push(next);
+                }
+                result.unterminated_quote |= !closed;
+            }
+            '"' => {
+                in_word = true;
+                let mut closed = false;
+                while let Some(next) = chars.next() {
+                    match next {
+                        '"' => {
+                            closed = true;
+                            break;
+                        }
+                        '\\' => word.extend(chars.next()),
+                        '$' | '`' => {
+                            result.has_expansion = true;
+                            word.push(next);
+                        }
+                        _ => word.push(next),
+                    }
+                }
+                result.unterminated_quote |= !closed;
+            }
+            '$' if chars.peek().is_some_and(|n| n.is_alphanumeric() || "_{(?".contains(*n)) => {
+                result.has_expansion = true;
+                in_word = true;
+                word.push(c);
+            }
+            '`' => {
+                result.has_expansion = true;
+                in_word = true;
+                word.push(c);
+            }
+            '|' | '&' | ';' | '<' | '>' | '(' | ')' => {
+                flush(&mut word, &mut in_word, &mut result.tokens);
+                let mut op = c.to_string();
+                if let Some(&next) = chars.peek() {
+                    if (next == c && "|&<>".contains(c)) || (c == '>' && next == '&') {
+                        op.push(next);
+                        chars.next();
+                    }
+                }
+                result.tokens.push(ShellToken::Operator(op));
+            }
+            _ => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    flush(&mut word, &mut in_word, &mut result.tokens);
+    result
+}
+
+/// Names that run something when typed first on a line
+#[derive(Debug, Clone, Default)]
+pub struct CommandLookup {
+    pub executables: HashSet<String>,
+    pub aliases: HashSet<String>,
+    pub functions: HashSet<String>,
+}
+
+impl CommandLookup {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Executables from every directory on `$PATH`, as listed by the
+    /// highlighter's `CommandResolver`
+    pub fn from_env() -> Self {
+        CommandResolver::from_env().lookup()
+    }
+
+    pub fn add_directory(&mut self, dir: &Path) {
+        self.executables.extend(read_executables(dir));
+    }
+
+    pub fn with_commands<'a>(mut self, names: impl IntoIterator<Item = &'a str>) -> Self {
+        self.executables.extend(names.into_iter().map(str::to_string));
+        self
+    }
+
+    pub fn add_alias(&mut self, name: impl Into<String>) {
+        self.aliases.insert(name.into());
+    }
+
+    pub fn add_function(&mut self, name: impl Into<String>) {
+        self.functions.insert(name.into());
+    }
+
+    /// Every name that resolves, builtins included
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        SHELL_BUILTINS
+            .iter()
+            .copied()
+            .chain(self.aliases.iter().map(String::as_str))
+            .chain(self.functions.iter().map(String::as_str))
+            .chain(self.executables.iter().map(String::as_str))
+    }
+
+    pub fn resolves(&self, name: &str) -> bool {
+        SHELL_BUILTINS.contains(&name)
+            || RESERVED_WORDS.contains(&name)
+            || self.aliases.contains(name)
+            || self.functions.contains(name)
+            || self.executables.contains(name)
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Names of the executables directly in `dir`
+pub(crate) fn read_executables(dir: &Path) -> HashSet<String> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return HashSet::new();
+    };
+    entries
+        .flatten()
+        .filter(|e| is_executable(&e.path()))
+        .filter_map(|e| e.file_name().to_str().map(str::to_string))
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputKind {
+    Shell,
+    NaturalLanguage,
+}
+
+#[derive(Debug, Clone)]
+pub struct Classification {
+    pub kind: InputKind,
+    /// Estimated probability that the input is a shell command
+    pub shell_probability: f32,
+    /// The features that fired and their weights, positive towards shell
+    pub features: Vec<(&'static str, f32)>,
+}
+
+/// Scores grammar features of a line of input to decide whether it is a
+/// shell command or a request in natural language
+#[derive(Debug, Clone, Default)]
+pub struct InputClassifier {
+    pub lookup: CommandLookup,
+}
+
+impl InputClassifier {
+    pub fn new(lookup: CommandLookup) -> Self {
+        Self { lookup }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(CommandLookup::from_env())
+    }
+
+    pub fn classify(&self, input: &str) -> Classification {
+        let features = self.features(input);
+        let score: f32 = features.iter().map(|(_, w)| w).sum();
+        let shell_probability = 1.0 / (1.0 + (-score).exp());
+        let kind = if shell_probability < NATURAL_LANGUAGE_THRESHOLD {
+            InputKind::NaturalLanguage
+        } else {
+            InputKind::Shell
+        };
+        Classification {
+            kind,
+            shell_probability,
+            features,
+        }
+    }
+
+    fn features(&self, input: &str) -> Vec<(&'static str, f32)> {
+        let mut features = vec![("bias", 0.5)];
+        let tokenized = tokenize(input.trim());
+        let words: Vec<&str> = tokenized.words().collect();
+        let Some(&first) = words.first() else {
+            return features;
+        };
+        let lower: Vec<String> = words.iter().map(|w| w.to_lowercase()).collect();
+
+        // First word: a command, a path to one, or an assignment
+        if first.contains('=') && !first.starts_with('=') {
+            features.push(("assignment", 2.0));
+        } else if first.starts_with("./") || first.starts_with('/') || first.starts_with("~/") {
+            features.push(("path_command", 3.0));
+        } else if self.lookup.resolves(first) {
+            features.push(("resolves", 3.0));
+        } else {
+            features.push(("unknown_command", -2.5));
+            // `do_thing`, `build-all`, `py3`: names rather than English words
+            if first.contains(|c: char| c == '_' || c == '-' || c.is_ascii_digit()) {
+                features.push(("identifier", 1.5));
+            }
+        }
+        if words.len() == 1 && !tokenized.unterminated_quote {
+            // A lone word is more likely a mistyped command than a request
+            features.push(("single_word", 2.0));
+        }
+
+        if tokenized.has_operator() {
+            features.push(("operators", 2.0));
+        }
+        if tokenized.has_expansion {
+            features.push(("expansion", 1.5));
+        }
+        let flags = words[1..]
+            .iter()
+            .filter(|w| w.len() > 1 && w.starts_with('-') && !w[1..].starts_with(|c: char| c.is_ascii_digit()))
+            .count();
+        if flags > 0 {
+            features.push(("flags", 1.5 * flags.min(3) as f32));
+        }
+        if words[1..].iter().any(|w| looks_like_path(w)) {
+            features.push(("path_argument", 1.0));
+        }
+
+        // Sentence structure
+        if input.trim_end().ends_with('?') {
+            features.push(("question_mark", -3.5));
+        }
+        if QUESTION_WORDS.contains(&lower[0].as_str()) {
+            features.push(("question_word", -2.5));
+        }
+        if tokenized.unterminated_quote {
+            features.push(("contraction", -2.0));
+        }
+        let function_words = lower[1..].iter().filter(|w| FUNCTION_WORDS.contains(&w.as_str())).count();
+        if function_words > 0 {
+            features.push(("function_words", -1.5 * function_words.min(4) as f32));
+        }
+        if lower.iter().any(|w| POLITE_WORDS.contains(&w.trim_matches(|c: char| !c.is_alphanumeric()))) {
+            features.push(("polite", -2.5));
+        }
+        let plain_words = words.iter().filter(|w| w.chars().all(|c| c.is_alphabetic())).count();
+        if words.len() >= 4 && plain_words * 4 >= words.len() * 3 && flags == 0 {
+            features.push(("prose_like", -1.5));
+        }
+        if first.chars().next().is_some_and(|c| c.is_uppercase()) && !self.lookup.resolves(first) {
+            features.push(("capitalised", -1.0));
+        }
+        features
+    }
+}
+
+fn looks_like_path(word: &str) -> bool {
+    word.contains('/') || word.starts_with('~') || word.starts_with('.') || word.contains('*')
+        || word.rsplit_once('.').is_some_and(|(stem, ext)| {
+            !stem.is_empty() && (1..=4).contains(&ext.len()) && ext.chars().all(|c| c.is_ascii_alphanumeric())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CORPUS: &str = include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/input_classifier_corpus.tsv"
+    ));
+
+    fn classifier() -> InputClassifier {
+        let mut lookup = CommandLookup::new().with_commands([
+            "ls", "git", "cargo", "docker", "grep", "find", "cat", "make", "npm", "kubectl",
+            "python3", "ssh", "curl", "tar", "rm", "mkdir", "chmod", "vim", "top", "ps", "du",
+            "df", "head", "tail", "sort", "awk", "sed", "which", "man", "brew", "sudo", "tree",
+            "wc", "node", "go", "rg", "htop", "scp", "ping", "open", "code", "less", "touch", "cp",
+            "mv", "xargs", "jq", "tmux", "systemctl", "journalctl", "apt", "pip",
+        ]);
+        lookup.add_alias("ll");
+        lookup.add_function("mkcd");
+        InputClassifier::new(lookup)
+    }
+
+    #[test]
+    fn test_tokenize_with_shell_rules() {
+        let tokenized = tokenize(r#"grep "a b" 'c$d' e\ f|wc -l 2>&1"#);
+        assert_eq!(
+            tokenized.tokens,
+            vec![
+                ShellToken::Word("grep".into()),
+                ShellToken::Word("a b".into()),
+                ShellToken::Word("c$d".into()),
+                ShellToken::Word("e f".into()),
+                ShellToken::Operator("|".into()),
+                ShellToken::Word("wc".into()),
+                ShellToken::Word("-l".into()),
+                ShellToken::Word("2".into()),
+                ShellToken::Operator(">&".into()),
+                ShellToken::Word("1".into()),
+            ]
+        );
+        assert!(!tokenized.has_expansion);
+        assert!(tokenize("what's in $HOME").unterminated_quote);
+        assert!(tokenize("echo \"$HOME\"").has_expansion);
+    }
+
+    #[test]
+    fn test_keywords_inside_commands_stay_shell() {
+        let classifier = classifier();
+        for input in ["cargo test", "docker run -it ubuntu", "make build", "do_thing --fast"] {
+            assert_eq!(classifier.classify(input).kind, InputKind::Shell, "{}", input);
+        }
+        let explain = classifier.classify("explain what abcdef means");
+        assert_eq!(explain.kind, InputKind::NaturalLanguage);
+    }
+
+    #[test]
+    fn test_corpus_accuracy() {
+        let classifier = classifier();
+        let mut total = 0;
+        let mut misses = Vec::new();
+        for line in CORPUS.lines().filter(|l| !l.trim().is_empty() && !l.starts_with('#')) {
+            let (label, input) = line.split_once('\t').expect("label<TAB>input");
+            let expected = match label {
+                "shell" => InputKind::Shell,
+                "nl" => InputKind::NaturalLanguage,
+                other => panic!("unknown label {}", other),
+            };
+            total += 1;
+            if classifier.classify(input).kind != expected {
+                misses.push(input);
+            }
+        }
+        let accuracy = 1.0 - misses.len() as f32 / total as f32;
+        assert!(total >= 100, "corpus has {} examples", total);
+        assert!(accuracy >= 0.95, "accuracy {:.3}, misclassified: {:?}", accuracy, misses);
+    }
+}
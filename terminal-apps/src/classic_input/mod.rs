@@ -1,17 +1,60 @@
+pub mod agent_backend;
 pub mod agent_mode;
+pub mod agent_plan;
+pub mod autosuggest;
 pub mod command_history;
+pub mod conversation;
 pub mod editor;
+pub mod history_file;
+pub mod history_search;
+pub mod input_classifier;
 pub mod prompt;
+pub mod prompt_segments;
+pub mod prompt_template;
 pub mod text_selection;
 
-pub use agent_mode::{AgentMode, AgentRequest, AgentResponse, AgentState};
-pub use command_history::CommandHistory;
+pub use agent_backend::{
+    AgentBackend, AgentError, AgentResult, MockAgentServer, OllamaBackend, OpenAiBackend,
+};
+pub use agent_mode::{AgentContext, AgentMode, AgentRequest, AgentResponse, AgentState};
+pub use agent_plan::{
+    Approval, ExecutionPlan, PermissionPolicy, PlanRunner, PlanStep, RiskLevel, StepStatus,
+};
+pub use autosuggest::{Autosuggester, Autosuggestion, SuggestionSource};
+pub use command_history::{CommandHistory, HistoryEntry, HistoryOptions};
+pub use conversation::{
+    ContextItem, Conversation, ConversationError, ConversationResult, ConversationStore,
+    ConversationSummary, Message, Role, TrimStrategy,
+};
 pub use editor::ClassicEditor;
-pub use prompt::Prompt;
+pub use history_file::{HistoryError, HistoryFile, HistoryResult, ShellHistoryFormat};
+pub use history_search::{HistoryPicker, PickerAction, PickerMatch, PickerScope, ReverseSearch};
+pub use input_classifier::{Classification, CommandLookup, InputClassifier, InputKind};
+pub use prompt::{Prompt, PromptStyle};
+pub use prompt_segments::{SegmentProvider, SegmentRunner, SegmentState};
+pub use prompt_template::{PromptDialect, PromptSegment};
 pub use text_selection::{Selection, SelectionMode, TextSelection};
 
+use crate::app::TerminalApp;
+use crate::blocks::Block;
+use crate::clipboard::{self, Clipboard, ClipboardResult};
+use crate::fixer::{CommandFixer, Fix};
+use crate::modern_editing::AliasExpander;
+use prompt_template::segments_to_ansi;
+use crate::universal_input::SpecCompleter;
 use std::collections::VecDeque;
 
+/// Dim grey for autosuggestion ghost text
+const GHOST_TEXT_STYLE: &str = "\x1b[2;38;5;244m";
+
+/// What happened to pasted text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PasteOutcome {
+    Inserted,
+    /// Multi-line text is held until `confirm_paste` or `cancel_paste`
+    NeedsConfirmation { lines: usize },
+}
+
 #[derive(Debug, Clone)]
 pub struct ClassicInput {
     pub editor: ClassicEditor,
@@ -20,9 +63,21 @@ pub struct ClassicInput {
     pub agent_mode: AgentMode,
     pub selection: TextSelection,
     pub input_hints_enabled: bool,
+    pub reverse_search: Option<ReverseSearch>,
+    pub history_picker: Option<HistoryPicker>,
+    pub prompt_segments: SegmentRunner,
+    pub fixer: CommandFixer,
+    pub autosuggester: Autosuggester,
+    pub aliases: AliasExpander,
+    history_draft: Option<String>,
+    pending_paste: Option<String>,
+    pending_fix: Option<Fix>,
 }
 
 impl ClassicInput {
+    /// An input that reads nothing from the environment: empty in-memory
+    /// history, no aliases, no completion specs and only builtins known as
+    /// commands. A terminal wants `from_env`.
     pub fn new() -> Self {
         Self {
             editor: ClassicEditor::new(),
@@ -31,7 +86,59 @@ impl ClassicInput {
             agent_mode: AgentMode::new(),
             selection: TextSelection::new(),
             input_hints_enabled: true,
+            reverse_search: None,
+            history_picker: None,
+            prompt_segments: SegmentRunner::with_default_providers(),
+            fixer: CommandFixer::with_default_rules(CommandLookup::new()),
+            autosuggester: Autosuggester::new(),
+            aliases: AliasExpander::new(),
+            history_draft: None,
+            pending_paste: None,
+            pending_fix: None,
+        }
+    }
+
+    /// An input set up from the user's environment: the default history file,
+    /// aliases from rc files, the built-in completion specs and `$PATH`,
+    /// scanned once and shared by agent mode and the fixer. Starts computing
+    /// the prompt segments for the current directory.
+    pub fn from_env() -> Self {
+        let aliases = AliasExpander::from_env();
+        let mut lookup = CommandLookup::from_env();
+        for (name, _) in aliases.aliases().chain(aliases.abbreviations()) {
+            lookup.add_alias(name);
+        }
+
+        let mut input = Self::new().with_command_lookup(lookup);
+        input.history = CommandHistory::open_default(1000);
+        input.autosuggester = Autosuggester::with_specs(SpecCompleter::from_env());
+        input.aliases = aliases;
+        input.refresh_prompt_segments();
+        input
+    }
+
+    /// Resolves command names against `lookup`, both when telling commands
+    /// from natural language and when suggesting fixes
+    pub fn with_command_lookup(mut self, lookup: CommandLookup) -> Self {
+        self.fixer = CommandFixer::with_default_rules(lookup.clone());
+        self.agent_mode.classifier_mut().lookup = lookup;
+        self
+    }
+
+    /// Applies the app's settings; for now the `history.*` options
+    pub fn apply_config(&mut self, app: &TerminalApp) {
+        self.history.set_options(HistoryOptions::from_config(app));
+    }
+
+    /// Uses `aliases` for expansion and abbreviations, and tells agent mode
+    /// their names are commands. Pass the same set to
+    /// `UniversalInput::with_aliases` rather than loading it twice.
+    pub fn set_aliases(&mut self, aliases: AliasExpander) {
+        let lookup = &mut self.agent_mode.classifier_mut().lookup;
+        for (name, _) in aliases.aliases().chain(aliases.abbreviations()) {
+            lookup.add_alias(name);
         }
+        self.aliases = aliases;
     }
 
     pub fn enable_input_hints(&mut self) {
@@ -59,37 +166,338 @@ impl ClassicInput {
     }
 
     pub fn handle_input(&mut self, ch: char) {
+        if let Some(picker) = &mut self.history_picker {
+            picker.push_char(ch);
+            return;
+        }
+        if let Some(search) = &mut self.reverse_search {
+            search.push_char(ch, &self.history);
+            return;
+        }
+        self.pending_fix = None;
+        self.stop_history_navigation();
+        if ch == ' ' {
+            self.expand_abbreviation();
+        }
         self.editor.insert_char(ch);
         self.agent_mode.check_natural_language(self.editor.current_input());
+        self.update_autosuggestion();
     }
 
     pub fn handle_backspace(&mut self) {
+        if let Some(picker) = &mut self.history_picker {
+            picker.backspace();
+            return;
+        }
+        if let Some(search) = &mut self.reverse_search {
+            search.backspace(&self.history);
+            return;
+        }
+        self.stop_history_navigation();
         self.editor.backspace();
+        self.update_autosuggestion();
+    }
+
+    /// Expands a fish-style abbreviation just before the cursor
+    fn expand_abbreviation(&mut self) {
+        if self.agent_mode.is_active() {
+            return;
+        }
+        let input = self.editor.current_input();
+        if let Some((expanded, cursor)) = self.aliases.expand_abbreviation(input, self.editor.cursor_position()) {
+            self.editor.set_input(expanded);
+            self.editor.set_cursor_position(cursor);
+        }
+    }
+
+    /// Editing a recalled entry makes it the new draft, so Up/Down start over
+    /// from what is on the line rather than the prefix typed before recall.
+    fn stop_history_navigation(&mut self) {
+        self.history.reset_index();
+        self.history_draft = None;
+    }
+
+    fn update_autosuggestion(&mut self) {
+        self.autosuggester
+            .update(self.editor.current_input(), &self.prompt.current_dir, &self.history);
+    }
+
+    /// Ghost text to draw dimmed after the cursor. Only shown with the cursor
+    /// at the end of the line, where accepting it makes sense.
+    pub fn autosuggestion(&self) -> Option<&str> {
+        let input = self.editor.current_input();
+        if self.editor.cursor_position() < input.len() {
+            return None;
+        }
+        self.autosuggester.ghost_text(input)
     }
 
+    /// Collect a suggestion computed in the background. Returns true when the
+    /// input line should be redrawn.
+    pub fn poll_autosuggestion(&mut self) -> bool {
+        self.autosuggester.poll()
+    }
+
+    /// Take the whole suggestion into the editor. Returns false when there was none.
+    pub fn accept_autosuggestion(&mut self) -> bool {
+        let Some(ghost) = self.autosuggestion().map(str::to_string) else {
+            return false;
+        };
+        self.editor.insert_str(&ghost);
+        self.update_autosuggestion();
+        true
+    }
+
+    /// Take the next word of the suggestion into the editor
+    pub fn accept_autosuggestion_word(&mut self) -> bool {
+        let input = self.editor.current_input();
+        if self.editor.cursor_position() < input.len() {
+            return false;
+        }
+        let Some(word) = self.autosuggester.current().and_then(|s| s.next_word(input)).map(str::to_string) else {
+            return false;
+        };
+        self.editor.insert_str(&word);
+        self.update_autosuggestion();
+        true
+    }
+
+    /// Right arrow: accept the suggestion at the end of the line, otherwise move
+    pub fn handle_right_arrow(&mut self) {
+        if !self.accept_autosuggestion() {
+            self.editor.move_cursor_right();
+        }
+    }
+
+    /// End: accept the suggestion, or move to the end of the line
+    pub fn handle_end(&mut self) {
+        if !self.accept_autosuggestion() {
+            self.editor.move_cursor_end();
+        }
+    }
+
+    /// Alt-right: accept one word of the suggestion
+    pub fn handle_alt_right(&mut self) {
+        self.accept_autosuggestion_word();
+    }
+
+    /// Insert pasted text at the cursor. Text spanning several lines is held
+    /// back for confirmation, since submitting it would run every line.
+    pub fn handle_paste(&mut self, text: &str) -> PasteOutcome {
+        let text = clipboard::sanitize_paste(text);
+        if clipboard::needs_paste_confirmation(&text) {
+            let lines = text.trim_end_matches('\n').lines().count();
+            self.pending_paste = Some(text);
+            return PasteOutcome::NeedsConfirmation { lines };
+        }
+        self.stop_history_navigation();
+        self.editor.insert_str(text.trim_end_matches('\n'));
+        self.update_autosuggestion();
+        PasteOutcome::Inserted
+    }
+
+    pub fn pending_paste(&self) -> Option<&str> {
+        self.pending_paste.as_deref()
+    }
+
+    pub fn confirm_paste(&mut self) {
+        if let Some(text) = self.pending_paste.take() {
+            self.stop_history_navigation();
+            self.editor.insert_str(text.trim_end_matches('\n'));
+            self.update_autosuggestion();
+        }
+    }
+
+    pub fn cancel_paste(&mut self) {
+        self.pending_paste = None;
+    }
+
+    /// Paste from the clipboard into the editor
+    pub fn paste_from(&mut self, clipboard: &mut Clipboard) -> ClipboardResult<PasteOutcome> {
+        let text = clipboard.paste()?;
+        Ok(self.handle_paste(&text))
+    }
+
+    /// Copy the selected part of the input line to the clipboard
+    pub fn copy_selection(&self, clipboard: &mut Clipboard) -> ClipboardResult<()> {
+        clipboard.copy(&self.selection.get_selected_text(self.editor.current_input()))
+    }
+
+    /// Ctrl-R: start a reverse search, or jump to the next older match if one is active
+    pub fn reverse_search(&mut self) {
+        match &mut self.reverse_search {
+            Some(search) => search.search_older(&self.history),
+            None => {
+                self.reverse_search = Some(ReverseSearch::new(self.editor.current_input()));
+            }
+        }
+    }
+
+    /// Put the current reverse-search match into the editor
+    pub fn accept_reverse_search(&mut self) {
+        if let Some(search) = self.reverse_search.take() {
+            let input = search
+                .current_match(&self.history)
+                .unwrap_or(search.original_input())
+                .to_string();
+            self.editor.set_input(input);
+            self.update_autosuggestion();
+        }
+    }
+
+    /// Leave reverse search and restore what was typed before it started
+    pub fn cancel_reverse_search(&mut self) {
+        if let Some(search) = self.reverse_search.take() {
+            self.editor.set_input(search.original_input().to_string());
+            self.update_autosuggestion();
+        }
+    }
+
+    pub fn open_history_picker(&mut self) {
+        let mut picker = HistoryPicker::open(&self.history, Some(self.prompt.current_dir.clone()));
+        picker.set_query(self.editor.current_input());
+        self.reverse_search = None;
+        self.history_picker = Some(picker);
+    }
+
+    pub fn close_history_picker(&mut self) {
+        self.history_picker = None;
+    }
+
+    /// Accept the picker selection: insert it into the editor, or submit it when `run`
+    /// is set. Returns the submitted command in the latter case.
+    pub fn accept_history_picker(&mut self, run: bool) -> Option<String> {
+        let action = self.history_picker.take()?.accept(run)?;
+        match action {
+            PickerAction::Insert(command) => {
+                self.editor.set_input(command);
+                self.update_autosuggestion();
+                None
+            }
+            PickerAction::Run(command) => {
+                self.editor.set_input(command);
+                Some(self.submit_command())
+            }
+        }
+    }
+
+    /// Up arrow: walk back through history entries starting with the text before the cursor
     pub fn navigate_history_prev(&mut self) {
-        if let Some(cmd) = self.history.previous() {
+        if !self.history.is_navigating() {
+            self.history_draft = Some(self.editor.current_input().to_string());
+        }
+        let prefix = self.editor.current_input()[..self.editor.cursor_position()].to_string();
+        let cwd = self.prompt.current_dir.clone();
+        if let Some(cmd) = self.history.previous_matching(&prefix, Some(&cwd)) {
             self.editor.set_input(cmd);
         }
+        self.update_autosuggestion();
     }
 
+    /// Down arrow: walk forward, restoring the original line past the newest match
     pub fn navigate_history_next(&mut self) {
-        if let Some(cmd) = self.history.next() {
+        if let Some(cmd) = self.history.next_matching() {
             self.editor.set_input(cmd);
         } else {
-            self.editor.clear_input();
+            match self.history_draft.take() {
+                Some(draft) => self.editor.set_input(draft),
+                None => self.editor.clear_input(),
+            }
         }
+        self.update_autosuggestion();
     }
 
     pub fn submit_command(&mut self) -> String {
+        self.editor.move_cursor_end();
+        self.expand_abbreviation();
         let cmd = self.editor.current_input().to_string();
-        self.history.add(cmd.clone());
+        self.history
+            .start_command(cmd.clone(), Some(self.prompt.current_dir.clone()));
         self.editor.clear_input();
+        self.autosuggester.clear();
         cmd
     }
 
+    /// Record the outcome of the last submitted command in history
+    pub fn finish_command(&mut self, exit_status: Option<i32>, duration_ms: Option<u64>) {
+        let _ = self.history.finish_command(exit_status, duration_ms);
+        self.prompt.set_last_command(exit_status, duration_ms);
+        self.prompt.history_number = self.history.len() + 1;
+        self.refresh_prompt_segments();
+    }
+
+    /// Look for a corrected command after `block` failed, keeping the best one
+    /// to offer until the user types or accepts it
+    pub fn suggest_fix(&mut self, block: &Block) -> Option<&Fix> {
+        self.pending_fix = self.fixer.suggest_for_block(block).into_iter().next();
+        self.pending_fix.as_ref()
+    }
+
+    pub fn suggested_fix(&self) -> Option<&Fix> {
+        self.pending_fix.as_ref()
+    }
+
+    /// Hint shown under a failed block while a fix is on offer
+    pub fn fix_hint(&self) -> Option<String> {
+        self.pending_fix
+            .as_ref()
+            .map(|fix| format!("Did you mean `{}`? Press Enter to run it", fix.command))
+    }
+
+    /// Run the suggested fix, returning the submitted command
+    pub fn accept_fix(&mut self) -> Option<String> {
+        let fix = self.pending_fix.take()?;
+        self.editor.set_input(fix.command);
+        Some(self.submit_command())
+    }
+
+    pub fn dismiss_fix(&mut self) {
+        self.pending_fix = None;
+    }
+
+    /// Recompute prompt segments in the background, e.g. after a command or `cd`
+    pub fn refresh_prompt_segments(&mut self) {
+        let cwd = std::path::PathBuf::from(&self.prompt.current_dir);
+        self.prompt_segments.refresh(&cwd);
+    }
+
+    /// Apply segments that have finished since the last call. Returns true when
+    /// the prompt changed and should be redrawn in place.
+    pub fn poll_prompt_segments(&mut self) -> bool {
+        let updates = self.prompt_segments.poll();
+        self.prompt.apply_segment_updates(updates)
+    }
+
     pub fn render_input_line(&self) -> String {
-        format!("{}{}", self.prompt.render(), self.editor.current_input())
+        if let Some(search) = &self.reverse_search {
+            return search.render(&self.history);
+        }
+        format!("{}{}", self.prompt.render(), segments_to_ansi(&self.input_with_ghost_text()))
+    }
+
+    /// The typed input followed by the dimmed autosuggestion, with the style
+    /// codes as zero-width segments so alignment only counts visible text
+    fn input_with_ghost_text(&self) -> Vec<PromptSegment> {
+        let mut segments = vec![PromptSegment::plain(self.editor.current_input())];
+        if let Some(ghost) = self.autosuggestion() {
+            segments.push(PromptSegment::zero_width(GHOST_TEXT_STYLE));
+            segments.push(PromptSegment::plain(ghost));
+            segments.push(PromptSegment::zero_width("\x1b[0m"));
+        }
+        segments
+    }
+
+    /// Input line with the right prompt aligned to `width` columns
+    pub fn render_input_line_with_width(&self, width: usize) -> String {
+        if let Some(search) = &self.reverse_search {
+            return search.render(&self.history);
+        }
+        self.prompt.render_line_segments(&self.input_with_ghost_text(), width)
+    }
+
+    /// Scrollback line for a submitted command, collapsed when the prompt is transient
+    pub fn render_submitted_line(&self, command: &str) -> String {
+        self.prompt.render_submitted(command)
     }
 }
 
@@ -98,3 +506,80 @@ impl Default for ClassicInput {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixer::FailedCommand;
+
+    #[test]
+    fn test_command_lookup_is_shared() {
+        let lookup = CommandLookup::new().with_commands(["mytool"]);
+        let mut input = ClassicInput::new().with_command_lookup(lookup);
+
+        let classification = input.agent_mode.classifier_mut().classify("mytool --verbose");
+        assert_eq!(classification.kind, InputKind::Shell);
+        let failed = FailedCommand::new("mytol --verbose", "mytol: command not found", 127);
+        assert_eq!(input.fixer.suggest(&failed)[0].command, "mytool --verbose");
+        assert!(input.history.is_empty());
+    }
+
+    #[test]
+    fn test_right_prompt_ignores_ghost_text_style() {
+        let mut input = ClassicInput::new();
+        input.prompt = Prompt::new(PromptStyle::Custom("{user} ".to_string()))
+            .with_right_prompt(Some("{exit_code}".to_string()));
+        input.prompt.user = "joey".to_string();
+        input.prompt.set_last_command(Some(130), None);
+        input.history.add("cargo build".to_string());
+        for ch in "cargo b".chars() {
+            input.handle_input(ch);
+        }
+        assert_eq!(input.autosuggestion(), Some("uild"));
+
+        // "joey cargo build" is 16 columns, leaving 6 before the 3-column right prompt
+        let line = input.render_input_line_with_width(25);
+        assert_eq!(line, format!("joey cargo b{}uild\x1b[0m{}130", GHOST_TEXT_STYLE, " ".repeat(6)));
+    }
+
+    #[test]
+    fn test_history_options_from_config() {
+        let mut app = TerminalApp::new("test");
+        app.set_config("history.prefer_current_directory", "true");
+        let mut input = ClassicInput::new();
+        input.apply_config(&app);
+        input.prompt.current_dir = "/project".to_string();
+        input.history.start_command("make test".to_string(), Some("/project".to_string()));
+        input.history.start_command("make clean".to_string(), Some("/other".to_string()));
+        input.history.flush_pending().unwrap();
+
+        input.editor.set_input("make".to_string());
+        input.navigate_history_prev();
+        assert_eq!(input.editor.current_input(), "make test");
+    }
+
+    #[test]
+    fn test_editing_recalled_entry_restarts_history_navigation() {
+        let mut input = ClassicInput::new();
+        input.history.add("cargo test".to_string());
+        input.history.add("git status".to_string());
+        for ch in "git ".chars() {
+            input.handle_input(ch);
+        }
+        input.navigate_history_prev();
+        assert_eq!(input.editor.current_input(), "git status");
+
+        for _ in 0.."git status".len() {
+            input.handle_backspace();
+        }
+        for ch in "cargo ".chars() {
+            input.handle_input(ch);
+        }
+        assert!(!input.history.is_navigating());
+        input.navigate_history_prev();
+        assert_eq!(input.editor.current_input(), "cargo test");
+
+        input.navigate_history_next();
+        assert_eq!(input.editor.current_input(), "cargo ");
+    }
+}
@@ -1,4 +1,13 @@
-use std::collections::VecDeque;
+use super::prompt_segments::SegmentState;
+use super::prompt_template::{
+    format_duration, render_template, segments_to_ansi, segments_width, PromptContext, PromptDialect,
+    PromptSegment,
+};
+use crate::ui::{ColorScheme, Style};
+use std::collections::HashMap;
+
+const DEFAULT_PS1: &str = "\\u@\\h:\\w\\$ ";
+const TRANSIENT_GLYPH: &str = "❯";
 
 #[derive(Debug, Clone)]
 pub enum PromptStyle {
@@ -14,6 +23,20 @@ pub struct Prompt {
     pub host: String,
     pub current_dir: String,
     pub git_branch: Option<String>,
+    pub exit_code: Option<i32>,
+    pub duration_ms: Option<u64>,
+    pub k8s_context: Option<String>,
+    pub job_count: usize,
+    pub history_number: usize,
+    /// Values from segment providers, available to templates as `{name}`
+    pub segments: HashMap<String, String>,
+    /// Template for the right-aligned prompt; `None` shows duration, exit code and time
+    pub right_template: Option<String>,
+    pub right_prompt_enabled: bool,
+    /// Collapse prompts of submitted commands to a bare `❯`
+    pub transient: bool,
+    /// Colours for the status glyph and right prompt; `None` renders plain text
+    pub color_scheme: Option<ColorScheme>,
 }
 
 impl Prompt {
@@ -30,25 +53,198 @@ impl Prompt {
                 .and_then(|p| p.to_str().map(|s| s.to_string()))
                 .unwrap_or_else(|| "/".to_string()),
             git_branch: None,
+            exit_code: None,
+            duration_ms: None,
+            k8s_context: None,
+            job_count: 0,
+            history_number: 0,
+            segments: HashMap::new(),
+            right_template: None,
+            right_prompt_enabled: false,
+            transient: false,
+            color_scheme: None,
         }
     }
 
+    pub fn with_right_prompt(mut self, template: Option<String>) -> Self {
+        self.right_prompt_enabled = true;
+        self.right_template = template;
+        self
+    }
+
+    pub fn with_transient(mut self, transient: bool) -> Self {
+        self.transient = transient;
+        self
+    }
+
+    pub fn with_color_scheme(mut self, color_scheme: ColorScheme) -> Self {
+        self.color_scheme = Some(color_scheme);
+        self
+    }
+
     pub fn with_git_branch(mut self, branch: Option<String>) -> Self {
         self.git_branch = branch;
         self
     }
 
+    pub fn with_k8s_context(mut self, context: Option<String>) -> Self {
+        self.k8s_context = context;
+        self
+    }
+
+    /// Remember how the last command ended, for `{exit_code}`, `{duration}`, `%?`
+    pub fn set_last_command(&mut self, exit_code: Option<i32>, duration_ms: Option<u64>) {
+        self.exit_code = exit_code;
+        self.duration_ms = duration_ms;
+    }
+
+    /// Fold in segments that finished computing. Returns whether anything changed.
+    pub fn apply_segment_updates(&mut self, updates: Vec<(&'static str, SegmentState)>) -> bool {
+        let mut changed = false;
+        for (name, state) in updates {
+            let value = match state {
+                SegmentState::Ready(value) => Some(value),
+                SegmentState::Empty | SegmentState::TimedOut => None,
+                SegmentState::Pending => continue,
+            };
+
+            match name {
+                "git_branch" => self.git_branch = value.clone(),
+                "k8s_context" => self.k8s_context = value.clone(),
+                _ => {}
+            }
+            match value {
+                Some(value) => self.segments.insert(name.to_string(), value),
+                None => self.segments.remove(name),
+            };
+            changed = true;
+        }
+        changed
+    }
+
     pub fn render(&self) -> String {
+        segments_to_ansi(&self.render_segments())
+    }
+
+    /// The prompt as styled segments, for renderers that apply their own colours
+    pub fn render_segments(&self) -> Vec<PromptSegment> {
         match &self.style {
-            PromptStyle::Warp => self.render_warp_style(),
-            PromptStyle::Custom(template) => template.clone(),
-            PromptStyle::Shell => self.render_ps1_style(),
+            PromptStyle::Warp => vec![
+                PromptSegment::plain(self.render_warp_style()),
+                self.status_glyph("$"),
+                PromptSegment::plain(" "),
+            ],
+            PromptStyle::Custom(template) => self.expand(template, PromptDialect::Any),
+            PromptStyle::Shell => {
+                let (template, dialect) = shell_prompt_template();
+                self.expand(&template, dialect)
+            }
+        }
+    }
+
+    /// Number of terminal columns the prompt occupies, ignoring escape sequences
+    pub fn visible_width(&self) -> usize {
+        segments_width(&self.render_segments())
+    }
+
+    /// Right-aligned prompt segments: last command duration, exit code and the time
+    pub fn render_right_segments(&self) -> Vec<PromptSegment> {
+        if !self.right_prompt_enabled {
+            return Vec::new();
+        }
+        if let Some(template) = &self.right_template {
+            return self.expand(template, PromptDialect::Any);
+        }
+
+        let mut segments = Vec::new();
+        if let Some(ms) = self.duration_ms {
+            segments.push(self.styled(format_duration(ms), |c| &c.secondary));
+            segments.push(PromptSegment::plain(" "));
+        }
+        if let Some(code) = self.exit_code.filter(|&c| c != 0) {
+            segments.push(self.styled(format!("✘ {}", code), |c| &c.error));
+            segments.push(PromptSegment::plain(" "));
+        }
+        segments.push(self.styled(chrono::Local::now().format("%H:%M:%S").to_string(), |c| &c.secondary));
+        segments
+    }
+
+    /// Prompt, input and right prompt laid out on one line of `width` columns.
+    /// The right prompt is dropped when it would collide with the input.
+    pub fn render_line(&self, input: &str, width: usize) -> String {
+        self.render_line_segments(&[PromptSegment::plain(input)], width)
+    }
+
+    /// Like `render_line`, for input carrying its own styling; zero-width
+    /// segments such as SGR codes don't count towards the alignment
+    pub fn render_line_segments(&self, input: &[PromptSegment], width: usize) -> String {
+        let left = self.render_segments();
+        let right = self.render_right_segments();
+        let used = segments_width(&left) + segments_width(input);
+        let right_width = segments_width(&right);
+
+        let mut line = segments_to_ansi(&left);
+        line.push_str(&segments_to_ansi(input));
+        if !right.is_empty() && used + right_width < width {
+            line.push_str(&" ".repeat(width - used - right_width));
+            line.push_str(&segments_to_ansi(&right));
+        }
+        line
+    }
+
+    /// How a submitted command stays in the scrollback: the full prompt, or just
+    /// a status-coloured `❯` in transient mode
+    pub fn render_submitted(&self, command: &str) -> String {
+        if self.transient {
+            let segments = [self.status_glyph(TRANSIENT_GLYPH), PromptSegment::plain(" ")];
+            format!("{}{}", segments_to_ansi(&segments), command)
+        } else {
+            format!("{}{}", self.render(), command)
         }
     }
 
+    /// The prompt character, green after success and red after a failure
+    pub fn status_glyph(&self, glyph: &str) -> PromptSegment {
+        match self.exit_code {
+            Some(code) if code != 0 => self.styled(glyph, |c| &c.error),
+            _ => self.styled(glyph, |c| &c.success),
+        }
+    }
+
+    fn styled(&self, text: impl Into<String>, pick: impl Fn(&ColorScheme) -> &Style) -> PromptSegment {
+        let mut segment = PromptSegment::plain(text);
+        if let Some(scheme) = &self.color_scheme {
+            let style = pick(scheme);
+            segment.fg = Some(style.fg_color);
+            segment.bold = style.bold;
+        }
+        segment
+    }
+
+    /// Expand a template against this prompt's state
+    pub fn expand(&self, template: &str, dialect: PromptDialect) -> Vec<PromptSegment> {
+        let home = std::env::var("HOME").ok();
+        let ctx = PromptContext {
+            user: &self.user,
+            host: &self.host,
+            cwd: &self.current_dir,
+            home: home.as_deref(),
+            git_branch: self.git_branch.as_deref(),
+            exit_code: self.exit_code,
+            duration_ms: self.duration_ms,
+            k8s_context: self.k8s_context.as_deref(),
+            jobs: self.job_count,
+            history_number: self.history_number,
+            is_root: self.user == "root",
+            now: chrono::Local::now(),
+            extra: Some(&self.segments),
+        };
+        render_template(template, dialect, &ctx)
+    }
+
     fn render_warp_style(&self) -> String {
         let mut prompt = format!("{}@{} ", self.user, self.host);
-        
+
         // Add directory
         if let Some(home) = std::env::var("HOME").ok() {
             if self.current_dir.starts_with(&home) {
@@ -68,13 +264,22 @@ impl Prompt {
             prompt.push(')');
         }
 
-        prompt.push_str(" $ ");
+        prompt.push(' ');
         prompt
     }
+}
 
-    fn render_ps1_style(&self) -> String {
-        // Render traditional shell prompt
-        format!("{}@{}:{}$ ", self.user, self.host, self.current_dir)
+/// PS1 (or zsh's PROMPT) from the environment, in the dialect of the user's shell
+fn shell_prompt_template() -> (String, PromptDialect) {
+    let is_zsh = std::env::var("SHELL").map(|s| s.ends_with("zsh")).unwrap_or(false);
+    if is_zsh {
+        let template = std::env::var("PROMPT")
+            .or_else(|_| std::env::var("PS1"))
+            .unwrap_or_else(|_| "%n@%m:%~%# ".to_string());
+        (template, PromptDialect::Zsh)
+    } else {
+        let template = std::env::var("PS1").unwrap_or_else(|_| DEFAULT_PS1.to_string());
+        (template, PromptDialect::Bash)
     }
 }
 
@@ -83,3 +288,44 @@ impl Default for Prompt {
         Self::new(PromptStyle::Warp)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prompt() -> Prompt {
+        let mut prompt = Prompt::new(PromptStyle::Custom("{user} ".to_string()));
+        prompt.user = "joey".to_string();
+        prompt
+    }
+
+    #[test]
+    fn test_right_prompt_aligned_to_width() {
+        let mut prompt = prompt().with_right_prompt(Some("{exit_code}".to_string()));
+        prompt.set_last_command(Some(130), None);
+
+        let line = prompt.render_line("ls", 20);
+        assert_eq!(line, format!("joey ls{}130", " ".repeat(10)));
+        assert_eq!(prompt.render_line("ls", 9), "joey ls");
+    }
+
+    #[test]
+    fn test_transient_glyph_follows_exit_status() {
+        let scheme = ColorScheme::warp();
+        let mut prompt = prompt().with_transient(true).with_color_scheme(scheme.clone());
+
+        prompt.set_last_command(Some(0), None);
+        assert_eq!(prompt.status_glyph("❯").fg, Some(scheme.success.fg_color));
+        assert!(prompt.render_submitted("ls").ends_with("\x1b[0m ls"));
+
+        prompt.set_last_command(Some(1), None);
+        assert_eq!(prompt.status_glyph("❯").fg, Some(scheme.error.fg_color));
+    }
+
+    #[test]
+    fn test_warp_style_is_plain_without_color_scheme() {
+        let prompt = Prompt::new(PromptStyle::Warp);
+        assert!(prompt.render().ends_with(" $ "));
+        assert!(!prompt.render().contains('\x1b'));
+    }
+}
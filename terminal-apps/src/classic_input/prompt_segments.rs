@@ -0,0 +1,708 @@
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How long `command_output` lets a subprocess run outside a segment runner
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+thread_local! {
+    /// When the segment computed on this thread stops being waited for
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Computes one prompt value (e.g. `git_branch`) for a directory.
+///
+/// Providers run on background threads, so `compute` may block on disk or a
+/// subprocess; the runner stops waiting once `timeout` elapses, and
+/// subprocesses started through `command_output` are killed then.
+pub trait SegmentProvider: Send + Sync + Debug {
+    /// Placeholder name the value is exposed under, as in `{git_branch}`
+    fn name(&self) -> &'static str;
+
+    fn timeout(&self) -> Duration {
+        Duration::from_millis(500)
+    }
+
+    fn compute(&self, cwd: &Path) -> Option<String>;
+}
+
+/// Progress of a single segment for the current directory
+#[derive(Debug, Clone, PartialEq)]
+pub enum SegmentState {
+    Pending,
+    Ready(String),
+    /// The provider ran but has nothing to show here
+    Empty,
+    TimedOut,
+}
+
+#[derive(Debug)]
+struct SegmentUpdate {
+    generation: u64,
+    name: &'static str,
+    value: Option<String>,
+}
+
+/// Runs segment providers in the background and collects their results
+#[derive(Debug)]
+pub struct SegmentRunner {
+    providers: Vec<Arc<dyn SegmentProvider>>,
+    states: HashMap<&'static str, SegmentState>,
+    deadlines: HashMap<&'static str, Instant>,
+    generation: u64,
+    cwd: PathBuf,
+    /// Providers with a computation in flight, for this or an earlier directory
+    running: HashSet<&'static str>,
+    /// Providers to start for the current directory once their previous run ends
+    queued: HashSet<&'static str>,
+    sender: Sender<SegmentUpdate>,
+    receiver: Receiver<SegmentUpdate>,
+}
+
+impl SegmentRunner {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            providers: Vec::new(),
+            states: HashMap::new(),
+            deadlines: HashMap::new(),
+            generation: 0,
+            cwd: PathBuf::new(),
+            running: HashSet::new(),
+            queued: HashSet::new(),
+            sender,
+            receiver,
+        }
+    }
+
+    /// Runner with the built-in git, toolchain and kubeconfig providers
+    pub fn with_default_providers() -> Self {
+        let mut runner = Self::new();
+        runner.register(GitBranchProvider);
+        runner.register(GitStatusProvider);
+        runner.register(RustVersionProvider);
+        runner.register(NodeVersionProvider);
+        runner.register(PythonVersionProvider);
+        runner.register(KubeContextProvider);
+        runner
+    }
+
+    pub fn register<P: SegmentProvider + 'static>(&mut self, provider: P) {
+        self.providers.push(Arc::new(provider));
+    }
+
+    /// Start computing every segment for `cwd`. Results from earlier refreshes
+    /// are discarded; a provider still busy with one is started again once it
+    /// finishes, rather than piling up threads behind it.
+    pub fn refresh(&mut self, cwd: &Path) {
+        self.generation += 1;
+        self.cwd = cwd.to_path_buf();
+        self.states.clear();
+        self.deadlines.clear();
+        self.queued.clear();
+
+        let now = Instant::now();
+        for provider in &self.providers {
+            let name = provider.name();
+            self.states.insert(name, SegmentState::Pending);
+            self.deadlines.insert(name, now + provider.timeout());
+            self.queued.insert(name);
+        }
+        self.start_queued();
+    }
+
+    /// Whether a computation for `name` is still in flight
+    pub fn is_running(&self, name: &str) -> bool {
+        self.running.contains(name)
+    }
+
+    fn start_queued(&mut self) {
+        for provider in &self.providers {
+            let name = provider.name();
+            if self.running.contains(name) || !self.queued.remove(name) {
+                continue;
+            }
+            self.running.insert(name);
+
+            let provider = Arc::clone(provider);
+            let sender = self.sender.clone();
+            let generation = self.generation;
+            let deadline = self.deadlines.get(name).copied();
+            let cwd = self.cwd.clone();
+            thread::spawn(move || {
+                DEADLINE.with(|d| d.set(deadline));
+                let value = provider.compute(&cwd);
+                let _ = sender.send(SegmentUpdate { generation, name, value });
+            });
+        }
+    }
+
+    /// Collect finished segments without blocking. Returns the ones that changed.
+    pub fn poll(&mut self) -> Vec<(&'static str, SegmentState)> {
+        let mut changed = Vec::new();
+        while let Ok(update) = self.receiver.try_recv() {
+            self.running.remove(update.name);
+            if update.generation != self.generation
+                || self.states.get(update.name) != Some(&SegmentState::Pending)
+            {
+                continue;
+            }
+            // A command killed at its deadline can report before the deadline check below
+            let timed_out = self.deadlines.get(update.name).is_some_and(|d| Instant::now() >= *d);
+            let state = match update.value {
+                Some(value) => SegmentState::Ready(value),
+                None if timed_out => SegmentState::TimedOut,
+                None => SegmentState::Empty,
+            };
+            self.states.insert(update.name, state.clone());
+            changed.push((update.name, state));
+        }
+
+        let now = Instant::now();
+        for (name, deadline) in &self.deadlines {
+            if now >= *deadline && self.states.get(name) == Some(&SegmentState::Pending) {
+                self.states.insert(name, SegmentState::TimedOut);
+                self.queued.remove(name);
+                changed.push((*name, SegmentState::TimedOut));
+            }
+        }
+        self.start_queued();
+        changed
+    }
+
+    /// Block until every segment is settled or `max_wait` passes
+    pub fn wait(&mut self, max_wait: Duration) -> Vec<(&'static str, SegmentState)> {
+        let give_up = Instant::now() + max_wait;
+        let mut changed = Vec::new();
+        loop {
+            changed.extend(self.poll());
+            if self.is_settled() || Instant::now() >= give_up {
+                return changed;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    pub fn is_settled(&self) -> bool {
+        !self.states.values().any(|s| *s == SegmentState::Pending)
+    }
+
+    pub fn state(&self, name: &str) -> Option<&SegmentState> {
+        self.states.get(name)
+    }
+
+    /// Values of all segments that have finished
+    pub fn values(&self) -> HashMap<String, String> {
+        self.states
+            .iter()
+            .filter_map(|(name, state)| match state {
+                SegmentState::Ready(value) => Some((name.to_string(), value.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// A clone gets its own channel, so results are never taken by the wrong
+/// copy; segments still pending are computed again for it
+impl Clone for SegmentRunner {
+    fn clone(&self) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let queued = self
+            .states
+            .iter()
+            .filter(|(_, state)| **state == SegmentState::Pending)
+            .map(|(name, _)| *name)
+            .collect();
+        let mut runner = Self {
+            providers: self.providers.clone(),
+            states: self.states.clone(),
+            deadlines: self.deadlines.clone(),
+            generation: self.generation,
+            cwd: self.cwd.clone(),
+            running: HashSet::new(),
+            queued,
+            sender,
+            receiver,
+        };
+        runner.start_queued();
+        runner
+    }
+}
+
+impl Default for SegmentRunner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Current branch, read directly from `.git/HEAD`
+#[derive(Debug, Clone, Copy)]
+pub struct GitBranchProvider;
+
+impl SegmentProvider for GitBranchProvider {
+    fn name(&self) -> &'static str {
+        "git_branch"
+    }
+
+    fn compute(&self, cwd: &Path) -> Option<String> {
+        read_git_branch(&find_git_dir(cwd)?)
+    }
+}
+
+/// Ahead/behind and dirty counts, e.g. `↑1 ↓2 +3 !1 ?4`
+#[derive(Debug, Clone, Copy)]
+pub struct GitStatusProvider;
+
+impl SegmentProvider for GitStatusProvider {
+    fn name(&self) -> &'static str {
+        "git_status"
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_secs(2)
+    }
+
+    fn compute(&self, cwd: &Path) -> Option<String> {
+        find_git_dir(cwd)?;
+        let output = command_output("git", &["status", "--porcelain=v2", "--branch"], cwd)?;
+        Some(GitStatusSummary::parse(&output).to_string())
+    }
+}
+
+/// Counts from `git status --porcelain=v2 --branch`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GitStatusSummary {
+    pub ahead: u32,
+    pub behind: u32,
+    pub staged: u32,
+    pub modified: u32,
+    pub untracked: u32,
+    pub conflicted: u32,
+}
+
+impl GitStatusSummary {
+    pub fn parse(porcelain: &str) -> Self {
+        let mut summary = Self::default();
+        for line in porcelain.lines() {
+            if let Some(ab) = line.strip_prefix("# branch.ab ") {
+                for part in ab.split_whitespace() {
+                    if let Some(n) = part.strip_prefix('+') {
+                        summary.ahead = n.parse().unwrap_or(0);
+                    } else if let Some(n) = part.strip_prefix('-') {
+                        summary.behind = n.parse().unwrap_or(0);
+                    }
+                }
+            } else if line.starts_with("1 ") || line.starts_with("2 ") {
+                let xy: Vec<char> = line[2..].chars().take(2).collect();
+                if xy.first().is_some_and(|&c| c != '.') {
+                    summary.staged += 1;
+                }
+                if xy.get(1).is_some_and(|&c| c != '.') {
+                    summary.modified += 1;
+                }
+            } else if line.starts_with("u ") {
+                summary.conflicted += 1;
+            } else if line.starts_with("? ") {
+                summary.untracked += 1;
+            }
+        }
+        summary
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.staged == 0 && self.modified == 0 && self.untracked == 0 && self.conflicted == 0
+    }
+}
+
+impl std::fmt::Display for GitStatusSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let parts: Vec<String> = [
+            ('↑', self.ahead),
+            ('↓', self.behind),
+            ('+', self.staged),
+            ('!', self.modified),
+            ('?', self.untracked),
+            ('✖', self.conflicted),
+        ]
+        .iter()
+        .filter(|(_, n)| *n > 0)
+        .map(|(symbol, n)| format!("{}{}", symbol, n))
+        .collect();
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+/// Rust toolchain from rust-toolchain(.toml), falling back to `rustc --version`
+#[derive(Debug, Clone, Copy)]
+pub struct RustVersionProvider;
+
+impl SegmentProvider for RustVersionProvider {
+    fn name(&self) -> &'static str {
+        "rust_version"
+    }
+
+    fn compute(&self, cwd: &Path) -> Option<String> {
+        let project = find_upwards(cwd, "Cargo.toml")?;
+        let root = project.parent()?;
+
+        if let Ok(content) = fs::read_to_string(root.join("rust-toolchain.toml")) {
+            if let Some(channel) = toml_string_value(&content, "channel") {
+                return Some(channel);
+            }
+        }
+        if let Ok(content) = fs::read_to_string(root.join("rust-toolchain")) {
+            let channel = content.trim();
+            if !channel.is_empty() {
+                return Some(channel.to_string());
+            }
+        }
+
+        let output = command_output("rustc", &["--version"], cwd)?;
+        output.split_whitespace().nth(1).map(|v| v.to_string())
+    }
+}
+
+/// Node version from .nvmrc/.node-version, falling back to `node --version`
+#[derive(Debug, Clone, Copy)]
+pub struct NodeVersionProvider;
+
+impl SegmentProvider for NodeVersionProvider {
+    fn name(&self) -> &'static str {
+        "node_version"
+    }
+
+    fn compute(&self, cwd: &Path) -> Option<String> {
+        let project = find_upwards(cwd, "package.json")?;
+        let root = project.parent()?;
+
+        for file in [".nvmrc", ".node-version"] {
+            if let Ok(content) = fs::read_to_string(root.join(file)) {
+                let version = content.trim().trim_start_matches('v');
+                if !version.is_empty() {
+                    return Some(version.to_string());
+                }
+            }
+        }
+
+        let output = command_output("node", &["--version"], cwd)?;
+        Some(output.trim().trim_start_matches('v').to_string())
+    }
+}
+
+/// Python version from .python-version, falling back to `python3 --version`
+#[derive(Debug, Clone, Copy)]
+pub struct PythonVersionProvider;
+
+impl SegmentProvider for PythonVersionProvider {
+    fn name(&self) -> &'static str {
+        "python_version"
+    }
+
+    fn compute(&self, cwd: &Path) -> Option<String> {
+        let marker = ["pyproject.toml", "requirements.txt", "setup.py", ".python-version"]
+            .iter()
+            .find_map(|m| find_upwards(cwd, m))?;
+        let root = marker.parent()?;
+
+        if let Ok(content) = fs::read_to_string(root.join(".python-version")) {
+            if let Some(version) = content.lines().next().map(str::trim).filter(|v| !v.is_empty()) {
+                return Some(version.to_string());
+            }
+        }
+
+        let output = command_output("python3", &["--version"], cwd)?;
+        output.split_whitespace().nth(1).map(|v| v.to_string())
+    }
+}
+
+/// `current-context` from $KUBECONFIG or ~/.kube/config
+#[derive(Debug, Clone, Copy)]
+pub struct KubeContextProvider;
+
+impl SegmentProvider for KubeContextProvider {
+    fn name(&self) -> &'static str {
+        "k8s_context"
+    }
+
+    fn compute(&self, _cwd: &Path) -> Option<String> {
+        let paths: Vec<PathBuf> = match std::env::var("KUBECONFIG") {
+            Ok(value) if !value.is_empty() => std::env::split_paths(&value).collect(),
+            _ => vec![Path::new(&std::env::var("HOME").ok()?).join(".kube/config")],
+        };
+
+        paths
+            .iter()
+            .filter_map(|p| fs::read_to_string(p).ok())
+            .find_map(|content| parse_kube_current_context(&content))
+    }
+}
+
+pub fn parse_kube_current_context(kubeconfig: &str) -> Option<String> {
+    kubeconfig.lines().find_map(|line| {
+        let value = line.strip_prefix("current-context:")?.trim().trim_matches('"').trim_matches('\'');
+        if value.is_empty() {
+            None
+        } else {
+            Some(value.to_string())
+        }
+    })
+}
+
+/// Nearest `.git` directory at or above `start`, following `gitdir:` files used by worktrees
+pub fn find_git_dir(start: &Path) -> Option<PathBuf> {
+    let dot_git = find_upwards(start, ".git")?;
+    if dot_git.is_dir() {
+        return Some(dot_git);
+    }
+
+    let content = fs::read_to_string(&dot_git).ok()?;
+    let target = content.trim().strip_prefix("gitdir:")?.trim();
+    let path = Path::new(target);
+    Some(if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        dot_git.parent()?.join(path)
+    })
+}
+
+/// Branch name from HEAD, or the short commit hash when detached
+pub fn read_git_branch(git_dir: &Path) -> Option<String> {
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    match head.strip_prefix("ref: ") {
+        Some(reference) => Some(reference.trim_start_matches("refs/heads/").to_string()),
+        None => Some(head.chars().take(7).collect()),
+    }
+}
+
+/// First path named `name` in `start` or one of its ancestors
+pub fn find_upwards(start: &Path, name: &str) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(name))
+        .find(|candidate| candidate.exists())
+}
+
+fn toml_string_value(content: &str, key: &str) -> Option<String> {
+    content.lines().find_map(|line| {
+        let (k, v) = line.split_once('=')?;
+        if k.trim() == key {
+            Some(v.trim().trim_matches('"').to_string())
+        } else {
+            None
+        }
+    })
+}
+
+/// Runs `program` and returns what it printed, or `None` if it failed. The
+/// child is killed once the segment it computes is no longer waited for, or
+/// after `COMMAND_TIMEOUT` outside a runner.
+pub(crate) fn command_output(program: &str, args: &[&str], cwd: &Path) -> Option<String> {
+    let deadline = DEADLINE
+        .with(Cell::get)
+        .unwrap_or_else(|| Instant::now() + COMMAND_TIMEOUT);
+    let mut child = Command::new(program)
+        .args(args)
+        .current_dir(cwd)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .ok()?;
+    // Drain both pipes so a chatty child can't block on a full one
+    let stdout = read_to_end_in_background(child.stdout.take()?);
+    let stderr = read_to_end_in_background(child.stderr.take()?);
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() < deadline => thread::sleep(Duration::from_millis(5)),
+            _ => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return None;
+            }
+        }
+    };
+    if !status.success() {
+        return None;
+    }
+    // Some tools (python2, old javas) print versions to stderr
+    let stdout = stdout.join().ok()?;
+    let text = if stdout.is_empty() { stderr.join().ok()? } else { stdout };
+    Some(String::from_utf8_lossy(&text).trim().to_string())
+}
+
+fn read_to_end_in_background(mut pipe: impl Read + Send + 'static) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        let _ = pipe.read_to_end(&mut bytes);
+        bytes
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Debug)]
+    struct SlowProvider(Duration);
+
+    impl SegmentProvider for SlowProvider {
+        fn name(&self) -> &'static str {
+            "slow"
+        }
+
+        fn timeout(&self) -> Duration {
+            Duration::from_millis(50)
+        }
+
+        fn compute(&self, _cwd: &Path) -> Option<String> {
+            thread::sleep(self.0);
+            Some("done".to_string())
+        }
+    }
+
+    #[derive(Debug)]
+    struct FixedProvider;
+
+    impl SegmentProvider for FixedProvider {
+        fn name(&self) -> &'static str {
+            "fixed"
+        }
+
+        fn compute(&self, _cwd: &Path) -> Option<String> {
+            Some("value".to_string())
+        }
+    }
+
+    #[test]
+    fn test_runner_times_out_slow_providers() {
+        let mut runner = SegmentRunner::new();
+        runner.register(SlowProvider(Duration::from_millis(500)));
+        runner.register(FixedProvider);
+
+        runner.refresh(Path::new("/"));
+        assert_eq!(runner.state("slow"), Some(&SegmentState::Pending));
+
+        runner.wait(Duration::from_secs(1));
+        assert_eq!(runner.state("fixed"), Some(&SegmentState::Ready("value".to_string())));
+        assert_eq!(runner.state("slow"), Some(&SegmentState::TimedOut));
+    }
+
+    #[derive(Debug)]
+    struct CountingProvider(Arc<AtomicUsize>);
+
+    impl SegmentProvider for CountingProvider {
+        fn name(&self) -> &'static str {
+            "counting"
+        }
+
+        fn timeout(&self) -> Duration {
+            Duration::from_secs(2)
+        }
+
+        fn compute(&self, cwd: &Path) -> Option<String> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(100));
+            Some(cwd.display().to_string())
+        }
+    }
+
+    #[derive(Debug)]
+    struct SleepProvider;
+
+    impl SegmentProvider for SleepProvider {
+        fn name(&self) -> &'static str {
+            "sleep"
+        }
+
+        fn timeout(&self) -> Duration {
+            Duration::from_millis(50)
+        }
+
+        fn compute(&self, cwd: &Path) -> Option<String> {
+            command_output("sleep", &["5"], cwd)
+        }
+    }
+
+    #[test]
+    fn test_busy_providers_are_not_restarted() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let mut runner = SegmentRunner::new();
+        runner.register(CountingProvider(Arc::clone(&runs)));
+
+        for dir in ["/a", "/b", "/c"] {
+            runner.refresh(Path::new(dir));
+        }
+        runner.wait(Duration::from_secs(2));
+        // The run for /a finishes first, then one more for the latest directory
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        assert_eq!(runner.state("counting"), Some(&SegmentState::Ready("/c".to_string())));
+    }
+
+    #[test]
+    fn test_timed_out_commands_are_killed() {
+        let mut runner = SegmentRunner::new();
+        runner.register(SleepProvider);
+        runner.refresh(Path::new("/"));
+        runner.wait(Duration::from_secs(1));
+        assert_eq!(runner.state("sleep"), Some(&SegmentState::TimedOut));
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while runner.is_running("sleep") && Instant::now() < deadline {
+            runner.poll();
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!runner.is_running("sleep"));
+    }
+
+    #[test]
+    fn test_clones_collect_their_own_results() {
+        let mut runner = SegmentRunner::new();
+        runner.register(FixedProvider);
+        runner.refresh(Path::new("/"));
+        let mut copy = runner.clone();
+
+        copy.wait(Duration::from_secs(1));
+        runner.wait(Duration::from_secs(1));
+        assert_eq!(copy.state("fixed"), Some(&SegmentState::Ready("value".to_string())));
+        assert_eq!(runner.state("fixed"), Some(&SegmentState::Ready("value".to_string())));
+    }
+
+    #[test]
+    fn test_git_status_summary() {
+        let porcelain = "# branch.oid abc\n# branch.head main\n# branch.ab +2 -1\n\
+            1 M. N... 100644 100644 100644 a b src/lib.rs\n\
+            1 .M N... 100644 100644 100644 a b README.md\n\
+            ? new.txt\n";
+        let summary = GitStatusSummary::parse(porcelain);
+        assert_eq!(summary.ahead, 2);
+        assert_eq!(summary.behind, 1);
+        assert_eq!(summary.to_string(), "↑2 ↓1 +1 !1 ?1");
+    }
+
+    #[test]
+    fn test_git_branch_from_head() {
+        let dir = TempDir::new("git_head");
+        dir.write(".git/HEAD", "ref: refs/heads/feature/prompt\n");
+
+        assert_eq!(GitBranchProvider.compute(&dir), Some("feature/prompt".to_string()));
+    }
+
+    #[test]
+    fn test_kube_current_context() {
+        let config = "apiVersion: v1\ncurrent-context: \"prod-eu\"\nkind: Config\n";
+        assert_eq!(parse_kube_current_context(config), Some("prod-eu".to_string()));
+    }
+}
@@ -0,0 +1,559 @@
+use chrono::{DateTime, Local};
+use std::collections::HashMap;
+
+/// A run of prompt text sharing one style
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PromptSegment {
+    pub text: String,
+    pub fg: Option<u8>,
+    pub bg: Option<u8>,
+    pub bold: bool,
+    /// Raw terminal output that takes no columns (bash `\[...\]`, zsh `%{...%}`)
+    pub zero_width: bool,
+}
+
+impl PromptSegment {
+    pub fn plain(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Raw terminal output, such as an SGR sequence, that takes no columns
+    pub fn zero_width(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            zero_width: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        if self.zero_width {
+            0
+        } else {
+            display_width(&self.text)
+        }
+    }
+}
+
+/// Which escape syntax a template uses
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PromptDialect {
+    /// bash PS1 backslash escapes
+    Bash,
+    /// zsh PROMPT percent escapes
+    Zsh,
+    /// Both, for user-written templates
+    Any,
+}
+
+/// Values the template escapes and placeholders expand to
+#[derive(Debug, Clone)]
+pub struct PromptContext<'a> {
+    pub user: &'a str,
+    pub host: &'a str,
+    pub cwd: &'a str,
+    pub home: Option<&'a str>,
+    pub git_branch: Option<&'a str>,
+    pub exit_code: Option<i32>,
+    pub duration_ms: Option<u64>,
+    pub k8s_context: Option<&'a str>,
+    pub jobs: usize,
+    pub history_number: usize,
+    pub is_root: bool,
+    pub now: DateTime<Local>,
+    /// Extra `{name}` placeholders, such as values from segment providers
+    pub extra: Option<&'a HashMap<String, String>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+struct StyleState {
+    fg: Option<u8>,
+    bg: Option<u8>,
+    bold: bool,
+}
+
+struct SegmentBuilder {
+    segments: Vec<PromptSegment>,
+    style: StyleState,
+    zero_width: bool,
+}
+
+impl SegmentBuilder {
+    fn push_str(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        match self.segments.last_mut() {
+            Some(last)
+                if last.fg == self.style.fg
+                    && last.bg == self.style.bg
+                    && last.bold == self.style.bold
+                    && last.zero_width == self.zero_width =>
+            {
+                last.text.push_str(text);
+            }
+            _ => self.segments.push(PromptSegment {
+                text: text.to_string(),
+                fg: self.style.fg,
+                bg: self.style.bg,
+                bold: self.style.bold,
+                zero_width: self.zero_width,
+            }),
+        }
+    }
+
+    fn push_char(&mut self, ch: char) {
+        let mut buf = [0u8; 4];
+        self.push_str(ch.encode_utf8(&mut buf));
+    }
+}
+
+/// Expand a PS1/PROMPT-style template into styled segments
+pub fn render_template(template: &str, dialect: PromptDialect, ctx: &PromptContext) -> Vec<PromptSegment> {
+    let bash = dialect != PromptDialect::Zsh;
+    let zsh = dialect != PromptDialect::Bash;
+    let chars: Vec<char> = template.chars().collect();
+    let mut out = SegmentBuilder {
+        segments: Vec::new(),
+        style: StyleState::default(),
+        zero_width: false,
+    };
+
+    let mut i = 0;
+    while i < chars.len() {
+        let ch = chars[i];
+        if ch == '\\' && bash && i + 1 < chars.len() {
+            i = expand_bash_escape(&chars, i + 1, ctx, &mut out);
+        } else if ch == '%' && zsh && i + 1 < chars.len() {
+            i = expand_zsh_escape(&chars, i + 1, ctx, &mut out);
+        } else if ch == '\x1b' {
+            i = apply_ansi_sequence(&chars, i, &mut out);
+        } else if ch == '{' {
+            i = expand_placeholder(&chars, i, ctx, &mut out);
+        } else {
+            out.push_char(ch);
+            i += 1;
+        }
+    }
+
+    out.segments
+}
+
+// Each expander receives the index just past the escape character and returns
+// the index of the first unconsumed char.
+
+fn expand_bash_escape(chars: &[char], i: usize, ctx: &PromptContext, out: &mut SegmentBuilder) -> usize {
+    match chars[i] {
+        'u' => out.push_str(ctx.user),
+        'h' => out.push_str(short_host(ctx.host)),
+        'H' => out.push_str(ctx.host),
+        'w' => out.push_str(&tilde_path(ctx.cwd, ctx.home)),
+        'W' => out.push_str(&basename(ctx.cwd, ctx.home)),
+        '$' => out.push_char(if ctx.is_root { '#' } else { '$' }),
+        't' => out.push_str(&ctx.now.format("%H:%M:%S").to_string()),
+        'T' => out.push_str(&ctx.now.format("%I:%M:%S").to_string()),
+        '@' => out.push_str(&ctx.now.format("%I:%M %p").to_string()),
+        'A' => out.push_str(&ctx.now.format("%H:%M").to_string()),
+        'd' => out.push_str(&ctx.now.format("%a %b %d").to_string()),
+        'j' => out.push_str(&ctx.jobs.to_string()),
+        '!' | '#' => out.push_str(&ctx.history_number.to_string()),
+        'n' => out.push_char('\n'),
+        '\\' => out.push_char('\\'),
+        'a' => {}
+        '[' => out.zero_width = true,
+        ']' => out.zero_width = false,
+        'e' => return apply_escape_char(chars, i + 1, out),
+        '0' if chars.get(i + 1..i + 3) == Some(&['3', '3'][..]) => {
+            return apply_escape_char(chars, i + 3, out);
+        }
+        other => {
+            out.push_char('\\');
+            out.push_char(other);
+        }
+    }
+    i + 1
+}
+
+fn expand_zsh_escape(chars: &[char], i: usize, ctx: &PromptContext, out: &mut SegmentBuilder) -> usize {
+    // Optional numeric argument, as in %1~ or %2d
+    let mut j = i;
+    while j < chars.len() && chars[j].is_ascii_digit() {
+        j += 1;
+    }
+    let count: Option<usize> = chars[i..j].iter().collect::<String>().parse().ok();
+    let code = match chars.get(j) {
+        Some(&c) => c,
+        None => {
+            out.push_char('%');
+            return i;
+        }
+    };
+
+    match code {
+        'n' => out.push_str(ctx.user),
+        'm' => out.push_str(short_host(ctx.host)),
+        'M' => out.push_str(ctx.host),
+        '~' => out.push_str(&trailing_components(&tilde_path(ctx.cwd, ctx.home), count)),
+        'd' | '/' => out.push_str(&trailing_components(ctx.cwd, count)),
+        'c' | '.' => out.push_str(&trailing_components(&tilde_path(ctx.cwd, ctx.home), Some(count.unwrap_or(1)))),
+        '#' => out.push_char(if ctx.is_root { '#' } else { '%' }),
+        '?' => out.push_str(&ctx.exit_code.unwrap_or(0).to_string()),
+        'j' => out.push_str(&ctx.jobs.to_string()),
+        'h' | '!' => out.push_str(&ctx.history_number.to_string()),
+        'T' => out.push_str(&ctx.now.format("%H:%M").to_string()),
+        't' | '@' => out.push_str(&ctx.now.format("%l:%M%p").to_string()),
+        '*' => out.push_str(&ctx.now.format("%H:%M:%S").to_string()),
+        'D' => out.push_str(&ctx.now.format("%y-%m-%d").to_string()),
+        'W' => out.push_str(&ctx.now.format("%m/%d/%y").to_string()),
+        '%' => out.push_char('%'),
+        'B' => out.style.bold = true,
+        'b' => out.style.bold = false,
+        'f' => out.style.fg = None,
+        'k' => out.style.bg = None,
+        '{' => out.zero_width = true,
+        '}' => out.zero_width = false,
+        'F' | 'K' => {
+            let (color, next) = match brace_argument(chars, j + 1) {
+                Some((arg, next)) => (parse_color(&arg), next),
+                None => (count.map(|c| c as u8), j + 1),
+            };
+            if code == 'F' {
+                out.style.fg = color;
+            } else {
+                out.style.bg = color;
+            }
+            return next;
+        }
+        other => {
+            out.push_char('%');
+            out.push_str(&chars[i..j].iter().collect::<String>());
+            out.push_char(other);
+        }
+    }
+    j + 1
+}
+
+fn expand_placeholder(chars: &[char], i: usize, ctx: &PromptContext, out: &mut SegmentBuilder) -> usize {
+    let (name, next) = match brace_argument(chars, i) {
+        Some(arg) => arg,
+        None => {
+            out.push_char('{');
+            return i + 1;
+        }
+    };
+
+    let value = match name.as_str() {
+        "git_branch" => ctx.git_branch.unwrap_or("").to_string(),
+        "exit_code" => ctx.exit_code.map(|c| c.to_string()).unwrap_or_default(),
+        "duration" => ctx.duration_ms.map(format_duration).unwrap_or_default(),
+        "k8s_context" => ctx.k8s_context.unwrap_or("").to_string(),
+        "time" => ctx.now.format("%H:%M:%S").to_string(),
+        "user" => ctx.user.to_string(),
+        "host" => short_host(ctx.host).to_string(),
+        "cwd" => tilde_path(ctx.cwd, ctx.home),
+        other => match ctx.extra.and_then(|extra| extra.get(other)) {
+            Some(value) => value.clone(),
+            None => {
+                out.push_char('{');
+                return i + 1;
+            }
+        },
+    };
+    out.push_str(&value);
+    next
+}
+
+/// `{...}` starting at `i`; returns the contents and the index past the closing brace
+fn brace_argument(chars: &[char], i: usize) -> Option<(String, usize)> {
+    if chars.get(i) != Some(&'{') {
+        return None;
+    }
+    let close = (i + 1..chars.len()).find(|&j| chars[j] == '}')?;
+    Some((chars[i + 1..close].iter().collect(), close + 1))
+}
+
+fn apply_escape_char(chars: &[char], i: usize, out: &mut SegmentBuilder) -> usize {
+    if chars.get(i) == Some(&'[') {
+        apply_csi(chars, i + 1, out)
+    } else {
+        out.push_char('\x1b');
+        i
+    }
+}
+
+fn apply_ansi_sequence(chars: &[char], i: usize, out: &mut SegmentBuilder) -> usize {
+    apply_escape_char(chars, i + 1, out)
+}
+
+/// Interpret an SGR sequence as a style change; keep any other CSI as zero-width output
+fn apply_csi(chars: &[char], i: usize, out: &mut SegmentBuilder) -> usize {
+    let end = match (i..chars.len()).find(|&j| chars[j].is_ascii_alphabetic()) {
+        Some(end) => end,
+        None => return chars.len(),
+    };
+    let params: String = chars[i..end].iter().collect();
+
+    if chars[end] != 'm' {
+        let was_zero_width = out.zero_width;
+        out.zero_width = true;
+        out.push_str(&format!("\x1b[{}{}", params, chars[end]));
+        out.zero_width = was_zero_width;
+        return end + 1;
+    }
+
+    let codes: Vec<u16> = params.split(';').map(|p| p.parse().unwrap_or(0)).collect();
+    let mut k = 0;
+    while k < codes.len() {
+        match codes[k] {
+            0 => out.style = StyleState::default(),
+            1 => out.style.bold = true,
+            22 => out.style.bold = false,
+            c @ 30..=37 => out.style.fg = Some((c - 30) as u8),
+            c @ 90..=97 => out.style.fg = Some((c - 90 + 8) as u8),
+            c @ 40..=47 => out.style.bg = Some((c - 40) as u8),
+            c @ 100..=107 => out.style.bg = Some((c - 100 + 8) as u8),
+            39 => out.style.fg = None,
+            49 => out.style.bg = None,
+            // Extended colours take their arguments with them: `38;5;n` from
+            // the 256-colour palette, `38;2;r;g;b` mapped onto it
+            38 | 48 => {
+                let (color, len) = match codes.get(k + 1) {
+                    Some(5) => (codes.get(k + 2).map(|&c| c as u8), 2),
+                    Some(2) => match codes.get(k + 2..k + 5) {
+                        Some(&[r, g, b]) => (Some(rgb_to_ansi256(r, g, b)), 4),
+                        _ => (None, codes.len() - k - 1),
+                    },
+                    _ => (None, 0),
+                };
+                if let Some(color) = color {
+                    if codes[k] == 38 {
+                        out.style.fg = Some(color);
+                    } else {
+                        out.style.bg = Some(color);
+                    }
+                }
+                k += len;
+            }
+            _ => {}
+        }
+        k += 1;
+    }
+    end + 1
+}
+
+/// Nearest entry of the xterm 256-colour palette: the 6x6x6 cube or the grey ramp
+fn rgb_to_ansi256(r: u16, g: u16, b: u16) -> u8 {
+    let [r, g, b] = [r, g, b].map(|c| c.min(255) as i32);
+    let cube_level = |c: i32| if c < 48 { 0 } else if c < 115 { 1 } else { (c - 35) / 40 };
+    let level_value = |l: i32| if l == 0 { 0 } else { 55 + l * 40 };
+    let (cr, cg, cb) = (cube_level(r), cube_level(g), cube_level(b));
+    let cube = (level_value(cr), level_value(cg), level_value(cb));
+
+    let grey_index = (((r + g + b) / 3 - 3) / 10).clamp(0, 23);
+    let grey = 8 + grey_index * 10;
+
+    let distance = |(x, y, z): (i32, i32, i32)| (x - r).pow(2) + (y - g).pow(2) + (z - b).pow(2);
+    if distance((grey, grey, grey)) < distance(cube) {
+        (232 + grey_index) as u8
+    } else {
+        (16 + 36 * cr + 6 * cg + cb) as u8
+    }
+}
+
+fn parse_color(name: &str) -> Option<u8> {
+    match name.to_lowercase().as_str() {
+        "black" => Some(0),
+        "red" => Some(1),
+        "green" => Some(2),
+        "yellow" => Some(3),
+        "blue" => Some(4),
+        "magenta" => Some(5),
+        "cyan" => Some(6),
+        "white" => Some(7),
+        other => other.parse().ok(),
+    }
+}
+
+fn short_host(host: &str) -> &str {
+    host.split('.').next().unwrap_or(host)
+}
+
+fn tilde_path(cwd: &str, home: Option<&str>) -> String {
+    match home {
+        Some(home) if !home.is_empty() && cwd == home => "~".to_string(),
+        Some(home) if !home.is_empty() && cwd.starts_with(&format!("{}/", home.trim_end_matches('/'))) => {
+            format!("~{}", &cwd[home.trim_end_matches('/').len()..])
+        }
+        _ => cwd.to_string(),
+    }
+}
+
+fn basename(cwd: &str, home: Option<&str>) -> String {
+    let path = tilde_path(cwd, home);
+    if path == "~" || path == "/" {
+        return path;
+    }
+    path.trim_end_matches('/').rsplit('/').next().unwrap_or(&path).to_string()
+}
+
+fn trailing_components(path: &str, count: Option<usize>) -> String {
+    match count {
+        Some(n) if n > 0 => {
+            let parts: Vec<&str> = path.split('/').filter(|p| !p.is_empty()).collect();
+            if parts.len() <= n {
+                path.to_string()
+            } else {
+                parts[parts.len() - n..].join("/")
+            }
+        }
+        _ => path.to_string(),
+    }
+}
+
+/// Human-readable duration: 350ms, 1.2s, 2m3s, 1h4m
+pub fn format_duration(ms: u64) -> String {
+    match ms {
+        0..=999 => format!("{}ms", ms),
+        1_000..=59_999 => format!("{:.1}s", ms as f64 / 1000.0),
+        60_000..=3_599_999 => format!("{}m{}s", ms / 60_000, (ms / 1000) % 60),
+        _ => format!("{}h{}m", ms / 3_600_000, (ms / 60_000) % 60),
+    }
+}
+
+/// Terminal columns taken by `text`: zero for control and combining characters,
+/// two for wide East Asian characters and emoji
+pub fn display_width(text: &str) -> usize {
+    text.chars().map(char_width).sum()
+}
+
+fn char_width(c: char) -> usize {
+    let cp = c as u32;
+    if c.is_control()
+        || (0x0300..=0x036F).contains(&cp)
+        || (0x200B..=0x200F).contains(&cp)
+        || (0xFE00..=0xFE0F).contains(&cp)
+    {
+        return 0;
+    }
+    let wide = (0x1100..=0x115F).contains(&cp)
+        || (0x2E80..=0xA4CF).contains(&cp)
+        || (0xAC00..=0xD7A3).contains(&cp)
+        || (0xF900..=0xFAFF).contains(&cp)
+        || (0xFE30..=0xFE4F).contains(&cp)
+        || (0xFF00..=0xFF60).contains(&cp)
+        || (0xFFE0..=0xFFE6).contains(&cp)
+        || (0x1F300..=0x1FAFF).contains(&cp)
+        || (0x20000..=0x3FFFD).contains(&cp);
+    if wide {
+        2
+    } else {
+        1
+    }
+}
+
+/// Total visible width of a rendered prompt
+pub fn segments_width(segments: &[PromptSegment]) -> usize {
+    segments.iter().map(|s| s.width()).sum()
+}
+
+/// Turn segments back into a terminal string using 256-colour SGR codes
+pub fn segments_to_ansi(segments: &[PromptSegment]) -> String {
+    let mut result = String::new();
+    for segment in segments {
+        let styled = segment.fg.is_some() || segment.bg.is_some() || segment.bold;
+        if styled && !segment.zero_width {
+            if let Some(fg) = segment.fg {
+                result.push_str(&format!("\x1b[38;5;{}m", fg));
+            }
+            if let Some(bg) = segment.bg {
+                result.push_str(&format!("\x1b[48;5;{}m", bg));
+            }
+            if segment.bold {
+                result.push_str("\x1b[1m");
+            }
+            result.push_str(&segment.text);
+            result.push_str("\x1b[0m");
+        } else {
+            result.push_str(&segment.text);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn context() -> PromptContext<'static> {
+        PromptContext {
+            user: "joey",
+            host: "noble.local",
+            cwd: "/home/joey/src/warp",
+            home: Some("/home/joey"),
+            git_branch: Some("main"),
+            exit_code: Some(1),
+            duration_ms: Some(1250),
+            k8s_context: Some("prod"),
+            jobs: 2,
+            history_number: 42,
+            is_root: false,
+            now: Local.with_ymd_and_hms(2024, 5, 26, 9, 5, 7).unwrap(),
+            extra: None,
+        }
+    }
+
+    fn text(segments: &[PromptSegment]) -> String {
+        segments.iter().map(|s| s.text.as_str()).collect()
+    }
+
+    #[test]
+    fn test_bash_escapes() {
+        let segments = render_template("\\u@\\h:\\w \\W \\t \\j \\!\\$ ", PromptDialect::Bash, &context());
+        assert_eq!(text(&segments), "joey@noble:~/src/warp warp 09:05:07 2 42$ ");
+    }
+
+    #[test]
+    fn test_bash_colors_are_zero_width() {
+        let segments = render_template("\\[\\e[32m\\]\\u\\[\\e[0m\\] $ ", PromptDialect::Bash, &context());
+        assert_eq!(segments[0].text, "joey");
+        assert_eq!(segments[0].fg, Some(2));
+        assert_eq!(segments_width(&segments), "joey $ ".len());
+    }
+
+    #[test]
+    fn test_extended_sgr_colors() {
+        // The 1 and 31 inside the truecolor arguments aren't bold and red
+        let segments = render_template("\\[\\e[38;2;255;1;31m\\]a\\[\\e[48;5;236;1m\\]b", PromptDialect::Bash, &context());
+        assert_eq!(text(&segments), "ab");
+        assert_eq!(segments[0].fg, Some(196));
+        assert!(!segments[0].bold);
+        assert_eq!(segments[1].bg, Some(236));
+        assert!(segments[1].bold);
+        assert_eq!(rgb_to_ansi256(128, 128, 128), 244);
+        assert_eq!(rgb_to_ansi256(0, 95, 255), 27);
+    }
+
+    #[test]
+    fn test_zsh_escapes() {
+        let segments = render_template("%F{blue}%n%f@%m %1~ %B%#%b ", PromptDialect::Zsh, &context());
+        assert_eq!(text(&segments), "joey@noble warp % ");
+        assert_eq!(segments[0].fg, Some(4));
+        assert!(segments.iter().any(|s| s.bold && s.text == "%"));
+    }
+
+    #[test]
+    fn test_placeholders() {
+        let segments = render_template("{git_branch} [{exit_code}] {duration} {k8s_context} {nope}", PromptDialect::Any, &context());
+        assert_eq!(text(&segments), "main [1] 1.2s prod {nope}");
+    }
+
+    #[test]
+    fn test_display_width() {
+        assert_eq!(display_width("❯ 日本"), 6);
+        assert_eq!(display_width("e\u{301}"), 1);
+    }
+}
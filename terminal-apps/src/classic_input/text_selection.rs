@@ -4,11 +4,90 @@ pub enum SelectionMode {
     Rectangular, // Column selection
 }
 
+/// A (row, column) location in multi-line text, counted in chars
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+    pub row: usize,
+    pub col: usize,
+}
+
+impl Position {
+    pub fn new(row: usize, col: usize) -> Self {
+        Self { row, col }
+    }
+
+    /// Position of the char at `offset`
+    pub fn from_offset(text: &str, offset: usize) -> Self {
+        let mut row = 0;
+        let mut col = 0;
+        for ch in text.chars().take(offset) {
+            if ch == '\n' {
+                row += 1;
+                col = 0;
+            } else {
+                col += 1;
+            }
+        }
+        Self { row, col }
+    }
+
+    /// Char offset of this position, clamped to the end of its line
+    pub fn to_offset(&self, text: &str) -> usize {
+        let mut offset = 0;
+        for (row, line) in text.split('\n').enumerate() {
+            let len = line.chars().count();
+            if row == self.row {
+                return offset + self.col.min(len);
+            }
+            offset += len + 1;
+        }
+        text.chars().count()
+    }
+}
+
+/// A selection between two char offsets. Rectangular selections treat the
+/// offsets as opposite corners of a block.
 #[derive(Debug, Clone)]
 pub struct Selection {
     pub start: usize,
     pub end: usize,
     pub mode: SelectionMode,
+    /// Block corners as (row, column) when the block was made by position.
+    /// Columns may lie past the end of short lines, so dragging across one
+    /// doesn't narrow the block; they are clamped per line on extraction.
+    pub corners: Option<(Position, Position)>,
+}
+
+impl Selection {
+    pub fn range(&self) -> (usize, usize) {
+        (self.start.min(self.end), self.start.max(self.end))
+    }
+
+    pub fn text(&self, text: &str) -> String {
+        match self.mode {
+            SelectionMode::Smart => {
+                let (start, end) = self.range();
+                text.chars().skip(start).take(end - start).collect()
+            }
+            SelectionMode::Rectangular => {
+                let (a, b) = self.corners.unwrap_or_else(|| {
+                    (Position::from_offset(text, self.start), Position::from_offset(text, self.end))
+                });
+                let (first_col, last_col) = (a.col.min(b.col), a.col.max(b.col));
+                text.split('\n')
+                    .skip(a.row.min(b.row))
+                    .take(a.row.abs_diff(b.row) + 1)
+                    .map(|line| {
+                        line.chars()
+                            .skip(first_col)
+                            .take(last_col - first_col)
+                            .collect::<String>()
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -28,18 +107,48 @@ impl TextSelection {
     }
 
     pub fn start_selection(&mut self, pos: usize) {
-        self.active = true;
         self.selections.clear();
+        self.add_selection(pos);
+    }
+
+    /// Start another selection, keeping the existing ones (e.g. cmd/alt-click)
+    pub fn add_selection(&mut self, pos: usize) {
+        self.active = true;
         self.selections.push(Selection {
             start: pos,
             end: pos,
             mode: self.mode,
+            corners: None,
         });
     }
 
+    /// Start a block selection at a (row, column) corner, which may lie past
+    /// the end of its line
+    pub fn start_block_selection(&mut self, text: &str, corner: Position) {
+        self.mode = SelectionMode::Rectangular;
+        self.start_selection(corner.to_offset(text));
+        if let Some(selection) = self.selections.last_mut() {
+            selection.corners = Some((corner, corner));
+        }
+    }
+
+    /// Move the current selection's end to a char offset. A block made by
+    /// position falls back to the offsets of its corners.
     pub fn extend_selection(&mut self, pos: usize) {
         if let Some(selection) = self.selections.last_mut() {
             selection.end = pos;
+            selection.corners = None;
+        }
+    }
+
+    /// Drag the current selection's moving corner to a (row, column) position
+    pub fn extend_to_position(&mut self, text: &str, position: Position) {
+        let Some(selection) = self.selections.last_mut() else {
+            return;
+        };
+        selection.end = position.to_offset(text);
+        if let Some((_, moving)) = &mut selection.corners {
+            *moving = position;
         }
     }
 
@@ -54,17 +163,42 @@ impl TextSelection {
         };
     }
 
+    pub fn mode(&self) -> SelectionMode {
+        self.mode
+    }
+
+    /// Handle a mouse click: a single click starts a selection, a double click
+    /// selects the word or smart token under the cursor and a triple click the
+    /// whole line. With `additive`, earlier selections are kept.
+    pub fn click(&mut self, text: &str, pos: usize, click_count: u8, additive: bool) {
+        let range = match click_count {
+            0 | 1 => None,
+            2 if self.mode == SelectionMode::Smart => Some(smart_range(text, pos)),
+            2 => Some(word_range(text, pos)),
+            _ => Some(line_range(text, pos)),
+        };
+
+        if !additive {
+            self.selections.clear();
+        }
+        match range {
+            Some((start, end)) => {
+                self.selections.push(Selection {
+                    start,
+                    end,
+                    mode: SelectionMode::Smart,
+                    corners: None,
+                });
+                self.active = false;
+            }
+            None => self.add_selection(pos),
+        }
+    }
+
     pub fn get_selected_text(&self, text: &str) -> String {
         self.selections
             .iter()
-            .map(|sel| {
-                let start = sel.start.min(sel.end);
-                let end = sel.start.max(sel.end);
-                text.chars()
-                    .skip(start)
-                    .take(end - start)
-                    .collect::<String>()
-            })
+            .map(|sel| sel.text(text))
             .collect::<Vec<_>>()
             .join("\n")
     }
@@ -88,3 +222,170 @@ impl Default for TextSelection {
         Self::new()
     }
 }
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Expand `pos` to the surrounding run of chars satisfying `pred`
+fn expand_while(chars: &[char], pos: usize, pred: impl Fn(char) -> bool) -> (usize, usize) {
+    if pos >= chars.len() || !pred(chars[pos]) {
+        return (pos, pos);
+    }
+    let mut start = pos;
+    while start > 0 && pred(chars[start - 1]) {
+        start -= 1;
+    }
+    let mut end = pos;
+    while end < chars.len() && pred(chars[end]) {
+        end += 1;
+    }
+    (start, end)
+}
+
+/// Char range of the word under `pos`
+pub fn word_range(text: &str, pos: usize) -> (usize, usize) {
+    let chars: Vec<char> = text.chars().collect();
+    expand_while(&chars, pos, is_word_char)
+}
+
+/// Char range of the line containing `pos`, without the newline
+pub fn line_range(text: &str, pos: usize) -> (usize, usize) {
+    let chars: Vec<char> = text.chars().collect();
+    let pos = pos.min(chars.len());
+    let start = chars[..pos].iter().rposition(|&c| c == '\n').map(|i| i + 1).unwrap_or(0);
+    let end = chars[pos..].iter().position(|&c| c == '\n').map(|i| pos + i).unwrap_or(chars.len());
+    (start, end)
+}
+
+/// Semantic expansion: the contents of a quoted string, or a URL, IP address or
+/// path under `pos`, falling back to the word
+pub fn smart_range(text: &str, pos: usize) -> (usize, usize) {
+    let chars: Vec<char> = text.chars().collect();
+    if pos >= chars.len() {
+        return (pos, pos);
+    }
+
+    if let Some(range) = quoted_range(&chars, pos) {
+        return range;
+    }
+
+    let (start, end) = expand_while(&chars, pos, |c| !c.is_whitespace() && !"\"'`".contains(c));
+    let (start, end) = trim_token(&chars, start, end);
+    if start <= pos && pos < end {
+        let token: String = chars[start..end].iter().collect();
+        if token.contains("://") || is_ip_address(&token) || looks_like_path(&token) {
+            return (start, end);
+        }
+    }
+
+    word_range(text, pos)
+}
+
+/// Inside a "..", '..' or `..` string on the same line: the range between the quotes
+fn quoted_range(chars: &[char], pos: usize) -> Option<(usize, usize)> {
+    let (line_start, line_end) = {
+        let start = chars[..pos].iter().rposition(|&c| c == '\n').map(|i| i + 1).unwrap_or(0);
+        let end = chars[pos..].iter().position(|&c| c == '\n').map(|i| pos + i).unwrap_or(chars.len());
+        (start, end)
+    };
+
+    let mut open: Option<(char, usize)> = None;
+    for i in line_start..line_end {
+        let c = chars[i];
+        match open {
+            Some((quote, start)) if c == quote && (i == 0 || chars[i - 1] != '\\') => {
+                if start < pos && pos < i {
+                    return Some((start + 1, i));
+                }
+                open = None;
+            }
+            None if c == '"' || c == '\'' || c == '`' => open = Some((c, i)),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Drop punctuation that usually surrounds rather than belongs to a token
+fn trim_token(chars: &[char], mut start: usize, mut end: usize) -> (usize, usize) {
+    while start < end && "([{<".contains(chars[start]) {
+        start += 1;
+    }
+    while end > start && ".,;:!?)]}>".contains(chars[end - 1]) {
+        end -= 1;
+    }
+    (start, end)
+}
+
+fn is_ip_address(token: &str) -> bool {
+    let host = match token.rsplit_once(':') {
+        Some((host, port)) if !port.is_empty() && port.chars().all(|c| c.is_ascii_digit()) => host,
+        _ => token,
+    };
+    let octets: Vec<&str> = host.split('.').collect();
+    octets.len() == 4 && octets.iter().all(|o| !o.is_empty() && o.len() <= 3 && o.parse::<u8>().is_ok())
+}
+
+fn looks_like_path(token: &str) -> bool {
+    token.contains('/') || token.starts_with('~') || token.starts_with("./") || token.starts_with("../")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "alpha beta\ngamma delta\nepsilon";
+
+    #[test]
+    fn test_rectangular_selection() {
+        let mut selection = TextSelection::new();
+        selection.start_block_selection(TEXT, Position::new(0, 1));
+        selection.extend_to_position(TEXT, Position::new(2, 4));
+        assert_eq!(selection.get_selected_text(TEXT), "lph\namm\npsi");
+
+        // Columns past a short line's end are kept, and clamped only per line
+        selection.start_block_selection(TEXT, Position::new(0, 8));
+        selection.extend_to_position(TEXT, Position::new(2, 10));
+        assert_eq!(selection.get_selected_text(TEXT), "ta\nlt\n");
+        selection.start_block_selection(TEXT, Position::new(2, 10));
+        selection.extend_to_position(TEXT, Position::new(1, 6));
+        assert_eq!(selection.get_selected_text(TEXT), "delt\nn");
+    }
+
+    #[test]
+    fn test_additive_selections() {
+        let mut selection = TextSelection::new();
+        selection.start_selection(0);
+        selection.extend_selection(5);
+        selection.add_selection(11);
+        selection.extend_selection(16);
+        assert_eq!(selection.get_selections().len(), 2);
+        assert_eq!(selection.get_selected_text(TEXT), "alpha\ngamma");
+    }
+
+    #[test]
+    fn test_click_expansion() {
+        let text = "curl https://example.com/a?b=1, then ssh 10.0.0.12:22 \"hello world\"";
+        let mut selection = TextSelection::new();
+
+        selection.click(text, 10, 2, false);
+        assert_eq!(selection.get_selected_text(text), "https://example.com/a?b=1");
+
+        selection.click(text, 44, 2, false);
+        assert_eq!(selection.get_selected_text(text), "10.0.0.12:22");
+
+        selection.click(text, 58, 2, true);
+        assert_eq!(selection.get_selected_text(text), "10.0.0.12:22\nhello world");
+
+        selection.click(TEXT, 13, 3, false);
+        assert_eq!(selection.get_selected_text(TEXT), "gamma delta");
+    }
+
+    #[test]
+    fn test_smart_path_and_word_fallback() {
+        let text = "error in (src/main.rs:12): oops";
+        assert_eq!(smart_range(text, 12), (10, 24));
+        assert_eq!(smart_range(text, 28), (27, 31));
+    }
+}
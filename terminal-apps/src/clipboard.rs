@@ -0,0 +1,383 @@
+use base64::Engine;
+use std::fmt;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use thiserror::Error;
+
+pub const BRACKETED_PASTE_ENABLE: &str = "\x1b[?2004h";
+pub const BRACKETED_PASTE_DISABLE: &str = "\x1b[?2004l";
+const PASTE_START: &str = "\x1b[200~";
+const PASTE_END: &str = "\x1b[201~";
+
+#[derive(Error, Debug)]
+pub enum ClipboardError {
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("Clipboard command failed: {0}")]
+    CommandFailed(String),
+    #[error("Clipboard backend '{0}' cannot read the clipboard")]
+    PasteUnsupported(&'static str),
+    #[error("Nothing to copy: {0}")]
+    NothingToCopy(String),
+}
+
+pub type ClipboardResult<T> = Result<T, ClipboardError>;
+
+/// Somewhere text can be copied to and pasted from
+pub trait ClipboardBackend: fmt::Debug + Send {
+    fn name(&self) -> &'static str;
+    fn copy(&mut self, text: &str) -> ClipboardResult<()>;
+    fn paste(&mut self) -> ClipboardResult<String>;
+}
+
+/// Copies through the terminal with an OSC 52 escape sequence, which works
+/// over SSH and, with passthrough, inside tmux. Reading back is not supported
+/// because most terminals disable OSC 52 queries.
+pub struct Osc52Clipboard {
+    writer: Box<dyn Write + Send>,
+    tmux: bool,
+}
+
+impl Osc52Clipboard {
+    pub fn new(writer: Box<dyn Write + Send>) -> Self {
+        Self {
+            writer,
+            tmux: std::env::var_os("TMUX").is_some(),
+        }
+    }
+
+    pub fn stdout() -> Self {
+        Self::new(Box::new(io::stdout()))
+    }
+
+    pub fn with_tmux_passthrough(mut self, tmux: bool) -> Self {
+        self.tmux = tmux;
+        self
+    }
+}
+
+impl fmt::Debug for Osc52Clipboard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Osc52Clipboard").field("tmux", &self.tmux).finish()
+    }
+}
+
+impl ClipboardBackend for Osc52Clipboard {
+    fn name(&self) -> &'static str {
+        "osc52"
+    }
+
+    fn copy(&mut self, text: &str) -> ClipboardResult<()> {
+        self.writer.write_all(osc52_sequence(text, self.tmux).as_bytes())?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn paste(&mut self) -> ClipboardResult<String> {
+        Err(ClipboardError::PasteUnsupported(self.name()))
+    }
+}
+
+/// The OSC 52 sequence setting the clipboard to `text`, wrapped in a DCS
+/// passthrough for tmux when `tmux` is set
+pub fn osc52_sequence(text: &str, tmux: bool) -> String {
+    let encoded = base64::engine::general_purpose::STANDARD.encode(text);
+    let sequence = format!("\x1b]52;c;{}\x07", encoded);
+    if tmux {
+        format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b"))
+    } else {
+        sequence
+    }
+}
+
+/// Copies and pastes by piping through external programs such as `xclip` or `wl-copy`
+#[derive(Debug, Clone)]
+pub struct CommandClipboard {
+    name: &'static str,
+    copy_command: Vec<String>,
+    paste_command: Vec<String>,
+}
+
+impl CommandClipboard {
+    pub fn new(name: &'static str, copy_command: &[&str], paste_command: &[&str]) -> Self {
+        Self {
+            name,
+            copy_command: copy_command.iter().map(|s| s.to_string()).collect(),
+            paste_command: paste_command.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    pub fn xclip() -> Self {
+        Self::new(
+            "xclip",
+            &["xclip", "-selection", "clipboard", "-in"],
+            &["xclip", "-selection", "clipboard", "-out"],
+        )
+    }
+
+    pub fn wayland() -> Self {
+        Self::new("wl-clipboard", &["wl-copy"], &["wl-paste", "--no-newline"])
+    }
+
+    /// The backend for the current graphical session, if there is one
+    pub fn detect() -> Option<Self> {
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            Some(Self::wayland())
+        } else if std::env::var_os("DISPLAY").is_some() {
+            Some(Self::xclip())
+        } else {
+            None
+        }
+    }
+}
+
+impl ClipboardBackend for CommandClipboard {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn copy(&mut self, text: &str) -> ClipboardResult<()> {
+        let (program, args) = self.copy_command.split_first().expect("copy command is not empty");
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(ClipboardError::CommandFailed(format!("{} exited with {}", program, status)));
+        }
+        Ok(())
+    }
+
+    fn paste(&mut self) -> ClipboardResult<String> {
+        let (program, args) = self.paste_command.split_first().expect("paste command is not empty");
+        let output = Command::new(program).args(args).stderr(Stdio::null()).output()?;
+        if !output.status.success() {
+            return Err(ClipboardError::CommandFailed(format!("{} exited with {}", program, output.status)));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+/// Process-local clipboard, used in tests and when nothing else is available
+#[derive(Debug, Clone, Default)]
+pub struct MemoryClipboard {
+    contents: String,
+}
+
+impl MemoryClipboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ClipboardBackend for MemoryClipboard {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn copy(&mut self, text: &str) -> ClipboardResult<()> {
+        self.contents = text.to_string();
+        Ok(())
+    }
+
+    fn paste(&mut self) -> ClipboardResult<String> {
+        Ok(self.contents.clone())
+    }
+}
+
+#[derive(Debug)]
+pub struct Clipboard {
+    backend: Box<dyn ClipboardBackend>,
+}
+
+impl Clipboard {
+    pub fn new(backend: Box<dyn ClipboardBackend>) -> Self {
+        Self { backend }
+    }
+
+    /// Pick a backend for the environment: OSC 52 over SSH, otherwise the
+    /// display server's tools, otherwise OSC 52 to the local terminal
+    pub fn detect() -> Self {
+        let over_ssh = std::env::var_os("SSH_TTY").is_some() || std::env::var_os("SSH_CONNECTION").is_some();
+        match CommandClipboard::detect() {
+            Some(backend) if !over_ssh => Self::new(Box::new(backend)),
+            _ => Self::new(Box::new(Osc52Clipboard::stdout())),
+        }
+    }
+
+    pub fn in_memory() -> Self {
+        Self::new(Box::new(MemoryClipboard::new()))
+    }
+
+    pub fn backend_name(&self) -> &'static str {
+        self.backend.name()
+    }
+
+    pub fn copy(&mut self, text: &str) -> ClipboardResult<()> {
+        if text.is_empty() {
+            return Err(ClipboardError::NothingToCopy("empty text".to_string()));
+        }
+        self.backend.copy(text)
+    }
+
+    pub fn paste(&mut self) -> ClipboardResult<String> {
+        self.backend.paste()
+    }
+}
+
+impl Default for Clipboard {
+    fn default() -> Self {
+        Self::detect()
+    }
+}
+
+/// A piece of terminal input, split on bracketed paste markers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PasteInput {
+    Typed(String),
+    Pasted(String),
+}
+
+/// Splits raw terminal input into typed text and bracketed pastes, coping with
+/// markers that arrive split across reads
+#[derive(Debug, Clone, Default)]
+pub struct BracketedPasteParser {
+    buffer: String,
+    in_paste: bool,
+}
+
+impl BracketedPasteParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_pasting(&self) -> bool {
+        self.in_paste
+    }
+
+    pub fn feed(&mut self, chunk: &str) -> Vec<PasteInput> {
+        self.buffer.push_str(chunk);
+        let mut events = Vec::new();
+
+        loop {
+            let marker = if self.in_paste { PASTE_END } else { PASTE_START };
+            match self.buffer.find(marker) {
+                Some(idx) => {
+                    let before: String = self.buffer.drain(..idx).collect();
+                    self.buffer.drain(..marker.len());
+                    if self.in_paste {
+                        events.push(PasteInput::Pasted(before));
+                    } else if !before.is_empty() {
+                        events.push(PasteInput::Typed(before));
+                    }
+                    self.in_paste = !self.in_paste;
+                }
+                None => {
+                    // Pasted text is only emitted once the end marker arrives
+                    if !self.in_paste {
+                        let keep = partial_marker_len(&self.buffer, PASTE_START);
+                        let typed: String = self.buffer.drain(..self.buffer.len() - keep).collect();
+                        if !typed.is_empty() {
+                            events.push(PasteInput::Typed(typed));
+                        }
+                    }
+                    return events;
+                }
+            }
+        }
+    }
+}
+
+/// Length of the longest suffix of `text` that is a prefix of `marker`
+fn partial_marker_len(text: &str, marker: &str) -> usize {
+    (1..marker.len())
+        .rev()
+        .find(|&n| text.ends_with(&marker[..n]))
+        .unwrap_or(0)
+}
+
+/// Normalise pasted text for the editor: CRLF/CR become LF and control
+/// characters other than newline and tab are dropped
+pub fn sanitize_paste(text: &str) -> String {
+    text.replace("\r\n", "\n")
+        .replace('\r', "\n")
+        .chars()
+        .filter(|&c| c == '\n' || c == '\t' || !c.is_control())
+        .collect()
+}
+
+/// Whether pasting `text` should ask for confirmation, because submitting it
+/// would run more than one line
+pub fn needs_paste_confirmation(text: &str) -> bool {
+    text.trim_end_matches('\n').contains('\n')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_osc52_sequences() {
+        assert_eq!(osc52_sequence("hi", false), "\x1b]52;c;aGk=\x07");
+        assert_eq!(osc52_sequence("hi", true), "\x1bPtmux;\x1b\x1b]52;c;aGk=\x07\x1b\\");
+
+        let buffer = SharedBuffer::default();
+        let mut clipboard = Clipboard::new(Box::new(
+            Osc52Clipboard::new(Box::new(buffer.clone())).with_tmux_passthrough(false),
+        ));
+        clipboard.copy("hi").unwrap();
+        assert_eq!(buffer.0.lock().unwrap().as_slice(), b"\x1b]52;c;aGk=\x07");
+        assert!(matches!(clipboard.paste(), Err(ClipboardError::PasteUnsupported("osc52"))));
+    }
+
+    #[test]
+    fn test_memory_clipboard_round_trip() {
+        let mut clipboard = Clipboard::in_memory();
+        clipboard.copy("cargo test").unwrap();
+        assert_eq!(clipboard.paste().unwrap(), "cargo test");
+        assert!(clipboard.copy("").is_err());
+    }
+
+    #[test]
+    fn test_bracketed_paste_split_across_reads() {
+        let mut parser = BracketedPasteParser::new();
+        assert_eq!(parser.feed("ls\x1b[20"), vec![PasteInput::Typed("ls".to_string())]);
+        assert!(parser.feed("0~echo a\r\necho").is_empty());
+        assert!(parser.is_pasting());
+        assert_eq!(
+            parser.feed(" b\x1b[201~x"),
+            vec![
+                PasteInput::Pasted("echo a\r\necho b".to_string()),
+                PasteInput::Typed("x".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sanitize_and_confirmation() {
+        assert_eq!(sanitize_paste("a\r\nb\x1b[31m\tc\r"), "a\nb[31m\tc\n");
+        assert!(!needs_paste_confirmation("ls -la\n"));
+        assert!(needs_paste_confirmation("cd /tmp\nrm -rf *"));
+    }
+}
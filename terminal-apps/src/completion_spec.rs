@@ -0,0 +1,159 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use thiserror::Error;
+
+/// Declarative completions for a command, in the spirit of Fig's specs: the
+/// root is the command itself, with nested subcommands, options and arguments
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CompletionSpec {
+    /// Every name the command answers to, e.g. `["remove", "rm"]`
+    #[serde(deserialize_with = "one_or_many")]
+    pub name: Vec<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub subcommands: Vec<CompletionSpec>,
+    #[serde(default)]
+    pub options: Vec<OptionSpec>,
+    #[serde(default)]
+    pub args: Vec<ArgSpec>,
+}
+
+/// A flag such as `-b` / `--branch`, possibly taking arguments
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct OptionSpec {
+    #[serde(deserialize_with = "one_or_many")]
+    pub name: Vec<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub args: Vec<ArgSpec>,
+}
+
+/// A positional argument and where its values come from
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ArgSpec {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Fixed values to offer
+    #[serde(default)]
+    pub suggestions: Vec<String>,
+    /// Filesystem completion instead of (or as well as) suggestions
+    #[serde(default)]
+    pub template: Option<ArgTemplate>,
+    #[serde(default)]
+    pub generators: Vec<Generator>,
+    #[serde(default)]
+    pub optional: bool,
+    /// Repeats for every remaining word
+    #[serde(default)]
+    pub variadic: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArgTemplate {
+    Filepaths,
+    Folders,
+}
+
+/// A shell command whose output lines become suggestions, e.g. branch names
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Generator {
+    pub script: String,
+    /// Separator between suggestions in the output
+    #[serde(default = "default_split_on")]
+    pub split_on: String,
+    /// Removed from the start of each suggestion, like git's `* ` for the current branch
+    #[serde(default)]
+    pub strip_prefix: Option<String>,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_split_on() -> String {
+    "\n".to_string()
+}
+
+fn default_timeout_ms() -> u64 {
+    1000
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(name) => vec![name],
+        OneOrMany::Many(names) => names,
+    })
+}
+
+impl CompletionSpec {
+    /// The name the command is registered under
+    pub fn primary_name(&self) -> &str {
+        self.name.first().map(String::as_str).unwrap_or("")
+    }
+
+    pub fn matches(&self, word: &str) -> bool {
+        self.name.iter().any(|n| n == word)
+    }
+
+    pub fn subcommand(&self, word: &str) -> Option<&CompletionSpec> {
+        self.subcommands.iter().find(|s| s.matches(word))
+    }
+
+    pub fn option(&self, word: &str) -> Option<&OptionSpec> {
+        self.options.iter().find(|o| o.name.iter().any(|n| n == word))
+    }
+}
+
+/// Completion spec loading errors
+#[derive(Error, Debug)]
+pub enum CompletionSpecError {
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("YAML parse error: {0}")]
+    YamlError(#[from] serde_yaml::Error),
+    #[error("JSON parse error: {0}")]
+    JsonError(#[from] serde_json::Error),
+}
+
+pub type CompletionSpecResult<T> = Result<T, CompletionSpecError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_yaml_spec() {
+        let yaml = r#"
+name: git
+description: Version control
+subcommands:
+  - name: [checkout, co]
+    description: Switch branches
+    options:
+      - name: [-b]
+        description: Create a branch
+        args:
+          - name: branch
+    args:
+      - name: branch
+        generators:
+          - script: git branch
+            strip_prefix: "* "
+"#;
+        let spec: CompletionSpec = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(spec.primary_name(), "git");
+        let checkout = spec.subcommand("co").unwrap();
+        assert_eq!(checkout.description.as_deref(), Some("Switch branches"));
+        assert_eq!(checkout.option("-b").unwrap().args.len(), 1);
+        assert_eq!(checkout.args[0].generators[0].split_on, "\n");
+        assert_eq!(checkout.args[0].generators[0].timeout_ms, 1000);
+    }
+}
@@ -1,9 +1,11 @@
+use crate::completion_spec::{CompletionSpec, CompletionSpecResult};
 use crate::keyset::{KeySet, KeySetError, KeySetResult};
 use crate::theme::{Theme, ThemeError, ThemeResult};
+use crate::universal_input::slash_commands::{SlashCommandResult, SlashCommandsFile};
 use std::fs;
 use std::path::Path;
 
-/// Configuration loader for themes and keysets
+/// Configuration loader for themes, keysets, completion specs and slash commands
 pub struct ConfigLoader;
 
 impl ConfigLoader {
@@ -59,6 +61,41 @@ impl ConfigLoader {
         Ok(keysets)
     }
 
+    /// Load a completion spec from a YAML or JSON file
+    pub fn load_completion_spec<P: AsRef<Path>>(path: P) -> CompletionSpecResult<CompletionSpec> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)?;
+        if path.extension().and_then(|s| s.to_str()) == Some("json") {
+            Ok(serde_json::from_str(&content)?)
+        } else {
+            Ok(serde_yaml::from_str(&content)?)
+        }
+    }
+
+    /// Load all completion specs from a directory
+    pub fn load_completion_specs_from_directory<P: AsRef<Path>>(dir: P) -> CompletionSpecResult<Vec<CompletionSpec>> {
+        let mut specs = Vec::new();
+        let entries = fs::read_dir(dir)?;
+
+        for entry in entries {
+            let entry = entry?;
+            let path = entry.path();
+            if matches!(path.extension().and_then(|s| s.to_str()), Some("yaml" | "yml" | "json")) {
+                if let Ok(spec) = Self::load_completion_spec(&path) {
+                    specs.push(spec);
+                }
+            }
+        }
+
+        Ok(specs)
+    }
+
+    /// Load user slash commands from a YAML file with a `commands` list
+    pub fn load_slash_commands<P: AsRef<Path>>(path: P) -> SlashCommandResult<SlashCommandsFile> {
+        let content = fs::read_to_string(path)?;
+        Ok(serde_yaml::from_str(&content)?)
+    }
+
     /// Save a theme to a YAML file
     pub fn save_theme<P: AsRef<Path>>(theme: &Theme, path: P) -> ThemeResult<()> {
         let yaml = serde_yaml::to_string(theme)?;
@@ -123,4 +160,25 @@ mod tests {
 
         let _ = fs::remove_file(theme_path);
     }
+
+    #[test]
+    fn test_load_shipped_completion_specs() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/completion_specs");
+        let specs = ConfigLoader::load_completion_specs_from_directory(dir).unwrap();
+        let mut names: Vec<&str> = specs.iter().map(|s| s.primary_name()).collect();
+        names.sort();
+        assert_eq!(names, vec!["cargo", "docker", "git", "kubectl", "npm"]);
+    }
+
+    #[test]
+    fn test_load_json_completion_spec() {
+        let path = std::env::temp_dir().join("test_completion_spec.json");
+        let mut file = fs::File::create(&path).unwrap();
+        write!(file, r#"{{"name": "tool", "subcommands": [{{"name": ["run", "r"]}}]}}"#).unwrap();
+
+        let spec = ConfigLoader::load_completion_spec(&path).unwrap();
+        assert!(spec.subcommand("r").is_some());
+
+        let _ = fs::remove_file(path);
+    }
 }
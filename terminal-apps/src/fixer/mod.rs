@@ -0,0 +1,165 @@
+//! Suggests corrected commands after a command fails, one rule per kind of mistake.
+
+pub mod git_push;
+pub mod missing_package;
+pub mod subcommand;
+pub mod sudo;
+pub mod typo;
+
+pub use git_push::GitPushUpstreamRule;
+pub use missing_package::MissingPackageRule;
+pub use subcommand::WrongSubcommandRule;
+pub use sudo::SudoRule;
+pub use typo::{edit_distance, TypoRule};
+
+use crate::blocks::Block;
+use crate::classic_input::input_classifier::{tokenize, CommandLookup};
+use crate::universal_input::smart_features::ErrorDetector;
+use std::fmt;
+use std::sync::Arc;
+
+/// Exit status shells use when a command can't be found
+pub const COMMAND_NOT_FOUND: i32 = 127;
+
+/// A command that exited unsuccessfully, with everything it printed
+#[derive(Debug, Clone)]
+pub struct FailedCommand {
+    pub command: String,
+    pub output: String,
+    pub exit_code: i32,
+}
+
+impl FailedCommand {
+    pub fn new(command: impl Into<String>, output: impl Into<String>, exit_code: i32) -> Self {
+        Self {
+            command: command.into(),
+            output: output.into(),
+            exit_code,
+        }
+    }
+
+    /// The failure recorded in a block, if the block failed
+    pub fn from_block(block: &Block) -> Option<Self> {
+        let exit_code = block.output.exit_code.filter(|&c| c != 0)?;
+        Some(Self::new(block.command.clone(), block.get_full_output(), exit_code))
+    }
+
+    /// The command split into words with shell quoting rules
+    pub fn words(&self) -> Vec<String> {
+        tokenize(&self.command).words().map(str::to_string).collect()
+    }
+
+    pub fn program(&self) -> Option<String> {
+        self.words().into_iter().next()
+    }
+
+    pub fn output_contains(&self, needle: &str) -> bool {
+        self.output.to_lowercase().contains(&needle.to_lowercase())
+    }
+}
+
+/// A corrected command offered to the user
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fix {
+    pub command: String,
+    pub rule: &'static str,
+    /// Higher is tried first
+    pub priority: u8,
+}
+
+impl Fix {
+    pub fn new(command: impl Into<String>, rule: &'static str, priority: u8) -> Self {
+        Self {
+            command: command.into(),
+            rule,
+            priority,
+        }
+    }
+}
+
+/// Recognises one kind of failure and proposes corrected commands for it
+pub trait Rule: Send + Sync + fmt::Debug {
+    fn name(&self) -> &'static str;
+    fn fixes(&self, failed: &FailedCommand) -> Vec<Fix>;
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CommandFixer {
+    rules: Vec<Arc<dyn Rule>>,
+}
+
+impl CommandFixer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The built-in rules, resolving command names against `lookup`
+    pub fn with_default_rules(lookup: CommandLookup) -> Self {
+        let typos = ErrorDetector::new().common_typos;
+        let mut fixer = Self::new();
+        fixer.register(Arc::new(GitPushUpstreamRule));
+        fixer.register(Arc::new(WrongSubcommandRule));
+        fixer.register(Arc::new(TypoRule::new(typos, lookup.clone())));
+        fixer.register(Arc::new(SudoRule));
+        fixer.register(Arc::new(MissingPackageRule::new(lookup)));
+        fixer
+    }
+
+    pub fn register(&mut self, rule: Arc<dyn Rule>) {
+        self.rules.push(rule);
+    }
+
+    /// Fixes from every matching rule, best first, without duplicates
+    pub fn suggest(&self, failed: &FailedCommand) -> Vec<Fix> {
+        let mut fixes: Vec<Fix> = self.rules.iter().flat_map(|rule| rule.fixes(failed)).collect();
+        fixes.retain(|fix| fix.command != failed.command);
+        // Stable, so a rule's own ordering is kept within a priority
+        fixes.sort_by_key(|fix| std::cmp::Reverse(fix.priority));
+        let mut seen = std::collections::HashSet::new();
+        fixes.retain(|fix| seen.insert(fix.command.clone()));
+        fixes
+    }
+
+    pub fn suggest_for_block(&self, block: &Block) -> Vec<Fix> {
+        FailedCommand::from_block(block)
+            .map(|failed| self.suggest(&failed))
+            .unwrap_or_default()
+    }
+}
+
+/// `command` with its first word swapped for `replacement`
+pub(crate) fn replace_program(command: &str, program: &str, replacement: &str) -> String {
+    let trimmed = command.trim_start();
+    match trimmed.strip_prefix(program) {
+        Some(rest) => format!("{}{}", replacement, rest),
+        None => command.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixer_orders_and_dedups() {
+        let lookup = CommandLookup::new().with_commands(["git", "grep", "apt"]);
+        let fixer = CommandFixer::with_default_rules(lookup);
+
+        let failed = FailedCommand::new("gti status", "zsh: command not found: gti", COMMAND_NOT_FOUND);
+        let fixes = fixer.suggest(&failed);
+        assert_eq!(fixes[0].command, "git status");
+        assert_eq!(fixes.iter().filter(|f| f.command == "git status").count(), 1);
+    }
+
+    #[test]
+    fn test_suggest_for_block_ignores_success() {
+        let fixer = CommandFixer::with_default_rules(CommandLookup::new());
+        let mut block = Block::new("ls".to_string(), "/".to_string());
+        block.set_output("a\n".to_string(), String::new(), 0);
+        assert!(fixer.suggest_for_block(&block).is_empty());
+
+        block.command = "cat /etc/shadow".to_string();
+        block.set_output(String::new(), "cat: /etc/shadow: Permission denied".to_string(), 1);
+        assert_eq!(fixer.suggest_for_block(&block)[0].command, "sudo cat /etc/shadow");
+    }
+}
@@ -0,0 +1,118 @@
+use super::{FailedCommand, Fix, Rule};
+
+/// A mistyped subcommand of a tool that names the closest match, like cargo and git
+#[derive(Debug, Clone, Copy)]
+pub struct WrongSubcommandRule;
+
+impl WrongSubcommandRule {
+    /// The (wrong, suggested) subcommand pair from the tool's error message
+    fn suggestion(program: &str, output: &str) -> Option<(String, String)> {
+        match program {
+            // error: no such command: `biuld`
+            //
+            //     Did you mean `build`?
+            "cargo" => {
+                let wrong = between(output, "no such command: `", "`")?;
+                let suggested = between(output, "Did you mean `", "`")?;
+                Some((wrong, suggested))
+            }
+            // git: 'stauts' is not a git command. See 'git --help'.
+            //
+            // The most similar command is
+            //         status
+            "git" => {
+                let wrong = between(output, "git: '", "' is not a git command")?;
+                let mut lines = output.lines();
+                lines.find(|l| l.starts_with("The most similar command"))?;
+                let suggested = lines.next()?.trim().to_string();
+                (!suggested.is_empty()).then_some((wrong, suggested))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Rule for WrongSubcommandRule {
+    fn name(&self) -> &'static str {
+        "wrong_subcommand"
+    }
+
+    fn fixes(&self, failed: &FailedCommand) -> Vec<Fix> {
+        let words = failed.words();
+        let Some(program) = words.first() else {
+            return Vec::new();
+        };
+        let Some((wrong, suggested)) = Self::suggestion(program, &failed.output) else {
+            return Vec::new();
+        };
+        // Replace the subcommand word itself, not an argument that happens to match
+        let fixed: Vec<String> = words
+            .iter()
+            .enumerate()
+            .map(|(i, w)| {
+                if i > 0 && *w == wrong && !words[1..i].contains(&wrong) {
+                    suggested.clone()
+                } else {
+                    w.clone()
+                }
+            })
+            .collect();
+        vec![Fix::new(join_words(&fixed), self.name(), 85)]
+    }
+}
+
+fn between(text: &str, start: &str, end: &str) -> Option<String> {
+    let from = text.find(start)? + start.len();
+    let len = text[from..].find(end)?;
+    Some(text[from..from + len].to_string())
+}
+
+/// Words back into a command line, quoting any that need it
+fn join_words(words: &[String]) -> String {
+    words
+        .iter()
+        .map(|w| {
+            if w.is_empty() || w.contains(|c: char| c.is_whitespace() || "'\"$`\\|&;<>()*?".contains(c)) {
+                format!("'{}'", w.replace('\'', r"'\''"))
+            } else {
+                w.clone()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CARGO_OUTPUT: &str = "error: no such command: `biuld`
+
+\tDid you mean `build`?
+
+\tView all installed commands with `cargo --list`
+\tFind a package to install `biuld` with `cargo search cargo-biuld`
+";
+
+    const GIT_OUTPUT: &str = "git: 'stauts' is not a git command. See 'git --help'.
+
+The most similar command is
+\tstatus
+";
+
+    #[test]
+    fn test_cargo_and_git_suggestions() {
+        let failed = FailedCommand::new("cargo biuld --release", CARGO_OUTPUT, 101);
+        assert_eq!(WrongSubcommandRule.fixes(&failed)[0].command, "cargo build --release");
+
+        let failed = FailedCommand::new("git stauts -s", GIT_OUTPUT, 1);
+        assert_eq!(WrongSubcommandRule.fixes(&failed)[0].command, "git status -s");
+    }
+
+    #[test]
+    fn test_keeps_quoted_arguments() {
+        let output = "error: no such command: `tset`\n\n\tDid you mean `test`?\n";
+        let failed = FailedCommand::new("cargo tset 'my test'", output, 101);
+        assert_eq!(WrongSubcommandRule.fixes(&failed)[0].command, "cargo test 'my test'");
+    }
+}
@@ -0,0 +1,65 @@
+use super::{FailedCommand, Fix, Rule};
+
+const PERMISSION_PATTERNS: &[&str] = &[
+    "permission denied",
+    "operation not permitted",
+    "eacces",
+    "must be run as root",
+    "must be root",
+    "are you root?",
+    "requires root",
+    "you don't have write permissions",
+    "could not open lock file",
+    "only root can",
+];
+
+/// Re-run with `sudo` after a permission error
+#[derive(Debug, Clone, Copy)]
+pub struct SudoRule;
+
+impl Rule for SudoRule {
+    fn name(&self) -> &'static str {
+        "sudo"
+    }
+
+    fn fixes(&self, failed: &FailedCommand) -> Vec<Fix> {
+        let command = failed.command.trim();
+        if command.starts_with("sudo ") || !PERMISSION_PATTERNS.iter().any(|p| failed.output_contains(p)) {
+            return Vec::new();
+        }
+        // sudo only elevates the first command of a list or pipeline
+        let fixed = if command.contains("&&") || command.contains('|') || command.contains('>') || command.contains(';') {
+            format!("sudo sh -c '{}'", command.replace('\'', r"'\''"))
+        } else {
+            format!("sudo {}", command)
+        };
+        vec![Fix::new(fixed, self.name(), 70)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permission_errors() {
+        let failed = FailedCommand::new(
+            "apt install htop",
+            "E: Could not open lock file /var/lib/dpkg/lock-frontend - open (13: Permission denied)\n\
+             E: Unable to acquire the dpkg frontend lock, are you root?",
+            100,
+        );
+        assert_eq!(SudoRule.fixes(&failed)[0].command, "sudo apt install htop");
+
+        let failed = FailedCommand::new("echo 1 > /proc/sys/vm/drop_caches", "zsh: permission denied: /proc/sys/vm/drop_caches", 1);
+        assert_eq!(SudoRule.fixes(&failed)[0].command, "sudo sh -c 'echo 1 > /proc/sys/vm/drop_caches'");
+    }
+
+    #[test]
+    fn test_skips_other_failures() {
+        let failed = FailedCommand::new("sudo ls /root", "ls: cannot open directory '/root': Permission denied", 2);
+        assert!(SudoRule.fixes(&failed).is_empty());
+        let failed = FailedCommand::new("ls nope", "ls: cannot access 'nope': No such file or directory", 2);
+        assert!(SudoRule.fixes(&failed).is_empty());
+    }
+}
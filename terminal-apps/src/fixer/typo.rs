@@ -0,0 +1,117 @@
+use super::{replace_program, FailedCommand, Fix, Rule, COMMAND_NOT_FOUND};
+use crate::classic_input::input_classifier::CommandLookup;
+use std::collections::HashMap;
+
+const MAX_CANDIDATES: usize = 3;
+
+/// A mistyped program name: a known typo, or the closest commands on `$PATH`
+#[derive(Debug, Clone)]
+pub struct TypoRule {
+    typos: HashMap<String, String>,
+    lookup: CommandLookup,
+}
+
+impl TypoRule {
+    pub fn new(typos: HashMap<String, String>, lookup: CommandLookup) -> Self {
+        Self { typos, lookup }
+    }
+}
+
+impl Rule for TypoRule {
+    fn name(&self) -> &'static str {
+        "typo"
+    }
+
+    fn fixes(&self, failed: &FailedCommand) -> Vec<Fix> {
+        let not_found = failed.exit_code == COMMAND_NOT_FOUND
+            || failed.output_contains("command not found")
+            || failed.output_contains("unknown command");
+        let Some(program) = failed.program() else {
+            return Vec::new();
+        };
+        if !not_found || self.lookup.resolves(&program) {
+            return Vec::new();
+        }
+
+        if let Some(correct) = self.typos.get(&program) {
+            return vec![Fix::new(replace_program(&failed.command, &program, correct), self.name(), 85)];
+        }
+
+        // Allow roughly one mistake per three characters
+        let max_distance = (program.chars().count() / 3).max(1);
+        let mut candidates: Vec<(usize, &str)> = self
+            .lookup
+            .names()
+            .filter(|name| name.len() > 1)
+            .map(|name| (edit_distance(&program, name), name))
+            .filter(|(distance, _)| *distance <= max_distance)
+            .collect();
+        candidates.sort();
+        candidates.dedup_by(|a, b| a.1 == b.1);
+        candidates
+            .into_iter()
+            .take(MAX_CANDIDATES)
+            .map(|(_, name)| Fix::new(replace_program(&failed.command, &program, name), self.name(), 80))
+            .collect()
+    }
+}
+
+/// Optimal string alignment distance: insertions, deletions, substitutions
+/// and transpositions of adjacent characters each cost one
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in rows[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut best = (rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                best = best.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = best;
+        }
+    }
+    rows[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule() -> TypoRule {
+        let typos = HashMap::from([("gti".to_string(), "git".to_string())]);
+        TypoRule::new(typos, CommandLookup::new().with_commands(["git", "grep", "docker", "cargo"]))
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("gti", "git"), 1);
+        assert_eq!(edit_distance("dcoker", "docker"), 1);
+        assert_eq!(edit_distance("carg", "cargo"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn test_known_typo_and_path_search() {
+        let failed = FailedCommand::new("gti push origin main", "bash: gti: command not found", 127);
+        assert_eq!(rule().fixes(&failed)[0].command, "git push origin main");
+
+        let failed = FailedCommand::new("dcoker ps -a", "zsh: command not found: dcoker", 127);
+        assert_eq!(rule().fixes(&failed)[0].command, "docker ps -a");
+    }
+
+    #[test]
+    fn test_ignores_commands_that_exist() {
+        let failed = FailedCommand::new("grep foo missing.txt", "grep: missing.txt: No such file or directory", 2);
+        assert!(rule().fixes(&failed).is_empty());
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::{HashMap, VecDeque};
+
+/// Points for each matched character
+pub const SCORE_MATCH: i64 = 16;
+/// Extra points when a match directly follows the previous one
+pub const BONUS_CONSECUTIVE: i64 = 8;
+/// Extra points for a match at the very start of the text
+pub const BONUS_START: i64 = 10;
+/// Extra points for a match after a separator: space, `/`, `-`, `_`, `.`
+pub const BONUS_BOUNDARY: i64 = 8;
+/// Extra points for the upper-case letter in a camelCase hump
+pub const BONUS_CAMEL: i64 = 7;
+/// Penalty for opening a gap between two matches
+pub const PENALTY_GAP_START: i64 = -3;
+/// Penalty for every further character in a gap
+pub const PENALTY_GAP_EXTENSION: i64 = -1;
+/// Penalty per unmatched character before the first match, up to `MAX_LEADING_PENALTY`
+pub const PENALTY_LEADING: i64 = -1;
+pub const MAX_LEADING_PENALTY: i64 = -8;
+
+/// A successful match: its score and the char indices that matched, for highlighting
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuzzyMatch {
+    pub score: i64,
+    pub positions: Vec<usize>,
+}
+
+/// Subsequence match of `query` in `text`, scoring the best alignment.
+///
+/// Smart case: a query with no upper-case letters matches case-insensitively.
+/// An empty query matches everything with a score of zero.
+pub fn fuzzy_match(query: &str, text: &str) -> Option<FuzzyMatch> {
+    let case_sensitive = query.chars().any(char::is_uppercase);
+    let fold = |c: char| if case_sensitive { c } else { c.to_ascii_lowercase() };
+    let query: Vec<char> = query.chars().map(fold).collect();
+    let original: Vec<char> = text.chars().collect();
+    let text: Vec<char> = original.iter().copied().map(fold).collect();
+    if query.is_empty() {
+        return Some(FuzzyMatch { score: 0, positions: Vec::new() });
+    }
+
+    // Cheap rejection before the quadratic part
+    let mut remaining = query.iter().peekable();
+    for c in &text {
+        if remaining.peek() == Some(&c) {
+            remaining.next();
+        }
+    }
+    if remaining.peek().is_some() {
+        return None;
+    }
+
+    let n = text.len();
+    let bonus: Vec<i64> = (0..n).map(|j| position_bonus(&original, j)).collect();
+    const NONE: i64 = i64::MIN / 2;
+    // score[i][j]: best alignment of query[..=i] with query[i] at text[j]
+    let mut score = vec![vec![NONE; n]; query.len()];
+    // from[i][j]: where query[i - 1] matched on that best alignment
+    let mut from = vec![vec![0usize; n]; query.len()];
+
+    for j in 0..n {
+        if text[j] == query[0] {
+            score[0][j] = SCORE_MATCH + bonus[j] + (PENALTY_LEADING * j as i64).max(MAX_LEADING_PENALTY);
+        }
+    }
+    for i in 1..query.len() {
+        // Best previous match followed by a gap of at least one character
+        let mut gap: (i64, usize) = (NONE, 0);
+        for j in 1..n {
+            if j >= 2 {
+                let opened = score[i - 1][j - 2] + PENALTY_GAP_START;
+                let extended = gap.0 + PENALTY_GAP_EXTENSION;
+                gap = if opened >= extended { (opened, j - 2) } else { (extended, gap.1) };
+            }
+            if text[j] != query[i] {
+                continue;
+            }
+            let consecutive = score[i - 1][j - 1] + BONUS_CONSECUTIVE;
+            let (best, prev) = if consecutive >= gap.0 { (consecutive, j - 1) } else { gap };
+            if best > NONE / 2 {
+                score[i][j] = best + SCORE_MATCH + bonus[j];
+                from[i][j] = prev;
+            }
+        }
+    }
+
+    let last = query.len() - 1;
+    let (mut j, best) = score[last]
+        .iter()
+        .copied()
+        .enumerate()
+        .max_by_key(|&(j, s)| (s, std::cmp::Reverse(j)))?;
+    if best <= NONE / 2 {
+        return None;
+    }
+    let mut positions = vec![0; query.len()];
+    for i in (0..query.len()).rev() {
+        positions[i] = j;
+        j = from[i][j];
+    }
+    Some(FuzzyMatch { score: best, positions })
+}
+
+fn position_bonus(text: &[char], j: usize) -> i64 {
+    if j == 0 {
+        return BONUS_START;
+    }
+    let (prev, cur) = (text[j - 1], text[j]);
+    if matches!(prev, ' ' | '/' | '-' | '_' | '.' | ':' | '=') && cur.is_alphanumeric() {
+        BONUS_BOUNDARY
+    } else if prev.is_lowercase() && cur.is_uppercase() {
+        BONUS_CAMEL
+    } else {
+        0
+    }
+}
+
+/// Weight of a single use by age, following the buckets z/zoxide use
+pub fn frecency_weight(age_secs: u64) -> f64 {
+    match age_secs {
+        0..=3_599 => 4.0,
+        3_600..=86_399 => 2.0,
+        86_400..=604_799 => 0.5,
+        _ => 0.25,
+    }
+}
+
+/// Timestamps of recent uses kept per command; older uses only count towards the total
+const SAMPLED_USES: usize = 10;
+
+#[derive(Debug, Clone, Default)]
+struct Usage {
+    uses: u32,
+    failures: u32,
+    recent: VecDeque<u64>,
+}
+
+impl Usage {
+    fn record(&mut self, timestamp: u64, exit_status: Option<i32>) {
+        self.uses += 1;
+        if exit_status.is_some_and(|code| code != 0) {
+            self.failures += 1;
+        }
+        if self.recent.len() == SAMPLED_USES {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(timestamp);
+    }
+
+    /// Average recency weight of the sampled uses, scaled by the total use
+    /// count, then discounted by up to half for commands that usually fail
+    fn score(&self, now: u64) -> f64 {
+        if self.recent.is_empty() {
+            return 0.0;
+        }
+        let recency: f64 = self.recent.iter().map(|&t| frecency_weight(now.saturating_sub(t))).sum::<f64>()
+            / self.recent.len() as f64;
+        let failure_rate = self.failures as f64 / self.uses as f64;
+        recency * self.uses as f64 * (1.0 - 0.5 * failure_rate)
+    }
+}
+
+/// How often and how recently commands ran, and whether they succeeded
+#[derive(Debug, Clone, Default)]
+pub struct Frecency {
+    commands: HashMap<String, Usage>,
+    programs: HashMap<String, Usage>,
+}
+
+impl Frecency {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_history(history: &crate::classic_input::CommandHistory) -> Self {
+        let mut frecency = Self::new();
+        for entry in history.entries() {
+            frecency.record(&entry.command, entry.timestamp, entry.exit_status);
+        }
+        frecency
+    }
+
+    pub fn record(&mut self, command: &str, timestamp: u64, exit_status: Option<i32>) {
+        let command = command.trim();
+        if command.is_empty() {
+            return;
+        }
+        self.commands.entry(command.to_string()).or_default().record(timestamp, exit_status);
+        if let Some(program) = command.split_whitespace().next() {
+            self.programs.entry(program.to_string()).or_default().record(timestamp, exit_status);
+        }
+    }
+
+    /// Records a finished block's command and exit status
+    pub fn record_block(&mut self, block: &crate::blocks::Block) {
+        self.record(&block.command, block.metadata.timestamp, block.output.exit_code);
+    }
+
+    /// Frecency of an exact command line
+    pub fn command_score(&self, command: &str, now: u64) -> f64 {
+        self.commands.get(command.trim()).map(|u| u.score(now)).unwrap_or(0.0)
+    }
+
+    /// Frecency of a program across every command line that ran it
+    pub fn program_score(&self, program: &str, now: u64) -> f64 {
+        self.programs.get(program).map(|u| u.score(now)).unwrap_or(0.0)
+    }
+}
+
+/// Points a frecency score is worth next to match points; logarithmic so a
+/// command run a thousand times doesn't drown out a much better match
+pub const FRECENCY_WEIGHT: f64 = 8.0;
+
+/// Combined ranking score for a suggestion
+pub fn rank(match_score: i64, category_weight: f64, frecency: f64) -> f64 {
+    match_score as f64 + category_weight + FRECENCY_WEIGHT * frecency.max(0.0).ln_1p()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_positions_and_rejection() {
+        let m = fuzzy_match("gco", "git checkout").unwrap();
+        assert_eq!(m.positions, vec![0, 4, 9]);
+        assert!(fuzzy_match("gcx", "git checkout").is_none());
+        assert_eq!(fuzzy_match("", "anything").unwrap().score, 0);
+    }
+
+    #[test]
+    fn test_prefers_boundaries_over_earliest_match() {
+        // Greedy matching would take the `s` in "test"; the `s` starting "suite" scores higher
+        let m = fuzzy_match("ts", "test suite").unwrap();
+        assert_eq!(m.positions, vec![0, 5]);
+
+        let kebab = fuzzy_match("cp", "cherry-pick").unwrap();
+        assert_eq!(kebab.positions, vec![0, 7]);
+        let camel = fuzzy_match("gH", "getHeight").unwrap();
+        assert_eq!(camel.positions, vec![0, 3]);
+    }
+
+    #[test]
+    fn test_scores_are_stable() {
+        // Pinned so that changes to the constants are deliberate
+        assert_eq!(fuzzy_match("git", "git").unwrap().score, 3 * SCORE_MATCH + BONUS_START + 2 * BONUS_CONSECUTIVE);
+        assert_eq!(fuzzy_match("git", "git").unwrap().score, 74);
+        assert_eq!(fuzzy_match("gt", "git").unwrap().score, 2 * SCORE_MATCH + BONUS_START + PENALTY_GAP_START);
+        assert_eq!(fuzzy_match("st", "git status").unwrap().score, 2 * SCORE_MATCH + BONUS_BOUNDARY + BONUS_CONSECUTIVE + 4 * PENALTY_LEADING);
+
+        assert!(fuzzy_match("ls", "ls -la").unwrap().score > fuzzy_match("ls", "less").unwrap().score);
+    }
+
+    #[test]
+    fn test_smart_case() {
+        assert!(fuzzy_match("readme", "README.md").is_some());
+        assert!(fuzzy_match("README", "readme.md").is_none());
+    }
+
+    #[test]
+    fn test_frecency_prefers_recent_successful_commands() {
+        let now = 10_000_000;
+        let mut frecency = Frecency::new();
+        frecency.record("cargo test", now - 60, Some(0));
+        frecency.record("cargo test", now - 120, Some(0));
+        frecency.record("cargo bench", now - 60, Some(101));
+        frecency.record("cargo bench", now - 120, Some(101));
+        frecency.record("make", now - 30 * 86_400, Some(0));
+        frecency.record("make", now - 30 * 86_400, Some(0));
+
+        assert_eq!(frecency.command_score("cargo test", now), 8.0);
+        assert_eq!(frecency.command_score("cargo bench", now), 4.0);
+        assert_eq!(frecency.command_score("make", now), 0.5);
+        assert_eq!(frecency.program_score("cargo", now), 4.0 * 4.0 * 0.75);
+        assert_eq!(frecency.command_score("unknown", now), 0.0);
+
+        assert_eq!(rank(50, 10.0, 0.0), 60.0);
+        assert!(rank(50, 0.0, 8.0) > rank(50, 0.0, 4.0));
+    }
+}
@@ -0,0 +1,39 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+/// Background thread answering requests with one function. Requests that
+/// pile up while it is busy are skipped in favour of the newest one, so slow
+/// work (completion generators, mostly) never queues up behind typing.
+#[derive(Debug)]
+pub(crate) struct LatestWorker<Q, R> {
+    requests: Sender<Q>,
+    results: Receiver<R>,
+}
+
+impl<Q: Send + 'static, R: Send + 'static> LatestWorker<Q, R> {
+    pub(crate) fn spawn(compute: impl Fn(Q) -> R + Send + 'static) -> Self {
+        let (requests, request_receiver) = mpsc::channel::<Q>();
+        let (result_sender, results) = mpsc::channel();
+        thread::spawn(move || {
+            while let Ok(mut request) = request_receiver.recv() {
+                while let Ok(newer) = request_receiver.try_recv() {
+                    request = newer;
+                }
+                if result_sender.send(compute(request)).is_err() {
+                    break;
+                }
+            }
+        });
+        Self { requests, results }
+    }
+
+    /// Queues `request`; false if the worker has stopped
+    pub(crate) fn send(&self, request: Q) -> bool {
+        self.requests.send(request).is_ok()
+    }
+
+    /// A finished result, without blocking
+    pub(crate) fn try_recv(&self) -> Option<R> {
+        self.results.try_recv().ok()
+    }
+}
@@ -1,6 +1,13 @@
 pub mod app;
+pub mod attachments;
+pub mod clipboard;
+pub mod completion_spec;
 pub mod config_loader;
+pub mod fixer;
+pub mod fuzzy;
 pub mod keyset;
+mod latest_worker;
+pub mod modern_editing;
 pub mod theme;
 pub mod workflow;
 pub mod ui;
@@ -9,9 +16,20 @@ pub mod universal_input;
 pub mod classic_input;
 pub mod blocks;
 
+#[cfg(test)]
+pub(crate) mod test_util;
+
 pub use app::TerminalApp;
+pub use attachments::{
+    Attachment, AttachmentContent, AttachmentError, AttachmentLimits, AttachmentResult, AttachmentSet, FileStamp,
+};
+pub use clipboard::{Clipboard, ClipboardBackend, ClipboardError, ClipboardResult};
+pub use completion_spec::{CompletionSpec, CompletionSpecError, CompletionSpecResult};
 pub use config_loader::ConfigLoader;
+pub use fixer::{CommandFixer, FailedCommand, Fix, Rule};
+pub use fuzzy::{fuzzy_match, Frecency, FuzzyMatch};
 pub use keyset::{KeySet, KeySetError, KeySetResult};
+pub use modern_editing::{AliasError, AliasExpander, AliasResult};
 pub use theme::{Theme, ThemeError, ThemeResult};
 pub use workflow::{Condition, ExecutionContext, ExtendedWorkflow, WorkflowError, WorkflowResult, WorkflowStep};
 pub use ui_app::WarpTerminalUI;
@@ -0,0 +1,389 @@
+use crate::universal_input::shell_lexer::{lex, TokenKind};
+use std::collections::{BTreeMap, HashSet};
+use std::io::Read;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// Rc files read by `AliasExpander::from_home`, relative to the home directory
+const RC_FILES: &[&str] = &[".bashrc", ".bash_aliases", ".zshrc", ".config/fish/config.fish"];
+
+/// How long to wait for an interactive shell to print its aliases
+const SHELL_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Longest chain of aliases referring to aliases that is followed
+const MAX_DEPTH: usize = 16;
+
+#[derive(Error, Debug)]
+pub enum AliasError {
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Shell failed: {0}")]
+    ShellError(String),
+}
+
+pub type AliasResult<T> = Result<T, AliasError>;
+
+/// Shell aliases, expanded when a command runs, and fish-style abbreviations,
+/// expanded in the input line as soon as the word is finished
+#[derive(Debug, Clone, Default)]
+pub struct AliasExpander {
+    aliases: BTreeMap<String, String>,
+    abbreviations: BTreeMap<String, String>,
+}
+
+impl AliasExpander {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Aliases and abbreviations from the rc files in `$HOME`, or none without it
+    pub fn from_env() -> Self {
+        std::env::var_os("HOME").map(Self::from_home).unwrap_or_default()
+    }
+
+    /// Aliases and abbreviations from the rc files in `home`; unreadable files are skipped
+    pub fn from_home<P: AsRef<Path>>(home: P) -> Self {
+        let mut expander = Self::new();
+        for file in RC_FILES {
+            let _ = expander.load_rc_file(home.as_ref().join(file));
+        }
+        expander
+    }
+
+    pub fn add_alias(&mut self, name: impl Into<String>, expansion: impl Into<String>) {
+        self.aliases.insert(name.into(), expansion.into());
+    }
+
+    pub fn add_abbreviation(&mut self, name: impl Into<String>, expansion: impl Into<String>) {
+        self.abbreviations.insert(name.into(), expansion.into());
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        let alias = self.aliases.remove(name).is_some();
+        self.abbreviations.remove(name).is_some() || alias
+    }
+
+    pub fn alias(&self, name: &str) -> Option<&str> {
+        self.aliases.get(name).map(String::as_str)
+    }
+
+    pub fn abbreviation(&self, name: &str) -> Option<&str> {
+        self.abbreviations.get(name).map(String::as_str)
+    }
+
+    pub fn aliases(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.aliases.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn abbreviations(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.abbreviations.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.aliases.is_empty() && self.abbreviations.is_empty()
+    }
+
+    /// Reads `alias` lines (bash, zsh, fish) and fish `abbr` lines from an rc file.
+    /// Returns how many were added.
+    pub fn load_rc_file<P: AsRef<Path>>(&mut self, path: P) -> AliasResult<usize> {
+        let content = std::fs::read_to_string(path)?;
+        Ok(self.parse_rc(&content))
+    }
+
+    pub fn parse_rc(&mut self, content: &str) -> usize {
+        let mut count = 0;
+        for words in content.lines().flat_map(shell_commands) {
+            match words.first().map(String::as_str) {
+                Some("alias") => {
+                    for (name, expansion) in parse_definitions(&words[1..]) {
+                        self.add_alias(name, expansion);
+                        count += 1;
+                    }
+                }
+                Some("abbr") => {
+                    // `abbr -a gco git checkout` and `abbr --erase gco`; other
+                    // flags manage existing abbreviations
+                    let rest: Vec<&String> = words[1..].iter().skip_while(|w| w.starts_with('-')).collect();
+                    if words[1..].iter().any(|w| w == "-e" || w == "--erase") {
+                        for name in rest {
+                            self.abbreviations.remove(name.as_str());
+                        }
+                        continue;
+                    }
+                    let adds = words[1..].iter().all(|w| !w.starts_with('-') || w == "-a" || w == "--add");
+                    if adds && rest.len() >= 2 {
+                        let expansion: Vec<&str> = rest[1..].iter().map(|w| w.as_str()).collect();
+                        self.add_abbreviation(rest[0].clone(), expansion.join(" "));
+                        count += 1;
+                    }
+                }
+                _ => {}
+            }
+        }
+        count
+    }
+
+    /// Runs `alias` in an interactive `shell`, so aliases defined anywhere in
+    /// its startup files are seen. Returns how many were added.
+    pub fn import_from_shell(&mut self, shell: &str) -> AliasResult<usize> {
+        let mut child = Command::new(shell)
+            .args(["-i", "-c", "alias"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        let mut stdout = child.stdout.take();
+        let reader = std::thread::spawn(move || {
+            let mut output = String::new();
+            if let Some(stdout) = stdout.as_mut() {
+                let _ = stdout.read_to_string(&mut output);
+            }
+            output
+        });
+
+        let deadline = Instant::now() + SHELL_TIMEOUT;
+        loop {
+            match child.try_wait()? {
+                Some(status) if status.success() => break,
+                Some(status) => return Err(AliasError::ShellError(format!("{} exited with {}", shell, status))),
+                None if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(10)),
+                None => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(AliasError::ShellError(format!("{} timed out", shell)));
+                }
+            }
+        }
+        let output = reader.join().unwrap_or_default();
+        Ok(self.parse_alias_output(&output))
+    }
+
+    /// Parses what `alias` prints: `alias ll='ls -l'` in bash, `ll='ls -l'`
+    /// in zsh and `alias ll 'ls -l'` in fish
+    pub fn parse_alias_output(&mut self, output: &str) -> usize {
+        let mut count = 0;
+        for words in output.lines().flat_map(shell_commands) {
+            let words = match words.first().map(String::as_str) {
+                Some("alias") => &words[1..],
+                _ => &words[..],
+            };
+            for (name, expansion) in parse_definitions(words) {
+                self.add_alias(name, expansion);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Expands the alias in command position of every command in `line`,
+    /// as the shell would before running it
+    pub fn expand(&self, line: &str) -> String {
+        let mut expanded = line.to_string();
+        for span in lex(line).iter().rev().filter(|s| s.kind == TokenKind::Command) {
+            if let Some(expansion) = resolve(&self.aliases, span.text(line)) {
+                expanded.replace_range(span.start..span.end, &expansion);
+            }
+        }
+        expanded
+    }
+
+    /// The full expansion of alias `name`, following aliases whose expansion
+    /// starts with another alias; `None` when `name` isn't one
+    pub fn expand_alias(&self, name: &str) -> Option<String> {
+        resolve(&self.aliases, name)
+    }
+
+    /// `line` with the command of its last command expanded, so completion
+    /// can treat `gco ma` like `git checkout ma`. Also returns how many bytes
+    /// longer the line got. `None` while the command word is still being typed.
+    pub fn expand_last_command(&self, line: &str) -> Option<(String, isize)> {
+        let span = lex(line).into_iter().rev().find(|s| s.kind == TokenKind::Command)?;
+        if span.end == line.len() {
+            return None;
+        }
+        let expansion = resolve(&self.aliases, span.text(line))?;
+        let delta = expansion.len() as isize - (span.end - span.start) as isize;
+        let mut expanded = line.to_string();
+        expanded.replace_range(span.start..span.end, &expansion);
+        Some((expanded, delta))
+    }
+
+    /// Expands an abbreviation in command position that ends at `cursor`, as
+    /// happens when space or enter is pressed after it. Returns the new input
+    /// and cursor.
+    pub fn expand_abbreviation(&self, input: &str, cursor: usize) -> Option<(String, usize)> {
+        let span = lex(input)
+            .into_iter()
+            .find(|s| s.kind == TokenKind::Command && s.end == cursor)?;
+        let expansion = resolve(&self.abbreviations, span.text(input))?;
+        let mut expanded = input.to_string();
+        expanded.replace_range(span.start..span.end, &expansion);
+        Some((expanded, span.start + expansion.len()))
+    }
+}
+
+/// Expands `name` from `table`, re-expanding the first word of the result
+/// until it isn't an entry or one already used, so `ls='ls -G'` and cycles
+/// like `a='b'`, `b='a'` terminate
+fn resolve(table: &BTreeMap<String, String>, name: &str) -> Option<String> {
+    let mut expansion = table.get(name)?.clone();
+    let mut seen = HashSet::from([name.to_string()]);
+    for _ in 0..MAX_DEPTH {
+        let trimmed = expansion.trim_start();
+        let first = trimmed.split_whitespace().next().unwrap_or("");
+        let Some(next) = table.get(first).filter(|_| seen.insert(first.to_string())) else {
+            break;
+        };
+        let rest = &trimmed[first.len()..];
+        expansion = format!("{}{}", next, rest);
+    }
+    Some(expansion)
+}
+
+/// `name=value` pairs, or fish's `name value`; flags like zsh's `-g` are skipped
+fn parse_definitions(words: &[String]) -> Vec<(String, String)> {
+    let words: Vec<&String> = words.iter().filter(|w| !w.starts_with('-')).collect();
+    if words.len() == 2 && !words[0].contains('=') {
+        return vec![(words[0].clone(), words[1].clone())];
+    }
+    words
+        .into_iter()
+        .filter_map(|w| w.split_once('='))
+        .filter(|(name, _)| !name.is_empty() && !name.contains(char::is_whitespace))
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+/// Splits a line into commands at unquoted `;`, and each command into words
+/// the way the shell would, removing quotes and escapes and stopping at a comment
+fn shell_commands(line: &str) -> Vec<Vec<String>> {
+    let mut commands = Vec::new();
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote: Option<char> = None;
+    let mut chars = line.trim_start().chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') => {
+                if let Some(next) = chars.next() {
+                    if !"\"\\$`".contains(next) {
+                        word.push('\\');
+                    }
+                    word.push(next);
+                }
+            }
+            (Some(_), c) => word.push(c),
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            (None, '#') if !in_word => break,
+            (None, ';') => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+                commands.push(std::mem::take(&mut words));
+            }
+            (None, c) => {
+                in_word = true;
+                match c {
+                    '\'' | '"' => quote = Some(c),
+                    '\\' => word.extend(chars.next()),
+                    c => word.push(c),
+                }
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    commands.push(words);
+    commands.retain(|words| !words.is_empty());
+    commands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rc_files() {
+        let mut expander = AliasExpander::new();
+        let count = expander.parse_rc(
+            r#"
+# aliases
+alias ll='ls -alF'
+  alias gs="git status" gd='git diff'   # inline comment
+alias -g G='| grep'
+alias quote='echo '\''hi'\'''
+export PATH=$PATH:~/bin
+abbr -a gco git checkout
+abbr -a gp git push
+abbr --erase gco
+alias a='x; y'; alias b='z' # two on one line
+"#,
+        );
+        assert_eq!(count, 9);
+        assert_eq!(expander.alias("ll"), Some("ls -alF"));
+        assert_eq!(expander.alias("gd"), Some("git diff"));
+        assert_eq!(expander.alias("G"), Some("| grep"));
+        assert_eq!(expander.alias("quote"), Some("echo 'hi'"));
+        assert_eq!(expander.abbreviation("gco"), None);
+        assert_eq!(expander.abbreviation("gp"), Some("git push"));
+        assert_eq!(expander.alias("a"), Some("x; y"));
+        assert_eq!(expander.alias("b"), Some("z"));
+        assert_eq!(expander.alias("PATH"), None);
+    }
+
+    #[test]
+    fn test_parse_alias_output() {
+        let mut expander = AliasExpander::new();
+        // bash, zsh and fish formats
+        expander.parse_alias_output("alias ll='ls -l'\ngst='git status'\nalias la 'ls -A'\n");
+        assert_eq!(expander.aliases().collect::<Vec<_>>(), vec![("gst", "git status"), ("la", "ls -A"), ("ll", "ls -l")]);
+    }
+
+    #[test]
+    fn test_expand_with_recursion_protection() {
+        let mut expander = AliasExpander::new();
+        expander.add_alias("ls", "ls --color=auto");
+        expander.add_alias("ll", "ls -l");
+        expander.add_alias("a", "b one");
+        expander.add_alias("b", "a two");
+
+        assert_eq!(expander.expand("ll src | grep rs && ls"), "ls --color=auto -l src | grep rs && ls --color=auto");
+        assert_eq!(expander.expand("echo ll"), "echo ll");
+        assert_eq!(expander.expand_alias("a").as_deref(), Some("a two one"));
+        assert_eq!(expander.expand_alias("nope"), None);
+    }
+
+    #[test]
+    fn test_abbreviation_expands_in_place() {
+        let mut expander = AliasExpander::new();
+        expander.add_abbreviation("gco", "git checkout");
+        expander.add_abbreviation("g", "git");
+        expander.add_abbreviation("gs", "g status");
+
+        assert_eq!(expander.expand_abbreviation("gco", 3), Some(("git checkout".to_string(), 12)));
+        assert_eq!(expander.expand_abbreviation("gs main", 2), Some(("git status main".to_string(), 10)));
+        // Only in command position, and only right at the cursor
+        assert_eq!(expander.expand_abbreviation("echo gco", 8), None);
+        assert_eq!(expander.expand_abbreviation("gco", 2), None);
+    }
+
+    #[test]
+    fn test_expand_last_command() {
+        let mut expander = AliasExpander::new();
+        expander.add_alias("gco", "git checkout");
+        assert_eq!(expander.expand_last_command("gco ma"), Some(("git checkout ma".to_string(), 9)));
+        assert_eq!(expander.expand_last_command("gco"), None);
+    }
+}
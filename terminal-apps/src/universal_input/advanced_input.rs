@@ -1,3 +1,6 @@
+use super::command_resolver::{looks_like_path, CommandResolver};
+use super::shell_lexer::{IncrementalLexer, Span, TokenKind};
+use crate::classic_input::CommandHistory;
 use std::collections::HashMap;
 
 /// Input modes: Terminal (shell commands), Agent (AI prompts), Auto (intelligent detection)
@@ -11,41 +14,48 @@ pub enum InputMode {
 /// Syntax highlighting rules for different contexts
 #[derive(Debug, Clone)]
 pub struct SyntaxHighlighting {
-    pub keywords: Vec<String>,
-    pub operators: Vec<String>,
     pub strings: bool,
     pub comments: bool,
+    pub resolver: CommandResolver,
+    lexer: IncrementalLexer,
 }
 
 impl SyntaxHighlighting {
     pub fn new() -> Self {
         Self {
-            keywords: vec![
-                "if", "then", "else", "fi", "for", "do", "done", "while",
-                "case", "function", "return", "export", "local",
-            ].iter().map(|s| s.to_string()).collect(),
-            operators: vec![
-                "&&", "||", "|", ">", "<", ">>", "<<", "&", ";",
-            ].iter().map(|s| s.to_string()).collect(),
             strings: true,
             comments: true,
+            resolver: CommandResolver::from_env(),
+            lexer: IncrementalLexer::new(),
         }
     }
 
-    pub fn highlight_token(&self, token: &str) -> Option<&str> {
-        if self.keywords.contains(&token.to_string()) {
-            return Some("keyword");
+    /// Highlight class for a span, or None to draw it plain
+    pub fn highlight_kind(&self, kind: TokenKind) -> Option<&'static str> {
+        match kind {
+            TokenKind::Argument => None,
+            TokenKind::String | TokenKind::Heredoc if !self.strings => None,
+            TokenKind::Comment if !self.comments => None,
+            kind => Some(kind.as_str()),
         }
-        if self.operators.contains(&token.to_string()) {
-            return Some("operator");
-        }
-        if token.starts_with('"') && token.ends_with('"') {
-            return Some("string");
-        }
-        if token.starts_with('#') {
-            return Some("comment");
+    }
+
+    /// Like `highlight_kind`, but commands are coloured by what they resolve
+    /// to and path arguments by whether they exist
+    pub fn highlight_span(&mut self, text: &str, span: &Span) -> Option<&'static str> {
+        let word = span.text(text);
+        match span.kind {
+            TokenKind::Command => Some(self.resolver.resolve(word).highlight_class()),
+            TokenKind::Argument if looks_like_path(word) => {
+                Some(self.resolver.path_status(word).highlight_class())
+            }
+            kind => self.highlight_kind(kind),
         }
-        None
+    }
+
+    /// Spans for `text`, re-lexing only what changed since the last call
+    pub fn highlight(&mut self, text: &str) -> &[Span] {
+        self.lexer.update(text)
     }
 }
 
@@ -56,8 +66,8 @@ pub struct AdvancedInput {
     pub mode: InputMode,
     pub cursor_position: usize,
     pub syntax_highlighting: SyntaxHighlighting,
-    pub history: Vec<String>,
-    pub history_index: Option<usize>,
+    pub history: CommandHistory,
+    history_draft: Option<String>,
 }
 
 impl AdvancedInput {
@@ -67,8 +77,8 @@ impl AdvancedInput {
             mode: InputMode::Auto,
             cursor_position: 0,
             syntax_highlighting: SyntaxHighlighting::new(),
-            history: Vec::new(),
-            history_index: None,
+            history: CommandHistory::new(1000),
+            history_draft: None,
         }
     }
 
@@ -110,39 +120,50 @@ impl AdvancedInput {
         self.cursor_position = self.content.len();
     }
 
+    /// Whether the input is a `/` command rather than shell input; `/usr/bin/env`
+    /// is a path because its first word has a second `/`
+    pub fn is_slash_command(&self) -> bool {
+        let Some(rest) = self.content.strip_prefix('/') else {
+            return false;
+        };
+        let name = rest.split_whitespace().next().unwrap_or("");
+        !self.content.contains('\n') && !rest.starts_with(char::is_whitespace) && !name.contains('/')
+    }
+
     pub fn set_mode(&mut self, mode: InputMode) {
         self.mode = mode;
     }
 
     pub fn add_to_history(&mut self) {
         if !self.content.is_empty() {
-            self.history.push(self.content.clone());
+            self.history.add(self.content.clone());
         }
     }
 
+    /// Walk back through history entries starting with the text before the cursor
     pub fn history_previous(&mut self) {
-        if let Some(idx) = self.history_index {
-            if idx > 0 {
-                self.history_index = Some(idx - 1);
-                self.content = self.history[idx - 1].clone();
-                self.move_cursor_end();
-            }
-        } else if !self.history.is_empty() {
-            self.history_index = Some(self.history.len() - 1);
-            self.content = self.history[self.history.len() - 1].clone();
+        if !self.history.is_navigating() {
+            self.history_draft = Some(self.content.clone());
+        }
+        let prefix = self.content.get(..self.cursor_position).unwrap_or("").to_string();
+        if let Some(cmd) = self.history.previous_matching(&prefix, None) {
+            self.content = cmd;
             self.move_cursor_end();
         }
     }
 
     pub fn history_next(&mut self) {
-        if let Some(idx) = self.history_index {
-            if idx < self.history.len() - 1 {
-                self.history_index = Some(idx + 1);
-                self.content = self.history[idx + 1].clone();
+        if !self.history.is_navigating() {
+            return;
+        }
+        match self.history.next() {
+            Some(cmd) => {
+                self.content = cmd;
+                self.move_cursor_end();
+            }
+            None => {
+                self.content = self.history_draft.take().unwrap_or_default();
                 self.move_cursor_end();
-            } else {
-                self.history_index = None;
-                self.content.clear();
             }
         }
     }
@@ -150,22 +171,35 @@ impl AdvancedInput {
     pub fn clear(&mut self) {
         self.content.clear();
         self.cursor_position = 0;
-        self.history_index = None;
-    }
-
-    pub fn get_highlighted_lines(&self) -> Vec<Vec<(String, Option<&str>)>> {
-        self.content
-            .lines()
-            .map(|line| {
-                let tokens = line.split_whitespace();
-                tokens
-                    .map(|token| {
-                        let highlight_type = self.syntax_highlighting.highlight_token(token);
-                        (token.to_string(), highlight_type)
-                    })
-                    .collect()
-            })
-            .collect()
+        self.history.reset_index();
+        self.history_draft = None;
+    }
+
+    pub fn get_highlighted_lines(&mut self) -> Vec<Vec<(String, Option<&'static str>)>> {
+        let line_count = self.content.lines().count();
+        let mut lines: Vec<Vec<(String, Option<&'static str>)>> = vec![Vec::new(); line_count];
+        let mut line_starts = vec![0];
+        line_starts.extend(self.content.match_indices('\n').map(|(i, _)| i + 1));
+
+        let spans = self.syntax_highlighting.highlight(&self.content).to_vec();
+        for span in spans {
+            let highlight_type = self.syntax_highlighting.highlight_span(&self.content, &span);
+            // Heredoc bodies and multi-line strings split across lines
+            let mut offset = span.start;
+            for piece in span.text(&self.content).split('\n') {
+                let line = line_starts.partition_point(|&start| start <= offset) - 1;
+                if !piece.is_empty() && line < lines.len() {
+                    lines[line].push((piece.to_string(), highlight_type));
+                }
+                offset += piece.len() + 1;
+            }
+        }
+        lines
+    }
+
+    /// Highlight spans for the current content; cheap to call on every keystroke
+    pub fn highlight(&mut self) -> &[Span] {
+        self.syntax_highlighting.highlight(&self.content)
     }
 }
 
@@ -204,4 +238,24 @@ mod tests {
         input.history_previous();
         assert_eq!(input.content, "cmd1");
     }
+
+    #[test]
+    fn test_highlighted_lines_follow_shell_syntax() {
+        let mut input = AdvancedInput::new();
+        input.syntax_highlighting.resolver = CommandResolver::new().with_directories([]);
+        input.content = "echo \"a b\" 2>&1\ncat <<EOF\nhi\nEOF".to_string();
+        let lines = input.get_highlighted_lines();
+        assert_eq!(
+            lines[0],
+            vec![
+                ("echo".to_string(), Some("builtin")),
+                ("\"a b\"".to_string(), Some("string")),
+                ("2>&1".to_string(), Some("redirection")),
+            ]
+        );
+        assert_eq!(lines[1][0], ("cat".to_string(), Some("command_not_found")));
+        assert_eq!(lines[2], vec![("hi".to_string(), Some("heredoc"))]);
+        assert_eq!(lines[3], vec![("EOF".to_string(), Some("heredoc"))]);
+        assert_eq!(input.highlight().len(), 7);
+    }
 }
@@ -0,0 +1,395 @@
+use super::context_probe::TOOL_ALIASES;
+use super::contextual_chips::{Chip, ChipType, ContextualChips, GitStatus};
+use super::shell_lexer::shell_quote;
+use crate::classic_input::prompt_segments::find_upwards;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Subdirectories offered by the directory picker
+const MAX_DIRECTORIES: usize = 50;
+
+/// Environment variables that move a version manager's data directory
+const MANAGER_ENV: &[&str] = &["RUSTUP_HOME", "NVM_DIR", "FNM_DIR", "PYENV_ROOT", "ASDF_DATA_DIR", "MISE_DATA_DIR"];
+
+/// A tool that installs and switches runtime versions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VersionManager {
+    Rustup,
+    Nvm,
+    Fnm,
+    Pyenv,
+    Asdf,
+    Mise,
+}
+
+impl VersionManager {
+    pub fn name(&self) -> &'static str {
+        match self {
+            VersionManager::Rustup => "rustup",
+            VersionManager::Nvm => "nvm",
+            VersionManager::Fnm => "fnm",
+            VersionManager::Pyenv => "pyenv",
+            VersionManager::Asdf => "asdf",
+            VersionManager::Mise => "mise",
+        }
+    }
+
+    /// Shell command that switches `tool` to `version` for the current directory
+    pub fn switch_command(&self, tool: &str, version: &str) -> String {
+        match self {
+            VersionManager::Rustup => format!("rustup override set {}", version),
+            VersionManager::Nvm => format!("nvm use {}", version),
+            VersionManager::Fnm => format!("fnm use {}", version),
+            VersionManager::Pyenv => format!("pyenv local {}", version),
+            VersionManager::Asdf => format!("asdf local {} {}", tool, version),
+            VersionManager::Mise => format!("mise use {}@{}", tool, version),
+        }
+    }
+}
+
+/// Something a chip can do when picked from its menu
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChipAction {
+    ChangeDirectory(PathBuf),
+    SwitchBranch(String),
+    Stash,
+    ShowDiff,
+    OpenConversation(String),
+    CloseConversation,
+    PreviewAttachment(String),
+    RemoveAttachment(String),
+    SwitchRuntime {
+        manager: VersionManager,
+        /// The runtime's name for the manager, like `nodejs`
+        tool: String,
+        version: String,
+    },
+    /// Drop an `@` reference; `kind` and `value` are the chip's label and value
+    RemoveMention { kind: String, value: String },
+}
+
+impl ChipAction {
+    /// The shell command that carries the action out, for actions that need one
+    pub fn command(&self) -> Option<String> {
+        match self {
+            ChipAction::ChangeDirectory(path) => Some(format!("cd {}", shell_quote(&path.display().to_string()))),
+            ChipAction::SwitchBranch(branch) => Some(format!("git switch {}", shell_quote(branch))),
+            ChipAction::Stash => Some("git stash".to_string()),
+            ChipAction::ShowDiff => Some("git diff".to_string()),
+            ChipAction::SwitchRuntime { manager, tool, version } => Some(manager.switch_command(tool, version)),
+            _ => None,
+        }
+    }
+}
+
+/// A row in a chip's menu
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChipMenuItem {
+    pub label: String,
+    pub action: ChipAction,
+}
+
+impl ChipMenuItem {
+    pub fn new(label: impl Into<String>, action: ChipAction) -> Self {
+        Self { label: label.into(), action }
+    }
+}
+
+/// The open menu of a chip and its selection
+#[derive(Debug, Clone, Default)]
+pub struct ChipMenu {
+    /// Index of the chip in `ContextualChips::chips`
+    pub chip: usize,
+    pub items: Vec<ChipMenuItem>,
+    pub selected: usize,
+}
+
+impl ChipMenu {
+    pub fn new(chip: usize, items: Vec<ChipMenuItem>) -> Self {
+        Self { chip, items, selected: 0 }
+    }
+
+    pub fn select_next(&mut self) {
+        if self.selected + 1 < self.items.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn select_prev(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    pub fn selected_item(&self) -> Option<&ChipMenuItem> {
+        self.items.get(self.selected)
+    }
+}
+
+/// Builds chip menus from the filesystem, git and installed version managers
+#[derive(Debug, Clone)]
+pub struct ChipActions {
+    home: PathBuf,
+    env: HashMap<String, String>,
+}
+
+impl ChipActions {
+    pub fn new() -> Self {
+        let home = std::env::var_os("HOME").map(PathBuf::from).unwrap_or_default();
+        let env = MANAGER_ENV
+            .iter()
+            .filter_map(|name| Some((name.to_string(), std::env::var(name).ok()?)))
+            .collect();
+        Self { home, env }
+    }
+
+    /// Looks for version managers under `home` only, ignoring the environment
+    pub fn with_home(mut self, home: impl Into<PathBuf>) -> Self {
+        self.home = home.into();
+        self.env.clear();
+        self
+    }
+
+    /// Menu rows for `chip`; empty when the chip has no actions
+    pub fn menu(&self, chip: &Chip, chips: &ContextualChips) -> Vec<ChipMenuItem> {
+        match &chip.chip_type {
+            ChipType::Directory => self.directory_items(Path::new(&chip.value)),
+            ChipType::GitStatus => self.git_items(chips),
+            ChipType::Conversation => match &chips.active_conversation {
+                Some(id) => vec![
+                    ChipMenuItem::new("Open conversation", ChipAction::OpenConversation(id.clone())),
+                    ChipMenuItem::new("Close conversation", ChipAction::CloseConversation),
+                ],
+                None => Vec::new(),
+            },
+            ChipType::Attachment => vec![
+                ChipMenuItem::new("Preview", ChipAction::PreviewAttachment(chip.value.clone())),
+                ChipMenuItem::new("Remove", ChipAction::RemoveAttachment(chip.value.clone())),
+            ],
+            ChipType::Mention => vec![ChipMenuItem::new(
+                "Remove",
+                ChipAction::RemoveMention { kind: chip.label.clone(), value: chip.value.clone() },
+            )],
+            ChipType::RuntimeVersion => self.runtime_items(chip, Path::new(&chips.working_directory)),
+            ChipType::Profile | ChipType::Custom(_) => Vec::new(),
+        }
+    }
+
+    /// The parent and the visible subdirectories of `dir`
+    fn directory_items(&self, dir: &Path) -> Vec<ChipMenuItem> {
+        let mut items = Vec::new();
+        if let Some(parent) = dir.parent() {
+            items.push(ChipMenuItem::new("..", ChipAction::ChangeDirectory(parent.to_path_buf())));
+        }
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter(|entry| entry.path().is_dir())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .filter(|name| !name.starts_with('.'))
+            .collect();
+        names.sort();
+        names.truncate(MAX_DIRECTORIES);
+        items.extend(
+            names
+                .into_iter()
+                .map(|name| ChipMenuItem::new(format!("{}/", name), ChipAction::ChangeDirectory(dir.join(name)))),
+        );
+        items
+    }
+
+    fn git_items(&self, chips: &ContextualChips) -> Vec<ChipMenuItem> {
+        let Some(info) = &chips.git_info else {
+            return Vec::new();
+        };
+        let mut items = vec![ChipMenuItem::new("Show diff", ChipAction::ShowDiff)];
+        if info.status != GitStatus::Clean {
+            items.push(ChipMenuItem::new("Stash changes", ChipAction::Stash));
+        }
+        // Listed by the context probe, so opening the menu never waits on git
+        items.extend(
+            chips
+                .git_branches
+                .iter()
+                .filter(|branch| **branch != info.branch)
+                .map(|branch| {
+                    ChipMenuItem::new(format!("Switch to {}", branch), ChipAction::SwitchBranch(branch.to_string()))
+                }),
+        );
+        items
+    }
+
+    fn runtime_items(&self, chip: &Chip, cwd: &Path) -> Vec<ChipMenuItem> {
+        let tool = tool_name(&chip.label);
+        let Some((manager, versions)) = self.detect_manager(&chip.label, tool, cwd) else {
+            return Vec::new();
+        };
+        versions
+            .into_iter()
+            .filter(|version| *version != chip.value)
+            .map(|version| {
+                ChipMenuItem::new(
+                    format!("Use {} ({})", version, manager.name()),
+                    ChipAction::SwitchRuntime { manager, tool: tool.to_string(), version },
+                )
+            })
+            .collect()
+    }
+
+    /// The manager that looks after `runtime` here, with its installed versions,
+    /// newest first. A `.tool-versions` file means asdf or mise is in charge.
+    pub fn detect_manager(&self, runtime: &str, tool: &str, cwd: &Path) -> Option<(VersionManager, Vec<String>)> {
+        let mut candidates = Vec::new();
+        if find_upwards(cwd, ".tool-versions").is_some() {
+            candidates.extend([VersionManager::Mise, VersionManager::Asdf]);
+        }
+        candidates.extend(match runtime {
+            "Rust" => &[VersionManager::Rustup][..],
+            "Node" => &[VersionManager::Nvm, VersionManager::Fnm][..],
+            "Python" => &[VersionManager::Pyenv][..],
+            _ => &[][..],
+        });
+        candidates.extend([VersionManager::Mise, VersionManager::Asdf]);
+        candidates.into_iter().find_map(|manager| {
+            let versions = self.installed_versions(manager, tool);
+            (!versions.is_empty()).then_some((manager, versions))
+        })
+    }
+
+    /// Versions of `tool` installed by `manager`, newest first
+    pub fn installed_versions(&self, manager: VersionManager, tool: &str) -> Vec<String> {
+        let dir = match manager {
+            VersionManager::Rustup => self.data_dir("RUSTUP_HOME", ".rustup").join("toolchains"),
+            VersionManager::Nvm => self.data_dir("NVM_DIR", ".nvm").join("versions/node"),
+            VersionManager::Fnm => self.data_dir("FNM_DIR", ".local/share/fnm").join("node-versions"),
+            VersionManager::Pyenv => self.data_dir("PYENV_ROOT", ".pyenv").join("versions"),
+            VersionManager::Asdf => self.data_dir("ASDF_DATA_DIR", ".asdf").join("installs").join(tool),
+            VersionManager::Mise => self.data_dir("MISE_DATA_DIR", ".local/share/mise").join("installs").join(tool),
+        };
+        let mut versions: Vec<String> = fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter(|entry| entry.path().is_dir())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .filter(|name| !name.starts_with('.'))
+            .map(|name| match manager {
+                VersionManager::Nvm | VersionManager::Fnm => name.trim_start_matches('v').to_string(),
+                _ => name,
+            })
+            .collect();
+        versions.sort_by_key(|version| Reverse(version_key(version)));
+        versions
+    }
+
+    fn data_dir(&self, var: &str, default: &str) -> PathBuf {
+        match self.env.get(var) {
+            Some(dir) => PathBuf::from(dir),
+            None => self.home.join(default),
+        }
+    }
+}
+
+impl Default for ChipActions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A runtime chip's label as version managers name the tool
+fn tool_name(label: &str) -> &str {
+    TOOL_ALIASES
+        .iter()
+        .find(|(_, chip_label)| *chip_label == label)
+        .map(|(tool, _)| *tool)
+        .unwrap_or(label)
+}
+
+/// Numeric parts of a version for ordering; `1.10.0` sorts after `1.9.2`
+fn version_key(version: &str) -> (Vec<u64>, String) {
+    let numbers = version
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|part| !part.is_empty())
+        .filter_map(|part| part.parse().ok())
+        .collect();
+    (numbers, version.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_directory_menu() {
+        let root = TempDir::new("chip_actions_dirs");
+        for dir in ["src", "docs", ".git"] {
+            root.mkdir(dir);
+        }
+        root.write("README.md", "");
+
+        let mut chips = ContextualChips::new();
+        chips.add_directory_chip(root.display().to_string());
+        let items = ChipActions::new().menu(&chips.chips[0], &chips);
+        let labels: Vec<&str> = items.iter().map(|i| i.label.as_str()).collect();
+        assert_eq!(labels, vec!["..", "docs/", "src/"]);
+        assert_eq!(items[2].action, ChipAction::ChangeDirectory(root.join("src")));
+        assert_eq!(
+            ChipAction::ChangeDirectory(PathBuf::from("/tmp/my dir")).command().as_deref(),
+            Some("cd '/tmp/my dir'")
+        );
+    }
+
+    #[test]
+    fn test_git_menu_uses_listed_branches() {
+        let mut chips = ContextualChips::new();
+        chips.add_git_chip("main".to_string(), GitStatus::Modified);
+        chips.git_branches = vec!["feature/x".to_string(), "main".to_string()];
+        let items = ChipActions::new().menu(&chips.chips[0], &chips);
+        let labels: Vec<&str> = items.iter().map(|i| i.label.as_str()).collect();
+        assert_eq!(labels, vec!["Show diff", "Stash changes", "Switch to feature/x"]);
+        assert_eq!(items[2].action.command().as_deref(), Some("git switch 'feature/x'"));
+    }
+
+    #[test]
+    fn test_runtime_versions_from_manager() {
+        let home = TempDir::new("chip_actions_home");
+        for version in ["v18.19.0", "v20.11.0", "v9.11.2"] {
+            home.mkdir(&format!(".nvm/versions/node/{}", version));
+        }
+        let project = TempDir::new("chip_actions_node_project");
+        let actions = ChipActions::new().with_home(&*home);
+
+        let mut chips = ContextualChips::new();
+        chips.working_directory = project.display().to_string();
+        chips.add_runtime_chip("Node".to_string(), "20.11.0".to_string());
+        let items = actions.menu(&chips.chips[0], &chips);
+        let commands: Vec<String> = items.iter().filter_map(|i| i.action.command()).collect();
+        assert_eq!(commands, vec!["nvm use 18.19.0", "nvm use 9.11.2"]);
+
+        // A .tool-versions file hands the runtime over to asdf
+        home.mkdir(".asdf/installs/nodejs/21.0.0");
+        project.write(".tool-versions", "nodejs 21.0.0\n");
+        let (manager, versions) = actions.detect_manager("Node", "nodejs", &project).unwrap();
+        assert_eq!(manager, VersionManager::Asdf);
+        assert_eq!(versions, vec!["21.0.0"]);
+        assert_eq!(manager.switch_command("nodejs", "21.0.0"), "asdf local nodejs 21.0.0");
+    }
+
+    #[test]
+    fn test_menus_per_chip_type() {
+        let mut chips = ContextualChips::new();
+        chips.set_conversation_chip("c1".to_string(), "Fix build".to_string());
+        chips.add_attachment_chip("log.txt".to_string());
+        chips.add_chip(Chip::new(ChipType::Profile, "Profile".into(), "default".into(), "👤".into()));
+        let actions = ChipActions::new();
+
+        let conversation = actions.menu(&chips.chips[0], &chips);
+        assert_eq!(conversation[0].action, ChipAction::OpenConversation("c1".to_string()));
+        assert_eq!(conversation[1].action, ChipAction::CloseConversation);
+        let attachment = actions.menu(&chips.chips[1], &chips);
+        assert_eq!(attachment[1].action, ChipAction::RemoveAttachment("log.txt".to_string()));
+        assert!(actions.menu(&chips.chips[2], &chips).is_empty());
+    }
+}
@@ -0,0 +1,352 @@
+use crate::classic_input::input_classifier::{is_executable, read_executables, CommandLookup, SHELL_BUILTINS};
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// What the first word of a command resolves to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    Alias,
+    Function,
+    Builtin,
+    Executable(PathBuf),
+    NotFound,
+}
+
+impl Resolution {
+    pub fn highlight_class(&self) -> &'static str {
+        match self {
+            Resolution::Alias => "alias",
+            Resolution::Function => "function",
+            Resolution::Builtin => "builtin",
+            Resolution::Executable(_) => "command",
+            Resolution::NotFound => "command_not_found",
+        }
+    }
+}
+
+/// Whether an argument that looks like a path names something on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathStatus {
+    Exists,
+    /// Doesn't exist yet, but an entry starts with it: the user is still typing
+    Prefix,
+    Missing,
+}
+
+impl PathStatus {
+    pub fn highlight_class(&self) -> &'static str {
+        match self {
+            PathStatus::Exists => "path",
+            PathStatus::Prefix => "path_prefix",
+            PathStatus::Missing => "path_missing",
+        }
+    }
+}
+
+/// Path arguments whose status is remembered before the cache starts over
+const PATH_CACHE_SIZE: usize = 512;
+
+/// Status of a path argument, as of its parent directory's modification time
+#[derive(Debug, Clone, Copy)]
+struct PathCacheEntry {
+    parent_modified: Option<SystemTime>,
+    status: PathStatus,
+}
+
+/// Executables in one `$PATH` directory, as of its last modification time
+#[derive(Debug, Clone, Default)]
+struct DirectoryCache {
+    modified: Option<SystemTime>,
+    executables: HashSet<String>,
+}
+
+/// Resolves command names against aliases, functions, builtins and `$PATH`.
+///
+/// Directory listings are cached and only re-read when `$PATH` itself or a
+/// directory's mtime changes, checked at most once per `check_interval`.
+/// Path arguments are cached the same way, against their parent directory.
+#[derive(Debug, Clone)]
+pub struct CommandResolver {
+    pub aliases: HashMap<String, String>,
+    pub functions: HashSet<String>,
+    pub cwd: PathBuf,
+    /// Whether `directories` tracks `$PATH` or was set explicitly
+    follows_env: bool,
+    path_var: Option<OsString>,
+    directories: Vec<PathBuf>,
+    cache: HashMap<PathBuf, DirectoryCache>,
+    /// Keyed by (cwd, word)
+    path_cache: HashMap<(PathBuf, String), PathCacheEntry>,
+    check_interval: Duration,
+    last_check: Option<Instant>,
+}
+
+impl CommandResolver {
+    pub fn new() -> Self {
+        Self {
+            aliases: HashMap::new(),
+            functions: HashSet::new(),
+            cwd: std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")),
+            follows_env: false,
+            path_var: None,
+            directories: Vec::new(),
+            cache: HashMap::new(),
+            path_cache: HashMap::new(),
+            check_interval: Duration::from_secs(1),
+            last_check: None,
+        }
+    }
+
+    /// Searches `$PATH`; directories are read lazily on the first lookup
+    pub fn from_env() -> Self {
+        let mut resolver = Self::new();
+        resolver.follows_env = true;
+        resolver.sync_path_var(std::env::var_os("PATH"));
+        resolver
+    }
+
+    /// Searches `dirs` instead of `$PATH`
+    pub fn with_directories(mut self, dirs: impl IntoIterator<Item = PathBuf>) -> Self {
+        self.follows_env = false;
+        self.directories = dirs.into_iter().collect();
+        self
+    }
+
+    pub fn with_cwd(mut self, cwd: impl Into<PathBuf>) -> Self {
+        self.cwd = cwd.into();
+        self
+    }
+
+    pub fn with_check_interval(mut self, interval: Duration) -> Self {
+        self.check_interval = interval;
+        self
+    }
+
+    pub fn set_cwd(&mut self, cwd: impl Into<PathBuf>) {
+        self.cwd = cwd.into();
+    }
+
+    pub fn add_alias(&mut self, name: impl Into<String>, expansion: impl Into<String>) {
+        self.aliases.insert(name.into(), expansion.into());
+    }
+
+    pub fn add_function(&mut self, name: impl Into<String>) {
+        self.functions.insert(name.into());
+    }
+
+    pub fn resolve(&mut self, name: &str) -> Resolution {
+        if name.contains('/') {
+            let path = self.expand(name);
+            return if is_executable(&path) {
+                Resolution::Executable(path)
+            } else {
+                Resolution::NotFound
+            };
+        }
+        if self.aliases.contains_key(name) {
+            return Resolution::Alias;
+        }
+        if self.functions.contains(name) {
+            return Resolution::Function;
+        }
+        if SHELL_BUILTINS.contains(&name) {
+            return Resolution::Builtin;
+        }
+
+        self.refresh();
+        self.directories
+            .iter()
+            .find(|dir| self.cache.get(*dir).is_some_and(|c| c.executables.contains(name)))
+            .map(|dir| Resolution::Executable(dir.join(name)))
+            .unwrap_or(Resolution::NotFound)
+    }
+
+    /// Everything that resolves by name, from the cached listings. Agent mode
+    /// and the fixer use this so they agree with the highlighter.
+    pub fn lookup(&mut self) -> CommandLookup {
+        self.refresh();
+        let mut lookup = CommandLookup::new();
+        for dir in &self.directories {
+            if let Some(cached) = self.cache.get(dir) {
+                lookup.executables.extend(cached.executables.iter().cloned());
+            }
+        }
+        lookup.aliases.extend(self.aliases.keys().cloned());
+        lookup.functions.extend(self.functions.iter().cloned());
+        lookup
+    }
+
+    /// Existence of a path argument, relative to `cwd`. Answers are reused
+    /// until the parent directory's mtime changes, so redrawing the same line
+    /// costs one `stat` per path.
+    pub fn path_status(&mut self, word: &str) -> PathStatus {
+        let path = self.expand(word);
+        let parent_modified = path
+            .parent()
+            .and_then(|parent| std::fs::metadata(parent).and_then(|m| m.modified()).ok());
+        let key = (self.cwd.clone(), word.to_string());
+        if let Some(entry) = self.path_cache.get(&key) {
+            if entry.parent_modified.is_some() && entry.parent_modified == parent_modified {
+                return entry.status;
+            }
+        }
+
+        let status = read_path_status(&path);
+        if self.path_cache.len() >= PATH_CACHE_SIZE {
+            self.path_cache.clear();
+        }
+        self.path_cache.insert(key, PathCacheEntry { parent_modified, status });
+        status
+    }
+
+    /// Re-reads `$PATH` directories that changed since they were cached
+    pub fn refresh(&mut self) {
+        if self.last_check.is_some_and(|at| at.elapsed() < self.check_interval) {
+            return;
+        }
+        self.last_check = Some(Instant::now());
+
+        if self.follows_env {
+            let path_var = std::env::var_os("PATH");
+            if path_var != self.path_var {
+                self.sync_path_var(path_var);
+            }
+        }
+
+        for dir in &self.directories {
+            let modified = std::fs::metadata(dir).and_then(|m| m.modified()).ok();
+            let cached = self.cache.entry(dir.clone()).or_default();
+            if cached.modified.is_some() && cached.modified == modified {
+                continue;
+            }
+            cached.modified = modified;
+            cached.executables = read_executables(dir);
+        }
+    }
+
+    fn sync_path_var(&mut self, path_var: Option<OsString>) {
+        self.directories = path_var
+            .as_ref()
+            .map(|p| std::env::split_paths(p).collect())
+            .unwrap_or_default();
+        // Keep listings for directories that are still on the path
+        self.cache.retain(|dir, _| self.directories.contains(dir));
+        self.path_var = path_var;
+    }
+
+    fn expand(&self, word: &str) -> PathBuf {
+        let expanded = match word.strip_prefix('~') {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => match std::env::var("HOME") {
+                Ok(home) => format!("{}{}", home, rest),
+                Err(_) => word.to_string(),
+            },
+            _ => word.to_string(),
+        };
+        self.cwd.join(expanded)
+    }
+}
+
+impl Default for CommandResolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn read_path_status(path: &Path) -> PathStatus {
+    if path.exists() {
+        return PathStatus::Exists;
+    }
+    let (Some(parent), Some(stem)) = (path.parent(), path.file_name().and_then(|n| n.to_str())) else {
+        return PathStatus::Missing;
+    };
+    let has_prefix = std::fs::read_dir(parent).is_ok_and(|entries| {
+        entries
+            .flatten()
+            .any(|e| e.file_name().to_str().is_some_and(|n| n.starts_with(stem)))
+    });
+    if has_prefix {
+        PathStatus::Prefix
+    } else {
+        PathStatus::Missing
+    }
+}
+
+/// Arguments worth checking on disk, like fish: anything with a slash, or
+/// starting with `~` or `.`
+pub fn looks_like_path(word: &str) -> bool {
+    word.contains('/') || word.starts_with('~') || (word.starts_with('.') && word.len() > 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[cfg(unix)]
+    fn write_executable(path: &Path) {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::write(path, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolution_order_and_cache_invalidation() {
+        let bin = TempDir::new("resolver_bin");
+        write_executable(&bin.join("mytool"));
+        let mut resolver = CommandResolver::new()
+            .with_directories([bin.to_path_buf()])
+            .with_check_interval(Duration::ZERO);
+        resolver.add_alias("ll", "ls -l");
+        resolver.add_function("mkcd");
+
+        assert_eq!(resolver.resolve("mytool"), Resolution::Executable(bin.join("mytool")));
+        assert_eq!(resolver.resolve("ll"), Resolution::Alias);
+        assert_eq!(resolver.resolve("mkcd"), Resolution::Function);
+        assert_eq!(resolver.resolve("cd"), Resolution::Builtin);
+        assert_eq!(resolver.resolve("gti"), Resolution::NotFound);
+
+        // A new executable bumps the directory mtime and is picked up
+        std::thread::sleep(Duration::from_millis(20));
+        write_executable(&bin.join("gti"));
+        let modified = SystemTime::now() + Duration::from_secs(1);
+        std::fs::File::open(&bin).unwrap().set_modified(modified).unwrap();
+        assert_eq!(resolver.resolve("gti"), Resolution::Executable(bin.join("gti")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_lookup_agrees_with_resolve() {
+        let bin = TempDir::new("resolver_lookup");
+        write_executable(&bin.join("mytool"));
+        bin.write("notes.txt", "");
+        let mut resolver = CommandResolver::new().with_directories([bin.to_path_buf()]);
+        resolver.add_alias("ll", "ls -l");
+
+        let lookup = resolver.lookup();
+        for name in ["mytool", "ll", "cd", "notes.txt", "gti"] {
+            let resolves = resolver.resolve(name) != Resolution::NotFound;
+            assert_eq!(lookup.resolves(name), resolves, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_path_status() {
+        let dir = TempDir::new("resolver_paths");
+        dir.write("src/main.rs", "");
+        let mut resolver = CommandResolver::new().with_cwd(&*dir);
+
+        assert_eq!(resolver.path_status("src/main.rs"), PathStatus::Exists);
+        assert_eq!(resolver.path_status("./src/ma"), PathStatus::Prefix);
+        assert_eq!(resolver.path_status("src/missing.rs"), PathStatus::Missing);
+
+        // Creating the file changes the parent's mtime, which drops the cached answer
+        dir.write("src/missing.rs", "");
+        let modified = SystemTime::now() + Duration::from_secs(1);
+        std::fs::File::open(dir.join("src")).unwrap().set_modified(modified).unwrap();
+        assert_eq!(resolver.path_status("src/missing.rs"), PathStatus::Exists);
+        assert!(looks_like_path("./build.sh") && looks_like_path("~/notes") && !looks_like_path("main"));
+    }
+}
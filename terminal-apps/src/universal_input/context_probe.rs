@@ -0,0 +1,345 @@
+use super::contextual_chips::{ChipType, ContextualChips, GitInfo, GitStatus};
+use crate::classic_input::prompt_segments::{
+    command_output, find_git_dir, find_upwards, read_git_branch, GitBranchProvider, GitStatusSummary,
+    NodeVersionProvider, PythonVersionProvider, RustVersionProvider, SegmentProvider, SegmentRunner,
+    SegmentState,
+};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Runtime chips from dedicated probes, as (probe, chip label)
+const RUNTIME_PROBES: &[(&str, &str)] = &[
+    ("rust_version", "Rust"),
+    ("node_version", "Node"),
+    ("python_version", "Python"),
+    ("go_version", "Go"),
+];
+
+/// `.tool-versions` names for the runtimes above, so they aren't shown twice
+pub(crate) const TOOL_ALIASES: &[(&str, &str)] = &[("rust", "Rust"), ("nodejs", "Node"), ("python", "Python"), ("golang", "Go")];
+
+/// Raw `git status --porcelain=v2 --branch`, which carries the branch, the
+/// commit and the working tree state in one call
+#[derive(Debug, Clone, Copy)]
+pub struct GitStateProvider;
+
+impl SegmentProvider for GitStateProvider {
+    fn name(&self) -> &'static str {
+        "git_state"
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_secs(2)
+    }
+
+    fn compute(&self, cwd: &Path) -> Option<String> {
+        find_git_dir(cwd)?;
+        command_output("git", &["status", "--porcelain=v2", "--branch"], cwd)
+    }
+}
+
+/// Local branch names, one per line, for switching from the git chip
+#[derive(Debug, Clone, Copy)]
+pub struct GitBranchesProvider;
+
+impl SegmentProvider for GitBranchesProvider {
+    fn name(&self) -> &'static str {
+        "git_branches"
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_secs(2)
+    }
+
+    fn compute(&self, cwd: &Path) -> Option<String> {
+        find_git_dir(cwd)?;
+        command_output("git", &["for-each-ref", "--format=%(refname:short)", "refs/heads"], cwd)
+    }
+}
+
+/// Go version from the `go` directive in go.mod, falling back to `go version`
+#[derive(Debug, Clone, Copy)]
+pub struct GoVersionProvider;
+
+impl SegmentProvider for GoVersionProvider {
+    fn name(&self) -> &'static str {
+        "go_version"
+    }
+
+    fn compute(&self, cwd: &Path) -> Option<String> {
+        let go_mod = find_upwards(cwd, "go.mod")?;
+        if let Ok(content) = fs::read_to_string(&go_mod) {
+            let directive = content.lines().find_map(|l| l.trim().strip_prefix("go ").map(str::trim));
+            if let Some(version) = directive.filter(|v| !v.is_empty()) {
+                return Some(version.to_string());
+            }
+        }
+        let output = command_output("go", &["version"], cwd)?;
+        output.split_whitespace().nth(2).map(|v| v.trim_start_matches("go").to_string())
+    }
+}
+
+/// asdf/mise `.tool-versions`, one `tool version` per line
+#[derive(Debug, Clone, Copy)]
+pub struct ToolVersionsProvider;
+
+impl SegmentProvider for ToolVersionsProvider {
+    fn name(&self) -> &'static str {
+        "tool_versions"
+    }
+
+    fn compute(&self, cwd: &Path) -> Option<String> {
+        let content = fs::read_to_string(find_upwards(cwd, ".tool-versions")?).ok()?;
+        let lines: Vec<String> = content
+            .lines()
+            .map(|l| l.split('#').next().unwrap_or("").trim())
+            .filter_map(|l| {
+                let mut words = l.split_whitespace();
+                Some(format!("{} {}", words.next()?, words.next()?))
+            })
+            .collect();
+        (!lines.is_empty()).then(|| lines.join("\n"))
+    }
+}
+
+/// Detects the project and git state of the working directory in the
+/// background and keeps the git and runtime chips in step with it
+#[derive(Debug, Clone)]
+pub struct ContextProbe {
+    runner: SegmentRunner,
+    cwd: PathBuf,
+}
+
+impl ContextProbe {
+    pub fn new() -> Self {
+        let mut runner = SegmentRunner::new();
+        runner.register(GitBranchProvider);
+        runner.register(GitStateProvider);
+        runner.register(GitBranchesProvider);
+        runner.register(RustVersionProvider);
+        runner.register(NodeVersionProvider);
+        runner.register(PythonVersionProvider);
+        runner.register(GoVersionProvider);
+        runner.register(ToolVersionsProvider);
+        Self { runner, cwd: PathBuf::new() }
+    }
+
+    pub fn cwd(&self) -> &Path {
+        &self.cwd
+    }
+
+    /// Starts probing `cwd`, dropping results still pending for the previous directory
+    pub fn refresh(&mut self, cwd: impl Into<PathBuf>) {
+        self.cwd = cwd.into();
+        self.runner.refresh(&self.cwd);
+    }
+
+    /// Collects finished probes without blocking and rebuilds the chips when
+    /// any changed. Returns true when the chips should be redrawn.
+    pub fn poll(&mut self, chips: &mut ContextualChips) -> bool {
+        if self.runner.poll().is_empty() {
+            return false;
+        }
+        self.apply(chips);
+        true
+    }
+
+    /// Blocks until every probe is done or `max_wait` passes, then updates the chips
+    pub fn wait(&mut self, chips: &mut ContextualChips, max_wait: Duration) {
+        self.runner.wait(max_wait);
+        self.apply(chips);
+    }
+
+    pub fn state(&self, probe: &str) -> Option<&SegmentState> {
+        self.runner.state(probe)
+    }
+
+    /// Git state for the directory, from whichever probes have finished
+    pub fn git_info(&self) -> Option<GitInfo> {
+        let values = self.runner.values();
+        let porcelain = values.get("git_state");
+        let branch = porcelain
+            .and_then(|p| porcelain_header(p, "branch.head"))
+            .filter(|b| *b != "(detached)")
+            .map(str::to_string)
+            .or_else(|| values.get("git_branch").cloned())
+            .or_else(|| read_git_branch(&find_git_dir(&self.cwd)?))?;
+        let commit_hash = porcelain
+            .and_then(|p| porcelain_header(p, "branch.oid"))
+            .filter(|oid| *oid != "(initial)")
+            .map(|oid| oid.chars().take(7).collect())
+            .unwrap_or_default();
+        let status = match porcelain.map(|p| GitStatusSummary::parse(p)) {
+            Some(summary) => git_status(&summary),
+            None => GitStatus::Clean,
+        };
+        Some(GitInfo { branch, status, commit_hash })
+    }
+
+    /// Local branches of the repository, once listed
+    pub fn branches(&self) -> Vec<String> {
+        self.runner
+            .values()
+            .get("git_branches")
+            .map(|branches| branches.lines().filter(|b| !b.is_empty()).map(str::to_string).collect())
+            .unwrap_or_default()
+    }
+
+    /// Runtime chips as (label, version), dedicated probes first
+    pub fn runtimes(&self) -> Vec<(String, String)> {
+        let values = self.runner.values();
+        let mut runtimes: Vec<(String, String)> = RUNTIME_PROBES
+            .iter()
+            .filter_map(|(probe, label)| Some((label.to_string(), values.get(*probe)?.clone())))
+            .collect();
+        if let Some(tools) = values.get("tool_versions") {
+            for line in tools.lines() {
+                let Some((tool, version)) = line.split_once(' ') else {
+                    continue;
+                };
+                let label = TOOL_ALIASES
+                    .iter()
+                    .find(|(alias, _)| *alias == tool)
+                    .map(|(_, label)| label.to_string())
+                    .unwrap_or_else(|| tool.to_string());
+                if !runtimes.iter().any(|(l, _)| *l == label) {
+                    runtimes.push((label, version.to_string()));
+                }
+            }
+        }
+        runtimes
+    }
+
+    /// Replaces the git and runtime chips with what the probes found. Chips
+    /// that didn't change stay put, so focus doesn't move under the user.
+    pub fn apply(&self, chips: &mut ContextualChips) {
+        match self.git_info() {
+            Some(info) => {
+                chips.set_git_chip(info);
+                chips.git_branches = self.branches();
+            }
+            None => chips.clear_git_chip(),
+        }
+        let runtimes = self
+            .runtimes()
+            .into_iter()
+            .map(|(runtime, version)| ContextualChips::runtime_chip(runtime, version))
+            .collect();
+        chips.replace_chips_of_type(ChipType::RuntimeVersion, runtimes);
+    }
+}
+
+impl Default for ContextProbe {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Value of a `# key value` header line from porcelain v2 output
+fn porcelain_header<'a>(porcelain: &'a str, key: &str) -> Option<&'a str> {
+    porcelain.lines().find_map(|line| {
+        let rest = line.strip_prefix("# ")?.strip_prefix(key)?;
+        rest.strip_prefix(' ').map(str::trim)
+    })
+}
+
+fn git_status(summary: &GitStatusSummary) -> GitStatus {
+    let changed = summary.staged + summary.modified + summary.conflicted > 0;
+    match (changed, summary.untracked > 0) {
+        (false, false) => GitStatus::Clean,
+        (true, false) => GitStatus::Modified,
+        (false, true) => GitStatus::Untracked,
+        (true, true) => GitStatus::Mixed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::process::Command;
+
+    fn project(name: &str) -> TempDir {
+        let root = TempDir::new(&format!("probe_{}", name));
+        root.mkdir("src");
+        root
+    }
+
+    #[test]
+    fn test_runtime_markers() {
+        let root = project("runtimes");
+        fs::write(root.join("package.json"), "{}").unwrap();
+        fs::write(root.join(".nvmrc"), "v20.11.0\n").unwrap();
+        fs::write(root.join("go.mod"), "module example.com/x\n\ngo 1.22\n").unwrap();
+        fs::write(root.join(".tool-versions"), "nodejs 18.0.0\nterraform 1.7.5 # infra\n").unwrap();
+
+        let mut probe = ContextProbe::new();
+        let mut chips = ContextualChips::new();
+        probe.refresh(root.join("src"));
+        probe.wait(&mut chips, Duration::from_secs(5));
+
+        assert_eq!(
+            probe.runtimes(),
+            vec![
+                ("Node".to_string(), "20.11.0".to_string()),
+                ("Go".to_string(), "1.22".to_string()),
+                ("terraform".to_string(), "1.7.5".to_string()),
+            ]
+        );
+        let runtime_chips: Vec<String> = chips
+            .chips
+            .iter()
+            .filter(|c| c.chip_type == ChipType::RuntimeVersion)
+            .map(|c| format!("{} {}", c.label, c.value))
+            .collect();
+        assert_eq!(runtime_chips, vec!["Node 20.11.0", "Go 1.22", "terraform 1.7.5"]);
+        assert!(chips.git_info.is_none());
+    }
+
+    #[test]
+    fn test_real_git_state() {
+        let root = project("git");
+        let git = |args: &[&str]| {
+            Command::new("git")
+                .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+                .args(args)
+                .current_dir(&root)
+                .output()
+                .unwrap()
+        };
+        git(&["init", "-q", "-b", "trunk"]);
+        fs::write(root.join("a.txt"), "a").unwrap();
+        git(&["add", "a.txt"]);
+        git(&["commit", "-q", "-m", "init"]);
+        git(&["branch", "feature"]);
+        let head = String::from_utf8(git(&["rev-parse", "--short=7", "HEAD"]).stdout).unwrap();
+        fs::write(root.join("a.txt"), "changed").unwrap();
+
+        let mut probe = ContextProbe::new();
+        let mut chips = ContextualChips::new();
+        probe.refresh(&*root);
+        probe.wait(&mut chips, Duration::from_secs(5));
+
+        let info = chips.git_info.clone().unwrap();
+        assert_eq!(info.branch, "trunk");
+        assert_eq!(info.commit_hash, head.trim());
+        assert_eq!(info.status, GitStatus::Modified);
+        assert_eq!(chips.git_branches, vec!["feature", "trunk"]);
+        assert_eq!(chips.chips.iter().filter(|c| c.chip_type == ChipType::GitStatus).count(), 1);
+
+        // Moving out of the repository drops the chip
+        probe.refresh(std::env::temp_dir().join("warp_probe_no_such_dir"));
+        probe.wait(&mut chips, Duration::from_secs(5));
+        assert!(chips.git_info.is_none() && chips.git_branches.is_empty());
+        assert!(!chips.chips.iter().any(|c| c.chip_type == ChipType::GitStatus));
+    }
+
+    #[test]
+    fn test_porcelain_headers() {
+        let porcelain = "# branch.oid 0123456789abcdef\n# branch.head main\n# branch.ab +1 -0\n? new.txt\n";
+        assert_eq!(porcelain_header(porcelain, "branch.head"), Some("main"));
+        assert_eq!(porcelain_header(porcelain, "branch.oid"), Some("0123456789abcdef"));
+        assert_eq!(git_status(&GitStatusSummary::parse(porcelain)), GitStatus::Untracked);
+    }
+}
@@ -8,6 +8,8 @@ pub enum ChipType {
     GitStatus,
     Conversation,
     Attachment,
+    /// A file, block, diff or workflow referenced with `@`
+    Mention,
     RuntimeVersion,
     Profile,
     Custom(String),
@@ -45,8 +47,12 @@ pub struct ContextualChips {
     pub chips: Vec<Chip>,
     pub max_chips: usize,
     pub git_info: Option<GitInfo>,
+    /// Local branches, listed in the background for the git chip's menu
+    pub git_branches: Vec<String>,
     pub working_directory: String,
     pub active_conversation: Option<String>,
+    /// Chip with keyboard focus; the input has it while this is None
+    pub focused: Option<usize>,
 }
 
 #[derive(Debug, Clone)]
@@ -70,18 +76,40 @@ impl ContextualChips {
             chips: Vec::new(),
             max_chips: 8,
             git_info: None,
-            working_directory: "/home/user".to_string(),
+            git_branches: Vec::new(),
+            working_directory: std::env::current_dir()
+                .map(|dir| dir.display().to_string())
+                .unwrap_or_else(|_| "/".to_string()),
             active_conversation: None,
+            focused: None,
         }
     }
 
+    /// Adds a chip, evicting the oldest one when full. Attachment chips are
+    /// never evicted: they stand for files the agent request will carry.
     pub fn add_chip(&mut self, chip: Chip) {
         if self.chips.len() >= self.max_chips {
-            self.chips.remove(0); // Remove oldest
+            if let Some(oldest) = self.chips.iter().position(|c| c.chip_type != ChipType::Attachment) {
+                self.remove_chip(oldest);
+            }
         }
         self.chips.push(chip);
     }
 
+    /// Removes the chip at `index`, keeping focus on the same chip
+    pub fn remove_chip(&mut self, index: usize) -> Option<Chip> {
+        if index >= self.chips.len() {
+            return None;
+        }
+        let chip = self.chips.remove(index);
+        self.focused = match self.focused {
+            Some(f) if f > index => Some(f - 1),
+            Some(f) if f == index => self.chips.len().checked_sub(1).map(|last| index.min(last)),
+            focused => focused,
+        };
+        Some(chip)
+    }
+
     pub fn add_directory_chip(&mut self, path: String) {
         let chip = Chip::new(
             ChipType::Directory,
@@ -94,24 +122,40 @@ impl ContextualChips {
     }
 
     pub fn add_git_chip(&mut self, branch: String, status: GitStatus) {
-        let status_icon = match status {
+        self.set_git_chip(GitInfo {
+            branch,
+            status,
+            commit_hash: String::new(),
+        });
+    }
+
+    /// Show the repository state, replacing any previous git chip
+    pub fn set_git_chip(&mut self, info: GitInfo) {
+        let chip = Self::git_chip(&info);
+        self.git_info = Some(info);
+        self.replace_chips_of_type(ChipType::GitStatus, vec![chip]);
+    }
+
+    /// Removes the git chip, as when leaving a repository
+    pub fn clear_git_chip(&mut self) {
+        self.git_info = None;
+        self.git_branches.clear();
+        self.remove_chip_by_type(ChipType::GitStatus);
+    }
+
+    fn git_chip(info: &GitInfo) -> Chip {
+        let status_icon = match info.status {
             GitStatus::Clean => "✓",
             GitStatus::Modified => "◆",
             GitStatus::Untracked => "◇",
             GitStatus::Mixed => "◈",
         };
-        let chip = Chip::new(
+        Chip::new(
             ChipType::GitStatus,
             "Git".to_string(),
-            format!("{} ({})", branch, status_icon),
+            format!("{} ({})", info.branch, status_icon),
             "⎇".to_string(),
-        );
-        self.git_info = Some(GitInfo {
-            branch,
-            status,
-            commit_hash: "abc1234".to_string(),
-        });
-        self.add_chip(chip);
+        )
     }
 
     pub fn add_conversation_chip(&mut self, conversation_id: String) {
@@ -125,6 +169,25 @@ impl ContextualChips {
         self.add_chip(chip);
     }
 
+    /// Show the active agent conversation, replacing any previous one
+    pub fn set_conversation_chip(&mut self, conversation_id: String, title: String) {
+        self.remove_chip_by_type(ChipType::Conversation);
+        let label = if title.is_empty() { "New conversation".to_string() } else { title };
+        let chip = Chip::new(
+            ChipType::Conversation,
+            "Conversation".to_string(),
+            label,
+            "💬".to_string(),
+        );
+        self.active_conversation = Some(conversation_id);
+        self.add_chip(chip);
+    }
+
+    pub fn clear_conversation_chip(&mut self) {
+        self.remove_chip_by_type(ChipType::Conversation);
+        self.active_conversation = None;
+    }
+
     pub fn add_attachment_chip(&mut self, filename: String) {
         let chip = Chip::new(
             ChipType::Attachment,
@@ -135,32 +198,130 @@ impl ContextualChips {
         self.add_chip(chip);
     }
 
-    pub fn add_runtime_chip(&mut self, runtime: String, version: String) {
-        let chip = Chip::new(
-            ChipType::RuntimeVersion,
-            runtime,
-            version,
-            "⚙".to_string(),
-        );
+    /// Show an `@` reference; `kind` is what it refers to, like "File"
+    pub fn add_mention_chip(&mut self, kind: &str, value: String) {
+        let chip = Chip::new(ChipType::Mention, kind.to_string(), value, "@".to_string());
         self.add_chip(chip);
     }
 
+    pub fn remove_mention_chip(&mut self, kind: &str, value: &str) {
+        let index = self
+            .chips
+            .iter()
+            .position(|c| c.chip_type == ChipType::Mention && c.label == kind && c.value == value);
+        if let Some(index) = index {
+            self.remove_chip(index);
+        }
+    }
+
+    pub fn remove_attachment_chip(&mut self, filename: &str) {
+        let index = self
+            .chips
+            .iter()
+            .position(|c| c.chip_type == ChipType::Attachment && c.value == filename);
+        if let Some(index) = index {
+            self.remove_chip(index);
+        }
+    }
+
+    pub fn add_runtime_chip(&mut self, runtime: String, version: String) {
+        self.add_chip(Self::runtime_chip(runtime, version));
+    }
+
+    pub fn runtime_chip(runtime: String, version: String) -> Chip {
+        Chip::new(ChipType::RuntimeVersion, runtime, version, "⚙".to_string())
+    }
+
+    /// All chips on one line, the focused one in brackets
     pub fn get_display_text(&self) -> String {
         self.chips
             .iter()
-            .map(|c| c.display())
+            .enumerate()
+            .map(|(i, c)| match self.focused {
+                Some(f) if f == i => format!("[{}]", c.display()),
+                _ => c.display(),
+            })
             .collect::<Vec<_>>()
             .join("  ")
     }
 
+    /// Removes every chip of `chip_type`; focus stays on the same chip, or
+    /// goes back to the input if that chip was removed
     pub fn remove_chip_by_type(&mut self, chip_type: ChipType) {
+        if let Some(f) = self.focused {
+            let removed_before = self.chips[..f.min(self.chips.len())]
+                .iter()
+                .filter(|c| c.chip_type == chip_type)
+                .count();
+            self.focused = match self.chips.get(f) {
+                Some(chip) if chip.chip_type != chip_type => Some(f - removed_before),
+                _ => None,
+            };
+        }
         self.chips.retain(|c| c.chip_type != chip_type);
     }
 
+    /// Replaces the chips of `chip_type` with `chips`, leaving everything
+    /// alone when they are the same so focus and order don't churn. Focus on
+    /// a replaced chip follows it by label.
+    pub fn replace_chips_of_type(&mut self, chip_type: ChipType, chips: Vec<Chip>) {
+        let current: Vec<(&str, &str)> = self
+            .chips
+            .iter()
+            .filter(|c| c.chip_type == chip_type)
+            .map(|c| (c.label.as_str(), c.value.as_str()))
+            .collect();
+        let wanted: Vec<(&str, &str)> = chips.iter().map(|c| (c.label.as_str(), c.value.as_str())).collect();
+        if current == wanted {
+            return;
+        }
+        let focused_label = self
+            .focused_chip()
+            .filter(|c| c.chip_type == chip_type)
+            .map(|c| c.label.clone());
+        self.remove_chip_by_type(chip_type.clone());
+        for chip in chips {
+            self.add_chip(chip);
+        }
+        if let Some(label) = focused_label {
+            self.focused = self.chips.iter().position(|c| c.chip_type == chip_type && c.label == label);
+        }
+    }
+
+    /// Moves focus from the input to the chip nearest it, the last one
+    pub fn focus_last(&mut self) -> bool {
+        self.focused = self.chips.len().checked_sub(1);
+        self.focused.is_some()
+    }
+
+    pub fn focus_next(&mut self) {
+        if let Some(f) = self.focused {
+            if f + 1 < self.chips.len() {
+                self.focused = Some(f + 1);
+            }
+        }
+    }
+
+    pub fn focus_prev(&mut self) {
+        if let Some(f) = self.focused {
+            self.focused = Some(f.saturating_sub(1));
+        }
+    }
+
+    /// Gives focus back to the input
+    pub fn blur(&mut self) {
+        self.focused = None;
+    }
+
+    pub fn focused_chip(&self) -> Option<&Chip> {
+        self.chips.get(self.focused?)
+    }
+
     pub fn clear_all(&mut self) {
         self.chips.clear();
         self.git_info = None;
         self.active_conversation = None;
+        self.focused = None;
     }
 }
 
@@ -188,6 +349,43 @@ mod tests {
         assert_eq!(chips.working_directory, "/home/user");
     }
 
+    #[test]
+    fn test_conversation_chip_replaced() {
+        let mut chips = ContextualChips::new();
+        chips.set_conversation_chip("a".to_string(), "first".to_string());
+        chips.set_conversation_chip("b".to_string(), String::new());
+        assert_eq!(chips.chips.len(), 1);
+        assert_eq!(chips.chips[0].value, "New conversation");
+        assert_eq!(chips.active_conversation.as_deref(), Some("b"));
+        chips.clear_conversation_chip();
+        assert!(chips.chips.is_empty());
+    }
+
+    #[test]
+    fn test_focus_survives_removals() {
+        let mut chips = ContextualChips::new();
+        chips.add_directory_chip("/src".to_string());
+        chips.add_git_chip("main".to_string(), GitStatus::Clean);
+        chips.add_attachment_chip("a.txt".to_string());
+        chips.add_runtime_chip("Rust".to_string(), "1.80".to_string());
+        chips.focused = Some(2);
+
+        chips.remove_chip_by_type(ChipType::GitStatus);
+        assert_eq!(chips.focused_chip().unwrap().value, "a.txt");
+
+        // Re-adding identical runtime chips leaves focus and order alone
+        chips.focused = Some(2);
+        let rust = ContextualChips::runtime_chip("Rust".to_string(), "1.80".to_string());
+        chips.replace_chips_of_type(ChipType::RuntimeVersion, vec![rust]);
+        assert_eq!(chips.focused, Some(2));
+        let rust = ContextualChips::runtime_chip("Rust".to_string(), "1.81".to_string());
+        chips.replace_chips_of_type(ChipType::RuntimeVersion, vec![rust]);
+        assert_eq!(chips.focused_chip().unwrap().value, "1.81");
+
+        chips.remove_chip_by_type(ChipType::RuntimeVersion);
+        assert!(chips.focused.is_none());
+    }
+
     #[test]
     fn test_max_chips_limit() {
         let mut chips = ContextualChips::new();
@@ -196,5 +394,13 @@ mod tests {
             chips.add_directory_chip(format!("/path/{}", i));
         }
         assert_eq!(chips.chips.len(), 3);
+
+        // Attachments survive eviction, so they are never silently dropped
+        chips.add_attachment_chip("notes.md".to_string());
+        for i in 0..4 {
+            chips.add_directory_chip(format!("/more/{}", i));
+        }
+        assert_eq!(chips.chips.len(), 3);
+        assert_eq!(chips.chips[0].value, "notes.md");
     }
 }
@@ -0,0 +1,454 @@
+use crate::blocks::Block;
+use crate::classic_input::prompt_segments::{command_output, find_git_dir};
+use crate::fuzzy::fuzzy_match;
+use crate::workflow::ExtendedWorkflow;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// Directories never offered in the file picker
+const SKIPPED_DIRS: &[&str] = &[".git", "target", "node_modules"];
+/// How deep below the cwd the file picker looks
+const MAX_FILE_DEPTH: usize = 4;
+/// Files scanned before the picker stops looking
+const MAX_FILES: usize = 2000;
+/// Largest part of a file or diff sent to the agent
+const MAX_CONTENT_BYTES: usize = 64 * 1024;
+/// Output lines of a mentioned block sent to the agent
+const MAX_BLOCK_LINES: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffScope {
+    /// Changes not yet staged
+    Unstaged,
+    Staged,
+}
+
+/// A reference picked with `@`, kept structured rather than as text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mention {
+    /// A file, relative to the directory it was picked in
+    File(PathBuf),
+    Block { id: String, command: String },
+    GitDiff(DiffScope),
+    Workflow(String),
+}
+
+impl Mention {
+    /// The text standing for the mention in the input
+    pub fn token(&self) -> String {
+        match self {
+            Mention::File(path) => {
+                let path = path.display().to_string();
+                if path.contains(char::is_whitespace) {
+                    format!("@\"{}\"", path)
+                } else {
+                    format!("@{}", path)
+                }
+            }
+            Mention::Block { id, .. } => format!("@block:{}", short_id(id)),
+            Mention::GitDiff(DiffScope::Unstaged) => "@diff".to_string(),
+            Mention::GitDiff(DiffScope::Staged) => "@diff:staged".to_string(),
+            Mention::Workflow(name) => format!("@workflow:{}", name),
+        }
+    }
+
+    /// Kind shown on the mention's chip
+    pub fn kind_label(&self) -> &'static str {
+        match self {
+            Mention::File(_) => "File",
+            Mention::Block { .. } => "Block",
+            Mention::GitDiff(_) => "Diff",
+            Mention::Workflow(_) => "Workflow",
+        }
+    }
+
+    /// Value shown on the mention's chip
+    pub fn label(&self) -> String {
+        match self {
+            Mention::File(path) => path.display().to_string(),
+            Mention::Block { command, .. } => command.clone(),
+            Mention::GitDiff(DiffScope::Unstaged) => "unstaged changes".to_string(),
+            Mention::GitDiff(DiffScope::Staged) => "staged changes".to_string(),
+            Mention::Workflow(name) => name.clone(),
+        }
+    }
+
+    /// Reads what the mention refers to, for the agent's context
+    pub fn resolve(&self, sources: &MentionSources) -> MentionPayload {
+        let content = match self {
+            Mention::File(path) => read_file(&sources.cwd.join(path)),
+            Mention::Block { id, command } => match sources.blocks.iter().find(|b| &b.id == id) {
+                Some(block) => render_block(block),
+                None => format!("$ {}\n(block no longer available)", command),
+            },
+            Mention::GitDiff(scope) => git_diff(sources.cwd, *scope),
+            Mention::Workflow(name) => match sources.workflows.get(name) {
+                Some(workflow) => serde_yaml::to_string(workflow).unwrap_or_default(),
+                None => "(workflow not found)".to_string(),
+            },
+        };
+        MentionPayload {
+            title: format!("{}: {}", self.kind_label(), self.label()),
+            content,
+        }
+    }
+}
+
+/// A mention expanded into text for an agent request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MentionPayload {
+    pub title: String,
+    pub content: String,
+}
+
+/// What mentions can refer to
+#[derive(Debug, Clone, Copy)]
+pub struct MentionSources<'a> {
+    pub cwd: &'a Path,
+    /// Oldest first, as kept in block history
+    pub blocks: &'a [&'a Block],
+    pub workflows: &'a HashMap<String, ExtendedWorkflow>,
+}
+
+/// An entry in the `@` picker
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MentionCandidate {
+    pub mention: Mention,
+    pub display: String,
+    pub description: String,
+    /// Char indices in `display` that matched, for highlighting
+    pub positions: Vec<usize>,
+    score: i64,
+}
+
+impl<'a> MentionSources<'a> {
+    /// Picker entries matching `query`, best first: recent blocks, diffs,
+    /// workflows, then files
+    pub fn candidates(&self, query: &str, limit: usize) -> Vec<MentionCandidate> {
+        self.candidates_in(query, &list_files(self.cwd), limit)
+    }
+
+    /// Like `candidates`, offering `files` (as listed by a `FileIndex`)
+    /// instead of walking the directory again
+    pub fn candidates_in(&self, query: &str, files: &[PathBuf], limit: usize) -> Vec<MentionCandidate> {
+        let mut candidates = Vec::new();
+        let mut push = |mention: Mention, display: String, description: String, bonus: i64, also: &str| {
+            let m = fuzzy_match(query, &display).or_else(|| {
+                // Matching the hidden text still counts, without highlights
+                fuzzy_match(query, also).map(|m| crate::fuzzy::FuzzyMatch { positions: Vec::new(), ..m })
+            });
+            if let Some(m) = m {
+                candidates.push(MentionCandidate { mention, display, description, positions: m.positions, score: m.score + bonus });
+            }
+        };
+
+        for block in self.blocks.iter().rev() {
+            let status = match block.output.exit_code {
+                Some(code) => format!("exit {}", code),
+                None => "running".to_string(),
+            };
+            push(
+                Mention::Block { id: block.id.clone(), command: block.command.clone() },
+                block.command.clone(),
+                format!("Block {} · {}", short_id(&block.id), status),
+                4,
+                &block.id,
+            );
+        }
+
+        if find_git_dir(self.cwd).is_some() {
+            push(Mention::GitDiff(DiffScope::Unstaged), "diff".to_string(), "Unstaged changes".to_string(), 2, "");
+            push(Mention::GitDiff(DiffScope::Staged), "diff:staged".to_string(), "Staged changes".to_string(), 2, "");
+        }
+
+        let mut workflows: Vec<&ExtendedWorkflow> = self.workflows.values().collect();
+        workflows.sort_by(|a, b| a.name.cmp(&b.name));
+        for workflow in workflows {
+            push(
+                Mention::Workflow(workflow.name.clone()),
+                workflow.name.clone(),
+                workflow.description.clone().unwrap_or_else(|| "Workflow".to_string()),
+                2,
+                "",
+            );
+        }
+
+        for path in files {
+            let display = path.display().to_string();
+            push(Mention::File(path.clone()), display, "File".to_string(), 0, "");
+        }
+
+        // Stable sort keeps source order between equal scores
+        candidates.sort_by_key(|c| std::cmp::Reverse(c.score));
+        candidates.truncate(limit);
+        candidates
+    }
+}
+
+/// Files the `@` picker offers, listed once per directory rather than on
+/// every keystroke
+#[derive(Debug, Clone, Default)]
+pub struct FileIndex {
+    cwd: Option<PathBuf>,
+    files: Vec<PathBuf>,
+}
+
+impl FileIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Files below `cwd`, listed again only when `cwd` changed since the last call
+    pub fn files(&mut self, cwd: &Path) -> &[PathBuf] {
+        if self.cwd.as_deref() != Some(cwd) {
+            self.files = list_files(cwd);
+            self.cwd = Some(cwd.to_path_buf());
+        }
+        &self.files
+    }
+
+    /// Forgets the listing, so the next `files` call sees files created since
+    pub fn invalidate(&mut self) {
+        self.cwd = None;
+        self.files.clear();
+    }
+}
+
+/// The open `@` picker: the query typed after `@` and where it sits in the input
+#[derive(Debug, Clone)]
+pub struct MentionPicker {
+    /// Byte range of `@query` in the input
+    pub range: Range<usize>,
+    pub query: String,
+    pub items: Vec<MentionCandidate>,
+    pub selected: usize,
+}
+
+impl MentionPicker {
+    pub fn select_next(&mut self) {
+        if self.selected + 1 < self.items.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn select_prev(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    pub fn selected_item(&self) -> Option<&MentionCandidate> {
+        self.items.get(self.selected)
+    }
+}
+
+/// The `@word` ending at `cursor`, if one is being typed. `@` only starts a
+/// mention at the start of a word, so email addresses don't open the picker.
+pub fn mention_at(input: &str, cursor: usize) -> Option<(Range<usize>, &str)> {
+    let before = input.get(..cursor)?;
+    let start = before.rfind(char::is_whitespace).map(|i| i + 1).unwrap_or(0);
+    let query = before[start..].strip_prefix('@')?;
+    Some((start..cursor, query))
+}
+
+fn short_id(id: &str) -> &str {
+    id.get(..8).unwrap_or(id)
+}
+
+/// Files below `root`, relative to it, breadth first so nearby files come first
+fn list_files(root: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut dirs = vec![(PathBuf::new(), 0)];
+    let mut seen = 0;
+    while !dirs.is_empty() {
+        let mut next = Vec::new();
+        for (dir, depth) in dirs {
+            let Ok(entries) = std::fs::read_dir(root.join(&dir)) else {
+                continue;
+            };
+            let mut entries: Vec<_> = entries.flatten().collect();
+            entries.sort_by_key(|e| e.file_name());
+            for entry in entries {
+                seen += 1;
+                if seen > MAX_FILES {
+                    return files;
+                }
+                let name = entry.file_name();
+                let Some(name) = name.to_str() else {
+                    continue;
+                };
+                if name.starts_with('.') || SKIPPED_DIRS.contains(&name) {
+                    continue;
+                }
+                let path = dir.join(name);
+                match entry.file_type() {
+                    Ok(t) if t.is_dir() && depth < MAX_FILE_DEPTH => next.push((path, depth + 1)),
+                    Ok(t) if t.is_file() => files.push(path),
+                    _ => {}
+                }
+            }
+        }
+        dirs = next;
+    }
+    files
+}
+
+/// A file's text, cut at `MAX_CONTENT_BYTES`; binary files are only described.
+/// Only what is kept is read, however big the file.
+fn read_file(path: &Path) -> String {
+    let read = File::open(path).and_then(|file| {
+        let size = file.metadata()?.len();
+        let mut bytes = Vec::new();
+        // One byte past the limit tells `truncate` there was more
+        file.take(MAX_CONTENT_BYTES as u64 + 1).read_to_end(&mut bytes)?;
+        Ok((size, bytes))
+    });
+    match read {
+        Ok((size, bytes)) if bytes.contains(&0) => format!("(binary file, {} bytes)", size),
+        Ok((_, bytes)) => truncate(String::from_utf8_lossy(&bytes).into_owned()),
+        Err(e) => format!("(could not read {}: {})", path.display(), e),
+    }
+}
+
+fn render_block(block: &Block) -> String {
+    let mut out = format!("$ {}\n", block.command);
+    if let Some(code) = block.output.exit_code {
+        out.push_str(&format!("(exit {})\n", code));
+    }
+    let output = block.get_full_output();
+    let lines: Vec<&str> = output.lines().collect();
+    for line in &lines[lines.len().saturating_sub(MAX_BLOCK_LINES)..] {
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+
+/// `git diff` for `scope`, given up on after `command_output`'s deadline
+fn git_diff(cwd: &Path, scope: DiffScope) -> String {
+    let args: &[&str] = match scope {
+        DiffScope::Unstaged => &["diff", "--no-color"],
+        DiffScope::Staged => &["diff", "--no-color", "--staged"],
+    };
+    match command_output("git", args, cwd) {
+        Some(diff) if diff.is_empty() => "(no changes)".to_string(),
+        Some(diff) => truncate(diff),
+        None => "(git diff failed or timed out)".to_string(),
+    }
+}
+
+fn truncate(mut text: String) -> String {
+    if text.len() > MAX_CONTENT_BYTES {
+        let mut end = MAX_CONTENT_BYTES;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+        text.push_str("\n(truncated)");
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn tree() -> TempDir {
+        let root = TempDir::new("mentions");
+        for dir in ["src", "target/debug", ".hidden"] {
+            root.mkdir(dir);
+        }
+        for file in ["src/main.rs", "README.md", "target/debug/app", ".hidden/secret"] {
+            root.write(file, "fn main() {}\n");
+        }
+        root
+    }
+
+    #[test]
+    fn test_mention_at_cursor() {
+        assert_eq!(mention_at("explain @src/ma", 15), Some((8..15, "src/ma")));
+        assert_eq!(mention_at("@", 1), Some((0..1, "")));
+        assert_eq!(mention_at("mail me@example.com", 19), None);
+        assert_eq!(mention_at("explain @src now", 16), None);
+    }
+
+    #[test]
+    fn test_candidates_from_every_source() {
+        let root = tree();
+        let mut block = Block::new("cargo test".to_string(), root.display().to_string());
+        block.set_output("ok".to_string(), String::new(), 0);
+        let blocks = [&block];
+        let mut workflows = HashMap::new();
+        workflows.insert("deploy".to_string(), ExtendedWorkflow::new("deploy"));
+        let sources = MentionSources { cwd: &root, blocks: &blocks, workflows: &workflows };
+
+        let all: Vec<String> = sources.candidates("", 50).into_iter().map(|c| c.mention.token()).collect();
+        assert_eq!(all, vec![format!("@block:{}", &block.id[..8]), "@workflow:deploy".to_string(), "@README.md".to_string(), "@src/main.rs".to_string()]);
+
+        let files = sources.candidates("main", 5);
+        assert_eq!(files[0].mention, Mention::File(PathBuf::from("src/main.rs")));
+        // Blocks match by id too
+        let by_id = sources.candidates(&block.id[..6], 5);
+        assert_eq!(by_id[0].mention.kind_label(), "Block");
+
+        // The index keeps its listing until the directory changes or it is invalidated
+        let mut index = FileIndex::new();
+        assert_eq!(index.files(&root).len(), 2);
+        std::fs::write(root.join("new.txt"), "").unwrap();
+        assert_eq!(index.files(&root).len(), 2);
+        index.invalidate();
+        assert_eq!(index.files(&root).len(), 3);
+    }
+
+    #[test]
+    fn test_resolve_payloads() {
+        let root = tree();
+        let mut block = Block::new("make".to_string(), "/".to_string());
+        block.set_output("building\n".to_string(), "error: oops\n".to_string(), 2);
+        let blocks = [&block];
+        let workflows = HashMap::new();
+        let sources = MentionSources { cwd: &root, blocks: &blocks, workflows: &workflows };
+
+        let file = Mention::File(PathBuf::from("src/main.rs")).resolve(&sources);
+        assert_eq!(file.title, "File: src/main.rs");
+        assert_eq!(file.content, "fn main() {}\n");
+
+        let block = Mention::Block { id: block.id.clone(), command: "make".to_string() }.resolve(&sources);
+        assert!(block.content.starts_with("$ make\n(exit 2)\n"));
+        assert!(block.content.contains("error: oops"));
+
+        std::fs::write(root.join("big.log"), "x".repeat(MAX_CONTENT_BYTES * 4)).unwrap();
+        let big = Mention::File(PathBuf::from("big.log")).resolve(&sources);
+        assert!(big.content.ends_with("\n(truncated)"));
+        assert_eq!(big.content.len(), MAX_CONTENT_BYTES + "\n(truncated)".len());
+    }
+
+    #[test]
+    fn test_git_diff_mentions() {
+        let root = tree();
+        let git = |args: &[&str]| {
+            std::process::Command::new("git")
+                .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+                .args(args)
+                .current_dir(&*root)
+                .output()
+                .unwrap()
+        };
+        git(&["init", "-q"]);
+        git(&["add", "README.md"]);
+        git(&["commit", "-q", "-m", "init"]);
+        std::fs::write(root.join("README.md"), "changed\n").unwrap();
+
+        let blocks = [];
+        let workflows = HashMap::new();
+        let sources = MentionSources { cwd: &root.join("src"), blocks: &blocks, workflows: &workflows };
+        let tokens: Vec<String> = sources.candidates("diff", 5).into_iter().map(|c| c.mention.token()).collect();
+        assert!(tokens.contains(&"@diff".to_string()) && tokens.contains(&"@diff:staged".to_string()));
+
+        let unstaged = Mention::GitDiff(DiffScope::Unstaged).resolve(&sources);
+        assert!(unstaged.content.contains("+changed"));
+        assert_eq!(Mention::GitDiff(DiffScope::Staged).resolve(&sources).content, "(no changes)");
+    }
+}
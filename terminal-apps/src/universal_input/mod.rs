@@ -1,14 +1,43 @@
 pub mod advanced_input;
+pub mod chip_actions;
+pub mod command_resolver;
+pub mod context_probe;
 pub mod contextual_chips;
 pub mod input_toolbelt;
+pub mod mentions;
 pub mod mode_detector;
+pub mod path_completion;
+pub mod shell_lexer;
+pub mod slash_commands;
 pub mod smart_features;
+pub mod spec_completion;
 
 pub use advanced_input::{AdvancedInput, InputMode, SyntaxHighlighting};
-pub use contextual_chips::{Chip, ChipType, ContextualChips};
+pub use crate::attachments::{
+    Attachment, AttachmentContent, AttachmentError, AttachmentLimits, AttachmentResult, AttachmentSet, FileStamp,
+};
+pub use chip_actions::{ChipAction, ChipActions, ChipMenu, ChipMenuItem, VersionManager};
+pub use command_resolver::{CommandResolver, PathStatus, Resolution};
+pub use context_probe::{ContextProbe, GitBranchesProvider, GitStateProvider, GoVersionProvider, ToolVersionsProvider};
+pub use contextual_chips::{Chip, ChipType, ContextualChips, GitInfo, GitStatus};
 pub use input_toolbelt::{ToolbeltItem, ToolbeltItemType, InputToolbelt};
+pub use mentions::{DiffScope, FileIndex, Mention, MentionCandidate, MentionPayload, MentionPicker, MentionSources};
 pub use mode_detector::{ModeDetector, DetectedMode};
+pub use path_completion::{CurrentWord, PathCompleter, PathCompletion};
+pub use shell_lexer::{shell_quote, IncrementalLexer, Span, TokenKind};
+pub use slash_commands::{
+    ArgValues, SlashArg, SlashCommand, SlashCommandConfig, SlashCommandError, SlashCommandRegistry,
+    SlashCommandResult, SlashCompletion, SlashContext, SlashMenu, SlashOutcome,
+};
 pub use smart_features::{SmartFeatures, Suggestion, AutoCompletion};
+pub use spec_completion::{GeneratorCache, SpecCompleter, SpecCompletion, SpecItemKind};
+
+use crate::app::TerminalApp;
+use crate::blocks::Block;
+use crate::modern_editing::AliasExpander;
+
+/// Entries shown in the `@` picker
+const MENTION_PICKER_SIZE: usize = 20;
 
 /// Complete Universal Input system combining all features
 #[derive(Debug, Clone)]
@@ -18,4 +47,487 @@ pub struct UniversalInput {
     pub toolbelt: InputToolbelt,
     pub mode_detector: ModeDetector,
     pub smart_features: SmartFeatures,
+    pub slash_commands: SlashCommandRegistry,
+    /// Open while the input starts with `/`
+    pub slash_menu: Option<SlashMenu>,
+    /// References picked with `@`, in the order they were added
+    pub mentions: Vec<Mention>,
+    /// Open while an `@word` is being typed
+    pub mention_picker: Option<MentionPicker>,
+    /// Files offered by the picker, listed again when it opens or the directory changes
+    mention_files: FileIndex,
+    /// Keeps the git and runtime chips in step with the working directory
+    pub context_probe: ContextProbe,
+    pub chip_actions: ChipActions,
+    /// Open while a focused chip shows its actions
+    pub chip_menu: Option<ChipMenu>,
+    /// Files and images for the next agent request, each shown as a chip
+    pub attachments: AttachmentSet,
+}
+
+impl UniversalInput {
+    pub fn new() -> Self {
+        let mut universal = Self {
+            input: AdvancedInput::new(),
+            chips: ContextualChips::new(),
+            toolbelt: InputToolbelt::new(),
+            mode_detector: ModeDetector::new(),
+            smart_features: SmartFeatures::new(),
+            slash_commands: SlashCommandRegistry::new(),
+            slash_menu: None,
+            mentions: Vec::new(),
+            mention_picker: None,
+            mention_files: FileIndex::new(),
+            context_probe: ContextProbe::new(),
+            chip_actions: ChipActions::new(),
+            chip_menu: None,
+            attachments: AttachmentSet::new(),
+        };
+        let cwd = universal.chips.working_directory.clone();
+        universal.smart_features.auto_completion.set_working_directory(cwd);
+        universal
+    }
+
+    /// An input set up from the user's environment, with their completion
+    /// specs loaded. Starts probing the git and runtime state of the current
+    /// directory for the chips.
+    pub fn from_env() -> Self {
+        let mut universal = Self {
+            smart_features: SmartFeatures::from_env(),
+            ..Self::new()
+        };
+        let cwd = universal.chips.working_directory.clone();
+        universal.smart_features.auto_completion.set_working_directory(cwd);
+        universal.refresh_context();
+        universal
+    }
+
+    /// Moves to `path`, keeping the directory chip and path completion in
+    /// step and re-probing the project and git state there
+    pub fn set_working_directory(&mut self, path: String) {
+        self.smart_features.auto_completion.set_working_directory(&path);
+        self.context_probe.refresh(&path);
+        self.chips.remove_chip_by_type(ChipType::Directory);
+        self.chips.add_directory_chip(path);
+    }
+
+    /// Probes the current directory again, e.g. after a branch switch or a
+    /// runtime change from a chip
+    pub fn refresh_context(&mut self) {
+        self.context_probe.refresh(&self.chips.working_directory);
+    }
+
+    /// Picks up finished context probes. Returns true when the chips changed
+    /// and should be redrawn.
+    pub fn poll_context(&mut self) -> bool {
+        self.context_probe.poll(&mut self.chips)
+    }
+
+    /// Picks up spec generator output listed in the background. Returns true
+    /// when suggestions should be fetched again.
+    pub fn poll_completions(&mut self) -> bool {
+        self.smart_features.poll_generators()
+    }
+
+    /// Learns from a finished block, so commands that ran recently and
+    /// succeeded rank higher in suggestions
+    pub fn record_block(&mut self, block: &Block) {
+        self.smart_features.auto_completion.record_block(block);
+    }
+
+    /// Starts with the aliases already loaded for the classic input, so rc
+    /// files are only read once
+    pub fn with_aliases(mut self, aliases: &AliasExpander) -> Self {
+        self.set_aliases(aliases);
+        self
+    }
+
+    /// Makes aliases and abbreviations known to completion, and to the
+    /// highlighter so they aren't marked as unknown commands
+    pub fn set_aliases(&mut self, aliases: &AliasExpander) {
+        let resolver = &mut self.input.syntax_highlighting.resolver;
+        for (name, expansion) in aliases.aliases().chain(aliases.abbreviations()) {
+            resolver.add_alias(name, expansion);
+        }
+        self.smart_features.auto_completion.aliases = aliases.clone();
+    }
+
+    /// Opens, refreshes or closes the `/` menu after the input changed.
+    /// `app` supplies theme, keyset and workflow names for arguments.
+    pub fn update_slash_menu(&mut self, app: &TerminalApp) {
+        if !self.input.is_slash_command() {
+            self.slash_menu = None;
+            return;
+        }
+        let items = self.slash_commands.complete(&self.input.content, app);
+        match &mut self.slash_menu {
+            // Keep the selection on the same entry while it's still offered
+            Some(menu) => {
+                let selected = menu.selected_item().map(|item| item.text.clone());
+                menu.selected = selected
+                    .and_then(|text| items.iter().position(|item| item.text == text))
+                    .unwrap_or(0);
+                menu.items = items;
+            }
+            None => self.slash_menu = Some(SlashMenu::new(items)),
+        }
+    }
+
+    /// Starts a slash command from the toolbelt's Commands item or its hotkey
+    pub fn open_slash_menu(&mut self, app: &TerminalApp) {
+        self.input.clear();
+        self.input.insert_char('/');
+        self.update_slash_menu(app);
+    }
+
+    /// Puts the selected menu entry into the input. Returns false when the menu is empty.
+    pub fn accept_slash_completion(&mut self, app: &TerminalApp) -> bool {
+        let Some(text) = self
+            .slash_menu
+            .as_ref()
+            .and_then(|menu| menu.selected_item())
+            .map(|item| item.text.clone())
+        else {
+            return false;
+        };
+        self.input.content = text;
+        self.input.move_cursor_end();
+        self.update_slash_menu(app);
+        true
+    }
+
+    /// Runs the slash command in the input and clears it, whatever the result
+    pub fn run_slash_command(&mut self, ctx: &mut SlashContext) -> SlashCommandResult<SlashOutcome> {
+        let line = self.input.content.clone();
+        self.input.add_to_history();
+        self.input.clear();
+        self.slash_menu = None;
+        self.slash_commands.execute(&line, ctx)
+    }
+
+    /// Opens or refreshes the `@` picker while a mention is being typed, and
+    /// forgets mentions whose text was deleted from the input
+    pub fn update_mentions(&mut self, sources: &MentionSources) {
+        let content = &self.input.content;
+        let removed: Vec<Mention> = self
+            .mentions
+            .iter()
+            .filter(|m| !content.contains(&m.token()))
+            .cloned()
+            .collect();
+        for mention in removed {
+            self.remove_mention(&mention);
+        }
+
+        let cursor = self.input.cursor_position;
+        let opening = self.mention_picker.is_none();
+        self.mention_picker = match mentions::mention_at(&self.input.content, cursor) {
+            Some((range, query)) if !self.input.is_slash_command() => {
+                if opening {
+                    self.mention_files.invalidate();
+                }
+                let files = self.mention_files.files(sources.cwd);
+                Some(MentionPicker {
+                    range,
+                    query: query.to_string(),
+                    items: sources.candidates_in(query, files, MENTION_PICKER_SIZE),
+                    selected: 0,
+                })
+            }
+            _ => None,
+        };
+    }
+
+    /// Starts a mention at the cursor from the toolbelt's Context item or its hotkey
+    pub fn open_mention_picker(&mut self, sources: &MentionSources) {
+        let before = &self.input.content[..self.input.cursor_position];
+        if !before.is_empty() && !before.ends_with(char::is_whitespace) {
+            self.input.insert_char(' ');
+        }
+        self.input.insert_char('@');
+        self.update_mentions(sources);
+    }
+
+    /// Replaces the `@query` being typed with the selected mention
+    pub fn accept_mention(&mut self) -> Option<Mention> {
+        let picker = self.mention_picker.take()?;
+        let mention = picker.selected_item()?.mention.clone();
+        let token = format!("{} ", mention.token());
+        self.input.content.replace_range(picker.range.clone(), &token);
+        self.input.cursor_position = picker.range.start + token.len();
+        if !self.mentions.contains(&mention) {
+            self.chips.add_mention_chip(mention.kind_label(), mention.label());
+            self.mentions.push(mention.clone());
+        }
+        Some(mention)
+    }
+
+    /// Drops a mention along with its chip and its text in the input
+    pub fn remove_mention(&mut self, mention: &Mention) {
+        self.mentions.retain(|m| m != mention);
+        self.chips.remove_mention_chip(mention.kind_label(), &mention.label());
+        let token = mention.token();
+        if let Some(start) = self.input.content.find(&token) {
+            let mut end = start + token.len();
+            if self.input.content[end..].starts_with(' ') {
+                end += 1;
+            }
+            self.input.content.replace_range(start..end, "");
+            let cursor = &mut self.input.cursor_position;
+            if *cursor >= end {
+                *cursor -= end - start;
+            } else if *cursor > start {
+                *cursor = start;
+            }
+        }
+    }
+
+    /// Every mention expanded, for an agent request
+    pub fn mention_context(&self, sources: &MentionSources) -> Vec<MentionPayload> {
+        self.mentions.iter().map(|m| m.resolve(sources)).collect()
+    }
+
+    /// Moves keyboard focus from the input (Shift+Tab) onto the chips,
+    /// starting with the one nearest the input. False when there are none.
+    pub fn focus_chips(&mut self) -> bool {
+        self.chip_menu = None;
+        self.chips.focus_last()
+    }
+
+    pub fn focus_next_chip(&mut self) {
+        self.chip_menu = None;
+        self.chips.focus_next();
+    }
+
+    pub fn focus_prev_chip(&mut self) {
+        self.chip_menu = None;
+        self.chips.focus_prev();
+    }
+
+    /// Returns focus to the input (Esc), closing any chip menu
+    pub fn blur_chips(&mut self) {
+        self.chip_menu = None;
+        self.chips.blur();
+    }
+
+    /// Opens the focused chip's menu (Enter). False when it has no actions.
+    pub fn open_chip_menu(&mut self) -> bool {
+        let Some(index) = self.chips.focused else {
+            return false;
+        };
+        let Some(chip) = self.chips.chips.get(index) else {
+            return false;
+        };
+        let items = self.chip_actions.menu(chip, &self.chips);
+        if items.is_empty() {
+            self.chip_menu = None;
+            return false;
+        }
+        self.chip_menu = Some(ChipMenu::new(index, items));
+        true
+    }
+
+    /// A mouse click: focuses the chip and opens its menu
+    pub fn click_chip(&mut self, index: usize) -> bool {
+        if index >= self.chips.chips.len() {
+            return false;
+        }
+        self.chips.focused = Some(index);
+        self.open_chip_menu()
+    }
+
+    /// Runs the selected menu entry and gives focus back to the input. Chip
+    /// and directory changes happen here; the caller runs `ChipAction::command`
+    /// for the rest and opens conversations or previews.
+    pub fn activate_chip_action(&mut self) -> Option<ChipAction> {
+        let action = self.chip_menu.take()?.selected_item()?.action.clone();
+        self.chips.blur();
+        match &action {
+            ChipAction::ChangeDirectory(path) => self.set_working_directory(path.display().to_string()),
+            ChipAction::CloseConversation => self.chips.clear_conversation_chip(),
+            ChipAction::RemoveAttachment(name) => self.detach(name),
+            ChipAction::RemoveMention { kind, value } => {
+                let mention = self
+                    .mentions
+                    .iter()
+                    .find(|m| m.kind_label() == kind && m.label() == *value)
+                    .cloned();
+                match mention {
+                    Some(mention) => self.remove_mention(&mention),
+                    None => self.chips.remove_mention_chip(kind, value),
+                }
+            }
+            _ => {}
+        }
+        Some(action)
+    }
+
+    /// Attaches a file for the agent, relative paths being taken from the
+    /// working directory
+    pub fn attach(&mut self, path: impl AsRef<std::path::Path>) -> AttachmentResult<&Attachment> {
+        let path = std::path::Path::new(&self.chips.working_directory).join(path);
+        let name = self.attachments.attach(path)?.name.clone();
+        self.chips.add_attachment_chip(name.clone());
+        Ok(self.attachments.get(&name).unwrap())
+    }
+
+    pub fn detach(&mut self, name: &str) {
+        self.attachments.detach(name);
+        self.chips.remove_attachment_chip(name);
+    }
+
+    /// Preview lines for an attachment, for the Preview chip action
+    pub fn attachment_preview(&self, name: &str, width: usize, height: usize) -> Option<Vec<String>> {
+        Some(self.attachments.get(name)?.preview(width, height))
+    }
+
+    /// Attachments for an agent request, refusing any file that changed since
+    /// it was attached; detach and attach it again to send the new version
+    pub fn agent_attachments(&self) -> AttachmentResult<Vec<Attachment>> {
+        self.attachments.verified()
+    }
+
+    /// Path completions for the word under the cursor
+    pub fn path_completions(&self) -> Vec<PathCompletion> {
+        PathCompleter::new(&self.chips.working_directory).complete(&self.input.content, self.input.cursor_position)
+    }
+}
+
+impl Default for UniversalInput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use crate::blocks::BlockManager;
+
+    #[test]
+    fn test_shared_aliases() {
+        let mut aliases = AliasExpander::new();
+        aliases.parse_rc("alias ll='ls -l'; abbr -a gco git checkout\n");
+        let mut universal = UniversalInput::new().with_aliases(&aliases);
+
+        let resolver = &mut universal.input.syntax_highlighting.resolver;
+        assert_eq!(resolver.resolve("ll"), Resolution::Alias);
+        assert_eq!(resolver.resolve("gco"), Resolution::Alias);
+        assert_eq!(universal.smart_features.auto_completion.aliases.abbreviation("gco"), Some("git checkout"));
+    }
+
+    #[test]
+    fn test_new_does_not_probe() {
+        let universal = UniversalInput::new();
+        assert_eq!(universal.context_probe.cwd(), std::path::Path::new(""));
+    }
+
+    #[test]
+    fn test_slash_menu_follows_input() {
+        let mut universal = UniversalInput::new();
+        let mut app = TerminalApp::new("test");
+        app.register_workflow(crate::workflow::ExtendedWorkflow::new("deploy"));
+
+        for ch in "/wor".chars() {
+            universal.input.insert_char(ch);
+            universal.update_slash_menu(&app);
+        }
+        assert_eq!(universal.slash_menu.as_ref().unwrap().selected_item().unwrap().text, "/workflow run ");
+        assert!(universal.accept_slash_completion(&app));
+        assert_eq!(universal.slash_menu.as_ref().unwrap().items[0].text, "/workflow run deploy");
+        assert!(universal.accept_slash_completion(&app));
+
+        let mut blocks = BlockManager::new(10);
+        let mut ctx = SlashContext { app: &mut app, blocks: &mut blocks };
+        assert!(matches!(universal.run_slash_command(&mut ctx), Ok(SlashOutcome::RunWorkflow(_))));
+        assert!(universal.slash_menu.is_none() && universal.input.content.is_empty());
+
+        // An absolute path is a command, not a slash command
+        universal.input.content = "/usr/bin/env".to_string();
+        universal.update_slash_menu(&app);
+        assert!(universal.slash_menu.is_none());
+    }
+
+    #[test]
+    fn test_mentions_become_chips_and_context() {
+        let root = TempDir::new("universal_mentions");
+        root.write("notes.txt", "remember the milk");
+        let workflows = std::collections::HashMap::new();
+        let sources = MentionSources { cwd: &root, blocks: &[], workflows: &workflows };
+
+        let mut universal = UniversalInput::new();
+        for ch in "summarize @not".chars() {
+            universal.input.insert_char(ch);
+            universal.update_mentions(&sources);
+        }
+        assert_eq!(universal.mention_picker.as_ref().unwrap().query, "not");
+        assert_eq!(universal.accept_mention(), Some(Mention::File("notes.txt".into())));
+        assert_eq!(universal.input.content, "summarize @notes.txt ");
+        assert!(universal.chips.chips.iter().any(|c| c.chip_type == ChipType::Mention && c.value == "notes.txt"));
+
+        let context = universal.mention_context(&sources);
+        assert_eq!(context[0].content, "remember the milk");
+
+        // Deleting the text drops the mention and its chip
+        universal.input.content = "summarize ".to_string();
+        universal.input.move_cursor_end();
+        universal.update_mentions(&sources);
+        assert!(universal.mentions.is_empty());
+        assert!(!universal.chips.chips.iter().any(|c| c.chip_type == ChipType::Mention));
+    }
+
+    #[test]
+    fn test_chip_focus_and_actions() {
+        let root = TempDir::new("universal_chips");
+        root.mkdir("sub");
+
+        let mut universal = UniversalInput::new();
+        universal.chips.clear_all();
+        universal.set_working_directory(root.display().to_string());
+        universal.chips.add_attachment_chip("log.txt".to_string());
+
+        assert!(universal.focus_chips());
+        assert_eq!(universal.chips.focused_chip().unwrap().chip_type, ChipType::Attachment);
+        assert!(universal.chips.get_display_text().ends_with("[📎 Attachment: log.txt]"));
+        assert!(universal.open_chip_menu());
+        universal.chip_menu.as_mut().unwrap().select_next();
+        assert_eq!(universal.activate_chip_action(), Some(ChipAction::RemoveAttachment("log.txt".to_string())));
+        assert!(universal.chips.chips.iter().all(|c| c.chip_type != ChipType::Attachment));
+        assert!(universal.chips.focused.is_none());
+
+        // Picking a subdirectory from the directory chip moves there
+        assert!(universal.click_chip(0));
+        let menu = universal.chip_menu.as_mut().unwrap();
+        menu.select_next();
+        assert_eq!(menu.selected_item().unwrap().label, "sub/");
+        let action = universal.activate_chip_action().unwrap();
+        assert_eq!(action.command(), Some(format!("cd '{}'", root.join("sub").display())));
+        assert_eq!(universal.chips.working_directory, root.join("sub").display().to_string());
+        assert_eq!(universal.chips.chips.iter().filter(|c| c.chip_type == ChipType::Directory).count(), 1);
+    }
+
+    #[test]
+    fn test_attachments_follow_chips() {
+        let root = TempDir::new("universal_attach");
+        root.write("todo.txt", "ship it\n");
+
+        let mut universal = UniversalInput::new();
+        universal.chips.clear_all();
+        universal.set_working_directory(root.display().to_string());
+        universal.attach("todo.txt").unwrap();
+        assert!(universal.chips.chips.iter().any(|c| c.chip_type == ChipType::Attachment && c.value == "todo.txt"));
+        assert_eq!(universal.attachment_preview("todo.txt", 80, 5).unwrap()[1], "ship it");
+        assert_eq!(universal.agent_attachments().unwrap()[0].text(), Some("ship it\n"));
+
+        root.write("todo.txt", "ship it tomorrow\n");
+        assert!(matches!(universal.agent_attachments(), Err(AttachmentError::Changed(_))));
+
+        // Removing the chip drops the attachment too
+        assert!(universal.click_chip(universal.chips.chips.len() - 1));
+        universal.chip_menu.as_mut().unwrap().select_next();
+        universal.activate_chip_action();
+        assert!(universal.attachments.is_empty());
+        assert!(universal.agent_attachments().unwrap().is_empty());
+    }
 }
@@ -0,0 +1,315 @@
+use std::ops::Range;
+use std::path::PathBuf;
+
+/// Commands whose argument can only be a directory
+const DIRECTORY_COMMANDS: &[&str] = &["cd", "pushd", "rmdir"];
+
+/// Characters that need a backslash outside quotes
+const SPECIAL_CHARS: &str = " \t'\"\\$`&|;<>()*?![]{}#";
+
+/// The word under the cursor, as typed and as the shell will see it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CurrentWord {
+    /// Byte range in the input, from the start of the word to the cursor
+    pub range: Range<usize>,
+    /// With quotes and escapes removed
+    pub value: String,
+    /// The quote the word was opened with, if any
+    pub quote: Option<char>,
+    /// Index of the word within its command; 0 is the command itself
+    pub index: usize,
+    /// The command the word is an argument of
+    pub command: Option<String>,
+    /// Unquoted words of the command before this one
+    pub previous: Vec<String>,
+}
+
+impl CurrentWord {
+    /// Splits `input[..cursor]` like a shell to find the word being typed
+    pub fn at(input: &str, cursor: usize) -> Self {
+        let mut cursor = cursor.min(input.len());
+        while !input.is_char_boundary(cursor) {
+            cursor -= 1;
+        }
+
+        let mut words: Vec<String> = Vec::new();
+        let mut start = 0;
+        let mut value = String::new();
+        let mut quote = None;
+        let mut opening_quote = None;
+        let mut in_word = false;
+        let mut chars = input[..cursor].char_indices();
+
+        while let Some((i, c)) = chars.next() {
+            match (quote, c) {
+                (Some(q), c) if c == q => quote = None,
+                (Some('"'), '\\') => match chars.next() {
+                    Some((_, next)) if "\"\\$`".contains(next) => value.push(next),
+                    Some((_, next)) => {
+                        value.push('\\');
+                        value.push(next);
+                    }
+                    None => {}
+                },
+                (Some(_), c) => value.push(c),
+                (None, c) if c.is_whitespace() || "|&;<>()".contains(c) => {
+                    if in_word {
+                        words.push(std::mem::take(&mut value));
+                        in_word = false;
+                    }
+                    if !c.is_whitespace() {
+                        // A new command starts after an operator
+                        words.clear();
+                    }
+                    start = i + c.len_utf8();
+                    opening_quote = None;
+                }
+                (None, c) => {
+                    if !in_word {
+                        in_word = true;
+                        start = i;
+                        if c == '\'' || c == '"' {
+                            opening_quote = Some(c);
+                        }
+                    }
+                    match c {
+                        '\'' | '"' => quote = Some(c),
+                        '\\' => {
+                            if let Some((_, next)) = chars.next() {
+                                value.push(next);
+                            }
+                        }
+                        c => value.push(c),
+                    }
+                }
+            }
+        }
+        if !in_word {
+            start = cursor;
+        }
+
+        Self {
+            range: start..cursor,
+            value,
+            quote: quote.or(opening_quote),
+            index: words.len(),
+            command: words.first().cloned(),
+            previous: words,
+        }
+    }
+}
+
+/// One completion for the current word
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathCompletion {
+    /// Byte range of the input to replace
+    pub range: Range<usize>,
+    /// Escaped or quoted text to put there
+    pub replacement: String,
+    /// The entry name as shown in a menu, with `/` for directories
+    pub display: String,
+    pub is_dir: bool,
+}
+
+impl PathCompletion {
+    /// The input with this completion applied, and the cursor after it
+    pub fn apply(&self, input: &str) -> (String, usize) {
+        let mut output = input.to_string();
+        output.replace_range(self.range.clone(), &self.replacement);
+        (output, self.range.start + self.replacement.len())
+    }
+}
+
+/// Completes the word under the cursor from the filesystem
+#[derive(Debug, Clone)]
+pub struct PathCompleter {
+    pub cwd: PathBuf,
+    pub home: Option<PathBuf>,
+    pub max_results: usize,
+}
+
+impl PathCompleter {
+    pub fn new(cwd: impl Into<PathBuf>) -> Self {
+        Self {
+            cwd: cwd.into(),
+            home: std::env::var_os("HOME").map(PathBuf::from),
+            max_results: 100,
+        }
+    }
+
+    pub fn with_home(mut self, home: impl Into<PathBuf>) -> Self {
+        self.home = Some(home.into());
+        self
+    }
+
+    pub fn complete(&self, input: &str, cursor: usize) -> Vec<PathCompletion> {
+        let word = CurrentWord::at(input, cursor);
+        let dirs_only = word.index > 0
+            && word
+                .command
+                .as_deref()
+                .is_some_and(|c| DIRECTORY_COMMANDS.contains(&c));
+
+        let (dir_part, prefix) = match word.value.rfind('/') {
+            Some(i) => word.value.split_at(i + 1),
+            None => ("", word.value.as_str()),
+        };
+        // `~` alone completes to the home directory itself
+        if word.value == "~" {
+            return vec![PathCompletion {
+                range: word.range,
+                replacement: "~/".to_string(),
+                display: "~/".to_string(),
+                is_dir: true,
+            }];
+        }
+
+        let Ok(entries) = std::fs::read_dir(self.resolve(dir_part)) else {
+            return Vec::new();
+        };
+        let mut matches: Vec<(String, bool)> = entries
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name().to_str()?.to_string();
+                // Hidden entries only when asked for with a leading dot
+                if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                    return None;
+                }
+                // Follow symlinks so a link to a directory counts as one
+                let is_dir = entry.path().is_dir();
+                (is_dir || !dirs_only).then_some((name, is_dir))
+            })
+            .collect();
+        matches.sort_by_key(|(name, _)| name.to_lowercase());
+        matches.truncate(self.max_results);
+
+        matches
+            .into_iter()
+            .map(|(name, is_dir)| {
+                let suffix = if is_dir { "/" } else { "" };
+                PathCompletion {
+                    range: word.range.clone(),
+                    replacement: quote_path(&format!("{}{}{}", dir_part, name, suffix), word.quote, !is_dir),
+                    display: format!("{}{}", name, suffix),
+                    is_dir,
+                }
+            })
+            .collect()
+    }
+
+    fn resolve(&self, dir_part: &str) -> PathBuf {
+        let dir = match (dir_part.strip_prefix("~/"), &self.home) {
+            (Some(rest), Some(home)) => home.join(rest),
+            _ => PathBuf::from(dir_part),
+        };
+        if dir.as_os_str().is_empty() {
+            self.cwd.clone()
+        } else {
+            self.cwd.join(dir)
+        }
+    }
+}
+
+/// Re-escapes a completed path in the quoting style the user started with.
+/// A finished file name closes the quote; a directory leaves it open to keep typing.
+pub(crate) fn quote_path(path: &str, quote: Option<char>, close: bool) -> String {
+    // A leading `~` only expands unquoted
+    if let Some(rest) = path.strip_prefix('~') {
+        return format!("~{}", quote_path(rest, None, close));
+    }
+    let closing = |q: char| if close { q.to_string() } else { String::new() };
+    match quote {
+        Some('\'') => format!("'{}{}", path.replace('\'', r"'\''"), closing('\'')),
+        Some(q) => {
+            let escaped: String = path
+                .chars()
+                .flat_map(|c| {
+                    let escape = "\"\\$`".contains(c).then_some('\\');
+                    escape.into_iter().chain(std::iter::once(c))
+                })
+                .collect();
+            format!("{}{}{}", q, escaped, closing(q))
+        }
+        None => path
+            .chars()
+            .flat_map(|c| {
+                let escape = SPECIAL_CHARS.contains(c).then_some('\\');
+                escape.into_iter().chain(std::iter::once(c))
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn tree(name: &str) -> TempDir {
+        let root = TempDir::new(&format!("paths_{}", name));
+        for dir in ["src/bin", "My Documents", ".config", "target"] {
+            root.mkdir(dir);
+        }
+        for file in ["src/main.rs", "src/lib.rs", "My Documents/it's.txt", ".env", "README.md"] {
+            root.write(file, "");
+        }
+        root
+    }
+
+    fn replacements(completer: &PathCompleter, input: &str) -> Vec<String> {
+        completer
+            .complete(input, input.len())
+            .into_iter()
+            .map(|c| c.apply(input).0)
+            .collect()
+    }
+
+    #[test]
+    fn test_current_word() {
+        let word = CurrentWord::at("ls | cat My\\ Doc", 16);
+        assert_eq!(word.value, "My Doc");
+        assert_eq!(word.range, 9..16);
+        assert_eq!(word.command.as_deref(), Some("cat"));
+        assert_eq!(word.index, 1);
+
+        let word = CurrentWord::at("cd \"My Do", 9);
+        assert_eq!((word.value.as_str(), word.quote), ("My Do", Some('"')));
+        assert_eq!(CurrentWord::at("ls ", 3).range, 3..3);
+    }
+
+    #[test]
+    fn test_completes_files_and_directories() {
+        let root = tree("basic");
+        let completer = PathCompleter::new(&*root);
+
+        assert_eq!(replacements(&completer, "cat src/"), vec!["cat src/bin/", "cat src/lib.rs", "cat src/main.rs"]);
+        assert_eq!(replacements(&completer, "vim R"), vec!["vim README.md"]);
+        // Hidden entries only with a leading dot
+        assert!(!replacements(&completer, "ls ").iter().any(|r| r.contains(".env")));
+        assert_eq!(replacements(&completer, "ls .e"), vec!["ls .env"]);
+    }
+
+    #[test]
+    fn test_escapes_and_quotes() {
+        let root = tree("quoting");
+        let completer = PathCompleter::new(&*root);
+
+        assert_eq!(replacements(&completer, "ls My"), vec!["ls My\\ Documents/"]);
+        assert_eq!(replacements(&completer, "cat My\\ Documents/"), vec!["cat My\\ Documents/it\\'s.txt"]);
+        assert_eq!(replacements(&completer, "cat \"My Documents/i"), vec!["cat \"My Documents/it's.txt\""]);
+        assert_eq!(replacements(&completer, "cat 'My Documents/i"), vec![r"cat 'My Documents/it'\''s.txt'"]);
+    }
+
+    #[test]
+    fn test_cd_only_completes_directories_and_tilde() {
+        let root = tree("cd");
+        let completer = PathCompleter::new("/").with_home(&*root);
+
+        assert_eq!(replacements(&completer, "cd ~/"), vec!["cd ~/My\\ Documents/", "cd ~/src/", "cd ~/target/"]);
+        assert_eq!(replacements(&completer, "cd ~"), vec!["cd ~/"]);
+        assert_eq!(
+            PathCompleter::new(&*root).complete("cd src/ && cat src/m", 20)[0].display,
+            "main.rs"
+        );
+    }
+}
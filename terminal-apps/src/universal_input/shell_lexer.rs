@@ -0,0 +1,632 @@
+/// What a piece of a shell command line is, for highlighting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Command,
+    Keyword,
+    Assignment,
+    Argument,
+    Flag,
+    String,
+    Variable,
+    Substitution,
+    Redirection,
+    Operator,
+    Comment,
+    Heredoc,
+}
+
+impl TokenKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenKind::Command => "command",
+            TokenKind::Keyword => "keyword",
+            TokenKind::Assignment => "assignment",
+            TokenKind::Argument => "argument",
+            TokenKind::Flag => "flag",
+            TokenKind::String => "string",
+            TokenKind::Variable => "variable",
+            TokenKind::Substitution => "substitution",
+            TokenKind::Redirection => "redirection",
+            TokenKind::Operator => "operator",
+            TokenKind::Comment => "comment",
+            TokenKind::Heredoc => "heredoc",
+        }
+    }
+}
+
+/// A highlighted byte range of the input. Spans are ordered, never overlap
+/// and leave whitespace uncovered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub kind: TokenKind,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn text<'a>(&self, source: &'a str) -> &'a str {
+        &source[self.start..self.end]
+    }
+}
+
+const KEYWORDS: &[&str] = &[
+    "if", "then", "else", "elif", "fi", "for", "in", "do", "done", "while", "until", "case",
+    "esac", "function", "select", "time", "{", "}", "!", "[[", "]]",
+];
+
+/// Keywords after which the next word is a command again
+const COMMAND_KEYWORDS: &[&str] = &["if", "then", "else", "elif", "do", "while", "until", "time", "{", "!"];
+
+/// Spans for a whole command line
+pub fn lex(source: &str) -> Vec<Span> {
+    let mut lexer = Lexer::new(source, 0);
+    lexer.lex_sequence(None, true);
+    lexer.spans
+}
+
+/// Re-lexes only from the last statement boundary before an edit, so that
+/// highlighting each keystroke doesn't redo the whole buffer
+#[derive(Debug, Clone, Default)]
+pub struct IncrementalLexer {
+    text: String,
+    spans: Vec<Span>,
+    /// Offsets where the lexer is back at the start of a command with nothing pending
+    safe_points: Vec<usize>,
+    relexed_from: usize,
+}
+
+impl IncrementalLexer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, text: &str) -> &[Span] {
+        let prefix = common_prefix_len(&self.text, text);
+        // A token ending at a safe point may have looked one char past it
+        let restart = self
+            .safe_points
+            .iter()
+            .rev()
+            .copied()
+            .find(|&p| p < prefix)
+            .unwrap_or(0);
+
+        self.spans.retain(|s| s.end <= restart);
+        self.safe_points.retain(|&p| p <= restart);
+
+        let mut lexer = Lexer::new(text, restart);
+        lexer.lex_sequence(None, true);
+        self.spans.extend(lexer.spans);
+        self.safe_points.extend(lexer.safe_points);
+        self.text = text.to_string();
+        self.relexed_from = restart;
+        &self.spans
+    }
+
+    pub fn spans(&self) -> &[Span] {
+        &self.spans
+    }
+
+    /// Byte offset the last update started lexing from
+    pub fn relexed_from(&self) -> usize {
+        self.relexed_from
+    }
+}
+
+fn common_prefix_len(a: &str, b: &str) -> usize {
+    a.char_indices()
+        .zip(b.chars())
+        .find(|((_, x), y)| x != y)
+        .map(|((i, _), _)| i)
+        .unwrap_or_else(|| a.len().min(b.len()))
+}
+
+struct Lexer<'a> {
+    src: &'a str,
+    pos: usize,
+    spans: Vec<Span>,
+    safe_points: Vec<usize>,
+    /// Heredoc delimiters whose bodies start after the next newline, and
+    /// whether leading tabs are stripped (`<<-`)
+    pending_heredocs: Vec<(String, bool)>,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(src: &'a str, start: usize) -> Self {
+        Self {
+            src,
+            pos: start,
+            spans: Vec::new(),
+            safe_points: Vec::new(),
+            pending_heredocs: Vec::new(),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+
+    fn peek_nth(&self, n: usize) -> Option<char> {
+        self.src[self.pos..].chars().nth(n)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        self.src[self.pos..].starts_with(s)
+    }
+
+    fn push(&mut self, kind: TokenKind, start: usize) {
+        if self.pos > start {
+            self.spans.push(Span { kind, start, end: self.pos });
+        }
+    }
+
+    fn mark_safe(&mut self, top_level: bool) {
+        if top_level && self.pending_heredocs.is_empty() {
+            self.safe_points.push(self.pos);
+        }
+    }
+
+    /// Commands separated by operators, until end of input or `terminator`
+    fn lex_sequence(&mut self, terminator: Option<char>, top_level: bool) {
+        let mut command_position = true;
+        while let Some(c) = self.peek() {
+            if c == ' ' || c == '\t' {
+                self.bump();
+                continue;
+            }
+            if Some(c) == terminator {
+                return;
+            }
+            let start = self.pos;
+            match c {
+                '\n' => {
+                    self.bump();
+                    self.read_heredoc_bodies();
+                    command_position = true;
+                    self.mark_safe(top_level);
+                }
+                '#' => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.bump();
+                    }
+                    self.push(TokenKind::Comment, start);
+                }
+                _ if self.at_redirection() => {
+                    self.lex_redirection();
+                }
+                '|' | '&' | ';' | '(' | ')' => {
+                    self.bump();
+                    let next = self.peek();
+                    if matches!((c, next), ('|', Some('|')) | ('&', Some('&')) | (';', Some(';')) | ('|', Some('&'))) {
+                        self.bump();
+                    }
+                    self.push(TokenKind::Operator, start);
+                    command_position = c != ')';
+                    if c != '(' && c != ')' {
+                        self.mark_safe(top_level);
+                    }
+                }
+                _ => {
+                    let word = self.lex_word(command_position, terminator);
+                    command_position = match word {
+                        WordRole::Keyword(k) => COMMAND_KEYWORDS.contains(&k),
+                        WordRole::Assignment => command_position,
+                        WordRole::Other => false,
+                    };
+                }
+            }
+        }
+    }
+
+    fn at_redirection(&self) -> bool {
+        let rest = &self.src[self.pos..];
+        let after_digits = rest.trim_start_matches(|c: char| c.is_ascii_digit());
+        // `<(` and `>(` are process substitutions, lexed as words
+        (after_digits.starts_with('<') || after_digits.starts_with('>'))
+            && !after_digits.starts_with("<(")
+            && !after_digits.starts_with(">(")
+            || rest.starts_with("&>")
+    }
+
+    fn lex_redirection(&mut self) {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.bump();
+        }
+        let op = ["<<<", "<<-", "&>>", "<<", "<>", "<&", ">>", ">&", ">|", "&>", "<", ">"]
+            .into_iter()
+            .find(|op| self.starts_with(op))
+            .unwrap_or(">");
+        self.pos += op.len();
+        if op.ends_with('&') {
+            // `2>&1`, `>&-`
+            while self.peek().is_some_and(|c| c.is_ascii_digit() || c == '-') {
+                self.bump();
+            }
+        }
+        self.push(TokenKind::Redirection, start);
+
+        while self.peek().is_some_and(|c| c == ' ' || c == '\t') {
+            self.bump();
+        }
+        if op == "<<" || op == "<<-" {
+            self.lex_heredoc_delimiter(op == "<<-");
+        } else if self.peek().is_some_and(|c| !c.is_whitespace() && !"|&;()<>".contains(c)) {
+            self.lex_word(false, None);
+        }
+    }
+
+    fn lex_heredoc_delimiter(&mut self, strip_tabs: bool) {
+        let start = self.pos;
+        let mut delimiter = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || "|&;()<>".contains(c) {
+                break;
+            }
+            self.bump();
+            if c != '\'' && c != '"' && c != '\\' {
+                delimiter.push(c);
+            }
+        }
+        self.push(TokenKind::Heredoc, start);
+        if !delimiter.is_empty() {
+            self.pending_heredocs.push((delimiter, strip_tabs));
+        }
+    }
+
+    fn read_heredoc_bodies(&mut self) {
+        for (delimiter, strip_tabs) in std::mem::take(&mut self.pending_heredocs) {
+            let start = self.pos;
+            let mut end = self.pos;
+            while self.pos < self.src.len() {
+                let line_end = self.src[self.pos..].find('\n').map(|i| self.pos + i).unwrap_or(self.src.len());
+                let line = &self.src[self.pos..line_end];
+                let line = if strip_tabs { line.trim_start_matches('\t') } else { line };
+                end = line_end;
+                self.pos = (line_end + 1).min(self.src.len());
+                if line == delimiter {
+                    break;
+                }
+            }
+            if end > start {
+                self.spans.push(Span { kind: TokenKind::Heredoc, start, end });
+            }
+        }
+    }
+
+    /// One word: plain text, quotes, variables and substitutions run together
+    fn lex_word(&mut self, command_position: bool, terminator: Option<char>) -> WordRole<'a> {
+        let word_start = self.pos;
+        // Indices into `spans` of unquoted literal parts, typed once the word is known
+        let mut plain_parts = Vec::new();
+        let mut plain_start = self.pos;
+        let mut all_plain = true;
+
+        macro_rules! flush_plain {
+            () => {
+                if self.pos > plain_start {
+                    plain_parts.push(self.spans.len());
+                    self.push(TokenKind::Argument, plain_start);
+                }
+            };
+        }
+
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || Some(c) == terminator || "|&;()<>".contains(c) {
+                // Process substitution `<(...)` inside a word
+                if (c == '<' || c == '>') && self.peek_nth(1) == Some('(') && self.pos == word_start {
+                    let start = self.pos;
+                    self.pos += 2;
+                    self.push(TokenKind::Substitution, start);
+                    self.lex_sequence(Some(')'), false);
+                    let start = self.pos;
+                    if self.peek() == Some(')') {
+                        self.bump();
+                    }
+                    self.push(TokenKind::Substitution, start);
+                    all_plain = false;
+                    plain_start = self.pos;
+                    continue;
+                }
+                break;
+            }
+            match c {
+                '\\' => {
+                    self.bump();
+                    self.bump();
+                }
+                '\'' => {
+                    flush_plain!();
+                    all_plain = false;
+                    let start = self.pos;
+                    self.bump();
+                    while let Some(c) = self.bump() {
+                        if c == '\'' {
+                            break;
+                        }
+                    }
+                    self.push(TokenKind::String, start);
+                    plain_start = self.pos;
+                }
+                '"' => {
+                    flush_plain!();
+                    all_plain = false;
+                    self.lex_double_quoted();
+                    plain_start = self.pos;
+                }
+                '$' | '`' => {
+                    flush_plain!();
+                    if self.lex_expansion() {
+                        all_plain = false;
+                    } else {
+                        // A lone `$` is literal
+                        self.bump();
+                        plain_parts.push(self.spans.len());
+                        self.push(TokenKind::Argument, self.pos - 1);
+                    }
+                    plain_start = self.pos;
+                }
+                _ => {
+                    self.bump();
+                }
+            }
+        }
+        flush_plain!();
+
+        let text = &self.src[word_start..self.pos];
+        let (role, kind) = if command_position && all_plain && KEYWORDS.contains(&text) {
+            (WordRole::Keyword(KEYWORDS.iter().find(|k| **k == text).copied().unwrap_or("")), TokenKind::Keyword)
+        } else if command_position && is_assignment(text) {
+            (WordRole::Assignment, TokenKind::Assignment)
+        } else if command_position {
+            (WordRole::Other, TokenKind::Command)
+        } else if text.starts_with('-') {
+            (WordRole::Other, TokenKind::Flag)
+        } else {
+            (WordRole::Other, TokenKind::Argument)
+        };
+        for index in plain_parts {
+            self.spans[index].kind = kind;
+        }
+        role
+    }
+
+    fn lex_double_quoted(&mut self) {
+        let mut start = self.pos;
+        self.bump();
+        while let Some(c) = self.peek() {
+            match c {
+                '"' => {
+                    self.bump();
+                    break;
+                }
+                '\\' => {
+                    self.bump();
+                    self.bump();
+                }
+                '$' | '`' => {
+                    let literal_end = self.pos;
+                    let literal_start = start;
+                    let saved = self.spans.len();
+                    if literal_end > literal_start {
+                        self.spans.push(Span { kind: TokenKind::String, start: literal_start, end: literal_end });
+                    }
+                    if self.lex_expansion() {
+                        start = self.pos;
+                    } else {
+                        // Literal `$`: undo the split and keep going
+                        self.spans.truncate(saved);
+                        self.bump();
+                    }
+                }
+                _ => {
+                    self.bump();
+                }
+            }
+        }
+        self.push(TokenKind::String, start);
+    }
+
+    /// `$VAR`, `${...}`, `$(...)`, `$((...))` or a backtick substitution.
+    /// Returns false, consuming nothing, for a `$` that starts none of these.
+    fn lex_expansion(&mut self) -> bool {
+        let start = self.pos;
+        if self.peek() == Some('`') {
+            self.bump();
+            while let Some(c) = self.bump() {
+                match c {
+                    '\\' => {
+                        self.bump();
+                    }
+                    '`' => break,
+                    _ => {}
+                }
+            }
+            self.push(TokenKind::Substitution, start);
+            return true;
+        }
+
+        match self.peek_nth(1) {
+            Some('(') if self.peek_nth(2) == Some('(') => {
+                // Arithmetic: no commands inside, so one span to the matching `))`
+                self.pos += 3;
+                let mut depth = 2;
+                while depth > 0 {
+                    match self.bump() {
+                        Some('(') => depth += 1,
+                        Some(')') => depth -= 1,
+                        Some(_) => {}
+                        None => break,
+                    }
+                }
+                self.push(TokenKind::Substitution, start);
+            }
+            Some('(') => {
+                self.pos += 2;
+                self.push(TokenKind::Substitution, start);
+                self.lex_sequence(Some(')'), false);
+                let close = self.pos;
+                if self.peek() == Some(')') {
+                    self.bump();
+                }
+                self.push(TokenKind::Substitution, close);
+            }
+            Some('{') => {
+                self.pos += 2;
+                let mut depth = 1;
+                while depth > 0 {
+                    match self.bump() {
+                        Some('{') => depth += 1,
+                        Some('}') => depth -= 1,
+                        Some(_) => {}
+                        None => break,
+                    }
+                }
+                self.push(TokenKind::Variable, start);
+            }
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                self.bump();
+                while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == '_') {
+                    self.bump();
+                }
+                self.push(TokenKind::Variable, start);
+            }
+            Some(c) if c.is_ascii_digit() || "?$!#@*-".contains(c) => {
+                self.pos += 2;
+                self.push(TokenKind::Variable, start);
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+enum WordRole<'a> {
+    Keyword(&'a str),
+    Assignment,
+    Other,
+}
+
+/// `value` as one shell word that expands to nothing: always single-quoted,
+/// so newlines, `~`, `$` and the rest stay literal
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+fn is_assignment(word: &str) -> bool {
+    let Some(eq) = word.find('=') else {
+        return false;
+    };
+    let name = word[..eq].strip_suffix('+').unwrap_or(&word[..eq]);
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(source: &str) -> Vec<(&str, TokenKind)> {
+        lex(source).iter().map(|s| (s.text(source), s.kind)).collect()
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("plain"), "'plain'");
+        assert_eq!(shell_quote("x\nreboot"), "'x\nreboot'");
+        assert_eq!(shell_quote("~root"), "'~root'");
+        assert_eq!(shell_quote("it's $HOME"), r"'it'\''s $HOME'");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[test]
+    fn test_words_strings_and_flags() {
+        use TokenKind::*;
+        assert_eq!(
+            kinds(r#"echo "a b" -n --name="$USER" 'x y' # done"#),
+            vec![
+                ("echo", Command),
+                ("\"a b\"", String),
+                ("-n", Flag),
+                ("--name=", Flag),
+                ("\"", String),
+                ("$USER", Variable),
+                ("\"", String),
+                ("'x y'", String),
+                ("# done", Comment),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_substitutions_redirections_and_operators() {
+        use TokenKind::*;
+        assert_eq!(
+            kinds("FOO=1 cat ${DIR}/a $(ls -a | wc -l) 2>&1 >out.txt && if true; then x; fi"),
+            vec![
+                ("FOO=1", Assignment),
+                ("cat", Command),
+                ("${DIR}", Variable),
+                ("/a", Argument),
+                ("$(", Substitution),
+                ("ls", Command),
+                ("-a", Flag),
+                ("|", Operator),
+                ("wc", Command),
+                ("-l", Flag),
+                (")", Substitution),
+                ("2>&1", Redirection),
+                (">", Redirection),
+                ("out.txt", Argument),
+                ("&&", Operator),
+                ("if", Keyword),
+                ("true", Command),
+                (";", Operator),
+                ("then", Keyword),
+                ("x", Command),
+                (";", Operator),
+                ("fi", Keyword),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_heredoc_body() {
+        use TokenKind::*;
+        let source = "cat <<-'EOF' | wc\n\techo $HOME\n\tEOF\nls";
+        assert_eq!(
+            kinds(source),
+            vec![
+                ("cat", Command),
+                ("<<-", Redirection),
+                ("'EOF'", Heredoc),
+                ("|", Operator),
+                ("wc", Command),
+                ("\techo $HOME\n\tEOF", Heredoc),
+                ("ls", Command),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_incremental_matches_full_lex() {
+        let mut lexer = IncrementalLexer::new();
+        let mut text = String::new();
+        for ch in "git add . && git commit -m \"wip $(date)\"; echo done".chars() {
+            text.push(ch);
+            assert_eq!(lexer.update(&text), lex(&text).as_slice(), "{}", text);
+        }
+        assert_eq!(lexer.relexed_from(), "git add . && git commit -m \"wip $(date)\";".len());
+
+        // An edit inside the first command re-lexes from the start
+        lexer.update("got add . && git commit");
+        assert_eq!(lexer.relexed_from(), 0);
+        assert_eq!(lexer.spans(), lex("got add . && git commit").as_slice());
+    }
+}
@@ -0,0 +1,636 @@
+use super::shell_lexer::shell_quote;
+use crate::app::TerminalApp;
+use crate::blocks::{Block, BlockManager, BlockStorage, StorageFormat};
+use crate::fuzzy::fuzzy_match;
+use crate::workflow::ExtendedWorkflow;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+/// Everything a slash command may change while it runs
+pub struct SlashContext<'a> {
+    pub app: &'a mut TerminalApp,
+    pub blocks: &'a mut BlockManager,
+}
+
+/// What the caller should do once a command has run
+#[derive(Debug, Clone)]
+pub enum SlashOutcome {
+    /// Nothing further; the command did its work
+    Done,
+    /// Show a message to the user
+    Message(String),
+    /// Run a shell command, as if it had been typed
+    RunCommand(String),
+    /// Run a workflow with its variables filled in from the arguments
+    RunWorkflow(ExtendedWorkflow),
+}
+
+#[derive(Error, Debug)]
+pub enum SlashCommandError {
+    #[error("Unknown command: /{0}")]
+    UnknownCommand(String),
+    #[error("Missing argument: {0}")]
+    MissingArgument(String),
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+    #[error("Command failed: {0}")]
+    Failed(String),
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("YAML parse error: {0}")]
+    YamlError(#[from] serde_yaml::Error),
+}
+
+pub type SlashCommandResult<T> = Result<T, SlashCommandError>;
+
+/// A parsed command line, as handed to the command's handler
+pub struct SlashInvocation<'a> {
+    pub registry: &'a SlashCommandRegistry,
+    pub command: &'a SlashCommand,
+    /// Arguments after the command name, split like shell words
+    pub args: Vec<String>,
+}
+
+pub type SlashHandler = fn(&SlashInvocation, &mut SlashContext) -> SlashCommandResult<SlashOutcome>;
+
+/// Where the values offered for an argument come from
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArgValues {
+    /// Free text, or one of the argument's `choices` when it has some
+    #[default]
+    Any,
+    Themes,
+    Keysets,
+    Workflows,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct SlashArg {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub required: bool,
+    #[serde(default)]
+    pub values: ArgValues,
+    #[serde(default)]
+    pub choices: Vec<String>,
+}
+
+impl SlashArg {
+    pub fn required(name: &str, values: ArgValues) -> Self {
+        Self { name: name.to_string(), description: None, required: true, values, choices: Vec::new() }
+    }
+
+    pub fn optional(name: &str, values: ArgValues) -> Self {
+        Self { name: name.to_string(), description: None, required: false, values, choices: Vec::new() }
+    }
+
+    pub fn with_choices(mut self, choices: &[&str]) -> Self {
+        self.choices = choices.iter().map(|c| c.to_string()).collect();
+        self
+    }
+
+    fn usage(&self) -> String {
+        if self.required {
+            format!("<{}>", self.name)
+        } else {
+            format!("[{}]", self.name)
+        }
+    }
+}
+
+/// A registered command. `name` may be several words, like `workflow run`.
+#[derive(Debug, Clone)]
+pub struct SlashCommand {
+    pub name: String,
+    pub help: String,
+    pub args: Vec<SlashArg>,
+    pub handler: SlashHandler,
+    /// Shell command template for commands defined in config, with `{{arg}}` placeholders
+    pub template: Option<String>,
+}
+
+impl SlashCommand {
+    pub fn new(name: &str, help: &str, handler: SlashHandler) -> Self {
+        Self {
+            name: name.to_string(),
+            help: help.to_string(),
+            args: Vec::new(),
+            handler,
+            template: None,
+        }
+    }
+
+    pub fn with_arg(mut self, arg: SlashArg) -> Self {
+        self.args.push(arg);
+        self
+    }
+
+    /// `/name <required> [optional]`
+    pub fn usage(&self) -> String {
+        let mut usage = format!("/{}", self.name);
+        for arg in &self.args {
+            usage.push(' ');
+            usage.push_str(&arg.usage());
+        }
+        usage
+    }
+
+    fn check_args(&self, args: &[String]) -> SlashCommandResult<()> {
+        if let Some(missing) = self.args.iter().skip(args.len()).find(|a| a.required) {
+            return Err(SlashCommandError::MissingArgument(format!("{} (usage: {})", missing.name, self.usage())));
+        }
+        for (arg, value) in self.args.iter().zip(args) {
+            if !arg.choices.is_empty() && !arg.choices.contains(value) {
+                return Err(SlashCommandError::InvalidArgument(format!(
+                    "{} must be one of {}, got {}",
+                    arg.name,
+                    arg.choices.join(", "),
+                    value
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A command defined in the user's config
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SlashCommandConfig {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub args: Vec<SlashArg>,
+    /// Shell command to run, with `{{arg}}` replaced by argument values
+    pub run: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct SlashCommandsFile {
+    #[serde(default)]
+    pub commands: Vec<SlashCommandConfig>,
+}
+
+/// One entry of the `/` menu
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlashCompletion {
+    /// The whole input after accepting this entry
+    pub text: String,
+    pub display: String,
+    pub description: String,
+    /// Char indices in `display` that matched, for highlighting
+    pub positions: Vec<usize>,
+}
+
+/// Every slash command by name
+#[derive(Debug, Clone)]
+pub struct SlashCommandRegistry {
+    commands: BTreeMap<String, SlashCommand>,
+}
+
+impl SlashCommandRegistry {
+    /// An empty registry
+    pub fn empty() -> Self {
+        Self { commands: BTreeMap::new() }
+    }
+
+    /// The built-in commands
+    pub fn new() -> Self {
+        let mut registry = Self::empty();
+        registry.register(
+            SlashCommand::new("theme", "Switch the color theme", set_theme)
+                .with_arg(SlashArg::required("name", ArgValues::Themes)),
+        );
+        registry.register(
+            SlashCommand::new("keyset", "Switch the keyboard bindings", set_keyset)
+                .with_arg(SlashArg::required("name", ArgValues::Keysets)),
+        );
+        registry.register(
+            SlashCommand::new("export", "Save every block to a file", export_blocks)
+                .with_arg(SlashArg::required("path", ArgValues::Any))
+                .with_arg(SlashArg::optional("format", ArgValues::Any).with_choices(&["json", "csv", "text"])),
+        );
+        registry.register(
+            SlashCommand::new("bookmark", "Bookmark a block, the latest one by default", bookmark_block)
+                .with_arg(SlashArg::optional("block", ArgValues::Any)),
+        );
+        registry.register(SlashCommand::new("clear", "Remove every block", clear_blocks));
+        registry.register(
+            SlashCommand::new("workflow run", "Run a workflow, setting variables with key=value", run_workflow)
+                .with_arg(SlashArg::required("name", ArgValues::Workflows))
+                .with_arg(SlashArg::optional("variables", ArgValues::Any)),
+        );
+        registry.register(
+            SlashCommand::new("help", "List commands, or describe one", help)
+                .with_arg(SlashArg::optional("command", ArgValues::Any)),
+        );
+        registry
+    }
+
+    /// Adds a command, replacing any with the same name
+    pub fn register(&mut self, command: SlashCommand) {
+        self.commands.insert(command.name.clone(), command);
+    }
+
+    /// Adds a command from config that runs a shell command template
+    pub fn register_config(&mut self, config: SlashCommandConfig) {
+        let name = config.name.trim_start_matches('/').to_string();
+        self.register(SlashCommand {
+            name,
+            help: config.description,
+            args: config.args,
+            handler: run_template,
+            template: Some(config.run),
+        });
+    }
+
+    /// Registers every command in a YAML file of the form `commands: [...]`
+    pub fn load_config<P: AsRef<std::path::Path>>(&mut self, path: P) -> SlashCommandResult<usize> {
+        let file = crate::config_loader::ConfigLoader::load_slash_commands(path)?;
+        let count = file.commands.len();
+        for config in file.commands {
+            self.register_config(config);
+        }
+        Ok(count)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&SlashCommand> {
+        self.commands.get(name)
+    }
+
+    pub fn commands(&self) -> impl Iterator<Item = &SlashCommand> {
+        self.commands.values()
+    }
+
+    /// The command `input` invokes and its arguments. The longest matching
+    /// name wins, so `/workflow run x` finds `workflow run` before `workflow`.
+    pub fn parse<'a>(&'a self, input: &str) -> SlashCommandResult<(&'a SlashCommand, Vec<String>)> {
+        let words = split_words(input.trim().strip_prefix('/').unwrap_or(input.trim()));
+        for len in (1..=words.len()).rev() {
+            if let Some(command) = self.commands.get(&words[..len].join(" ")) {
+                return Ok((command, words[len..].to_vec()));
+            }
+        }
+        Err(SlashCommandError::UnknownCommand(words.first().cloned().unwrap_or_default()))
+    }
+
+    /// Parses and runs `input`
+    pub fn execute(&self, input: &str, ctx: &mut SlashContext) -> SlashCommandResult<SlashOutcome> {
+        let (command, args) = self.parse(input)?;
+        command.check_args(&args)?;
+        let invocation = SlashInvocation { registry: self, command, args };
+        (command.handler)(&invocation, ctx)
+    }
+
+    /// Menu entries for `input`: command names while the name is being
+    /// typed, then values for the argument under the cursor
+    pub fn complete(&self, input: &str, app: &TerminalApp) -> Vec<SlashCompletion> {
+        let Some(body) = input.strip_prefix('/') else {
+            return Vec::new();
+        };
+
+        // An argument is being typed once a full command name is followed by a space
+        if let Ok((command, args)) = self.parse(input) {
+            let typing_new = body.ends_with(char::is_whitespace);
+            let named = body.trim_end().len() > command.name.len();
+            if typing_new || named {
+                let (index, prefix) = match (typing_new, args.last()) {
+                    (false, Some(last)) => (args.len() - 1, last.as_str()),
+                    _ => (args.len(), ""),
+                };
+                let head = input.strip_suffix(prefix).unwrap_or(input);
+                let Some(arg) = command.args.get(index) else {
+                    return Vec::new();
+                };
+                let mut values = match &arg.values {
+                    ArgValues::Any => arg.choices.clone(),
+                    ArgValues::Themes => app.list_themes().into_iter().map(String::from).collect(),
+                    ArgValues::Keysets => app.list_keysets().into_iter().map(String::from).collect(),
+                    ArgValues::Workflows => app.list_workflows().into_iter().map(String::from).collect(),
+                };
+                values.sort();
+                return values
+                    .into_iter()
+                    .filter_map(|value| {
+                        let m = fuzzy_match(prefix, &value)?;
+                        Some(SlashCompletion {
+                            text: format!("{}{}", head, value),
+                            description: arg.description.clone().unwrap_or_else(|| arg.name.clone()),
+                            display: value,
+                            positions: m.positions,
+                        })
+                    })
+                    .collect();
+            }
+        }
+
+        let query = body.trim_start();
+        let mut matches: Vec<(i64, SlashCompletion)> = self
+            .commands
+            .values()
+            .filter_map(|command| {
+                let m = fuzzy_match(query, &command.name)?;
+                let trailing = if command.args.is_empty() { "" } else { " " };
+                Some((m.score, SlashCompletion {
+                    text: format!("/{}{}", command.name, trailing),
+                    display: command.usage(),
+                    description: command.help.clone(),
+                    // Shifted past the `/` in the usage line
+                    positions: m.positions.into_iter().map(|p| p + 1).collect(),
+                }))
+            })
+            .collect();
+        matches.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.text.cmp(&b.1.text)));
+        matches.into_iter().map(|(_, c)| c).collect()
+    }
+}
+
+impl Default for SlashCommandRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The open `/` menu and its selection
+#[derive(Debug, Clone, Default)]
+pub struct SlashMenu {
+    pub items: Vec<SlashCompletion>,
+    pub selected: usize,
+}
+
+impl SlashMenu {
+    pub fn new(items: Vec<SlashCompletion>) -> Self {
+        Self { items, selected: 0 }
+    }
+
+    pub fn select_next(&mut self) {
+        if self.selected + 1 < self.items.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn select_prev(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    pub fn selected_item(&self) -> Option<&SlashCompletion> {
+        self.items.get(self.selected)
+    }
+}
+
+/// Splits on whitespace, keeping quoted strings together
+fn split_words(input: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote = None;
+    for c in input.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    words
+}
+
+fn set_theme(call: &SlashInvocation, ctx: &mut SlashContext) -> SlashCommandResult<SlashOutcome> {
+    let name = &call.args[0];
+    if !ctx.app.set_theme(name) {
+        let mut themes = ctx.app.list_themes();
+        themes.sort();
+        return Err(SlashCommandError::InvalidArgument(format!(
+            "no theme named {} (available: {})",
+            name,
+            themes.join(", ")
+        )));
+    }
+    Ok(SlashOutcome::Message(format!("Theme set to {}", name)))
+}
+
+fn set_keyset(call: &SlashInvocation, ctx: &mut SlashContext) -> SlashCommandResult<SlashOutcome> {
+    let name = &call.args[0];
+    if !ctx.app.set_keyset(name) {
+        let mut keysets = ctx.app.list_keysets();
+        keysets.sort();
+        return Err(SlashCommandError::InvalidArgument(format!(
+            "no keyset named {} (available: {})",
+            name,
+            keysets.join(", ")
+        )));
+    }
+    Ok(SlashOutcome::Message(format!("Keyset set to {}", name)))
+}
+
+fn export_blocks(call: &SlashInvocation, ctx: &mut SlashContext) -> SlashCommandResult<SlashOutcome> {
+    let path = &call.args[0];
+    let format = match call.args.get(1).map(String::as_str) {
+        Some("json") => StorageFormat::Json,
+        Some("csv") => StorageFormat::Csv,
+        Some("text") => StorageFormat::PlainText,
+        Some(other) => return Err(SlashCommandError::InvalidArgument(format!("unknown format {}", other))),
+        // Otherwise go by the extension
+        None if path.ends_with(".csv") => StorageFormat::Csv,
+        None if path.ends_with(".txt") => StorageFormat::PlainText,
+        None => StorageFormat::Json,
+    };
+    let blocks: Vec<Block> = ctx.blocks.get_blocks().into_iter().cloned().collect();
+    BlockStorage::save_blocks(&blocks, path, format).map_err(SlashCommandError::Failed)?;
+    Ok(SlashOutcome::Message(format!("Exported {} blocks to {}", blocks.len(), path)))
+}
+
+fn bookmark_block(call: &SlashInvocation, ctx: &mut SlashContext) -> SlashCommandResult<SlashOutcome> {
+    let id = match call.args.first() {
+        Some(id) => id.clone(),
+        None => match ctx.blocks.get_blocks().last() {
+            Some(block) => block.id.clone(),
+            None => return Err(SlashCommandError::Failed("no blocks to bookmark".to_string())),
+        },
+    };
+    ctx.blocks.toggle_bookmark(&id).map_err(SlashCommandError::InvalidArgument)?;
+    let bookmarked = ctx.blocks.get_block(&id).is_some_and(|b| b.is_bookmarked());
+    let verb = if bookmarked { "Bookmarked" } else { "Removed bookmark from" };
+    Ok(SlashOutcome::Message(format!("{} block {}", verb, id)))
+}
+
+fn clear_blocks(_: &SlashInvocation, ctx: &mut SlashContext) -> SlashCommandResult<SlashOutcome> {
+    ctx.blocks.history_mut().clear();
+    Ok(SlashOutcome::Done)
+}
+
+fn run_workflow(call: &SlashInvocation, ctx: &mut SlashContext) -> SlashCommandResult<SlashOutcome> {
+    let name = &call.args[0];
+    let Some(mut workflow) = ctx.app.get_workflow(name).cloned() else {
+        return Err(SlashCommandError::InvalidArgument(format!("no workflow named {}", name)));
+    };
+    for assignment in &call.args[1..] {
+        let Some((key, value)) = assignment.split_once('=') else {
+            return Err(SlashCommandError::InvalidArgument(format!("expected key=value, got {}", assignment)));
+        };
+        workflow.set_variable(key, value);
+    }
+    Ok(SlashOutcome::RunWorkflow(workflow))
+}
+
+fn help(call: &SlashInvocation, _: &mut SlashContext) -> SlashCommandResult<SlashOutcome> {
+    if !call.args.is_empty() {
+        let (command, _) = call.registry.parse(&call.args.join(" "))?;
+        return Ok(SlashOutcome::Message(format!("{}\n  {}", command.usage(), command.help)));
+    }
+    let lines: Vec<String> = call
+        .registry
+        .commands()
+        .map(|c| format!("{:<28} {}", c.usage(), c.help))
+        .collect();
+    Ok(SlashOutcome::Message(lines.join("\n")))
+}
+
+/// Fills the template's placeholders, single-quoting each value so an
+/// argument is always one literal word: no expansion, no second command
+fn run_template(call: &SlashInvocation, _: &mut SlashContext) -> SlashCommandResult<SlashOutcome> {
+    let mut script = call.command.template.clone().unwrap_or_default();
+    for (i, arg) in call.command.args.iter().enumerate() {
+        let value = call.args.get(i).map(String::as_str).unwrap_or("");
+        script = script.replace(&format!("{{{{{}}}}}", arg.name), &shell_quote(value));
+    }
+    Ok(SlashOutcome::RunCommand(script))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyset::KeySet;
+
+    fn app() -> TerminalApp {
+        let mut app = TerminalApp::new("test");
+        app.register_keyset(KeySet::new("vim"));
+        app.register_keyset(KeySet::new("emacs"));
+        app
+    }
+
+    #[test]
+    fn test_menu_completes_names_then_arguments() {
+        let registry = SlashCommandRegistry::new();
+        let app = app();
+
+        let names: Vec<String> = registry.complete("/", &app).into_iter().map(|c| c.text).collect();
+        assert!(names.contains(&"/clear".to_string()) && names.contains(&"/workflow run ".to_string()));
+
+        let items = registry.complete("/exp", &app);
+        assert_eq!(items[0].text, "/export ");
+        assert_eq!(items[0].display, "/export <path> [format]");
+
+        let keysets: Vec<String> = registry.complete("/keyset ", &app).into_iter().map(|c| c.text).collect();
+        assert_eq!(keysets, vec!["/keyset emacs", "/keyset vim"]);
+        let formats: Vec<String> = registry.complete("/export out.json c", &app).into_iter().map(|c| c.text).collect();
+        assert_eq!(formats, vec!["/export out.json csv"]);
+    }
+
+    #[test]
+    fn test_dispatch_to_handlers() {
+        let registry = SlashCommandRegistry::new();
+        let mut app = app();
+        let mut blocks = BlockManager::new(10);
+        blocks.add_block(Block::new("ls".to_string(), "/".to_string()));
+        let mut ctx = SlashContext { app: &mut app, blocks: &mut blocks };
+
+        registry.execute("/keyset vim", &mut ctx).unwrap();
+        assert_eq!(ctx.app.current_keyset.as_ref().unwrap().name, "vim");
+        assert!(matches!(registry.execute("/theme nope", &mut ctx), Err(SlashCommandError::InvalidArgument(_))));
+        assert!(matches!(registry.execute("/theme", &mut ctx), Err(SlashCommandError::MissingArgument(_))));
+        assert!(matches!(registry.execute("/nope", &mut ctx), Err(SlashCommandError::UnknownCommand(_))));
+
+        registry.execute("/bookmark", &mut ctx).unwrap();
+        assert_eq!(ctx.blocks.get_bookmarked().len(), 1);
+
+        let mut workflow = ExtendedWorkflow::new("deploy");
+        workflow.set_variable("env", "staging");
+        ctx.app.register_workflow(workflow);
+        match registry.execute("/workflow run deploy env=prod", &mut ctx).unwrap() {
+            SlashOutcome::RunWorkflow(w) => assert_eq!(w.variables["env"], "prod"),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        registry.execute("/clear", &mut ctx).unwrap();
+        assert!(ctx.blocks.get_blocks().is_empty());
+    }
+
+    #[test]
+    fn test_commands_from_config() {
+        let yaml = r#"
+commands:
+  - name: deploy
+    description: Deploy to an environment
+    args:
+      - name: env
+        required: true
+        choices: [staging, prod]
+    run: make deploy ENV={{env}}
+"#;
+        let file: SlashCommandsFile = serde_yaml::from_str(yaml).unwrap();
+        let mut registry = SlashCommandRegistry::new();
+        for config in file.commands {
+            registry.register_config(config);
+        }
+        let mut app = app();
+        let mut blocks = BlockManager::new(10);
+        let mut ctx = SlashContext { app: &mut app, blocks: &mut blocks };
+
+        match registry.execute("/deploy prod", &mut ctx).unwrap() {
+            SlashOutcome::RunCommand(command) => assert_eq!(command, "make deploy ENV='prod'"),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(registry.execute("/deploy qa", &mut ctx), Err(SlashCommandError::InvalidArgument(_))));
+        assert_eq!(registry.complete("/deploy s", ctx.app)[0].text, "/deploy staging");
+    }
+
+    #[test]
+    fn test_template_args_quoted() {
+        let yaml = r#"
+commands:
+  - name: grep-logs
+    description: Search the app log
+    args:
+      - name: pattern
+        required: true
+    run: grep {{pattern}} /var/log/app.log
+"#;
+        let file: SlashCommandsFile = serde_yaml::from_str(yaml).unwrap();
+        let mut registry = SlashCommandRegistry::new();
+        for config in file.commands {
+            registry.register_config(config);
+        }
+        let mut app = app();
+        let mut blocks = BlockManager::new(10);
+        let mut ctx = SlashContext { app: &mut app, blocks: &mut blocks };
+
+        let run = |line: &str, ctx: &mut SlashContext| match registry.execute(line, ctx).unwrap() {
+            SlashOutcome::RunCommand(command) => command,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(run("/grep-logs 'disk full; rm -rf ~'", &mut ctx), "grep 'disk full; rm -rf ~' /var/log/app.log");
+        assert_eq!(run("/grep-logs \"x\nreboot\"", &mut ctx), "grep 'x\nreboot' /var/log/app.log");
+        assert_eq!(run("/grep-logs ~root", &mut ctx), "grep '~root' /var/log/app.log");
+        assert_eq!(run("/grep-logs \"it's\"", &mut ctx), r"grep 'it'\''s' /var/log/app.log");
+    }
+}
@@ -1,4 +1,11 @@
-use std::collections::HashMap;
+use super::path_completion::{CurrentWord, PathCompleter, PathCompletion};
+use super::spec_completion::{GeneratorCache, SpecCompleter, SpecItemKind};
+use crate::blocks::Block;
+use crate::classic_input::command_history::unix_now;
+use crate::completion_spec::CompletionSpec;
+use crate::fuzzy::{fuzzy_match, rank, Frecency, FuzzyMatch};
+use crate::modern_editing::AliasExpander;
+use std::collections::{HashMap, HashSet};
 
 /// A suggestion for autocomplete or command execution
 #[derive(Debug, Clone)]
@@ -7,6 +14,10 @@ pub struct Suggestion {
     pub description: String,
     pub category: SuggestionCategory,
     pub priority: u8,  // 0-255, higher = more relevant
+    /// Ranking score from match quality, priority and frecency; higher first
+    pub score: f64,
+    /// Char indices in `text` that matched the input, for highlighting
+    pub positions: Vec<usize>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -31,8 +42,18 @@ impl Suggestion {
             description,
             category,
             priority,
+            score: priority as f64,
+            positions: Vec::new(),
         }
     }
+
+    /// Scores the suggestion from a match against it; `offset` shifts match
+    /// positions found in part of `text` to positions in the whole
+    pub fn with_match(mut self, m: FuzzyMatch, offset: usize, frecency: f64) -> Self {
+        self.score = rank(m.score, self.priority as f64 / 5.0, frecency);
+        self.positions = m.positions.into_iter().map(|p| p + offset).collect();
+        self
+    }
 }
 
 /// Auto-completion engine with context awareness
@@ -40,8 +61,14 @@ impl Suggestion {
 pub struct AutoCompletion {
     pub available_commands: Vec<String>,
     pub command_descriptions: HashMap<String, String>,
-    pub available_files: Vec<String>,
+    pub paths: PathCompleter,
+    pub specs: SpecCompleter,
+    /// Output of spec generators, listed in the background
+    pub generators: GeneratorCache,
+    pub aliases: AliasExpander,
     pub history: Vec<String>,
+    pub frecency: Frecency,
+    pub max_suggestions: usize,
 }
 
 impl AutoCompletion {
@@ -52,8 +79,13 @@ impl AutoCompletion {
                 "git", "npm", "cargo", "python", "node", "ruby",
             ].iter().map(|s| s.to_string()).collect(),
             command_descriptions: HashMap::new(),
-            available_files: Vec::new(),
+            paths: PathCompleter::new("."),
+            specs: SpecCompleter::new(),
+            generators: GeneratorCache::new(),
+            aliases: AliasExpander::new(),
             history: Vec::new(),
+            frecency: Frecency::new(),
+            max_suggestions: 10,
         };
 
         // Add command descriptions
@@ -62,59 +94,146 @@ impl AutoCompletion {
         engine.command_descriptions.insert("grep".to_string(), "Search text patterns".to_string());
         engine.command_descriptions.insert("git".to_string(), "Version control system".to_string());
 
+        for spec in SpecCompleter::builtin().specs() {
+            engine.add_spec(spec.clone());
+        }
+
+        engine
+    }
+
+    /// An engine completing paths in the current directory, with the user's
+    /// own completion specs loaded over the builtin ones
+    pub fn from_env() -> Self {
+        let mut engine = Self::new();
+        if let Ok(cwd) = std::env::current_dir() {
+            engine.set_working_directory(cwd);
+        }
+        let mut user_specs = SpecCompleter::new();
+        let _ = user_specs.load_directory(SpecCompleter::default_dir());
+        for spec in user_specs.specs() {
+            engine.add_spec(spec.clone());
+        }
         engine
     }
 
     pub fn get_suggestions(&self, input: &str) -> Vec<Suggestion> {
+        let now = unix_now();
         let mut suggestions = Vec::new();
-
-        // Command suggestions
-        for cmd in &self.available_commands {
-            if cmd.starts_with(input) {
-                let desc = self.command_descriptions
-                    .get(cmd)
-                    .cloned()
-                    .unwrap_or_default();
-                suggestions.push(Suggestion::new(
-                    cmd.clone(),
-                    desc,
-                    SuggestionCategory::Command,
-                    100,
-                ));
+        let query = input.trim_start();
+
+        // Command suggestions while the first word is being typed
+        if !query.is_empty() && !query.contains(char::is_whitespace) {
+            for cmd in &self.available_commands {
+                if let Some(m) = fuzzy_match(query, cmd) {
+                    let desc = self.command_descriptions
+                        .get(cmd)
+                        .cloned()
+                        .unwrap_or_default();
+                    let suggestion = Suggestion::new(cmd.clone(), desc, SuggestionCategory::Command, 100);
+                    suggestions.push(suggestion.with_match(m, 0, self.frecency.program_score(cmd, now)));
+                }
+            }
+            let aliases = self.aliases.aliases().chain(self.aliases.abbreviations());
+            for (name, expansion) in aliases {
+                if let Some(m) = fuzzy_match(query, name) {
+                    let suggestion = Suggestion::new(name.to_string(), expansion.to_string(), SuggestionCategory::Alias, 100);
+                    suggestions.push(suggestion.with_match(m, 0, self.frecency.program_score(name, now)));
+                }
             }
         }
 
-        // File suggestions
-        for file in &self.available_files {
-            if file.starts_with(input) {
-                suggestions.push(Suggestion::new(
-                    file.clone(),
-                    "File".to_string(),
-                    SuggestionCategory::File,
-                    50,
-                ));
+        // Completions of the current word, ranked by how well the word matches them
+        let word = CurrentWord::at(input, input.len());
+        let offset = input[..word.range.start].chars().count();
+        let word_match = |display: &str| {
+            fuzzy_match(&word.value, display).unwrap_or(FuzzyMatch { score: 0, positions: Vec::new() })
+        };
+
+        // Subcommands, options and arguments from completion specs. Specs see
+        // through aliases: `gco ma` completes like `git checkout ma`. Generator
+        // values show up once `poll_generators` has collected them.
+        let (spec_input, delta) = self
+            .aliases
+            .expand_last_command(input)
+            .unwrap_or_else(|| (input.to_string(), 0));
+        for mut completion in self.generators.complete(&self.specs, &spec_input, spec_input.len()) {
+            let start = completion.range.start as isize - delta;
+            let end = completion.range.end as isize - delta;
+            if start < 0 {
+                continue;
             }
+            completion.range = start as usize..end as usize;
+            let category = match completion.kind {
+                SpecItemKind::Subcommand => SuggestionCategory::Command,
+                SpecItemKind::Option => SuggestionCategory::Custom("option".to_string()),
+                SpecItemKind::Argument => SuggestionCategory::Custom("argument".to_string()),
+            };
+            let text = completion.apply(input).0;
+            let frecency = self.frecency.command_score(&text, now);
+            let suggestion = Suggestion::new(text, completion.description.unwrap_or_default(), category, 90);
+            suggestions.push(suggestion.with_match(word_match(&completion.display), offset, frecency));
+        }
+
+        // File suggestions for the last word
+        for completion in self.paths.complete(input, input.len()) {
+            let frecency = self.frecency.command_score(&completion.apply(input).0, now);
+            let suggestion = Self::path_suggestion(input, &completion);
+            suggestions.push(suggestion.with_match(word_match(&completion.display), offset, frecency));
         }
 
         // History suggestions
-        for hist in &self.history {
-            if hist.starts_with(input) && !suggestions.iter().any(|s| s.text == *hist) {
-                suggestions.push(Suggestion::new(
-                    hist.clone(),
-                    "From history".to_string(),
-                    SuggestionCategory::History,
-                    75,
-                ));
+        if !query.is_empty() {
+            for hist in &self.history {
+                if let Some(m) = fuzzy_match(query, hist) {
+                    let suggestion = Suggestion::new(
+                        hist.clone(),
+                        "From history".to_string(),
+                        SuggestionCategory::History,
+                        75,
+                    );
+                    suggestions.push(suggestion.with_match(m, 0, self.frecency.command_score(hist, now)));
+                }
             }
         }
 
-        // Sort by priority (higher first)
-        suggestions.sort_by(|a, b| b.priority.cmp(&a.priority));
-        suggestions.truncate(10); // Limit to 10 suggestions
+        Self::rank_suggestions(&mut suggestions);
+        suggestions.truncate(self.max_suggestions);
 
         suggestions
     }
 
+    /// Best first; ties broken by text so the order never depends on insertion.
+    /// Keeps only the best-scoring suggestion for each text.
+    pub fn rank_suggestions(suggestions: &mut Vec<Suggestion>) {
+        suggestions.sort_by(|a, b| {
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.text.cmp(&b.text))
+        });
+        let mut seen = HashSet::new();
+        suggestions.retain(|s| seen.insert(s.text.clone()));
+    }
+
+    fn path_suggestion(input: &str, completion: &PathCompletion) -> Suggestion {
+        let (category, description) = if completion.is_dir {
+            (SuggestionCategory::Directory, "Directory")
+        } else {
+            (SuggestionCategory::File, "File")
+        };
+        Suggestion::new(completion.apply(input).0, description.to_string(), category, 50)
+    }
+
+    /// Picks up generator output listed in the background. Returns true when
+    /// suggestions should be fetched again.
+    pub fn poll_generators(&mut self) -> bool {
+        self.generators.poll()
+    }
+
+    pub fn add_alias(&mut self, name: impl Into<String>, expansion: impl Into<String>) {
+        self.aliases.add_alias(name, expansion);
+    }
+
     pub fn add_command(&mut self, cmd: String, description: String) {
         if !self.available_commands.contains(&cmd) {
             self.available_commands.push(cmd.clone());
@@ -122,10 +241,36 @@ impl AutoCompletion {
         self.command_descriptions.insert(cmd, description);
     }
 
-    pub fn add_files(&mut self, files: Vec<String>) {
-        self.available_files.extend(files);
+    /// Learns from a finished block: its command joins history, and the run
+    /// and its exit status count towards the command's frecency
+    pub fn record_block(&mut self, block: &Block) {
+        self.frecency.record_block(block);
+        self.add_to_history(block.command.trim().to_string());
     }
 
+    /// Registers a completion spec, and its command with the spec's description
+    pub fn add_spec(&mut self, spec: CompletionSpec) {
+        let description = spec.description.clone().unwrap_or_default();
+        self.add_command(spec.primary_name().to_string(), description);
+        self.specs.add_spec(spec);
+    }
+
+    /// Filesystem completions for the word at `cursor`; each suggestion is the whole new input
+    pub fn complete_paths(&self, input: &str, cursor: usize) -> Vec<Suggestion> {
+        self.paths
+            .complete(input, cursor)
+            .into_iter()
+            .map(|completion| Self::path_suggestion(input, &completion))
+            .collect()
+    }
+
+    pub fn set_working_directory(&mut self, cwd: impl Into<std::path::PathBuf>) {
+        self.paths.cwd = cwd.into();
+        self.specs.cwd = self.paths.cwd.clone();
+    }
+
+    /// Offers `entry` as a history suggestion. Frecency is left to
+    /// `record_block`, which knows whether the command succeeded.
     pub fn add_to_history(&mut self, entry: String) {
         if !entry.is_empty() && self.history.last() != Some(&entry) {
             self.history.push(entry);
@@ -238,6 +383,14 @@ impl SmartFeatures {
         }
     }
 
+    /// Completion set up from the environment; see `AutoCompletion::from_env`
+    pub fn from_env() -> Self {
+        Self {
+            auto_completion: AutoCompletion::from_env(),
+            ..Self::new()
+        }
+    }
+
     pub fn get_suggestions(&self, input: &str) -> Vec<Suggestion> {
         if self.enabled {
             self.auto_completion.get_suggestions(input)
@@ -246,6 +399,10 @@ impl SmartFeatures {
         }
     }
 
+    pub fn poll_generators(&mut self) -> bool {
+        self.auto_completion.poll_generators()
+    }
+
     pub fn check_input(&self, input: &str) -> Vec<ParseError> {
         if self.enabled {
             self.error_detector.check_for_errors(input)
@@ -270,6 +427,93 @@ mod tests {
         assert!(suggestions.iter().any(|s| s.text == "ls"));
     }
 
+    #[test]
+    fn test_spec_suggestions() {
+        let engine = AutoCompletion::new();
+        let suggestions = engine.get_suggestions("git ch");
+        assert_eq!(suggestions[0].text, "git checkout");
+        assert_eq!(suggestions[0].description, "Switch branches or restore working tree files");
+        assert_eq!(engine.command_descriptions["kubectl"], "Controls the Kubernetes cluster manager");
+    }
+
+    #[test]
+    fn test_generator_values_arrive_in_background() {
+        let spec: CompletionSpec = serde_yaml::from_str(
+            "name: deploy\nargs:\n  - generators:\n      - script: echo staging production\n        split_on: ' '\n",
+        )
+        .unwrap();
+        let mut engine = AutoCompletion::new();
+        engine.set_working_directory(std::env::temp_dir());
+        engine.add_spec(spec);
+
+        // The first lookup doesn't wait for the generator
+        assert!(!engine.get_suggestions("deploy st").iter().any(|s| s.text == "deploy staging"));
+
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while !engine.poll_generators() && std::time::Instant::now() < deadline {
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        assert_eq!(engine.get_suggestions("deploy st")[0].text, "deploy staging");
+    }
+
+    #[test]
+    fn test_fuzzy_ranking_with_frecency() {
+        let mut engine = AutoCompletion::new();
+        engine.set_working_directory(std::env::temp_dir().join("warp_no_such_dir"));
+        // Subsequence match on the command name, with positions for highlighting
+        let suggestions = engine.get_suggestions("crg");
+        assert_eq!(suggestions[0].text, "cargo");
+        assert_eq!(suggestions[0].positions, vec![0, 2, 3]);
+
+        // History that failed ranks below history that succeeded
+        let mut ok = Block::new("make test".to_string(), "/tmp".to_string());
+        ok.set_output(String::new(), String::new(), 0);
+        let mut failed = Block::new("make tset".to_string(), "/tmp".to_string());
+        failed.set_output(String::new(), String::new(), 2);
+        for _ in 0..3 {
+            engine.record_block(&failed);
+            engine.record_block(&ok);
+        }
+        let texts: Vec<String> = engine.get_suggestions("make t").into_iter().map(|s| s.text).collect();
+        assert_eq!(texts, vec!["make test", "make tset"]);
+
+        // Typing a command and then finishing its block counts it once
+        let now = unix_now();
+        let before = engine.frecency.command_score("make test", now);
+        engine.add_to_history("make test".to_string());
+        assert_eq!(engine.frecency.command_score("make test", now), before);
+    }
+
+    #[test]
+    fn test_alias_suggestions_and_completion_through_aliases() {
+        let mut engine = AutoCompletion::new();
+        engine.set_working_directory(std::env::temp_dir().join("warp_no_such_dir"));
+        engine.add_alias("gco", "git checkout");
+        engine.add_alias("gcp", "git cherry-pick");
+        engine.aliases.add_abbreviation("gcm", "git commit -m");
+
+        let suggestions = engine.get_suggestions("gc");
+        let alias = suggestions.iter().find(|s| s.text == "gco").unwrap();
+        assert_eq!(alias.category, SuggestionCategory::Alias);
+        assert_eq!(alias.description, "git checkout");
+        assert!(suggestions.iter().any(|s| s.text == "gcm" && s.category == SuggestionCategory::Alias));
+
+        let texts: Vec<String> = engine.get_suggestions("gcp --ab").into_iter().map(|s| s.text).collect();
+        assert_eq!(texts[0], "gcp --abort");
+    }
+
+    #[test]
+    fn test_rank_suggestions_is_stable() {
+        let mut suggestions = vec![
+            Suggestion::new("b".to_string(), String::new(), SuggestionCategory::History, 75),
+            Suggestion::new("a".to_string(), String::new(), SuggestionCategory::History, 75),
+            Suggestion::new("a".to_string(), String::new(), SuggestionCategory::Command, 100),
+        ];
+        AutoCompletion::rank_suggestions(&mut suggestions);
+        let order: Vec<(&str, u8)> = suggestions.iter().map(|s| (s.text.as_str(), s.priority)).collect();
+        assert_eq!(order, vec![("a", 100), ("b", 75)]);
+    }
+
     #[test]
     fn test_error_detection() {
         let detector = ErrorDetector::new();
@@ -0,0 +1,513 @@
+use super::path_completion::{quote_path, CurrentWord, PathCompleter};
+use crate::completion_spec::{ArgSpec, ArgTemplate, CompletionSpec, CompletionSpecResult, Generator};
+use crate::config_loader::ConfigLoader;
+use crate::latest_worker::LatestWorker;
+use std::cell::{OnceCell, RefCell};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::Read;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+/// Specs compiled into the binary, so completions work without a config directory
+const BUILTIN_SPECS: &[&str] = &[
+    include_str!("../../completion_specs/cargo.yaml"),
+    include_str!("../../completion_specs/docker.yaml"),
+    include_str!("../../completion_specs/git.yaml"),
+    include_str!("../../completion_specs/kubectl.yaml"),
+    include_str!("../../completion_specs/npm.yaml"),
+];
+
+/// How long generator output is served before the generator runs again
+const GENERATOR_TTL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpecItemKind {
+    Subcommand,
+    Option,
+    Argument,
+}
+
+/// One completion from a spec for the word under the cursor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpecCompletion {
+    /// Byte range of the input to replace
+    pub range: Range<usize>,
+    pub replacement: String,
+    pub display: String,
+    pub description: Option<String>,
+    pub kind: SpecItemKind,
+}
+
+impl SpecCompletion {
+    /// The input with this completion applied, and the cursor after it
+    pub fn apply(&self, input: &str) -> (String, usize) {
+        let mut output = input.to_string();
+        output.replace_range(self.range.clone(), &self.replacement);
+        (output, self.range.start + self.replacement.len())
+    }
+}
+
+/// Completes subcommands, options and arguments from completion specs
+#[derive(Debug, Clone, Default)]
+pub struct SpecCompleter {
+    specs: HashMap<String, CompletionSpec>,
+    /// Where generators run and file arguments are completed from
+    pub cwd: PathBuf,
+}
+
+impl SpecCompleter {
+    pub fn new() -> Self {
+        Self {
+            specs: HashMap::new(),
+            cwd: std::env::current_dir().unwrap_or_default(),
+        }
+    }
+
+    /// The specs shipped in `completion_specs/`
+    pub fn builtin() -> Self {
+        let mut completer = Self::new();
+        for source in BUILTIN_SPECS {
+            if let Ok(spec) = serde_yaml::from_str(source) {
+                completer.add_spec(spec);
+            }
+        }
+        completer
+    }
+
+    /// The builtin specs, overridden by any in the user's spec directory
+    pub fn from_env() -> Self {
+        let mut completer = Self::builtin();
+        let _ = completer.load_directory(Self::default_dir());
+        completer
+    }
+
+    /// `$WARP_COMPLETION_SPECS_DIR`, or `~/.warp/completion_specs`
+    pub fn default_dir() -> PathBuf {
+        if let Ok(dir) = std::env::var("WARP_COMPLETION_SPECS_DIR") {
+            return PathBuf::from(dir);
+        }
+        let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
+        PathBuf::from(home).join(".warp").join("completion_specs")
+    }
+
+    /// Adds every spec in `dir`, replacing any with the same name
+    pub fn load_directory<P: AsRef<Path>>(&mut self, dir: P) -> CompletionSpecResult<usize> {
+        let specs = ConfigLoader::load_completion_specs_from_directory(dir)?;
+        let count = specs.len();
+        for spec in specs {
+            self.add_spec(spec);
+        }
+        Ok(count)
+    }
+
+    pub fn add_spec(&mut self, spec: CompletionSpec) {
+        for name in &spec.name {
+            self.specs.insert(name.clone(), spec.clone());
+        }
+    }
+
+    pub fn spec(&self, command: &str) -> Option<&CompletionSpec> {
+        self.specs.get(command)
+    }
+
+    pub fn specs(&self) -> impl Iterator<Item = &CompletionSpec> {
+        self.specs.values()
+    }
+
+    pub fn complete(&self, input: &str, cursor: usize) -> Vec<SpecCompletion> {
+        self.complete_in(input, cursor, &self.cwd)
+    }
+
+    /// Like `complete`, with generators and file arguments relative to `cwd`
+    pub fn complete_in(&self, input: &str, cursor: usize, cwd: &Path) -> Vec<SpecCompletion> {
+        self.complete_with(input, cursor, cwd, &mut |generator| run_generator(generator, cwd))
+    }
+
+    /// Like `complete_in`, taking generator output from `generate` instead of
+    /// running generators, e.g. from a cache filled in the background
+    pub fn complete_with(
+        &self,
+        input: &str,
+        cursor: usize,
+        cwd: &Path,
+        generate: &mut dyn FnMut(&Generator) -> Vec<String>,
+    ) -> Vec<SpecCompletion> {
+        let word = CurrentWord::at(input, cursor);
+        // Completing the command name itself is left to command completion
+        let Some(spec) = word.previous.first().and_then(|c| self.specs.get(c)) else {
+            return Vec::new();
+        };
+
+        let mut spec = spec;
+        let mut arg_index = 0;
+        let mut option_args: VecDeque<&ArgSpec> = VecDeque::new();
+        let mut end_of_options = false;
+        for w in &word.previous[1..] {
+            if option_args.pop_front().is_some() {
+                continue;
+            }
+            if w == "--" {
+                end_of_options = true;
+            } else if w.starts_with('-') && w.len() > 1 && !end_of_options {
+                // `--opt=value` carries its own argument
+                if let Some(option) = spec.option(w).filter(|_| !w.contains('=')) {
+                    option_args.extend(option.args.iter());
+                }
+            } else if let Some(sub) = spec.subcommand(w).filter(|_| arg_index == 0) {
+                spec = sub;
+            } else if !arg_at(spec, arg_index).is_some_and(|a| a.variadic) {
+                arg_index += 1;
+            }
+        }
+
+        let prefix = word.value.as_str();
+        let item = |replacement: String, display: &str, description: &Option<String>, kind| SpecCompletion {
+            range: word.range.clone(),
+            replacement,
+            display: display.to_string(),
+            description: description.clone(),
+            kind,
+        };
+
+        if let Some(arg) = option_args.front() {
+            return arg_values(arg, &word, input, cursor, cwd, generate);
+        }
+
+        let mut completions = Vec::new();
+        if prefix.starts_with('-') && !end_of_options {
+            for option in &spec.options {
+                for name in option.name.iter().filter(|n| n.starts_with(prefix)) {
+                    completions.push(item(name.clone(), name, &option.description, SpecItemKind::Option));
+                }
+            }
+            return completions;
+        }
+
+        if arg_index == 0 {
+            for sub in &spec.subcommands {
+                if let Some(name) = sub.name.iter().find(|n| n.starts_with(prefix)) {
+                    completions.push(item(name.clone(), name, &sub.description, SpecItemKind::Subcommand));
+                }
+            }
+        }
+        if let Some(arg) = arg_at(spec, arg_index) {
+            completions.extend(arg_values(arg, &word, input, cursor, cwd, generate));
+        }
+        completions
+    }
+}
+
+fn arg_values(
+    arg: &ArgSpec,
+    word: &CurrentWord,
+    input: &str,
+    cursor: usize,
+    cwd: &Path,
+    generate: &mut dyn FnMut(&Generator) -> Vec<String>,
+) -> Vec<SpecCompletion> {
+    let prefix = word.value.as_str();
+    let mut values: Vec<String> = arg.suggestions.clone();
+    for generator in &arg.generators {
+        values.extend(generate(generator));
+    }
+    // Static suggestions and generator output often overlap; keep the first of each
+    let mut seen = HashSet::new();
+    values.retain(|v| v.starts_with(prefix) && seen.insert(v.clone()));
+
+    let mut completions: Vec<SpecCompletion> = values
+        .into_iter()
+        .map(|value| SpecCompletion {
+            range: word.range.clone(),
+            replacement: quote_path(&value, word.quote, true),
+            display: value,
+            description: arg.description.clone().or_else(|| arg.name.clone()),
+            kind: SpecItemKind::Argument,
+        })
+        .collect();
+
+    if let Some(template) = arg.template {
+        let paths = PathCompleter::new(cwd).complete(input, cursor);
+        completions.extend(
+            paths
+                .into_iter()
+                .filter(|p| p.is_dir || template == ArgTemplate::Filepaths)
+                .map(|p| SpecCompletion {
+                    range: p.range,
+                    replacement: p.replacement,
+                    display: p.display,
+                    description: None,
+                    kind: SpecItemKind::Argument,
+                }),
+        );
+    }
+    completions
+}
+
+/// The positional argument at `index`, or the last one if it repeats
+fn arg_at(spec: &CompletionSpec, index: usize) -> Option<&ArgSpec> {
+    spec.args
+        .get(index)
+        .or_else(|| spec.args.last().filter(|a| a.variadic))
+}
+
+/// Runs a generator's script with `sh`, giving up after its timeout
+pub fn run_generator(generator: &Generator, cwd: &Path) -> Vec<String> {
+    let Ok(mut child) = Command::new("sh")
+        .arg("-c")
+        .arg(&generator.script)
+        .current_dir(cwd)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+    else {
+        return Vec::new();
+    };
+
+    // Read on a thread so a large listing can't fill the pipe and stall the child
+    let mut stdout = child.stdout.take();
+    let (sender, output) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut output = String::new();
+        if let Some(stdout) = stdout.as_mut() {
+            let _ = stdout.read_to_string(&mut output);
+        }
+        let _ = sender.send(output);
+    });
+
+    let deadline = Instant::now() + Duration::from_millis(generator.timeout_ms);
+    let succeeded = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status.success(),
+            Ok(None) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(5)),
+            _ => {
+                let _ = child.kill();
+                let _ = child.wait();
+                break false;
+            }
+        }
+    };
+    if !succeeded {
+        return Vec::new();
+    }
+    // Something the script started in the background may still hold the
+    // pipe open; the reader is left to it once the deadline has passed
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    let Ok(output) = output.recv_timeout(remaining) else {
+        return Vec::new();
+    };
+
+    output
+        .split(generator.split_on.as_str())
+        .map(|item| {
+            let item = item.trim();
+            generator
+                .strip_prefix
+                .as_deref()
+                .and_then(|p| item.strip_prefix(p))
+                .unwrap_or(item)
+                .trim()
+                .to_string()
+        })
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// A generator's script and the directory it runs in
+type GeneratorKey = (String, PathBuf);
+/// Generators for the worker to run, each with its directory
+type GeneratorBatch = Vec<(Generator, PathBuf)>;
+/// What the worker sends back: each generator's output
+type GeneratorOutput = Vec<(GeneratorKey, Vec<String>)>;
+
+/// Generator output collected off the UI thread. Lookups answer from what
+/// has finished and queue the rest on a background worker; `poll` picks up
+/// the results.
+#[derive(Debug, Default)]
+pub struct GeneratorCache {
+    values: HashMap<GeneratorKey, (Instant, Vec<String>)>,
+    /// Queued for the latest lookup and not back yet
+    pending: RefCell<HashSet<GeneratorKey>>,
+    /// Started on the first lookup that misses; ends when this is dropped
+    worker: OnceCell<LatestWorker<GeneratorBatch, GeneratorOutput>>,
+}
+
+impl GeneratorCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Completions from `specs` with generator output taken from the cache.
+    /// Generators with nothing cached, or only stale output, are queued.
+    pub fn complete(&self, specs: &SpecCompleter, input: &str, cursor: usize) -> Vec<SpecCompletion> {
+        let cwd = &specs.cwd;
+        let now = Instant::now();
+        let mut missing = Vec::new();
+        let completions = specs.complete_with(input, cursor, cwd, &mut |generator| {
+            match self.values.get(&(generator.script.clone(), cwd.clone())) {
+                Some((listed, values)) => {
+                    if now.duration_since(*listed) > GENERATOR_TTL {
+                        missing.push(generator.clone());
+                    }
+                    values.clone()
+                }
+                None => {
+                    missing.push(generator.clone());
+                    Vec::new()
+                }
+            }
+        });
+        self.request(missing, cwd);
+        completions
+    }
+
+    /// Queues `generators` unless they are already on their way. Requests
+    /// replace each other, so only the latest lookup's generators run.
+    fn request(&self, generators: Vec<Generator>, cwd: &Path) {
+        let mut pending = self.pending.borrow_mut();
+        let mut keys = HashSet::new();
+        let batch: GeneratorBatch = generators
+            .into_iter()
+            .filter(|g| keys.insert((g.script.clone(), cwd.to_path_buf())))
+            .map(|g| (g, cwd.to_path_buf()))
+            .collect();
+        if batch.is_empty() || keys.is_subset(&pending) {
+            return;
+        }
+        let worker = self.worker.get_or_init(|| {
+            LatestWorker::spawn(|batch: GeneratorBatch| {
+                batch
+                    .into_iter()
+                    .map(|(generator, cwd)| {
+                        let values = run_generator(&generator, &cwd);
+                        ((generator.script, cwd), values)
+                    })
+                    .collect()
+            })
+        });
+        if worker.send(batch) {
+            *pending = keys;
+        }
+    }
+
+    /// Stores finished generator output, without blocking. Returns true when
+    /// something arrived and suggestions should be fetched again.
+    pub fn poll(&mut self) -> bool {
+        let Some(worker) = self.worker.get() else {
+            return false;
+        };
+        let mut changed = false;
+        while let Some(results) = worker.try_recv() {
+            let now = Instant::now();
+            for (key, values) in results {
+                self.pending.get_mut().remove(&key);
+                self.values.insert(key, (now, values));
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+/// Clones keep the cached output but start their own worker when they need
+/// one, so results never go to the wrong copy
+impl Clone for GeneratorCache {
+    fn clone(&self) -> Self {
+        Self {
+            values: self.values.clone(),
+            pending: RefCell::default(),
+            worker: OnceCell::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn displays(completions: &[SpecCompletion]) -> Vec<&str> {
+        completions.iter().map(|c| c.display.as_str()).collect()
+    }
+
+    #[test]
+    fn test_subcommands_with_descriptions() {
+        let completer = SpecCompleter::builtin();
+        let completions = completer.complete("git ch", 6);
+        assert_eq!(displays(&completions), vec!["checkout", "cherry-pick"]);
+        assert_eq!(
+            completions[0].description.as_deref(),
+            Some("Switch branches or restore working tree files")
+        );
+        assert_eq!(completions[1].apply("git ch").0, "git cherry-pick");
+
+        // Aliases resolve, and options come from the subcommand
+        assert_eq!(displays(&completer.complete("cargo b --rel", 13)), vec!["--release"]);
+        assert_eq!(displays(&completer.complete("kubectl get -o y", 16)), vec!["yaml"]);
+    }
+
+    #[test]
+    fn test_generator_lists_branches() {
+        let repo = TempDir::new("spec_git");
+        let git = |args: &[&str]| {
+            Command::new("git")
+                .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+                .args(args)
+                .current_dir(&repo)
+                .output()
+                .unwrap()
+        };
+        git(&["init", "-q", "-b", "main"]);
+        git(&["commit", "-q", "--allow-empty", "-m", "init"]);
+        git(&["branch", "feature/login"]);
+
+        let mut completer = SpecCompleter::builtin();
+        completer.cwd = repo.to_path_buf();
+        let completions = completer.complete("git checkout ", 13);
+        assert!(displays(&completions).starts_with(&["feature/login", "main"]));
+        // A new branch name has nothing to suggest
+        assert!(completer.complete("git checkout -b ", 16).is_empty());
+    }
+
+    #[test]
+    fn test_argument_values_are_deduplicated() {
+        let spec: CompletionSpec = serde_yaml::from_str(
+            "name: deploy\nargs:\n  - suggestions: [main, dev]\n    generators:\n      - script: list-envs\n",
+        )
+        .unwrap();
+        let mut completer = SpecCompleter::new();
+        completer.add_spec(spec);
+        let mut generate = |_: &Generator| vec!["dev".to_string(), "staging".to_string(), "main".to_string()];
+        let completions = completer.complete_with("deploy ", 7, Path::new("."), &mut generate);
+        assert_eq!(displays(&completions), vec!["main", "dev", "staging"]);
+    }
+
+    #[test]
+    fn test_generator_timeout() {
+        let generator = Generator {
+            script: "sleep 5; echo late".to_string(),
+            split_on: "\n".to_string(),
+            strip_prefix: None,
+            timeout_ms: 50,
+        };
+        let started = Instant::now();
+        assert!(run_generator(&generator, Path::new(".")).is_empty());
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_generator_background_child_does_not_block() {
+        let generator = Generator {
+            script: "sleep 5 & echo now".to_string(),
+            split_on: "\n".to_string(),
+            strip_prefix: None,
+            timeout_ms: 100,
+        };
+        let started = Instant::now();
+        run_generator(&generator, Path::new("."));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+}